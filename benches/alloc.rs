@@ -0,0 +1,145 @@
+//! Benchmarks comparing the huge page allocator with the system allocator (and jemalloc with the
+//! bench-jemalloc feature) for large allocation workloads.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use huge_global_alloc::HugeGlobalAllocator;
+
+static HUGE: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+
+/// Maps every allocation, for holding many segments cheaply
+static SEGMENTS: HugeGlobalAllocator = HugeGlobalAllocator::new(PAGE);
+
+#[cfg(feature = "bench-jemalloc")]
+static JEMALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+const PAGE: usize = 4096;
+
+/// Returns the allocators to compare
+fn allocators() -> Vec<(&'static str, &'static (dyn GlobalAlloc + Sync))> {
+    vec![
+        ("huge", &HUGE),
+        ("system", &System),
+        #[cfg(feature = "bench-jemalloc")]
+        ("jemalloc", &JEMALLOC),
+    ]
+}
+
+/// Writes a byte to every page of a block
+fn touch(ptr: *mut u8, size: usize) {
+    for offset in (0..size).step_by(PAGE) {
+        unsafe { ptr.add(offset).write_volatile(1) };
+    }
+}
+
+/// Grows a block by doubling its size from 4kb, touching the new space each time as Vec would
+fn vec_growth(alloc: &dyn GlobalAlloc, size: usize) {
+    let mut layout = Layout::from_size_align(PAGE, 8).unwrap();
+    let mut ptr = unsafe { alloc.alloc(layout) };
+
+    touch(ptr, PAGE);
+
+    while layout.size() < size {
+        let new_size = layout.size() * 2;
+
+        ptr = unsafe { alloc.realloc(ptr, layout, new_size) };
+        touch(unsafe { ptr.add(layout.size()) }, new_size - layout.size());
+
+        layout = Layout::from_size_align(new_size, 8).unwrap();
+    }
+
+    unsafe { alloc.dealloc(black_box(ptr), layout) };
+}
+
+/// Allocates, touches and frees a block
+fn alloc_free(alloc: &dyn GlobalAlloc, size: usize) {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+
+    let ptr = unsafe { alloc.alloc(layout) };
+    touch(ptr, size);
+
+    unsafe { alloc.dealloc(black_box(ptr), layout) };
+}
+
+fn bench_vec_growth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vec_growth");
+    group.sample_size(10);
+
+    for size in [4 * 1024 * 1024, 64 * 1024 * 1024] {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        for (name, alloc) in allocators() {
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |b, &size| b.iter(|| vec_growth(alloc, size)));
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_alloc_free(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_free");
+    group.sample_size(10);
+
+    for size in [2 * 1024 * 1024, 32 * 1024 * 1024] {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        for (name, alloc) in allocators() {
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |b, &size| b.iter(|| alloc_free(alloc, size)));
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contention");
+    group.sample_size(10);
+
+    const SIZE: usize = 2 * 1024 * 1024;
+    const ITERATIONS: usize = 16;
+
+    for threads in [2, 8] {
+        for (name, alloc) in allocators() {
+            group.bench_with_input(BenchmarkId::new(name, threads), &threads, |b, &threads| {
+                b.iter(|| {
+                    thread::scope(|s| {
+                        for _ in 0..threads {
+                            s.spawn(|| {
+                                for _ in 0..ITERATIONS {
+                                    alloc_free(alloc, SIZE);
+                                }
+                            });
+                        }
+                    })
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Maps and frees a segment while other segments are live. The cost should stay flat as the
+/// number of live segments grows.
+fn bench_live_segments(c: &mut Criterion) {
+    let mut group = c.benchmark_group("live_segments");
+    let layout = Layout::from_size_align(PAGE, 8).unwrap();
+
+    for live in [16, 256, 4096] {
+        let ptrs: Vec<_> = (0..live).map(|_| unsafe { SEGMENTS.alloc(layout) }).collect();
+
+        group.bench_with_input(BenchmarkId::from_parameter(live), &live, |b, _| b.iter(|| alloc_free(&SEGMENTS, PAGE)));
+
+        for ptr in ptrs {
+            unsafe { SEGMENTS.dealloc(ptr, layout) };
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_vec_growth, bench_alloc_free, bench_contention, bench_live_segments);
+criterion_main!(benches);
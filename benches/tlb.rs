@@ -0,0 +1,241 @@
+//! Measures data TLB misses for a pointer chasing workload over a buffer backed by the huge page
+//! allocator and one backed by default size pages, using the dTLB read miss hardware counter from
+//! perf_event_open. Each hop lands on a random page, so with default pages nearly every hop
+//! misses the TLB once the buffer outgrows it.
+//!
+//! Usage: cargo bench --features bench-perf --bench tlb -- [megabytes] [hops]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ffi::{c_int, c_ulong, c_void};
+use std::hint::black_box;
+use std::io;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+static HUGE: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+
+const PAGE: usize = 4096;
+
+/// Bytes between the nodes of the chain, one cache line
+const NODE: usize = 64;
+
+/// perf_event_attr type for hardware cache events
+const PERF_TYPE_HW_CACHE: u32 = 3;
+
+/// perf_event_attr config for data TLB read misses: the dTLB cache id (3) in the first byte, the
+/// read op (0) in the second and the miss result (1) in the third
+const DTLB_READ_MISS: u64 = 3 | (1 << 16);
+
+/// perf_event_attr flag bits: start disabled, exclude kernel and hypervisor
+const DISABLED: u64 = 1 << 0;
+const EXCLUDE_KERNEL: u64 = 1 << 5;
+const EXCLUDE_HV: u64 = 1 << 6;
+
+/// perf event ioctls
+const PERF_EVENT_IOC_ENABLE: c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: c_ulong = 0x2403;
+
+/// The first version of the perf_event_attr structure, which every kernel accepts
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// A hardware counter for the calling thread
+struct Counter {
+    fd: c_int,
+}
+
+impl Counter {
+    /// Opens the data TLB read miss counter for the calling thread, in user space only
+    fn dtlb_misses() -> io::Result<Self> {
+        let attr = PerfEventAttr {
+            kind: PERF_TYPE_HW_CACHE,
+            size: size_of::<PerfEventAttr>() as u32,
+            config: DTLB_READ_MISS,
+            flags: DISABLED | EXCLUDE_KERNEL | EXCLUDE_HV,
+            ..Default::default()
+        };
+
+        let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, &attr as *const PerfEventAttr, 0, -1, -1, 0) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd: fd as c_int })
+    }
+
+    /// Returns the count of events while f runs
+    fn measure<R>(&self, f: impl FnOnce() -> R) -> io::Result<(u64, R)> {
+        unsafe {
+            libc::ioctl(self.fd, PERF_EVENT_IOC_RESET as _, 0);
+            libc::ioctl(self.fd, PERF_EVENT_IOC_ENABLE as _, 0);
+        }
+
+        let result = f();
+
+        unsafe { libc::ioctl(self.fd, PERF_EVENT_IOC_DISABLE as _, 0) };
+
+        let mut count = 0u64;
+
+        if unsafe { libc::read(self.fd, &mut count as *mut u64 as *mut c_void, size_of::<u64>()) } != size_of::<u64>() as isize {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((count, result))
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Result of chasing the chain in one buffer
+struct Run {
+    huge: bool,
+    misses: Option<u64>,
+    elapsed: Duration,
+}
+
+/// Links one node on every page of the buffer in to a single cycle in random page order, so
+/// each hop lands on a different page
+fn build_chain(buffer: *mut u8, size: usize) {
+    let pages = size / PAGE;
+    let mut order: Vec<usize> = (0..pages).collect();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+    // Fisher-Yates shuffle
+    for i in (1..pages).rev() {
+        order.swap(i, rng.next() as usize % (i + 1));
+    }
+
+    for (i, page) in order.iter().enumerate() {
+        let next = order[(i + 1) % pages];
+
+        // Vary the line within the page so the hops don't all map to the same cache set
+        let node = unsafe { buffer.add(page * PAGE + page % (PAGE / NODE) * NODE) } as *mut usize;
+        let target = next * PAGE + next % (PAGE / NODE) * NODE;
+
+        unsafe { node.write(buffer as usize + target) };
+    }
+}
+
+/// Follows the chain from the start of the buffer for the given number of hops
+fn chase(buffer: *mut u8, hops: usize) -> usize {
+    let mut node = buffer as usize;
+
+    for _ in 0..hops {
+        node = unsafe { *(node as *const usize) };
+    }
+
+    node
+}
+
+/// Builds the chain in a buffer from the allocator and times chasing it, counting TLB misses if
+/// the counter is available
+fn run(alloc: &dyn GlobalAlloc, size: usize, hops: usize, counter: Option<&Counter>, advise: impl Fn(*mut u8)) -> Run {
+    let layout = Layout::from_size_align(size, PAGE).unwrap();
+    let buffer = unsafe { alloc.alloc(layout) };
+    assert!(!buffer.is_null(), "failed to allocate {} bytes", size);
+
+    advise(buffer);
+    build_chain(buffer, size);
+
+    // Warm up, faulting in any pages the chain build didn't touch
+    black_box(chase(buffer, hops / 10));
+
+    let start = Instant::now();
+
+    let misses = match counter {
+        Some(counter) => counter.measure(|| black_box(chase(buffer, hops))).ok().map(|(misses, _)| misses),
+        None => {
+            black_box(chase(buffer, hops));
+            None
+        }
+    };
+
+    let elapsed = start.elapsed();
+    let huge = HUGE.is_huge(buffer);
+
+    unsafe { alloc.dealloc(buffer, layout) };
+
+    Run { huge, misses, elapsed }
+}
+
+/// Prints a result row, with the page size the buffer was actually backed by
+fn report(name: &str, hops: usize, run: &Run) {
+    let misses = match run.misses {
+        Some(misses) => format!("{:>14} {:>12.1}", misses, misses as f64 * 1000.0 / hops as f64),
+        None => format!("{:>14} {:>12}", "-", "-"),
+    };
+
+    println!(
+        "{:<8} {:<10} {} {:>10.1}",
+        name,
+        if run.huge { "huge" } else { "default" },
+        misses,
+        run.elapsed.as_nanos() as f64 / hops as f64
+    );
+}
+
+fn main() {
+    // cargo bench passes --bench, which isn't a setting
+    let mut args = std::env::args().skip(1).filter(|arg| !arg.starts_with("--"));
+
+    let megabytes: usize = args.next().map_or(256, |arg| arg.parse().expect("megabytes not numeric"));
+    let hops: usize = args.next().map_or(10_000_000, |arg| arg.parse().expect("hops not numeric"));
+    let size = megabytes * 1024 * 1024;
+
+    let counter = match Counter::dtlb_misses() {
+        Ok(counter) => Some(counter),
+        Err(e) => {
+            eprintln!("dTLB miss counter unavailable ({}), reporting timings only", e);
+            eprintln!("(check /proc/sys/kernel/perf_event_paranoid and that the CPU exposes the event)");
+            None
+        }
+    };
+
+    println!("Chasing {} hops over {}mb", hops, megabytes);
+    println!("{:<8} {:<10} {:>14} {:>12} {:>10}", "buffer", "backing", "dTLB misses", "per 1k hops", "ns/hop");
+
+    let huge = run(&HUGE, size, hops, counter.as_ref(), |_| ());
+    report("huge", hops, &huge);
+
+    // Keep transparent huge pages out of the default page buffer so it is a fair baseline
+    let default = run(&System, size, hops, counter.as_ref(), |buffer| unsafe {
+        libc::madvise(buffer as *mut c_void, size, libc::MADV_NOHUGEPAGE);
+    });
+    report("default", hops, &default);
+
+    if let (Some(huge), Some(default)) = (huge.misses, default.misses) {
+        println!("huge pages took {:.1}% of the default page dTLB misses", huge as f64 * 100.0 / default.max(1) as f64);
+    }
+}
+
+/// xorshift64 random number generator
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
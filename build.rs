@@ -0,0 +1,47 @@
+//! Build script. With the c-header feature a C header for the C interface is generated in to
+//! OUT_DIR, and copied to the path in HUGE_GLOBAL_ALLOC_HEADER if set.
+
+fn main() {
+    #[cfg(feature = "c-header")]
+    header::generate();
+}
+
+#[cfg(feature = "c-header")]
+mod header {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Name of the generated header
+    const HEADER: &str = "huge_global_alloc.h";
+
+    /// Generates the header with cbindgen
+    pub fn generate() {
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join(HEADER);
+
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-changed=src/ffi");
+        println!("cargo:rerun-if-env-changed=HUGE_GLOBAL_ALLOC_HEADER");
+
+        let config = cbindgen::Config::from_file(PathBuf::from(&crate_dir).join("cbindgen.toml"))
+            .expect("failed to read cbindgen.toml");
+
+        cbindgen::Builder::new()
+            .with_crate(&crate_dir)
+            .with_config(config)
+            .generate()
+            .expect("failed to generate C header")
+            .write_to_file(&out_path);
+
+        if let Ok(path) = env::var("HUGE_GLOBAL_ALLOC_HEADER") {
+            let path = PathBuf::from(path);
+
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).expect("failed to create header directory");
+            }
+
+            fs::copy(&out_path, &path).expect("failed to copy C header");
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Key value cache in a hashbrown map, shared by the hash_cache example and the examples
+//! integration test. The map's table is a single allocation which is reallocated as the cache
+//! grows, ending up in a memory mapped segment once it passes the threshold, as do values larger
+//! than the threshold, while small values stay with the inner allocator.
+
+use hashbrown::HashMap;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+/// Cache run settings
+pub struct Config {
+    /// Allocator threshold in bytes
+    pub threshold: usize,
+    /// Number of entries to insert
+    pub entries: usize,
+    /// Every this many entries has a value twice the threshold, the rest are small
+    pub large_every: usize,
+}
+
+/// Results of a cache run
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of entries inserted
+    pub inserts: usize,
+    /// Number of lookups which found their entry
+    pub hits: usize,
+    /// Number of lookups of evicted entries
+    pub misses: usize,
+    /// Number of values held in memory mapped segments
+    pub managed_values: usize,
+    /// Bytes allocated for the map's table when full
+    pub table_bytes: usize,
+}
+
+/// Fills a cache, evicts every other entry and looks every key up again. Panics if a value is
+/// corrupted or an allocation isn't placed as expected.
+pub fn run(allocator: &HugeGlobalAllocator, config: &Config) -> Summary {
+    let mut cache: HashMap<u64, Vec<u8>> = HashMap::new();
+    let mut summary = Summary::default();
+
+    for key in 0..config.entries as u64 {
+        let size = if (key as usize).is_multiple_of(config.large_every) { config.threshold * 2 } else { 256 };
+
+        cache.insert(key, vec![fill(key); size]);
+        summary.inserts += 1;
+    }
+
+    summary.table_bytes = cache.allocation_size();
+
+    for value in cache.values() {
+        let managed = allocator.is_managed(value.as_ptr());
+
+        if !cfg!(feature = "passthrough") {
+            assert_eq!(value.len() >= allocator.threshold(), managed, "value of {} bytes placement", value.len());
+        }
+
+        summary.managed_values += usize::from(managed);
+    }
+
+    cache.retain(|key, _| key % 2 == 1);
+
+    for key in 0..config.entries as u64 {
+        match cache.get(&key) {
+            Some(value) => {
+                assert!(value.iter().all(|byte| *byte == fill(key)), "value {} corrupted", key);
+                summary.hits += 1;
+            }
+            None => summary.misses += 1,
+        }
+    }
+
+    summary
+}
+
+/// Returns the byte a key's value is filled with
+fn fill(key: u64) -> u8 {
+    key.wrapping_mul(31) as u8
+}
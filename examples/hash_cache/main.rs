@@ -0,0 +1,32 @@
+//! Key value cache in a hashbrown map using the allocator as the global allocator
+//!
+//! Usage: cargo run --release --example hash_cache -- [entries] [large every]
+
+mod harness;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+const THRESHOLD: usize = 1024 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(THRESHOLD);
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let entries = args.next().map_or(1_000_000, |arg| arg.parse().expect("entries not numeric"));
+    let large_every = args.next().map_or(10_000, |arg| arg.parse().expect("large every not numeric"));
+
+    println!("Caching {} entries, one in {} large", entries, large_every);
+
+    let config = harness::Config {
+        threshold: THRESHOLD,
+        entries,
+        large_every,
+    };
+
+    let summary = harness::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+    println!("{:?}", GLOBAL_ALLOCATOR.stats().unwrap());
+}
@@ -0,0 +1,76 @@
+//! Numeric workload over vectors of floats, shared by the huge_vec example and the examples
+//! integration test. The vectors are grown an element at a time, so they are reallocated through
+//! the threshold and then resized in place, or moved with mremap rather than copied, as they
+//! keep growing.
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+/// Workload settings
+pub struct Config {
+    /// Number of elements in each vector
+    pub elements: usize,
+}
+
+/// Results of a workload run
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of times the first vector's capacity grew
+    pub grows: usize,
+    /// Number of those growths which moved the vector
+    pub moves: usize,
+    /// True if the first vector ended up backed by huge pages
+    pub huge: bool,
+    /// Dot product of the vectors after the update
+    pub dot: f64,
+}
+
+/// Builds x and y vectors, computes y = a * x + y and the dot product of x and y, checking the
+/// result against its closed form. Panics if the result is wrong or a vector isn't placed as
+/// expected.
+pub fn run(allocator: &HugeGlobalAllocator, config: &Config) -> Summary {
+    const A: f64 = 0.5;
+
+    let mut summary = Summary::default();
+    let mut xs: Vec<f64> = Vec::new();
+
+    for i in 0..config.elements {
+        let (ptr, capacity) = (xs.as_ptr(), xs.capacity());
+
+        xs.push(i as f64);
+
+        if xs.capacity() != capacity {
+            summary.grows += 1;
+            summary.moves += usize::from(capacity > 0 && xs.as_ptr() != ptr);
+        }
+    }
+
+    let mut ys = vec![1.0; config.elements];
+
+    for (y, x) in ys.iter_mut().zip(&xs) {
+        *y += A * x;
+    }
+
+    summary.dot = xs.iter().zip(&ys).map(|(x, y)| x * y).sum();
+    summary.huge = allocator.is_huge(xs.as_ptr() as *const u8);
+
+    if !cfg!(feature = "passthrough") {
+        let bytes = config.elements * size_of::<f64>();
+
+        assert_eq!(bytes >= allocator.threshold(), allocator.is_managed(xs.as_ptr() as *const u8), "x placement");
+        assert_eq!(bytes >= allocator.threshold(), allocator.is_managed(ys.as_ptr() as *const u8), "y placement");
+    }
+
+    // Sum of i * (a * i + 1) over 0..n
+    let n = config.elements as f64;
+    let expected = A * (n - 1.0) * n * (2.0 * n - 1.0) / 6.0 + (n - 1.0) * n / 2.0;
+
+    assert!((summary.dot - expected).abs() <= expected * 1e-9, "dot product {} != {}", summary.dot, expected);
+
+    // Shrinking keeps the remaining elements
+    xs.truncate(config.elements / 2);
+    xs.shrink_to_fit();
+
+    assert!(xs.iter().enumerate().all(|(i, x)| *x == i as f64), "shrunk vector corrupted");
+
+    summary
+}
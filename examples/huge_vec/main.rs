@@ -0,0 +1,29 @@
+//! Numeric workload over vectors of floats in huge pages
+//!
+//! Usage: cargo run --release --example huge_vec -- [elements]
+
+mod harness;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+const THRESHOLD: usize = 1024 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(THRESHOLD);
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let elements = args.next().map_or(64 * 1024 * 1024, |arg| arg.parse().expect("elements not numeric"));
+
+    println!("Running over {} elements", elements);
+
+    let config = harness::Config {
+        elements,
+    };
+
+    let summary = harness::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+    println!("{:?}", GLOBAL_ALLOCATOR.stats().unwrap());
+}
@@ -0,0 +1,103 @@
+//! Statistics polling monitor thread, shared by the monitor example and the examples integration
+//! test. Worker threads allocate and free buffers around the threshold while the monitor polls
+//! the statistics the way a metrics exporter would: exactly with try_stats() when it can, falling
+//! back to the lock free stats_snapshot() rather than waiting for a busy allocator.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+/// Monitor run settings
+pub struct Config {
+    /// Allocator threshold in bytes. Buffer sizes range from half to twice this
+    pub threshold: usize,
+    /// Number of worker threads
+    pub threads: usize,
+    /// How long the workers run for
+    pub duration: Duration,
+    /// Time between polls
+    pub interval: Duration,
+}
+
+/// Results of a monitor run
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of buffers the workers allocated
+    pub allocs: usize,
+    /// Number of polls answered exactly by try_stats()
+    pub exact: usize,
+    /// Number of polls answered by stats_snapshot() because the allocator was busy
+    pub snapshots: usize,
+    /// Most bytes seen mapped by a poll
+    pub peak_mapped: usize,
+}
+
+/// Runs the workers and the monitor until the duration is up. Panics if an exact poll is
+/// inconsistent.
+pub fn run(allocator: &'static HugeGlobalAllocator, config: &Config) -> Summary {
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads)
+            .map(|i| {
+                let stop = &stop;
+                scope.spawn(move || worker(i + 1, config.threshold, stop))
+            })
+            .collect();
+
+        let monitor = scope.spawn(|| monitor(allocator, config.interval, &stop));
+
+        thread::sleep(config.duration);
+        stop.store(true, Ordering::Relaxed);
+
+        let mut summary = monitor.join().expect("monitor panicked");
+        summary.allocs = workers.into_iter().map(|worker| worker.join().expect("worker panicked")).sum();
+
+        summary
+    })
+}
+
+/// Allocates, fills and frees buffers until told to stop, returning the number allocated
+fn worker(seed: usize, threshold: usize, stop: &AtomicBool) -> usize {
+    let mut allocs = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let size = threshold / 2 + (seed * 7919 + allocs * 104_729) % (threshold * 3 / 2);
+        let buffer = vec![seed as u8; size];
+
+        assert_eq!(seed as u8, buffer[size - 1], "buffer corrupted");
+        allocs += 1;
+    }
+
+    allocs
+}
+
+/// Polls the statistics until told to stop
+fn monitor(allocator: &HugeGlobalAllocator, interval: Duration, stop: &AtomicBool) -> Summary {
+    let mut summary = Summary::default();
+
+    while !stop.load(Ordering::Relaxed) {
+        let stats = match allocator.try_stats() {
+            Ok(stats) => {
+                assert_eq!(stats.default_segments + stats.huge_segments, stats.segments, "segment sum");
+                assert_eq!(stats.default_mapped + stats.huge_mapped, stats.mapped, "mapped sum");
+                assert!(stats.mapped >= stats.alloc, "mapped >= alloc");
+
+                summary.exact += 1;
+                stats
+            }
+            Err(_) => {
+                summary.snapshots += 1;
+                allocator.stats_snapshot()
+            }
+        };
+
+        summary.peak_mapped = summary.peak_mapped.max(stats.mapped);
+
+        thread::sleep(interval);
+    }
+
+    summary
+}
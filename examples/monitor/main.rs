@@ -0,0 +1,35 @@
+//! Monitor thread polling the statistics while worker threads allocate
+//!
+//! Usage: cargo run --release --example monitor -- [threads] [seconds]
+
+mod harness;
+
+use std::time::Duration;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+const THRESHOLD: usize = 1024 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(THRESHOLD);
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let threads = args.next().map_or(4, |arg| arg.parse().expect("threads not numeric"));
+    let seconds = args.next().map_or(10, |arg| arg.parse().expect("seconds not numeric"));
+
+    println!("Monitoring {} threads for {} seconds", threads, seconds);
+
+    let config = harness::Config {
+        threshold: THRESHOLD,
+        threads,
+        duration: Duration::from_secs(seconds),
+        interval: Duration::from_millis(10),
+    };
+
+    let summary = harness::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+    println!("{:?}", GLOBAL_ALLOCATOR.stats().unwrap());
+}
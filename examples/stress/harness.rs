@@ -0,0 +1,191 @@
+//! Multithreaded stress harness shared by the stress example and the stress integration test.
+//! Worker threads allocate, grow, shrink and free blocks with sizes around the threshold while a
+//! monitor thread checks the allocator's statistics stay consistent.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+/// Stress run settings
+pub struct Config {
+    /// Allocator threshold in bytes. Block sizes range from a quarter to four times this
+    pub threshold: usize,
+    /// Number of worker threads
+    pub threads: usize,
+    /// How long the workers run for
+    pub duration: Duration,
+    /// Maximum number of live blocks per worker
+    pub live: usize,
+    /// Time allowed for the workers to finish after the run ends before reporting a deadlock
+    pub deadlock_timeout: Duration,
+}
+
+/// Counts of operations performed by a stress run
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of allocations
+    pub allocs: usize,
+    /// Number of reallocations
+    pub reallocs: usize,
+    /// Number of deallocations
+    pub deallocs: usize,
+    /// Number of statistics checks made by the monitor
+    pub checks: usize,
+}
+
+/// Runs the stress test against the allocator, which must be installed as the global allocator
+/// with the configured threshold. Panics if data is corrupted, the statistics are inconsistent or
+/// the workers fail to finish in time.
+pub fn run(allocator: &'static HugeGlobalAllocator, config: &Config) -> Summary {
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..config.threads)
+        .map(|i| {
+            let stop = stop.clone();
+            let tx = tx.clone();
+            let threshold = config.threshold;
+            let live = config.live;
+
+            thread::spawn(move || {
+                let summary = worker(i as u64 + 1, threshold, live, &stop);
+                let _ = tx.send(());
+                summary
+            })
+        })
+        .collect();
+
+    drop(tx);
+
+    let monitor = {
+        let stop = stop.clone();
+        thread::spawn(move || monitor(allocator, &stop))
+    };
+
+    thread::sleep(config.duration);
+    stop.store(true, Ordering::Relaxed);
+
+    // Wait for every worker to signal completion, treating a timeout as a deadlock
+    let deadline = Instant::now() + config.deadlock_timeout;
+
+    for _ in 0..config.threads {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if rx.recv_timeout(remaining).is_err() {
+            panic!("workers did not finish within {:?} - possible deadlock", config.deadlock_timeout);
+        }
+    }
+
+    let mut summary = Summary::default();
+
+    for worker in workers {
+        let counts = worker.join().expect("worker panicked");
+        summary.allocs += counts.allocs;
+        summary.reallocs += counts.reallocs;
+        summary.deallocs += counts.deallocs;
+    }
+
+    summary.checks = monitor.join().expect("monitor panicked");
+
+    // Everything has been freed so nothing should remain mapped
+    let stats = allocator.stats().unwrap();
+    assert_eq!(0, stats.segments, "segments after run");
+    assert_eq!(0, stats.alloc, "alloc after run");
+    assert_eq!(0, stats.mapped, "mapped after run");
+
+    summary
+}
+
+/// Allocates, resizes and frees blocks until told to stop
+fn worker(seed: u64, threshold: usize, live: usize, stop: &AtomicBool) -> Summary {
+    let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    let mut blocks: Vec<(Vec<u8>, u8)> = Vec::with_capacity(live);
+    let mut summary = Summary::default();
+
+    while !stop.load(Ordering::Relaxed) {
+        let size = rng.size(threshold);
+
+        match rng.next() % 3 {
+            0 if blocks.len() < live => {
+                // Allocate a new block
+                let fill = rng.next() as u8;
+                blocks.push((vec![fill; size], fill));
+                summary.allocs += 1;
+            }
+            1 if !blocks.is_empty() => {
+                // Grow or shrink a block
+                let idx = rng.next() as usize % blocks.len();
+                let (block, fill) = &mut blocks[idx];
+
+                check(block, *fill);
+                block.resize(size, *fill);
+                block.shrink_to_fit();
+                check(block, *fill);
+                summary.reallocs += 1;
+            }
+            _ if !blocks.is_empty() => {
+                // Free a block
+                let idx = rng.next() as usize % blocks.len();
+                let (block, fill) = blocks.swap_remove(idx);
+
+                check(&block, fill);
+                summary.deallocs += 1;
+            }
+            _ => (),
+        }
+    }
+
+    summary.deallocs += blocks.len();
+
+    summary
+}
+
+/// Checks the allocator statistics are consistent until told to stop
+fn monitor(allocator: &HugeGlobalAllocator, stop: &AtomicBool) -> usize {
+    let mut checks = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let stats = allocator.stats().unwrap();
+
+        assert_eq!(stats.default_segments + stats.huge_segments, stats.segments, "segment sum");
+        assert_eq!(stats.default_mapped + stats.huge_mapped, stats.mapped, "mapped sum");
+        assert_eq!(stats.default_alloc + stats.huge_alloc, stats.alloc, "alloc sum");
+        assert!(stats.mapped >= stats.alloc, "mapped >= alloc");
+
+        checks += 1;
+
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    checks
+}
+
+/// Checks the first, middle and last bytes of a block hold the fill byte
+fn check(block: &[u8], fill: u8) {
+    if let (Some(first), Some(last)) = (block.first(), block.last()) {
+        assert_eq!(fill, *first, "first byte corrupted");
+        assert_eq!(fill, block[block.len() / 2], "middle byte corrupted");
+        assert_eq!(fill, *last, "last byte corrupted");
+    }
+}
+
+/// xorshift64 random number generator
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a block size from a quarter to four times the threshold
+    fn size(&mut self, threshold: usize) -> usize {
+        threshold / 4 + self.next() as usize % (threshold * 4 - threshold / 4)
+    }
+}
@@ -0,0 +1,36 @@
+//! Long running multithreaded stress test
+//!
+//! Usage: cargo run --release --example stress -- [threads] [seconds]
+
+mod harness;
+
+use std::time::Duration;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+const THRESHOLD: usize = 1024 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(THRESHOLD);
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let threads = args.next().map_or(16, |arg| arg.parse().expect("threads not numeric"));
+    let seconds = args.next().map_or(60, |arg| arg.parse().expect("seconds not numeric"));
+
+    println!("Stressing with {} threads for {} seconds", threads, seconds);
+
+    let config = harness::Config {
+        threshold: THRESHOLD,
+        threads,
+        duration: Duration::from_secs(seconds),
+        live: 8,
+        deadlock_timeout: Duration::from_secs(30),
+    };
+
+    let summary = harness::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+    println!("{:?}", GLOBAL_ALLOCATOR.stats().unwrap());
+}
@@ -0,0 +1,96 @@
+//! Replays arbitrary sequences of alloc / realloc / dealloc operations, checking the allocator's
+//! bookkeeping and that data is preserved across reallocations.
+
+#![no_main]
+
+use std::alloc::{GlobalAlloc, Layout};
+
+use arbitrary::Arbitrary;
+use huge_global_alloc::HugeGlobalAllocator;
+use libfuzzer_sys::fuzz_target;
+
+const THRESHOLD: usize = 1024 * 1024;
+const MAX_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+    Alloc { size: u32, align_shift: u8 },
+    Realloc { idx: u8, size: u32 },
+    Dealloc { idx: u8 },
+}
+
+fn size(size: u32) -> usize {
+    (size as usize % MAX_SIZE) + 1
+}
+
+/// Checks the first and last byte of a block hold the fill byte
+fn check(ptr: *mut u8, len: usize, fill: u8) {
+    assert_eq!(fill, unsafe { *ptr });
+    assert_eq!(fill, unsafe { *ptr.add(len - 1) });
+}
+
+/// Writes the fill byte to the first and last byte of a block
+fn fill(ptr: *mut u8, len: usize, fill: u8) {
+    unsafe {
+        *ptr = fill;
+        *ptr.add(len - 1) = fill;
+    }
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let allocator = HugeGlobalAllocator::new(THRESHOLD);
+    let mut blocks: Vec<(*mut u8, Layout, u8)> = Vec::new();
+
+    for (i, op) in ops.into_iter().take(64).enumerate() {
+        match op {
+            Op::Alloc { size: sz, align_shift } => {
+                let layout = Layout::from_size_align(size(sz), 1 << (align_shift % 13)).unwrap();
+
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                assert_eq!(0, ptr as usize % layout.align());
+
+                fill(ptr, layout.size(), i as u8);
+                blocks.push((ptr, layout, i as u8));
+            }
+            Op::Realloc { idx, size: sz } if !blocks.is_empty() => {
+                let len = blocks.len();
+                let (ptr, layout, byte) = &mut blocks[idx as usize % len];
+                let new_size = size(sz);
+
+                let new_ptr = unsafe { allocator.realloc(*ptr, *layout, new_size) };
+                assert!(!new_ptr.is_null());
+
+                assert_eq!(*byte, unsafe { *new_ptr });
+
+                if new_size >= layout.size() {
+                    assert_eq!(*byte, unsafe { *new_ptr.add(layout.size() - 1) });
+                }
+
+                *ptr = new_ptr;
+                *layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                fill(*ptr, new_size, *byte);
+            }
+            Op::Dealloc { idx } if !blocks.is_empty() => {
+                let len = blocks.len();
+                let (ptr, layout, byte) = blocks.swap_remove(idx as usize % len);
+
+                check(ptr, layout.size(), byte);
+
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+            _ => (),
+        }
+
+        let stats = allocator.stats().unwrap();
+        let managed = blocks.iter().filter(|(_, layout, _)| layout.size() >= THRESHOLD);
+
+        assert_eq!(managed.clone().count(), stats.segments);
+        assert_eq!(managed.map(|(_, layout, _)| layout.size()).sum::<usize>(), stats.alloc);
+        assert_eq!(stats.default_segments + stats.huge_segments, stats.segments);
+    }
+
+    for (ptr, layout, _) in blocks {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+});
@@ -0,0 +1,92 @@
+//! Region source for arena allocators
+//!
+//! Arena and bump allocators carve many small objects out of a few large chunks. Sourcing those
+//! chunks through [RegionSource] maps them as managed segments, so they are backed by huge pages
+//! when available and counted in the allocator's statistics, whatever the threshold.
+//!
+//! ```rust
+//! use std::alloc::Layout;
+//! use huge_global_alloc::{HugeGlobalAllocator, RegionSource};
+//!
+//! /// A bump arena which takes its chunks from any region source
+//! struct Arena<S: RegionSource> {
+//!     source: S,
+//!     chunks: Vec<std::ptr::NonNull<[u8]>>,
+//! }
+//!
+//! impl<S: RegionSource> Arena<S> {
+//!     fn add_chunk(&mut self, size: usize) -> Option<*mut u8> {
+//!         let chunk = self.source.map_region(Layout::from_size_align(size, 4096).ok()?)?;
+//!         self.chunks.push(chunk);
+//!         Some(chunk.as_ptr() as *mut u8)
+//!     }
+//! }
+//!
+//! impl<S: RegionSource> Drop for Arena<S> {
+//!     fn drop(&mut self) {
+//!         for chunk in self.chunks.drain(..) {
+//!             unsafe { self.source.unmap_region(chunk) };
+//!         }
+//!     }
+//! }
+//!
+//! static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+//!
+//! let mut arena = Arena { source: &ALLOCATOR, chunks: Vec::new() };
+//!
+//! # #[cfg(not(feature = "passthrough"))]
+//! # {
+//! // Chunks below the threshold are mapped too
+//! arena.add_chunk(64 * 1024).unwrap();
+//! assert_eq!(ALLOCATOR.stats().unwrap().segments, 1);
+//! # }
+//! ````
+
+use std::alloc::Layout;
+use std::ptr::NonNull;
+
+use crate::HugeGlobalAllocator;
+
+/// A source of large memory regions for arena allocators
+///
+/// # Safety
+///
+/// A region returned by map_region must be valid for reads and writes of its whole length, aligned
+/// as requested, and not used by anything else until it is passed to unmap_region.
+pub unsafe trait RegionSource {
+    /// Maps a region for the layout. The returned slice's length is the mapped size, which may be
+    /// larger than requested, and all of it can be used. Returns None if the layout is zero sized
+    /// or the region could not be mapped.
+    fn map_region(&self, layout: Layout) -> Option<NonNull<[u8]>>;
+
+    /// Unmaps a region
+    ///
+    /// # Safety
+    ///
+    /// The region must have been returned by map_region on this source and not already unmapped.
+    unsafe fn unmap_region(&self, region: NonNull<[u8]>);
+}
+
+unsafe impl<S: RegionSource + ?Sized> RegionSource for &S {
+    #[track_caller]
+    fn map_region(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        (**self).map_region(layout)
+    }
+
+    unsafe fn unmap_region(&self, region: NonNull<[u8]>) {
+        (**self).unmap_region(region)
+    }
+}
+
+/// Regions are mapped with [alloc_raw](HugeGlobalAllocator::alloc_raw), so they are attributed
+/// to the caller in the callsite statistics. Passthrough builds map nothing.
+unsafe impl<A, P> RegionSource for HugeGlobalAllocator<A, P> {
+    #[track_caller]
+    fn map_region(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        self.alloc_raw(layout)
+    }
+
+    unsafe fn unmap_region(&self, region: NonNull<[u8]>) {
+        self.dealloc_raw(region)
+    }
+}
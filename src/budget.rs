@@ -0,0 +1,96 @@
+//! Shared huge page budgets
+//!
+//! A [Budget] caps the huge page memory mapped by every allocator drawing from it, so a process
+//! running an allocator per subsystem can hold its hugetlb consumption to one limit whichever
+//! subsystem allocates. Huge page segments are charged their mapped size when they are mapped or
+//! grown and refunded when they shrink, are demoted or are unmapped. A segment the budget can't
+//! cover is treated as though the huge page pool were exhausted, so it is mapped with default size
+//! pages instead. Default page size segments are never charged.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A limit on huge page memory shared by allocators. See
+/// [HugeGlobalAllocator::set_budget](crate::HugeGlobalAllocator::set_budget).
+///
+/// ```rust
+/// use huge_global_alloc::{Budget, HugeGlobalAllocator};
+///
+/// static HUGE_PAGES: Budget = Budget::new(512 * 1024 * 1024);
+///
+/// static INGEST: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+/// static INDEX: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+///
+/// INGEST.set_budget(&HUGE_PAGES).unwrap();
+/// INDEX.set_budget(&HUGE_PAGES).unwrap();
+///
+/// assert!(HUGE_PAGES.used() <= HUGE_PAGES.limit());
+/// ````
+#[derive(Debug)]
+pub struct Budget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    denied: AtomicUsize,
+}
+
+impl Budget {
+    /// Creates a budget of limit bytes of huge pages. A limit of zero is unlimited.
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+            denied: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the limit in bytes
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Sets the limit in bytes. Lowering it below the bytes in use unmaps nothing, but no more
+    /// huge pages are charged until enough are returned.
+    pub fn set_limit(&self, bytes: usize) {
+        self.limit.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the bytes of huge pages charged to the budget
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes of huge pages which can still be charged, or usize::MAX if unlimited
+    pub fn available(&self) -> usize {
+        match self.limit() {
+            0 => usize::MAX,
+            limit => limit.saturating_sub(self.used()),
+        }
+    }
+
+    /// Returns the number of huge page mappings and resizes refused by the budget
+    pub fn denied(&self) -> usize {
+        self.denied.load(Ordering::Relaxed)
+    }
+
+    /// Charges bytes to the budget if they fit within the limit, returning false if they don't
+    pub(crate) fn charge(&self, bytes: usize) -> bool {
+        let limit = self.limit();
+
+        let charged = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&new| limit == 0 || new <= limit)
+            })
+            .is_ok();
+
+        if !charged {
+            self.denied.fetch_add(1, Ordering::Relaxed);
+        }
+
+        charged
+    }
+
+    /// Returns bytes to the budget
+    pub(crate) fn refund(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,74 @@
+//! Buffers holding file contents in a memory mapped segment
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::slice;
+
+use crate::{DefaultPagePolicy, HugeGlobalAllocator, PagePolicy};
+
+/// A byte buffer in a memory mapped segment, returned by
+/// [HugeGlobalAllocator::read_to_huge](crate::HugeGlobalAllocator::read_to_huge). The segment is
+/// freed when the buffer is dropped.
+pub struct HugeBuffer<'a, A: GlobalAlloc, P: PagePolicy = DefaultPagePolicy> {
+    /// The allocator owning the segment
+    allocator: &'a HugeGlobalAllocator<A, P>,
+    /// Start of the buffer
+    ptr: NonNull<u8>,
+    /// Length of the buffer in bytes
+    len: usize,
+}
+
+// The buffer owns its segment
+unsafe impl<A: GlobalAlloc + Sync, P: PagePolicy + Sync> Send for HugeBuffer<'_, A, P> {}
+unsafe impl<A: GlobalAlloc + Sync, P: PagePolicy + Sync> Sync for HugeBuffer<'_, A, P> {}
+
+impl<'a, A: GlobalAlloc, P: PagePolicy> HugeBuffer<'a, A, P> {
+    /// Takes ownership of len bytes at ptr, allocated from the allocator with byte alignment, or
+    /// dangling if len is zero
+    pub(crate) unsafe fn from_raw(allocator: &'a HugeGlobalAllocator<A, P>, ptr: NonNull<u8>, len: usize) -> Self {
+        Self { allocator, ptr, len }
+    }
+
+    /// Returns true if the buffer is backed by huge pages
+    pub fn is_huge(&self) -> bool {
+        self.allocator.is_huge(self.ptr.as_ptr())
+    }
+}
+
+impl<A: GlobalAlloc, P: PagePolicy> Deref for HugeBuffer<'_, A, P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<A: GlobalAlloc, P: PagePolicy> DerefMut for HugeBuffer<'_, A, P> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<A: GlobalAlloc, P: PagePolicy> AsRef<[u8]> for HugeBuffer<'_, A, P> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<A: GlobalAlloc, P: PagePolicy> fmt::Debug for HugeBuffer<'_, A, P> {
+    /// Formats the address and length rather than the contents
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HugeBuffer").field("ptr", &self.ptr).field("len", &self.len).finish()
+    }
+}
+
+impl<A: GlobalAlloc, P: PagePolicy> Drop for HugeBuffer<'_, A, P> {
+    /// Frees the segment on drop
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { self.allocator.dealloc(self.ptr.as_ptr(), Layout::from_size_align_unchecked(self.len, 1)) };
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Per-node shared cache of freed segments
+//!
+//! Worker threads often free a segment and allocate another of the same size straight after, eg.
+//! a buffer per request. With the cache enabled, freed segments are kept mapped in a small stash
+//! shared by the threads on the freeing thread's NUMA node, and the next allocation of the same
+//! mapped size on that node reuses one. That saves the mmap and munmap calls, which take the
+//! process wide mmap lock in the kernel, and faulting the pages in again.
+//!
+//! Threads are spread over a fixed number of slots, each with its own lock, so the cache never
+//! allocates. A slot is only shared, and its lock contended, when more threads than slots use
+//! the cache. The slots are grouped by NUMA node, so a thread reuses segments cached by threads
+//! on its own node.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{counters::LockStats, mmap::MMap, numa::node_shard, sync::Mutex};
+
+/// Number of slots threads are spread over
+const SLOTS: usize = 16;
+
+/// Most segments a slot can hold
+pub(crate) const MAX_SEGMENTS: usize = 4;
+
+/// Segments cached for the threads using a slot, oldest first
+struct Slot {
+    segments: [Option<(MMap, Instant)>; MAX_SEGMENTS],
+    len: usize,
+    bytes: usize,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            segments: [const { None }; MAX_SEGMENTS],
+            len: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Removes and returns the segment at index, keeping the rest in order
+    fn remove(&mut self, index: usize) -> MMap {
+        self.segments[index..self.len].rotate_left(1);
+        self.len -= 1;
+
+        let (mmap, _) = self.segments[self.len].take().expect("cached segment");
+        self.bytes -= mmap.alloc_size();
+
+        mmap
+    }
+}
+
+/// Freed segments cached in slots shared by the threads on each node, bounded by a count and a
+/// number of bytes per slot
+pub(crate) struct NodeCaches {
+    slots: [Mutex<Slot>; SLOTS],
+    max_count: AtomicUsize,
+    max_bytes: AtomicUsize,
+}
+
+impl NodeCaches {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { Mutex::new(Slot::new()) }; SLOTS],
+            max_count: AtomicUsize::new(0),
+            max_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            slots: std::array::from_fn(|_| Mutex::new(Slot::new())),
+            max_count: AtomicUsize::new(0),
+            max_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the most segments and bytes cached per slot. The count is capped at
+    /// [MAX_SEGMENTS], and a count or bytes of zero disables the cache.
+    pub fn set_limits(&self, count: usize, bytes: usize) {
+        self.max_count.store(count.min(MAX_SEGMENTS), Ordering::Relaxed);
+        self.max_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the most segments and bytes cached per slot
+    pub fn limits(&self) -> (usize, usize) {
+        (self.max_count.load(Ordering::Relaxed), self.max_bytes.load(Ordering::Relaxed))
+    }
+
+    /// Returns true if segments are cached
+    pub fn enabled(&self) -> bool {
+        let (count, bytes) = self.limits();
+
+        count > 0 && bytes > 0
+    }
+
+    /// Takes the most recently cached segment in the current thread's slot with the mapped size and
+    /// page size whose address meets the alignment
+    pub fn take(&self, alloc_size: usize, page_size: usize, align: usize) -> Option<MMap> {
+        let mut slot = self.slot()?.lock();
+
+        let index = slot.segments[..slot.len].iter().rposition(|entry| {
+            entry.as_ref().is_some_and(|(mmap, _)| {
+                mmap.alloc_size() == alloc_size && mmap.page_size() == page_size && mmap.ptr().is_multiple_of(align)
+            })
+        })?;
+
+        Some(slot.remove(index))
+    }
+
+    /// Caches a freed segment in the current thread's slot, evicting the slot's oldest segments to
+    /// stay within the limits. Evicted segments, or the segment itself if it is larger than the
+    /// byte limit, are passed to release once the slot is unlocked.
+    pub fn put(&self, mmap: MMap, mut release: impl FnMut(MMap)) {
+        let (max_count, max_bytes) = self.limits();
+
+        let slot = match self.slot() {
+            Some(slot) if max_count > 0 && mmap.alloc_size() <= max_bytes => slot,
+            _ => return release(mmap),
+        };
+
+        let mut evicted: [Option<MMap>; MAX_SEGMENTS] = [const { None }; MAX_SEGMENTS];
+        let mut lock = slot.lock();
+
+        for evict in evicted.iter_mut() {
+            if lock.len == 0 || (lock.len < max_count && lock.bytes + mmap.alloc_size() <= max_bytes) {
+                break;
+            }
+
+            *evict = Some(lock.remove(0));
+        }
+
+        let len = lock.len;
+        lock.bytes += mmap.alloc_size();
+        lock.segments[len] = Some((mmap, Instant::now()));
+        lock.len += 1;
+
+        drop(lock);
+
+        evicted.into_iter().flatten().for_each(release);
+    }
+
+    /// Removes the segments in every slot cached for at least min_age, passing each to release
+    /// with its slot unlocked. Returns the number of bytes released.
+    pub fn flush(&self, min_age: Duration, mut release: impl FnMut(MMap)) -> usize {
+        let mut bytes = 0;
+
+        for slot in &self.slots {
+            let mut flushed: [Option<MMap>; MAX_SEGMENTS] = [const { None }; MAX_SEGMENTS];
+            let mut lock = slot.lock();
+            let mut count = 0;
+            let mut index = 0;
+
+            while index < lock.len {
+                match &lock.segments[index] {
+                    Some((_, cached)) if cached.elapsed() >= min_age => {
+                        let mmap = lock.remove(index);
+                        bytes += mmap.alloc_size();
+
+                        flushed[count] = Some(mmap);
+                        count += 1;
+                    }
+                    _ => index += 1,
+                }
+            }
+
+            drop(lock);
+
+            flushed.into_iter().flatten().for_each(&mut release);
+        }
+
+        bytes
+    }
+
+    /// Returns the contention counters of the slot locks
+    pub fn lock_stats(&self) -> impl Iterator<Item = &LockStats> {
+        self.slots.iter().map(Mutex::stats)
+    }
+
+    /// Returns the current thread's slot, or None if the thread is exiting
+    fn slot(&self) -> Option<&Mutex<Slot>> {
+        Some(&self.slots[node_shard(SLOTS)?])
+    }
+}
@@ -0,0 +1,109 @@
+//! Per callsite attribution of explicit allocations
+//!
+//! Allocations made through the explicit APIs such as
+//! [alloc_raw](crate::HugeGlobalAllocator::alloc_raw) are attributed to the source location of the
+//! caller with #[track_caller], giving a cheap breakdown of where mapped memory is requested
+//! without capturing backtraces. Callsites are kept in a fixed size lock free table so recording
+//! never allocates. Allocations from callsites beyond its capacity are not attributed.
+
+use std::cmp::Reverse;
+use std::panic::Location;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::counters::Counter;
+
+/// Maximum number of callsites tracked
+pub const CALLSITE_CAPACITY: usize = 64;
+
+/// Allocations attributed to a callsite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallsiteStats {
+    /// Source location of the call
+    pub location: &'static Location<'static>,
+    /// Number of allocations made from the callsite
+    pub allocs: usize,
+    /// Total bytes allocated from the callsite
+    pub bytes: usize,
+}
+
+/// A callsite table entry
+struct Entry {
+    location: AtomicPtr<Location<'static>>,
+    allocs: Counter,
+    bytes: Counter,
+}
+
+/// Open addressed table of callsites keyed by their location
+pub(crate) struct Callsites {
+    entries: [Entry; CALLSITE_CAPACITY],
+}
+
+impl Callsites {
+    pub const fn new() -> Self {
+        Self {
+            entries: [const {
+                Entry {
+                    location: AtomicPtr::new(null_mut()),
+                    allocs: Counter::new(),
+                    bytes: Counter::new(),
+                }
+            }; CALLSITE_CAPACITY],
+        }
+    }
+
+    /// Attributes an allocation of size bytes to a callsite
+    pub fn record(&self, location: &'static Location<'static>, size: usize) {
+        if cfg!(feature = "no-stats") {
+            return;
+        }
+
+        let key = location as *const Location<'static> as *mut Location<'static>;
+        let start = (key as usize >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) % CALLSITE_CAPACITY;
+
+        for i in 0..CALLSITE_CAPACITY {
+            let entry = &self.entries[(start + i) % CALLSITE_CAPACITY];
+
+            let found = match entry.location.compare_exchange(null_mut(), key, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => true,
+                Err(current) => current == key,
+            };
+
+            if found {
+                entry.allocs.add(1);
+                entry.bytes.add(size as u64);
+                return;
+            }
+        }
+    }
+
+    /// Returns the callsites which have allocated, largest total first
+    pub fn stats(&self) -> Vec<CallsiteStats> {
+        let mut stats: Vec<CallsiteStats> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let location = entry.location.load(Ordering::Acquire);
+
+                (!location.is_null()).then(|| CallsiteStats {
+                    location: unsafe { &*location },
+                    allocs: entry.allocs.get() as usize,
+                    bytes: entry.bytes.get() as usize,
+                })
+            })
+            .filter(|stats| stats.allocs > 0)
+            .collect();
+
+        stats.sort_by_key(|stats| Reverse(stats.bytes));
+
+        stats
+    }
+
+    /// Zeroes the counts, keeping the callsites
+    pub fn reset(&self) {
+        for entry in &self.entries {
+            entry.allocs.reset();
+            entry.bytes.reset();
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Canary bytes after the payload
+//!
+//! With the canary feature, the start of the slack between the end of an allocation and the end of
+//! its mapping is filled with up to [CANARY_BYTES] bytes of a pattern derived from the segment's
+//! address and size. The pattern is checked when the segment is reallocated or freed, and a
+//! mismatch, left by a write past the end of the buffer, is reported with the segment's details
+//! before aborting. The canary stays within the payload's last base size page, so allocations which
+//! end on a page boundary have no canary. Payloads start at the beginning of their mapping, so there
+//! is no room for a canary before them. Segments with memory tagging or a protection key have no
+//! canary.
+//!
+//! Only allocations made through [GlobalAlloc](std::alloc::GlobalAlloc) have canaries. Raw blocks
+//! own their whole mapping, so the slack is theirs to use.
+
+use std::io::Write;
+
+use crate::{mmap::MMap, pagesize::base_page_size, raw::round_to_pages};
+
+/// Most canary bytes written after a payload
+pub(crate) const CANARY_BYTES: usize = 64;
+
+/// Returns the number of canary bytes after the payload of a segment. Segments with memory tagging
+/// have no canary, as the hardware checks the bytes after the payload, and neither do segments with
+/// a protection key, as the thread freeing them may have no access.
+pub(crate) fn len(mmap: &MMap) -> usize {
+    if mmap.mte_tag() != 0 || mmap.pkey() != 0 {
+        return 0;
+    }
+
+    (round_to_pages(mmap.size(), base_page_size()) - mmap.size()).min(CANARY_BYTES)
+}
+
+/// Writes the canary after the payload of a segment, replacing any canary it had
+pub(crate) fn write(mmap: &mut MMap) {
+    mmap.set_canary(true);
+
+    let canary = (mmap.ptr() + mmap.size()) as *mut u8;
+
+    for offset in 0..len(mmap) {
+        unsafe { canary.add(offset).write(byte(mmap, offset)) };
+    }
+}
+
+/// Returns the offset past the end of the payload of the first overwritten canary byte
+pub(crate) fn check(mmap: &MMap) -> Option<usize> {
+    let canary = (mmap.ptr() + mmap.size()) as *const u8;
+
+    (0..len(mmap)).find(|&offset| unsafe { canary.add(offset).read() } != byte(mmap, offset))
+}
+
+/// Checks the canary of a segment if it has one, reporting the segment and aborting if it has been
+/// overwritten
+pub(crate) fn verify(mmap: &MMap) {
+    if !mmap.has_canary() {
+        return;
+    }
+
+    if let Some(offset) = check(mmap) {
+        // Formatting to stderr doesn't allocate
+        let _ = writeln!(
+            std::io::stderr(),
+            "huge_global_alloc: canary overwritten {} bytes past the end of segment {:#x} (size {}, mapped {}, page size {}, age {:.3?}, tag {})",
+            offset,
+            mmap.ptr(),
+            mmap.size(),
+            mmap.alloc_size(),
+            mmap.page_size(),
+            mmap.created().elapsed(),
+            mmap.tag().unwrap_or("-")
+        );
+
+        std::process::abort();
+    }
+}
+
+/// Returns the canary byte at an offset past the end of the payload
+fn byte(mmap: &MMap, offset: usize) -> u8 {
+    let seed = 0xca7a_5c0d_e5af_e000 ^ mmap.ptr() as u64 ^ (mmap.size() as u64).rotate_left(32);
+
+    (seed >> (offset % 8 * 8)) as u8
+}
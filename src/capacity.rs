@@ -0,0 +1,73 @@
+//! Collections created with memory mapped buffers
+//!
+//! [HugeCapacity] adds with_huge_capacity() to the standard collections. The current thread's
+//! next allocation, the collection's initial buffer, is memory mapped whatever its size. Growing
+//! the buffer later follows the allocator's usual rules, so a buffer which grows but stays below
+//! the threshold moves to the inner allocator.
+//!
+//! Buffers are allocated by the global allocator, so this takes effect when
+//! [HugeGlobalAllocator](crate::HugeGlobalAllocator) is the global allocator, or when the malloc
+//! interface is preloaded in to a program which keeps the system allocator. Otherwise the
+//! collections are ordinary ones. [HugeGlobalAllocatorConst](crate::HugeGlobalAllocatorConst)
+//! keeps to its fixed threshold, so doesn't map small buffers.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+
+use crate::local;
+
+/// Creates a collection with its initial buffer memory mapped regardless of the threshold
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use huge_global_alloc::{HugeCapacity, HugeGlobalAllocator};
+///
+/// #[global_allocator]
+/// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024 * 1024);
+///
+/// let vec: Vec<u64> = Vec::with_huge_capacity(1024);
+/// # #[cfg(not(feature = "passthrough"))]
+/// assert!(GLOBAL_ALLOCATOR.is_managed(vec.as_ptr() as *const u8));
+///
+/// let map: HashMap<u64, u64> = HashMap::with_huge_capacity(1024);
+/// assert!(map.capacity() >= 1024);
+/// ````
+pub trait HugeCapacity: Sized {
+    /// Creates an empty collection with room for at least capacity elements, in a memory mapped
+    /// buffer
+    fn with_huge_capacity(capacity: usize) -> Self;
+}
+
+/// Runs f with the thread's next allocation memory mapped. The flag is cleared afterwards in case
+/// f didn't allocate.
+fn mapped<R>(f: impl FnOnce() -> R) -> R {
+    local::set_map_next(true);
+
+    let result = f();
+
+    local::set_map_next(false);
+
+    result
+}
+
+impl<T> HugeCapacity for Vec<T> {
+    fn with_huge_capacity(capacity: usize) -> Self {
+        mapped(|| Vec::with_capacity(capacity))
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher + Default> HugeCapacity for HashMap<K, V, S> {
+    fn with_huge_capacity(capacity: usize) -> Self {
+        let hasher = S::default();
+
+        mapped(|| HashMap::with_capacity_and_hasher(capacity, hasher))
+    }
+}
+
+impl<T: Eq + Hash, S: BuildHasher + Default> HugeCapacity for HashSet<T, S> {
+    fn with_huge_capacity(capacity: usize) -> Self {
+        let hasher = S::default();
+
+        mapped(|| HashSet::with_capacity_and_hasher(capacity, hasher))
+    }
+}
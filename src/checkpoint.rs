@@ -0,0 +1,46 @@
+//! Enumeration of segments for checkpoint and live migration tools
+//!
+//! Tools such as CRIU copy every mapping of a process, which for multi-gigabyte huge page
+//! segments can dominate the checkpoint. Segments holding data which can be rebuilt, such as
+//! caches, can be marked to be skipped, and the enumeration gives the tool or the application's
+//! own checkpoint code the address ranges to treat specially.
+
+use std::fmt::{self, Display};
+
+/// A memory mapped segment as seen by a checkpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointSegment {
+    /// Start of the mapping
+    pub start: usize,
+    /// End of the mapping (exclusive)
+    pub end: usize,
+    /// Bytes in use from the start of the mapping. Anything after them needn't be saved.
+    pub size: usize,
+    /// Page size backing the segment, which the restored mapping must use
+    pub page_size: usize,
+    /// True if backed by huge pages
+    pub huge: bool,
+    /// True if the pages are shared with forked children
+    pub shared: bool,
+    /// True if the segment has been marked to be skipped by checkpoints
+    pub skip: bool,
+    /// Tag attached by the application
+    pub tag: Option<&'static str>,
+}
+
+impl Display for CheckpointSegment {
+    /// Formats the segment on one line, with the address range as in /proc/self/maps
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:x}-{:x} {} {} {} {} {}",
+            self.start,
+            self.end,
+            self.size,
+            self.page_size,
+            if self.shared { "shared" } else { "private" },
+            if self.skip { "skip" } else { "save" },
+            self.tag.unwrap_or("-")
+        )
+    }
+}
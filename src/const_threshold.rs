@@ -0,0 +1,220 @@
+//! Allocator with a threshold fixed at compile time
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error::Error;
+use std::ptr::NonNull;
+
+use crate::{
+    layers::Layers, mmapper::MMapper, report, sync::const_fn, Budget, DefaultPagePolicy, HugeGlobalAllocator, HugeGlobalAllocatorStats,
+    PagePolicy, RegionSource, SegmentInfo, DEFAULT_THRESHOLD, PASSTHROUGH,
+};
+
+/// A global allocator with the threshold fixed at compile time. The threshold comparisons fold to
+/// constants, and the allocator holds no state beyond the mapper. The threshold defaults to
+/// [DEFAULT_THRESHOLD] and allocations below it, unless aligned to at least
+/// [HUGE_ALIGNMENT](crate::HUGE_ALIGNMENT), are passed to the system allocator. Use
+/// [HugeGlobalAllocator](crate::HugeGlobalAllocator) to change the threshold or other settings
+/// at runtime.
+///
+/// ```rust
+/// use huge_global_alloc::HugeGlobalAllocatorConst;
+///
+/// #[global_allocator]
+/// static GLOBAL_ALLOCATOR: HugeGlobalAllocatorConst<{ 2 * 1024 * 1024 }> = HugeGlobalAllocatorConst::new();
+///
+/// let vec: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024); // 2mb
+/// assert_eq!(GLOBAL_ALLOCATOR.stats().unwrap().segments, 1);
+/// ````
+pub struct HugeGlobalAllocatorConst<const THRESHOLD: usize = DEFAULT_THRESHOLD> {
+    mapper: MMapper,
+}
+
+impl<const THRESHOLD: usize> HugeGlobalAllocatorConst<THRESHOLD> {
+    const_fn! {
+        /// Creates a new allocator
+        pub fn new() -> Self {
+            Self {
+                mapper: MMapper::new(),
+            }
+        }
+    }
+
+    /// Returns the minimum number of bytes to consider a huge page allocation
+    pub const fn threshold(&self) -> usize {
+        THRESHOLD
+    }
+
+    /// Returns allocation statistics from the allocator
+    pub fn stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
+        self.mapper.stats()
+    }
+
+    /// Returns allocation statistics, or an error without waiting if they can't be gathered
+    /// without blocking
+    pub fn try_stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
+        self.mapper.try_stats()
+    }
+
+    /// Returns allocation statistics without taking any locks
+    pub fn stats_snapshot(&self) -> HugeGlobalAllocatorStats {
+        self.mapper.stats_snapshot()
+    }
+
+    /// Draws huge pages from a budget shared with other allocators. See
+    /// [HugeGlobalAllocator::set_budget](crate::HugeGlobalAllocator::set_budget)
+    pub fn set_budget(&self, budget: &'static Budget) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_budget(budget)
+    }
+
+    /// Returns the budget huge pages are drawn from, if set
+    pub fn budget(&self) -> Option<&'static Budget> {
+        self.mapper.budget()
+    }
+
+    /// Registers the allocator under a name. See
+    /// [HugeGlobalAllocator::register](crate::HugeGlobalAllocator::register)
+    pub fn register(&'static self, name: &'static str) -> Result<(), Box<dyn Error>> {
+        crate::registry::register(name, &self.mapper)
+    }
+
+    /// Returns the name the allocator is registered under
+    pub fn name(&self) -> Option<&'static str> {
+        crate::registry::name_of(&self.mapper)
+    }
+
+    /// Registers the statistics as OpenTelemetry metrics. See
+    /// [HugeGlobalAllocator::register_metrics](crate::HugeGlobalAllocator::register_metrics)
+    #[cfg(feature = "otel")]
+    pub fn register_metrics(&'static self, meter: &opentelemetry::metrics::Meter) {
+        crate::otel::register(meter, move || self.stats_snapshot());
+    }
+
+    /// Serves the statistics and segment listing over HTTP on localhost. See
+    /// [HugeGlobalAllocator::serve_debug_http](crate::HugeGlobalAllocator::serve_debug_http)
+    #[cfg(feature = "debug-http")]
+    pub fn serve_debug_http(&'static self, port: u16) -> Result<std::net::SocketAddr, Box<dyn Error>> {
+        crate::http::spawn(port, move || self.stats_snapshot(), move || self.dump_segments())
+    }
+
+    /// Collapses default page size segments in to transparent huge pages in place. See
+    /// [HugeGlobalAllocator::collapse](crate::HugeGlobalAllocator::collapse)
+    pub fn collapse(&self) -> usize {
+        self.mapper.collapse()
+    }
+
+    /// Returns the bytes of default page size fallbacks backed with transparent huge pages. See
+    /// [HugeGlobalAllocator::thp_promoted](crate::HugeGlobalAllocator::thp_promoted)
+    pub fn thp_promoted(&self) -> Result<usize, Box<dyn Error>> {
+        self.mapper.thp_promoted()
+    }
+
+    /// Starts the background maintenance worker. See
+    /// [HugeGlobalAllocator::start_maintenance](crate::HugeGlobalAllocator::start_maintenance)
+    pub fn start_maintenance(&'static self, interval: std::time::Duration) -> Result<(), Box<dyn Error>> {
+        self.mapper.maintenance().start(interval, move || self.mapper.maintain())
+    }
+
+    /// Stops the background maintenance worker after any pass in progress
+    pub fn stop_maintenance(&self) {
+        self.mapper.maintenance().stop();
+    }
+
+    /// Returns true if ptr is the start of an allocation in a memory mapped segment
+    pub fn is_managed(&self, ptr: *const u8) -> bool {
+        self.mapper.is_managed_ptr(ptr as *mut u8)
+    }
+
+    /// Returns true if ptr is the start of an allocation in a memory mapped segment backed by huge
+    /// pages
+    pub fn is_huge(&self, ptr: *const u8) -> bool {
+        self.mapper.is_huge_ptr(ptr as *mut u8)
+    }
+
+    /// Returns the metadata of the memory mapped allocation containing ptr. See
+    /// [HugeGlobalAllocator::owner_of](crate::HugeGlobalAllocator::owner_of)
+    pub fn owner_of(&self, ptr: *const u8) -> Option<SegmentInfo> {
+        self.mapper.owner_of(ptr)
+    }
+
+    /// Attaches a tag to the memory mapped allocation starting at ptr. See
+    /// [HugeGlobalAllocator::tag](crate::HugeGlobalAllocator::tag)
+    pub fn tag(&self, ptr: *const u8, tag: &'static str) -> bool {
+        self.mapper.set_tag(ptr as *mut u8, tag)
+    }
+
+    /// Records the size a memory mapped allocation is expected to grow to. See
+    /// [HugeGlobalAllocator::reserve_hint](crate::HugeGlobalAllocator::reserve_hint)
+    pub fn reserve_hint(&self, ptr: *const u8, expected_size: usize) -> bool {
+        self.mapper.reserve_hint(ptr as *mut u8, expected_size)
+    }
+
+    /// Releases the pages mapped after the end of a memory mapped allocation. See
+    /// [HugeGlobalAllocator::trim_segment](crate::HugeGlobalAllocator::trim_segment)
+    pub fn trim_segment(&self, ptr: *const u8) -> Option<usize> {
+        self.mapper.trim_segment(ptr as *mut u8)
+    }
+
+    /// Returns a multi-line listing of the live memory mapped segments. See
+    /// [HugeGlobalAllocator::dump_segments](crate::HugeGlobalAllocator::dump_segments)
+    pub fn dump_segments(&self) -> String {
+        report::format(&self.mapper.segments())
+    }
+
+    /// Returns true if an allocation with the layout should be mapped
+    fn use_mapper(layout: Layout) -> bool {
+        !PASSTHROUGH && DefaultPagePolicy.maps(layout, THRESHOLD)
+    }
+
+    /// Returns the allocation paths for this allocator
+    fn layers(&self) -> Layers<'_, System, DefaultPagePolicy, fn(Layout) -> bool> {
+        Layers {
+            mapper: &self.mapper,
+            inner: &System,
+            policy: &DefaultPagePolicy,
+            use_mapper: Self::use_mapper,
+        }
+    }
+}
+
+impl<const THRESHOLD: usize> Default for HugeGlobalAllocatorConst<THRESHOLD> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const THRESHOLD: usize> GlobalAlloc for HugeGlobalAllocatorConst<THRESHOLD> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.layers().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.layers().dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.layers().alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, old_ptr: *mut u8, old_layout: Layout, new_size: usize) -> *mut u8 {
+        self.layers().realloc(old_ptr, old_layout, new_size)
+    }
+}
+
+/// Regions are mapped regardless of the threshold. Passthrough builds map nothing.
+unsafe impl<const THRESHOLD: usize> RegionSource for HugeGlobalAllocatorConst<THRESHOLD> {
+    fn map_region(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        if PASSTHROUGH || layout.size() == 0 {
+            return None;
+        }
+
+        self.mapper.alloc_raw(layout)
+    }
+
+    unsafe fn unmap_region(&self, region: NonNull<[u8]>) {
+        if !self.mapper.dealloc(region.as_ptr() as *mut u8) {
+            let layout = Layout::from_size_align_unchecked(region.len(), 1);
+
+            HugeGlobalAllocator::alloc_error_layout("HugeGlobalAllocatorConst::unmap_region: region not found", layout);
+        }
+    }
+}
@@ -0,0 +1,553 @@
+//! Statistics counters
+//!
+//! The mapper's event counters and segment totals are atomics, so neither the allocation paths nor
+//! stats readers ever wait on each other. The event counters are sharded by thread, grouped by
+//! NUMA node, and added up when read, so threads don't contend for their cache lines. With the no-stats feature the
+//! counters are zero sized, updating them compiles to nothing and reading them returns zero,
+//! removing all statistics overhead from the allocation paths.
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::{mmap::MMap, mte, numa::node_shard, HugeGlobalAllocatorStats};
+
+#[cfg(not(feature = "no-stats"))]
+mod imp {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    /// A statistics counter
+    pub struct Counter(AtomicU64);
+
+    impl Counter {
+        pub const fn new() -> Self {
+            Self(AtomicU64::new(0))
+        }
+
+        /// Adds to the counter
+        #[inline]
+        pub fn add(&self, n: u64) {
+            self.0.fetch_add(n, Ordering::Relaxed);
+        }
+
+        /// Subtracts from the counter
+        #[inline]
+        pub fn sub(&self, n: u64) {
+            self.0.fetch_sub(n, Ordering::Relaxed);
+        }
+
+        /// Raises the counter to n if it is lower
+        #[inline]
+        pub fn max(&self, n: u64) {
+            self.0.fetch_max(n, Ordering::Relaxed);
+        }
+
+        /// Returns the counter value
+        pub fn get(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+
+        /// Zeroes the counter
+        pub fn reset(&self) {
+            self.0.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Times an operation for the statistics
+    pub struct Timer(Instant);
+
+    impl Timer {
+        #[inline]
+        pub fn start() -> Self {
+            Self(Instant::now())
+        }
+
+        /// Returns the nanoseconds elapsed since the timer was started
+        #[inline]
+        pub fn elapsed_ns(&self) -> u64 {
+            self.0.elapsed().as_nanos() as u64
+        }
+    }
+}
+
+#[cfg(feature = "no-stats")]
+mod imp {
+    pub struct Counter;
+
+    impl Counter {
+        pub const fn new() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub fn add(&self, _n: u64) {}
+
+        #[inline(always)]
+        pub fn sub(&self, _n: u64) {}
+
+        #[inline(always)]
+        pub fn max(&self, _n: u64) {}
+
+        pub fn get(&self) -> u64 {
+            0
+        }
+
+        pub fn reset(&self) {}
+    }
+
+    pub struct Timer;
+
+    impl Timer {
+        #[inline(always)]
+        pub fn start() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub fn elapsed_ns(&self) -> u64 {
+            0
+        }
+    }
+}
+
+pub(crate) use imp::{Counter, Timer};
+
+/// Number of shards the event counters are spread over
+const STAT_SHARDS: usize = 16;
+
+/// Returns a counter which may have been taken below zero in its shards, stopping at zero
+fn non_negative(n: u64) -> u64 {
+    if n > i64::MAX as u64 { 0 } else { n }
+}
+
+/// Event counters for a mapper, sharded by thread and NUMA node so threads counting events don't
+/// contend for the counters' cache lines. Dereferences to the current thread's shard, and the shards are added
+/// up when the statistics are read.
+pub(crate) struct ShardedStats {
+    shards: [StatShard; STAT_SHARDS],
+}
+
+impl ShardedStats {
+    pub const fn new() -> Self {
+        Self {
+            shards: [const { StatShard(MMapperStats::new()) }; STAT_SHARDS],
+        }
+    }
+
+    /// Adds up the shards in to the statistics
+    pub fn read(&self, out_stats: &mut HugeGlobalAllocatorStats) {
+        let total = MMapperStats::new();
+
+        for shard in &self.shards {
+            total.merge(&shard.0);
+        }
+
+        total.read(out_stats);
+    }
+
+    /// Zeroes the counters
+    pub fn reset(&self) {
+        for shard in &self.shards {
+            shard.0.reset();
+        }
+    }
+}
+
+impl Deref for ShardedStats {
+    type Target = MMapperStats;
+
+    /// Returns the current thread's shard
+    #[inline]
+    fn deref(&self) -> &MMapperStats {
+        if cfg!(feature = "no-stats") {
+            return &self.shards[0].0;
+        }
+
+        &self.shards[node_shard(STAT_SHARDS).unwrap_or(0)].0
+    }
+}
+
+/// A shard of the event counters, on cache lines of its own
+#[repr(align(128))]
+struct StatShard(MMapperStats);
+
+/// Event counters for a shard of a mapper. Counters which can be taken back, such as the missed
+/// allocations, may go below zero in one shard and are only meaningful added up.
+pub(crate) struct MMapperStats {
+    pub missed_allocs: Counter,
+    pub missed_bytes: Counter,
+    pub remaps_failed: Counter,
+    pub remaps_copied: Counter,
+    pub remaps_refused: Counter,
+    pub remap_copied_bytes: Counter,
+    pub huge_retries: Counter,
+    pub populate_failed: Counter,
+    pub prefault_time_ns: Counter,
+    pub prefault_max_time_ns: Counter,
+    pub soft_limit_exceeded: Counter,
+    pub unmaps_failed: Counter,
+    pub leaked_bytes: Counter,
+    pub promotions: Counter,
+    pub maintenance_passes: Counter,
+    pub collapses: Counter,
+    pub collapses_failed: Counter,
+    pub shrinks_deferred: Counter,
+    pub trimmed_bytes: Counter,
+    pub exact_size_allocs: Counter,
+    pub move_memcpy_bytes: Counter,
+    pub move_mremap_bytes: Counter,
+    pub move_dontunmap_bytes: Counter,
+    pub demotions: Counter,
+    pub demotions_vetoed: Counter,
+    pub randomized_placements: Counter,
+    pub placement_fallbacks: Counter,
+    pub mte_segments: Counter,
+    pub secure_segments: Counter,
+    pub secure_failures: Counter,
+    pub segment_cache_hits: Counter,
+    pub segment_cache_misses: Counter,
+    pub recent_segment_hits: Counter,
+    pub recent_segment_misses: Counter,
+}
+
+impl MMapperStats {
+    pub const fn new() -> Self {
+        Self {
+            missed_allocs: Counter::new(),
+            missed_bytes: Counter::new(),
+            remaps_failed: Counter::new(),
+            remaps_copied: Counter::new(),
+            remaps_refused: Counter::new(),
+            remap_copied_bytes: Counter::new(),
+            huge_retries: Counter::new(),
+            populate_failed: Counter::new(),
+            prefault_time_ns: Counter::new(),
+            prefault_max_time_ns: Counter::new(),
+            soft_limit_exceeded: Counter::new(),
+            unmaps_failed: Counter::new(),
+            leaked_bytes: Counter::new(),
+            promotions: Counter::new(),
+            maintenance_passes: Counter::new(),
+            collapses: Counter::new(),
+            collapses_failed: Counter::new(),
+            shrinks_deferred: Counter::new(),
+            trimmed_bytes: Counter::new(),
+            exact_size_allocs: Counter::new(),
+            move_memcpy_bytes: Counter::new(),
+            move_mremap_bytes: Counter::new(),
+            move_dontunmap_bytes: Counter::new(),
+            demotions: Counter::new(),
+            demotions_vetoed: Counter::new(),
+            randomized_placements: Counter::new(),
+            placement_fallbacks: Counter::new(),
+            mte_segments: Counter::new(),
+            secure_segments: Counter::new(),
+            secure_failures: Counter::new(),
+            segment_cache_hits: Counter::new(),
+            segment_cache_misses: Counter::new(),
+            recent_segment_hits: Counter::new(),
+            recent_segment_misses: Counter::new(),
+        }
+    }
+
+    /// Adds the counters of another shard to these
+    fn merge(&self, other: &Self) {
+        self.missed_allocs.add(other.missed_allocs.get());
+        self.missed_bytes.add(other.missed_bytes.get());
+        self.remaps_failed.add(other.remaps_failed.get());
+        self.remaps_copied.add(other.remaps_copied.get());
+        self.remaps_refused.add(other.remaps_refused.get());
+        self.remap_copied_bytes.add(other.remap_copied_bytes.get());
+        self.huge_retries.add(other.huge_retries.get());
+        self.populate_failed.add(other.populate_failed.get());
+        self.prefault_time_ns.add(other.prefault_time_ns.get());
+        self.prefault_max_time_ns.max(other.prefault_max_time_ns.get());
+        self.soft_limit_exceeded.add(other.soft_limit_exceeded.get());
+        self.unmaps_failed.add(other.unmaps_failed.get());
+        self.leaked_bytes.add(other.leaked_bytes.get());
+        self.promotions.add(other.promotions.get());
+        self.maintenance_passes.add(other.maintenance_passes.get());
+        self.collapses.add(other.collapses.get());
+        self.collapses_failed.add(other.collapses_failed.get());
+        self.shrinks_deferred.add(other.shrinks_deferred.get());
+        self.trimmed_bytes.add(other.trimmed_bytes.get());
+        self.exact_size_allocs.add(other.exact_size_allocs.get());
+        self.move_memcpy_bytes.add(other.move_memcpy_bytes.get());
+        self.move_mremap_bytes.add(other.move_mremap_bytes.get());
+        self.move_dontunmap_bytes.add(other.move_dontunmap_bytes.get());
+        self.demotions.add(other.demotions.get());
+        self.demotions_vetoed.add(other.demotions_vetoed.get());
+        self.randomized_placements.add(other.randomized_placements.get());
+        self.placement_fallbacks.add(other.placement_fallbacks.get());
+        self.mte_segments.add(other.mte_segments.get());
+        self.secure_segments.add(other.secure_segments.get());
+        self.secure_failures.add(other.secure_failures.get());
+        self.segment_cache_hits.add(other.segment_cache_hits.get());
+        self.segment_cache_misses.add(other.segment_cache_misses.get());
+        self.recent_segment_hits.add(other.recent_segment_hits.get());
+        self.recent_segment_misses.add(other.recent_segment_misses.get());
+    }
+
+    /// Copies the counters in to the statistics
+    fn read(&self, out_stats: &mut HugeGlobalAllocatorStats) {
+        out_stats.missed_allocs = non_negative(self.missed_allocs.get()) as usize;
+        out_stats.missed_mb = non_negative(self.missed_bytes.get()) as f64 / (1024 * 1024) as f64;
+        out_stats.remaps_failed = self.remaps_failed.get() as usize;
+        out_stats.remaps_copied = self.remaps_copied.get() as usize;
+        out_stats.remaps_refused = self.remaps_refused.get() as usize;
+        out_stats.remap_copied_bytes = self.remap_copied_bytes.get() as usize;
+        out_stats.huge_retries = self.huge_retries.get() as usize;
+        out_stats.populate_failed = self.populate_failed.get() as usize;
+        out_stats.prefault_time = Duration::from_nanos(self.prefault_time_ns.get());
+        out_stats.prefault_max_time = Duration::from_nanos(self.prefault_max_time_ns.get());
+        out_stats.soft_limit_exceeded = self.soft_limit_exceeded.get() as usize;
+        out_stats.unmaps_failed = self.unmaps_failed.get() as usize;
+        out_stats.leaked_bytes = self.leaked_bytes.get() as usize;
+        out_stats.promotions = self.promotions.get() as usize;
+        out_stats.maintenance_passes = self.maintenance_passes.get() as usize;
+        out_stats.collapses = self.collapses.get() as usize;
+        out_stats.collapses_failed = self.collapses_failed.get() as usize;
+        out_stats.shrinks_deferred = self.shrinks_deferred.get() as usize;
+        out_stats.trimmed_bytes = self.trimmed_bytes.get() as usize;
+        out_stats.exact_size_allocs = self.exact_size_allocs.get() as usize;
+        out_stats.move_memcpy_bytes = self.move_memcpy_bytes.get() as usize;
+        out_stats.move_mremap_bytes = self.move_mremap_bytes.get() as usize;
+        out_stats.move_dontunmap_bytes = self.move_dontunmap_bytes.get() as usize;
+        out_stats.demotions = self.demotions.get() as usize;
+        out_stats.demotions_vetoed = self.demotions_vetoed.get() as usize;
+        out_stats.randomized_placements = self.randomized_placements.get() as usize;
+        out_stats.placement_fallbacks = self.placement_fallbacks.get() as usize;
+        out_stats.mte_segments = self.mte_segments.get() as usize;
+        out_stats.mte_tag_faults = mte::tag_faults();
+        out_stats.secure_segments = self.secure_segments.get() as usize;
+        out_stats.secure_failures = self.secure_failures.get() as usize;
+        out_stats.segment_cache_hits = self.segment_cache_hits.get() as usize;
+        out_stats.segment_cache_misses = self.segment_cache_misses.get() as usize;
+        out_stats.recent_segment_hits = self.recent_segment_hits.get() as usize;
+        out_stats.recent_segment_misses = self.recent_segment_misses.get() as usize;
+    }
+
+    /// Zeroes the counters
+    fn reset(&self) {
+        self.missed_allocs.reset();
+        self.missed_bytes.reset();
+        self.remaps_failed.reset();
+        self.remaps_copied.reset();
+        self.remaps_refused.reset();
+        self.remap_copied_bytes.reset();
+        self.huge_retries.reset();
+        self.populate_failed.reset();
+        self.prefault_time_ns.reset();
+        self.prefault_max_time_ns.reset();
+        self.soft_limit_exceeded.reset();
+        self.unmaps_failed.reset();
+        self.leaked_bytes.reset();
+        self.promotions.reset();
+        self.maintenance_passes.reset();
+        self.collapses.reset();
+        self.collapses_failed.reset();
+        self.shrinks_deferred.reset();
+        self.trimmed_bytes.reset();
+        self.exact_size_allocs.reset();
+        self.move_memcpy_bytes.reset();
+        self.move_mremap_bytes.reset();
+        self.move_dontunmap_bytes.reset();
+        self.demotions.reset();
+        self.demotions_vetoed.reset();
+        self.randomized_placements.reset();
+        self.placement_fallbacks.reset();
+        self.mte_segments.reset();
+        mte::reset_tag_faults();
+        self.secure_segments.reset();
+        self.secure_failures.reset();
+        self.segment_cache_hits.reset();
+        self.segment_cache_misses.reset();
+        self.recent_segment_hits.reset();
+        self.recent_segment_misses.reset();
+    }
+}
+
+/// Contention counters for one lock
+pub(crate) struct LockStats {
+    acquisitions: Counter,
+    contended: Counter,
+    wait_ns: Counter,
+    max_wait_ns: Counter,
+}
+
+impl LockStats {
+    pub const fn new() -> Self {
+        Self {
+            acquisitions: Counter::new(),
+            contended: Counter::new(),
+            wait_ns: Counter::new(),
+            max_wait_ns: Counter::new(),
+        }
+    }
+
+    /// Counts an acquisition of the lock
+    #[inline]
+    pub fn acquired(&self) {
+        self.acquisitions.add(1);
+    }
+
+    /// Counts an acquisition which waited ns nanoseconds for another thread to unlock
+    pub fn waited(&self, ns: u64) {
+        self.contended.add(1);
+        self.wait_ns.add(ns);
+        self.max_wait_ns.max(ns);
+    }
+
+    /// Adds the counters to the lock totals in the statistics
+    pub fn read(&self, out_stats: &mut HugeGlobalAllocatorStats) {
+        out_stats.lock_acquisitions += self.acquisitions.get() as usize;
+        out_stats.lock_contended += self.contended.get() as usize;
+        out_stats.lock_wait_time += Duration::from_nanos(self.wait_ns.get());
+        out_stats.lock_max_wait_time = out_stats.lock_max_wait_time.max(Duration::from_nanos(self.max_wait_ns.get()));
+    }
+
+    /// Zeroes the counters
+    pub fn reset(&self) {
+        self.acquisitions.reset();
+        self.contended.reset();
+        self.wait_ns.reset();
+        self.max_wait_ns.reset();
+    }
+}
+
+/// Running totals of the segments of one page size, updated as segments enter and leave the
+/// pointer map
+pub(crate) struct Gauges {
+    alloc: Counter,
+    mapped: Counter,
+    segments: Counter,
+}
+
+impl Gauges {
+    pub const fn new() -> Self {
+        Self {
+            alloc: Counter::new(),
+            mapped: Counter::new(),
+            segments: Counter::new(),
+        }
+    }
+
+    /// Adds a segment to the totals
+    #[inline]
+    pub fn add(&self, mmap: &MMap) {
+        self.alloc.add(mmap.size() as u64);
+        self.mapped.add(mmap.alloc_size() as u64);
+        self.segments.add(1);
+    }
+
+    /// Removes a segment from the totals
+    #[inline]
+    pub fn sub(&self, mmap: &MMap) {
+        self.alloc.sub(mmap.size() as u64);
+        self.mapped.sub(mmap.alloc_size() as u64);
+        self.segments.sub(1);
+    }
+
+    /// Returns the allocated bytes, mapped bytes and segment count
+    pub fn read(&self) -> (usize, usize, usize) {
+        (self.alloc.get() as usize, self.mapped.get() as usize, self.segments.get() as usize)
+    }
+}
+
+/// Length of the window churn rates are averaged over in seconds
+pub(crate) const CHURN_WINDOW_SECS: u64 = 60;
+
+/// Operations on memory mapped segments counted for the churn rates
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Churn {
+    Alloc,
+    Dealloc,
+    Remap,
+}
+
+/// Operation counts for one second of the churn window
+struct ChurnBucket {
+    /// Monotonic clock second the counts belong to
+    second: AtomicU64,
+    counts: [Counter; 3],
+}
+
+impl ChurnBucket {
+    const fn new() -> Self {
+        Self {
+            second: AtomicU64::new(0),
+            counts: [Counter::new(), Counter::new(), Counter::new()],
+        }
+    }
+}
+
+/// Rolling window of operation counts, one bucket per second. Buckets are recycled without
+/// locking, so an operation racing with a bucket being recycled may be lost and the rates are
+/// approximate.
+pub(crate) struct ChurnWindow {
+    buckets: [ChurnBucket; CHURN_WINDOW_SECS as usize],
+}
+
+impl ChurnWindow {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [const { ChurnBucket::new() }; CHURN_WINDOW_SECS as usize],
+        }
+    }
+
+    /// Counts an operation in the current second
+    #[inline]
+    pub fn record(&self, op: Churn) {
+        if cfg!(not(feature = "no-stats")) {
+            self.record_at(op, now_secs());
+        }
+    }
+
+    /// Counts an operation in the given second
+    pub fn record_at(&self, op: Churn, now: u64) {
+        let bucket = &self.buckets[(now % CHURN_WINDOW_SECS) as usize];
+        let second = bucket.second.load(Ordering::Relaxed);
+
+        if second != now && bucket.second.compare_exchange(second, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            // First operation in this second - recycle the bucket
+            for count in &bucket.counts {
+                count.reset();
+            }
+        }
+
+        bucket.counts[op as usize].add(1);
+    }
+
+    /// Copies the rates in to the statistics
+    pub fn read(&self, out_stats: &mut HugeGlobalAllocatorStats) {
+        [out_stats.allocs_per_sec, out_stats.deallocs_per_sec, out_stats.remaps_per_sec] = self.rates_at(now_secs());
+    }
+
+    /// Returns the alloc, dealloc and remap rates per second over the window ending at the given
+    /// second
+    pub fn rates_at(&self, now: u64) -> [f64; 3] {
+        let mut totals = [0u64; 3];
+
+        for bucket in &self.buckets {
+            let second = bucket.second.load(Ordering::Relaxed);
+
+            if second <= now && now - second < CHURN_WINDOW_SECS {
+                for (total, count) in totals.iter_mut().zip(&bucket.counts) {
+                    *total += count.get();
+                }
+            }
+        }
+
+        totals.map(|total| total as f64 / CHURN_WINDOW_SECS as f64)
+    }
+}
+
+/// Returns the monotonic clock in whole seconds
+fn now_secs() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_COARSE, &mut ts) };
+
+    ts.tv_sec as u64
+}
@@ -0,0 +1,51 @@
+//! CSV time series of the statistics
+//!
+//! Each row is a timestamp in seconds since the Unix epoch followed by the statistics, in the same
+//! order and with the same names as the stats.* ctl keys.
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    ctl::{stat, STAT_NAMES},
+    HugeGlobalAllocatorStats,
+};
+
+/// Writes the header row
+pub(crate) fn write_header(w: &mut impl Write) -> std::io::Result<()> {
+    writeln!(w, "time,{}", STAT_NAMES.join(","))
+}
+
+/// Writes a row for the statistics, timestamped now
+pub(crate) fn write_row(w: &mut impl Write, stats: &HugeGlobalAllocatorStats) -> std::io::Result<()> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    write!(w, "{:.3}", time.as_secs_f64())?;
+
+    for name in STAT_NAMES {
+        write!(w, ",{}", stat(stats, name).unwrap_or_default())?;
+    }
+
+    writeln!(w)
+}
+
+/// Appends a row to a CSV file, creating it with a header row if it is empty
+pub(crate) fn append(path: &Path, stats: &HugeGlobalAllocatorStats) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    // Build the lines first so each is appended with a single write
+    let mut buf = Vec::new();
+
+    if file.metadata()?.len() == 0 {
+        write_header(&mut buf)?;
+    }
+
+    write_row(&mut buf, stats)?;
+
+    file.write_all(&buf)?;
+
+    Ok(())
+}
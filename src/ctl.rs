@@ -0,0 +1,283 @@
+//! String keyed control interface. See [HugeGlobalAllocator::ctl] for the keys.
+
+use std::error::Error;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::{
+    cache::MAX_SEGMENTS, HugeGlobalAllocator, HugeGlobalAllocatorStats, MapStrategy, MoveStrategy, RemapFailure,
+    UnmapFailure,
+};
+
+/// Writes a setting
+pub(crate) fn write<A, P>(allocator: &HugeGlobalAllocator<A, P>, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let mapper = &allocator.mapper;
+    let value = value.trim();
+
+    match name {
+        "threshold" => allocator.set_threshold(parse(name, value, parse_size)?),
+        "soft_limit" => mapper.set_soft_limit_bytes(parse(name, value, parse_size)?),
+        "huge_retry.count" => {
+            let (_, backoff) = mapper.huge_retry();
+            mapper.set_huge_retry(parse(name, value, |v| v.parse().ok())?, backoff);
+        }
+        "huge_retry.backoff_us" => {
+            let (retries, _) = mapper.huge_retry();
+            mapper.set_huge_retry(retries, Duration::from_micros(parse(name, value, |v| v.parse().ok())?));
+        }
+        "sigbus_protection" => mapper.set_sigbus_protection(parse(name, value, parse_bool)?),
+        "pretouch_validation" => mapper.set_pretouch_validation(parse(name, value, parse_bool)?),
+        "remap_failure" => mapper.set_remap_failure(parse(name, value, parse_remap_failure)?),
+        "move_strategy" => mapper.set_move_strategy(parse(name, value, parse_move_strategy)?),
+        "map_strategy" => mapper.set_map_strategy(parse(name, value, parse_map_strategy)?),
+        "unmap_failure" => mapper.set_unmap_failure(parse(name, value, parse_unmap_failure)?),
+        "shrink_threshold" => mapper.set_shrink_threshold(parse(name, value, parse_percent)?),
+        "max_slack" => mapper.set_max_slack(parse(name, value, parse_percent)?),
+        "maintenance.interval_ms" => mapper
+            .maintenance()
+            .set_interval(Duration::from_millis(parse(name, value, |v| v.parse().ok())?))?,
+        "placement.window" => allocator.set_placement_window(parse(name, value, parse_window)?)?,
+        "mte" => mapper.set_mte(parse(name, value, parse_bool)?)?,
+        "secure_profile" => mapper.set_secure_profile(parse(name, value, parse_bool)?),
+        "fork_shared" => mapper.set_fork_shared(parse(name, value, parse_bool)?),
+        "numa_local" => mapper.set_numa_local(parse(name, value, parse_bool)?),
+        "strict_overcommit" => mapper.set_strict_overcommit(parse(name, value, parse_bool)?),
+        "segment_cache.count" => {
+            let (_, bytes) = mapper.segment_cache();
+            mapper.set_segment_cache(parse(name, value, parse_cache_count)?, bytes);
+        }
+        "segment_cache.bytes" => {
+            let (count, _) = mapper.segment_cache();
+            mapper.set_segment_cache(count, parse(name, value, parse_size)?);
+        }
+        "stats.reset" => mapper.reset_stats(),
+        _ if read(allocator, name).is_ok() => return Err(format!("ctl key {} is read only", name).into()),
+        _ => return Err(format!("unknown ctl key {}", name).into()),
+    }
+
+    Ok(())
+}
+
+/// Reads a setting or statistic
+pub(crate) fn read<A, P>(allocator: &HugeGlobalAllocator<A, P>, name: &str) -> Result<String, Box<dyn Error>> {
+    let mapper = &allocator.mapper;
+
+    let value = match name {
+        "threshold" => allocator.threshold().to_string(),
+        "soft_limit" => mapper.soft_limit().to_string(),
+        "huge_retry.count" => mapper.huge_retry().0.to_string(),
+        "huge_retry.backoff_us" => mapper.huge_retry().1.as_micros().to_string(),
+        "sigbus_protection" => mapper.sigbus_protection().to_string(),
+        "pretouch_validation" => mapper.pretouch_validation().to_string(),
+        "remap_failure" => match mapper.remap_failure() {
+            RemapFailure::Copy => "copy",
+            RemapFailure::Fail => "fail",
+        }
+        .to_string(),
+        "move_strategy" => match mapper.move_strategy() {
+            MoveStrategy::Memcpy => "memcpy",
+            MoveStrategy::Mremap => "mremap",
+            MoveStrategy::DontUnmap => "dontunmap",
+        }
+        .to_string(),
+        "map_strategy" => match mapper.map_strategy() {
+            MapStrategy::Hash => "hash".to_string(),
+            MapStrategy::Ordered => "ordered".to_string(),
+            MapStrategy::Fixed(segments) => format!("fixed:{}", segments),
+        },
+        "unmap_failure" => match mapper.unmap_failure() {
+            UnmapFailure::Abort => "abort",
+            UnmapFailure::Leak => "leak",
+            UnmapFailure::Log => "log",
+        }
+        .to_string(),
+        "shrink_threshold" => mapper.shrink_threshold().to_string(),
+        "max_slack" => mapper.max_slack().to_string(),
+        "maintenance.interval_ms" => mapper.maintenance().interval().as_millis().to_string(),
+        "placement.window" => match mapper.placement_window() {
+            Some(window) => format!("{:#x}-{:#x}", window.start, window.end),
+            None => "off".to_string(),
+        },
+        "mte" => mapper.mte().to_string(),
+        "secure_profile" => mapper.secure_profile().to_string(),
+        "fork_shared" => mapper.fork_shared().to_string(),
+        "numa_local" => mapper.numa_local().to_string(),
+        "strict_overcommit" => mapper.strict_overcommit().to_string(),
+        "segment_cache.count" => mapper.segment_cache().0.to_string(),
+        "segment_cache.bytes" => mapper.segment_cache().1.to_string(),
+        _ => match name.strip_prefix("stats.") {
+            Some(stat_name) => match stat(&allocator.stats()?, stat_name) {
+                Some(value) => value,
+                None => return Err(format!("unknown ctl key {}", name).into()),
+            },
+            None => return Err(format!("unknown ctl key {}", name).into()),
+        },
+    };
+
+    Ok(value)
+}
+
+/// Names of the statistics readable with stats.* keys
+pub(crate) const STAT_NAMES: [&str; 53] = [
+    "alloc", "mapped", "segments",
+    "default_alloc", "default_mapped", "default_segments",
+    "huge_alloc", "huge_mapped", "huge_segments",
+    "missed_allocs", "missed_mb",
+    "remaps_failed", "remaps_copied", "remaps_refused", "remap_copied_bytes",
+    "huge_retries", "populate_failed", "prefault_time_ns", "prefault_max_time_ns",
+    "efficiency", "unmaps_failed", "leaked_bytes", "soft_limit_exceeded", "pressure",
+    "allocs_per_sec", "deallocs_per_sec", "remaps_per_sec",
+    "promotions", "maintenance_passes", "collapses", "collapses_failed", "shrinks_deferred", "trimmed_bytes",
+    "exact_size_allocs", "move_memcpy_bytes", "move_mremap_bytes", "move_dontunmap_bytes",
+    "demotions", "demotions_vetoed",
+    "randomized_placements", "placement_fallbacks",
+    "mte_segments", "mte_tag_faults",
+    "secure_segments", "secure_failures",
+    "segment_cache_hits", "segment_cache_misses",
+    "lock_acquisitions", "lock_contended", "lock_wait_time_ns", "lock_max_wait_time_ns",
+    "recent_segment_hits", "recent_segment_misses",
+];
+
+/// Returns a statistic by name
+pub(crate) fn stat(stats: &HugeGlobalAllocatorStats, name: &str) -> Option<String> {
+    let value = match name {
+        "alloc" => stats.alloc,
+        "mapped" => stats.mapped,
+        "segments" => stats.segments,
+        "default_alloc" => stats.default_alloc,
+        "default_mapped" => stats.default_mapped,
+        "default_segments" => stats.default_segments,
+        "huge_alloc" => stats.huge_alloc,
+        "huge_mapped" => stats.huge_mapped,
+        "huge_segments" => stats.huge_segments,
+        "missed_allocs" => stats.missed_allocs,
+        "missed_mb" => return Some(stats.missed_mb.to_string()),
+        "remaps_failed" => stats.remaps_failed,
+        "remaps_copied" => stats.remaps_copied,
+        "remaps_refused" => stats.remaps_refused,
+        "remap_copied_bytes" => stats.remap_copied_bytes,
+        "huge_retries" => stats.huge_retries,
+        "populate_failed" => stats.populate_failed,
+        "prefault_time_ns" => return Some(stats.prefault_time.as_nanos().to_string()),
+        "prefault_max_time_ns" => return Some(stats.prefault_max_time.as_nanos().to_string()),
+        "efficiency" => stats.efficiency,
+        "unmaps_failed" => stats.unmaps_failed,
+        "leaked_bytes" => stats.leaked_bytes,
+        "soft_limit_exceeded" => stats.soft_limit_exceeded,
+        "pressure" => stats.pressure,
+        "allocs_per_sec" => return Some(stats.allocs_per_sec.to_string()),
+        "deallocs_per_sec" => return Some(stats.deallocs_per_sec.to_string()),
+        "remaps_per_sec" => return Some(stats.remaps_per_sec.to_string()),
+        "promotions" => stats.promotions,
+        "maintenance_passes" => stats.maintenance_passes,
+        "collapses" => stats.collapses,
+        "collapses_failed" => stats.collapses_failed,
+        "shrinks_deferred" => stats.shrinks_deferred,
+        "trimmed_bytes" => stats.trimmed_bytes,
+        "exact_size_allocs" => stats.exact_size_allocs,
+        "move_memcpy_bytes" => stats.move_memcpy_bytes,
+        "move_mremap_bytes" => stats.move_mremap_bytes,
+        "move_dontunmap_bytes" => stats.move_dontunmap_bytes,
+        "demotions" => stats.demotions,
+        "demotions_vetoed" => stats.demotions_vetoed,
+        "randomized_placements" => stats.randomized_placements,
+        "placement_fallbacks" => stats.placement_fallbacks,
+        "mte_segments" => stats.mte_segments,
+        "mte_tag_faults" => stats.mte_tag_faults,
+        "secure_segments" => stats.secure_segments,
+        "secure_failures" => stats.secure_failures,
+        "segment_cache_hits" => stats.segment_cache_hits,
+        "segment_cache_misses" => stats.segment_cache_misses,
+        "lock_acquisitions" => stats.lock_acquisitions,
+        "lock_contended" => stats.lock_contended,
+        "lock_wait_time_ns" => return Some(stats.lock_wait_time.as_nanos().to_string()),
+        "lock_max_wait_time_ns" => return Some(stats.lock_max_wait_time.as_nanos().to_string()),
+        "recent_segment_hits" => stats.recent_segment_hits,
+        "recent_segment_misses" => stats.recent_segment_misses,
+        _ => return None,
+    };
+
+    Some(value.to_string())
+}
+
+/// Parses a value, returning an error naming the key if it is invalid
+fn parse<T>(name: &str, value: &str, parse: impl Fn(&str) -> Option<T>) -> Result<T, Box<dyn Error>> {
+    parse(value).ok_or_else(|| format!("invalid value {:?} for ctl key {}", value, name).into())
+}
+
+/// Parses a size in bytes with an optional k, m or g suffix
+pub(crate) fn parse_size(value: &str) -> Option<usize> {
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses a percentage from 0 to 100
+fn parse_percent(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|percent| *percent <= 100)
+}
+
+/// Parses a number of segments to cache per segment cache slot
+fn parse_cache_count(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|count| *count <= MAX_SEGMENTS)
+}
+
+/// Parses a boolean
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_remap_failure(value: &str) -> Option<RemapFailure> {
+    match value {
+        "copy" => Some(RemapFailure::Copy),
+        "fail" => Some(RemapFailure::Fail),
+        _ => None,
+    }
+}
+
+fn parse_move_strategy(value: &str) -> Option<MoveStrategy> {
+    match value {
+        "memcpy" => Some(MoveStrategy::Memcpy),
+        "mremap" => Some(MoveStrategy::Mremap),
+        "dontunmap" => Some(MoveStrategy::DontUnmap),
+        _ => None,
+    }
+}
+
+fn parse_map_strategy(value: &str) -> Option<MapStrategy> {
+    match value {
+        "hash" => Some(MapStrategy::Hash),
+        "ordered" => Some(MapStrategy::Ordered),
+        _ => value.strip_prefix("fixed:")?.parse().ok().map(MapStrategy::Fixed),
+    }
+}
+
+fn parse_window(value: &str) -> Option<Option<Range<usize>>> {
+    if value == "off" {
+        return Some(None);
+    }
+
+    let parse_hex = |value: &str| usize::from_str_radix(value.trim().strip_prefix("0x")?, 16).ok();
+
+    let (start, end) = value.split_once('-')?;
+
+    Some(Some(parse_hex(start)?..parse_hex(end)?))
+}
+
+fn parse_unmap_failure(value: &str) -> Option<UnmapFailure> {
+    match value {
+        "abort" => Some(UnmapFailure::Abort),
+        "leak" => Some(UnmapFailure::Leak),
+        "log" => Some(UnmapFailure::Log),
+        _ => None,
+    }
+}
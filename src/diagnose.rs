@@ -0,0 +1,245 @@
+//! Detection of the environment the allocator runs in and the page backing it will get
+//!
+//! Virtual machines and containers often run without a huge page pool, with a cgroup limiting
+//! huge page use to nothing, or with system calls blocked, and the allocator then quietly falls
+//! back to smaller pages. [diagnose] gathers everything which decides the backing in one place.
+
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    hugepages::{huge_pages, HugePageInfo},
+    pagesize::huge_page_size,
+    probe::{self, Probe},
+    sys::LinuxSyscalls,
+    PASSTHROUGH,
+};
+
+/// Transparent huge page mode, from /sys/kernel/mm/transparent_hugepage/enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThpMode {
+    /// Every suitable anonymous mapping is backed by transparent huge pages
+    Always,
+    /// Only mappings advised MADV_HUGEPAGE, or collapsed, get transparent huge pages
+    Madvise,
+    /// Transparent huge pages are switched off
+    Never,
+}
+
+/// The pages new memory mapped allocations will be backed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Huge pages from the hugetlb pool
+    HugePages,
+    /// Default size pages, which the kernel may back with transparent huge pages
+    TransparentHugePages,
+    /// Default size pages
+    DefaultPages,
+    /// Nothing is mapped; passthrough builds use the inner allocator
+    InnerAllocator,
+}
+
+/// The environment the allocator runs in and the backing it will get, returned by [diagnose]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    /// The container runtime the process appears to run under
+    pub container: Option<&'static str>,
+    /// The hypervisor the process appears to run under, "unknown" if the CPU reports one but the
+    /// firmware doesn't name it
+    pub hypervisor: Option<String>,
+    /// The huge page size the allocator uses
+    pub huge_page_size: usize,
+    /// The huge page pools, empty if the kernel has no hugetlb support
+    pub pools: Vec<HugePageInfo>,
+    /// The bytes of huge pages of the allocator's size the process's cgroup may use, None if
+    /// unlimited
+    pub hugetlb_limit: Option<usize>,
+    /// The transparent huge page mode, None if the kernel has no transparent huge page support
+    pub thp: Option<ThpMode>,
+    /// The system calls the allocator depends on
+    pub probes: Vec<Probe>,
+    /// The backing new allocations will get
+    pub backing: Backing,
+    /// Why they get that backing
+    pub reason: &'static str,
+}
+
+impl Diagnosis {
+    /// Returns the pool of the allocator's huge page size
+    pub fn pool(&self) -> Option<&HugePageInfo> {
+        self.pools.iter().find(|pool| pool.page_size == self.huge_page_size)
+    }
+}
+
+impl Display for Diagnosis {
+    /// Formats the diagnosis as a report for support tickets
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "container:      {}", self.container.unwrap_or("none detected"))?;
+        writeln!(f, "hypervisor:     {}", self.hypervisor.as_deref().unwrap_or("none detected"))?;
+        writeln!(f, "huge page size: {}kb", self.huge_page_size / 1024)?;
+
+        if self.pools.is_empty() {
+            writeln!(f, "hugetlb pools:  none")?;
+        }
+
+        for pool in &self.pools {
+            writeln!(f, "hugetlb pool:   {}kb pages, {} free of {}", pool.page_size / 1024, pool.free, pool.total)?;
+        }
+
+        match self.hugetlb_limit {
+            Some(limit) => writeln!(f, "cgroup limit:   {limit} bytes")?,
+            None => writeln!(f, "cgroup limit:   none")?,
+        }
+
+        match self.thp {
+            Some(mode) => writeln!(f, "thp:            {mode:?}")?,
+            None => writeln!(f, "thp:            unsupported")?,
+        }
+
+        for probe in self.probes.iter().filter(|probe| !probe.available()) {
+            writeln!(f, "unavailable:    {} ({})", probe.name, probe.used_for)?;
+        }
+
+        writeln!(f, "backing:        {:?}: {}", self.backing, self.reason)
+    }
+}
+
+/// Detects the environment and works out the page backing new memory mapped allocations will get
+///
+/// ```rust
+/// use huge_global_alloc::{diagnose, Backing};
+///
+/// let diagnosis = diagnose();
+///
+/// if diagnosis.backing != Backing::HugePages {
+///     eprint!("{diagnosis}");
+/// }
+/// ````
+pub fn diagnose() -> Diagnosis {
+    let huge_page_size = huge_page_size();
+    let pools = huge_pages().unwrap_or_default();
+    let hugetlb_limit = hugetlb_limit(huge_page_size);
+    let thp = fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled").ok().and_then(|s| parse_thp(&s));
+    let probes = probe::run(&LinuxSyscalls);
+
+    let hugetlb_mappable = probes.iter().any(|probe| probe.name == probe::MMAP_HUGETLB && probe.available());
+    let pool = pools.iter().find(|pool| pool.page_size == huge_page_size);
+
+    // Surplus pages can be mapped beyond the pool, so the pool only explains a failed mapping
+    let (backing, reason) = match pool {
+        _ if PASSTHROUGH => (Backing::InnerAllocator, "passthrough build"),
+        _ if hugetlb_limit == Some(0) => fallback(thp, "the cgroup hugetlb limit is zero"),
+        None => fallback(thp, "no hugetlb pool of the huge page size"),
+        Some(_) if hugetlb_mappable => (Backing::HugePages, "huge pages can be mapped"),
+        Some(pool) if pool.total == 0 => fallback(thp, "no huge pages are reserved"),
+        Some(pool) if pool.free == 0 => fallback(thp, "the hugetlb pool is exhausted"),
+        Some(_) => fallback(thp, "huge page mappings fail"),
+    };
+
+    Diagnosis {
+        container: detect_container(),
+        hypervisor: detect_hypervisor(),
+        huge_page_size,
+        pools,
+        hugetlb_limit,
+        thp,
+        probes,
+        backing,
+        reason,
+    }
+}
+
+/// Returns the backing when hugetlb pages can't be used
+fn fallback(thp: Option<ThpMode>, reason: &'static str) -> (Backing, &'static str) {
+    match thp {
+        Some(ThpMode::Always | ThpMode::Madvise) => (Backing::TransparentHugePages, reason),
+        _ => (Backing::DefaultPages, reason),
+    }
+}
+
+/// Parses the transparent huge page mode, the bracketed word in eg. "always [madvise] never"
+pub(crate) fn parse_thp(enabled: &str) -> Option<ThpMode> {
+    let mode = enabled.split_whitespace().find(|word| word.starts_with('['))?;
+
+    match mode.trim_matches(|c| c == '[' || c == ']') {
+        "always" => Some(ThpMode::Always),
+        "madvise" => Some(ThpMode::Madvise),
+        "never" => Some(ThpMode::Never),
+        _ => None,
+    }
+}
+
+/// Names the container runtime from marker files and the process's cgroups
+fn detect_container() -> Option<&'static str> {
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker");
+    }
+
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman");
+    }
+
+    fs::read_to_string("/proc/self/cgroup").ok().and_then(|cgroup| parse_container(&cgroup))
+}
+
+/// Names the container runtime from the contents of /proc/self/cgroup
+pub(crate) fn parse_container(cgroup: &str) -> Option<&'static str> {
+    const MARKERS: [(&str, &str); 5] = [
+        ("kubepods", "kubernetes"),
+        ("docker", "docker"),
+        ("libpod", "podman"),
+        ("containerd", "containerd"),
+        ("lxc", "lxc"),
+    ];
+
+    MARKERS
+        .into_iter()
+        .find(|(marker, _)| cgroup.contains(marker))
+        .map(|(_, name)| name)
+}
+
+/// Names the hypervisor from the CPU flags and the firmware vendor
+fn detect_hypervisor() -> Option<String> {
+    let vendor = fs::read_to_string("/sys/class/dmi/id/sys_vendor").ok().map(|vendor| vendor.trim().to_string());
+
+    let flagged = fs::read_to_string("/proc/cpuinfo").is_ok_and(|cpuinfo| {
+        cpuinfo
+            .lines()
+            .any(|line| line.starts_with("flags") && line.split_whitespace().any(|flag| flag == "hypervisor"))
+    });
+
+    let known = ["QEMU", "KVM", "VMware", "innotek", "Xen", "Microsoft", "Amazon", "Google"];
+
+    match vendor {
+        Some(vendor) if known.iter().any(|name| vendor.contains(name)) => Some(vendor),
+        _ if flagged => Some("unknown".to_string()),
+        _ => None,
+    }
+}
+
+/// Returns the process's cgroup v2 limit on huge pages of the given size, None if unlimited
+fn hugetlb_limit(page_size: usize) -> Option<usize> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+
+    let file = Path::new("/sys/fs/cgroup")
+        .join(path.trim_start_matches('/'))
+        .join(format!("hugetlb.{}.max", size_label(page_size)));
+
+    parse_limit(&fs::read_to_string(file).ok()?)
+}
+
+/// Returns the size label cgroups use in hugetlb file names, eg. 2MB
+pub(crate) fn size_label(page_size: usize) -> String {
+    match page_size {
+        size if size >= 1 << 30 => format!("{}GB", size >> 30),
+        size if size >= 1 << 20 => format!("{}MB", size >> 20),
+        size => format!("{}KB", size >> 10),
+    }
+}
+
+/// Parses a cgroup limit, None for "max"
+pub(crate) fn parse_limit(limit: &str) -> Option<usize> {
+    limit.trim().parse().ok()
+}
@@ -0,0 +1,154 @@
+//! Recording of the operations performed on memory mapped segments
+//!
+//! When recording is enabled the allocator keeps an ordered log of the allocations,
+//! reallocations and deallocations of memory mapped segments along with the page size chosen to
+//! back each one, so tests can assert precise allocator behaviour for a workload. Allocations
+//! passed to the system allocator are not recorded. The log holds up to [CAPACITY] events, after
+//! which further events are dropped.
+//!
+//! ```rust
+//! use std::alloc::{GlobalAlloc, Layout};
+//! use huge_global_alloc::events::Operation;
+//! use huge_global_alloc::HugeGlobalAllocator;
+//!
+//! let allocator = HugeGlobalAllocator::new(1024 * 1024);
+//! allocator.set_event_recording(true);
+//!
+//! let layout = Layout::from_size_align(2 * 1024 * 1024, 8).unwrap();
+//! let ptr = unsafe { allocator.alloc(layout) };
+//! unsafe { allocator.dealloc(ptr, layout) };
+//!
+//! let events = allocator.events();
+//! assert_eq!(events.len(), 2);
+//! assert_eq!(events[0].operation, Operation::Alloc);
+//! assert_eq!(events[0].size, 2 * 1024 * 1024);
+//! assert_eq!(events[1].operation, Operation::Dealloc);
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{mmap::MMap, sync::{const_fn, Mutex}};
+
+/// Maximum number of events held in the log
+pub const CAPACITY: usize = 1024;
+
+/// Operation performed on a memory mapped segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// A new segment was mapped
+    Alloc,
+    /// A segment was resized, either in place or by copying to a new segment
+    Realloc,
+    /// A segment was unmapped
+    Dealloc,
+}
+
+/// Page size backing a memory mapped segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backing {
+    /// Backed by huge pages
+    Huge,
+    /// Backed by default page size pages
+    Default,
+}
+
+/// A recorded operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// The operation performed
+    pub operation: Operation,
+    /// Identifier of the allocation (see [SegmentInfo::id](crate::SegmentInfo::id))
+    pub id: u64,
+    /// Size of the segment before the operation in bytes (0 for allocations)
+    pub old_size: usize,
+    /// Size of the segment after the operation in bytes (0 for deallocations)
+    pub size: usize,
+    /// Page size backing the segment
+    pub backing: Backing,
+}
+
+impl Event {
+    const EMPTY: Event = Event {
+        operation: Operation::Alloc,
+        id: 0,
+        old_size: 0,
+        size: 0,
+        backing: Backing::Default,
+    };
+}
+
+/// Fixed size event storage, so recording never allocates
+struct Log {
+    events: [Event; CAPACITY],
+    len: usize,
+}
+
+/// An event log for a mapper
+pub(crate) struct EventLog {
+    enabled: AtomicBool,
+    log: Mutex<Log>,
+}
+
+impl EventLog {
+    const_fn! {
+        /// Creates a new, disabled event log
+        pub fn new() -> Self {
+            Self {
+                enabled: AtomicBool::new(false),
+                log: Mutex::new(Log {
+                    events: [Event::EMPTY; CAPACITY],
+                    len: 0,
+                }),
+            }
+        }
+    }
+
+    /// Enables or disables recording
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Records an operation on a segment if recording is enabled
+    pub fn record(&self, operation: Operation, old_size: usize, size: usize, mmap: &MMap) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let backing = if mmap.is_default_page_size() {
+            Backing::Default
+        } else {
+            Backing::Huge
+        };
+
+        let mut log = self.log.lock();
+
+        if log.len < CAPACITY {
+            let idx = log.len;
+
+            log.events[idx] = Event {
+                operation,
+                id: mmap.id(),
+                old_size,
+                size,
+                backing,
+            };
+            log.len += 1;
+        }
+    }
+
+    /// Returns the recorded events in order
+    pub fn events(&self) -> Vec<Event> {
+        // Copy out of the lock before allocating, as the allocation may itself be recorded
+        let log = self.log.lock();
+        let events = log.events;
+        let len = log.len;
+        drop(log);
+
+        events[..len].to_vec()
+    }
+
+    /// Clears the recorded events
+    pub fn clear(&self) {
+        self.log.lock().len = 0;
+    }
+}
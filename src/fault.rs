@@ -0,0 +1,108 @@
+//! Fault injection for the memory mapping system calls
+//!
+//! Faults are injected on the calling thread only, so tests running in parallel don't interfere
+//! with each other. Injected faults fail with ENOMEM.
+//!
+//! ```rust
+//! use std::alloc::{GlobalAlloc, Layout};
+//! use huge_global_alloc::{fault, HugeGlobalAllocator};
+//!
+//! let allocator = HugeGlobalAllocator::new(1024 * 1024);
+//!
+//! // Fail all huge page mappings on this thread
+//! fault::fail_hugetlb(true);
+//!
+//! let layout = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+//! let ptr = unsafe { allocator.alloc(layout) };
+//!
+//! let stats = allocator.stats().unwrap();
+//! assert_eq!(stats.default_segments, 1);
+//! assert_eq!(stats.huge_segments, 0);
+//!
+//! unsafe { allocator.dealloc(ptr, layout) };
+//! fault::reset();
+//! ```
+
+use std::cell::RefCell;
+
+use nix::errno::Errno;
+
+/// System calls which faults can be injected in to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syscall {
+    /// mmap
+    Mmap,
+    /// mremap
+    Mremap,
+    /// munmap
+    Munmap,
+    /// madvise
+    Madvise,
+}
+
+/// Fault injection state for a thread
+struct State {
+    calls: [usize; 4],
+    fail_at: [usize; 4],
+    fail_hugetlb: bool,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            calls: [0; 4],
+            fail_at: [0; 4],
+            fail_hugetlb: false,
+        }
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = const { RefCell::new(State::new()) };
+}
+
+/// Fails the nth call (counting from 1) to the given system call made by this thread from now on
+pub fn fail_nth(syscall: Syscall, n: usize) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let idx = syscall as usize;
+
+        state.fail_at[idx] = state.calls[idx] + n;
+    });
+}
+
+/// Fails all huge page mappings made by this thread while enabled
+pub fn fail_hugetlb(enabled: bool) {
+    STATE.with(|state| state.borrow_mut().fail_hugetlb = enabled);
+}
+
+/// Returns the number of calls to the given system call made by this thread since the last reset
+pub fn calls(syscall: Syscall) -> usize {
+    STATE.with(|state| state.borrow().calls[syscall as usize])
+}
+
+/// Clears all injected faults and call counts for this thread
+pub fn reset() {
+    STATE.with(|state| *state.borrow_mut() = State::new());
+}
+
+/// Counts a system call, returning an error if a fault should be injected
+pub(crate) fn check(syscall: Syscall, hugetlb: bool) -> nix::Result<()> {
+    STATE
+        .try_with(|state| {
+            let mut state = state.borrow_mut();
+            let idx = syscall as usize;
+
+            state.calls[idx] += 1;
+
+            if state.fail_at[idx] != 0 && state.calls[idx] == state.fail_at[idx] {
+                state.fail_at[idx] = 0;
+                Err(Errno::ENOMEM)
+            } else if hugetlb && state.fail_hugetlb {
+                Err(Errno::ENOMEM)
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap_or(Ok(()))
+}
@@ -0,0 +1,104 @@
+//! Passing shared segments between processes over Unix domain sockets
+//!
+//! [send_segment] sends the memfd backing a [SharedSegment] as SCM_RIGHTS ancillary data along
+//! with the segment's layout and page size. [recv_segment] receives it and maps the same memory in
+//! the receiving process.
+//!
+//! ```rust
+//! use std::alloc::Layout;
+//! use std::os::unix::net::UnixStream;
+//! use huge_global_alloc::fdpass::{recv_segment, send_segment};
+//! use huge_global_alloc::shared::SharedSegment;
+//!
+//! let (producer, consumer) = UnixStream::pair().unwrap();
+//!
+//! let layout = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+//! let segment = SharedSegment::new(layout).unwrap();
+//! unsafe { *segment.as_ptr() = 42 };
+//! send_segment(&producer, &segment).unwrap();
+//!
+//! let received = recv_segment(&consumer).unwrap();
+//! assert_eq!(received.layout(), layout);
+//! assert_eq!(unsafe { *received.as_ptr() }, 42);
+//! ````
+
+use std::alloc::Layout;
+use std::error::Error;
+use std::io::{IoSlice, IoSliceMut};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use nix::{
+    cmsg_space,
+    sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags, UnixAddr},
+};
+
+use crate::shared::SharedSegment;
+
+/// Size of the segment metadata message (size, alignment and page size as little endian u64s)
+const METADATA_SIZE: usize = 3 * 8;
+
+/// Sends a shared segment's memfd and metadata over a connected Unix domain socket
+pub fn send_segment(socket: &impl AsRawFd, segment: &SharedSegment) -> Result<(), Box<dyn Error>> {
+    let layout = segment.layout();
+
+    let mut metadata = [0u8; METADATA_SIZE];
+    metadata[0..8].copy_from_slice(&(layout.size() as u64).to_le_bytes());
+    metadata[8..16].copy_from_slice(&(layout.align() as u64).to_le_bytes());
+    metadata[16..24].copy_from_slice(&(segment.page_size() as u64).to_le_bytes());
+
+    let fds = [segment.as_fd().as_raw_fd()];
+
+    let sent = sendmsg::<UnixAddr>(
+        socket.as_raw_fd(),
+        &[IoSlice::new(&metadata)],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )?;
+
+    if sent != METADATA_SIZE {
+        Err(format!("short send of shared segment metadata ({sent} of {METADATA_SIZE} bytes)"))?
+    }
+
+    Ok(())
+}
+
+/// Receives a shared segment sent with [send_segment] and maps it
+pub fn recv_segment(socket: &impl AsRawFd) -> Result<SharedSegment, Box<dyn Error>> {
+    let mut metadata = [0u8; METADATA_SIZE];
+    let mut cmsg_buffer = cmsg_space!([RawFd; 1]);
+
+    let msg = recvmsg::<UnixAddr>(
+        socket.as_raw_fd(),
+        &mut [IoSliceMut::new(&mut metadata)],
+        Some(&mut cmsg_buffer),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+
+    // Take ownership of every received descriptor first so none are leaked on error
+    let mut fds = Vec::new();
+
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(raw_fds) = cmsg {
+            fds.extend(raw_fds.into_iter().map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }));
+        }
+    }
+
+    if msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+        Err("shared segment control message truncated")?
+    }
+
+    if msg.bytes != METADATA_SIZE {
+        Err(format!("expected {METADATA_SIZE} bytes of shared segment metadata, received {}", msg.bytes))?
+    }
+
+    if fds.len() != 1 {
+        Err(format!("expected 1 shared segment file descriptor, received {}", fds.len()))?
+    }
+
+    let field = |i: usize| u64::from_le_bytes(metadata[i * 8..(i + 1) * 8].try_into().unwrap()) as usize;
+
+    let layout = Layout::from_size_align(field(0), field(1))?;
+
+    SharedSegment::from_fd(fds.remove(0), layout, field(2))
+}
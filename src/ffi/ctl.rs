@@ -0,0 +1,71 @@
+//! C control interface
+//!
+//! Exports huge_global_alloc_ctl and huge_global_alloc_ctl_read, which write and read the settings
+//! and statistics of the allocator registered with [HugeGlobalAllocator::export_stats] using the
+//! dotted keys described in [HugeGlobalAllocator::ctl]. From C:
+//!
+//! ```c
+//! char value[32];
+//!
+//! huge_global_alloc_ctl("threshold", "2m");
+//! huge_global_alloc_ctl_read("stats.segments", value, sizeof(value));
+//! ```
+
+use std::ffi::{c_char, c_int, CStr};
+use std::fmt::Write;
+
+use super::{exported, CBuffer};
+use crate::HugeGlobalAllocator;
+
+/// Writes the setting name with value. Returns 0 on success, or -1 if either string is null or
+/// not valid UTF-8, no allocator has been exported, or the key or value is invalid.
+///
+/// # Safety
+///
+/// name and value must be null or point to nul terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn huge_global_alloc_ctl(name: *const c_char, value: *const c_char) -> c_int {
+    let (Some(name), Some(value)) = (to_str(name), to_str(value)) else {
+        return -1;
+    };
+
+    match exported().map(|allocator| allocator.ctl(name, value)) {
+        Some(Ok(())) => 0,
+        _ => -1,
+    }
+}
+
+/// Reads the setting or statistic name in to buf as a nul terminated string, truncating it to
+/// fit len bytes. Returns the length of the full value excluding the terminator, so a return value
+/// of len or more indicates truncation, or -1 if name is null or not valid UTF-8, no allocator has
+/// been exported, or the key is invalid.
+///
+/// # Safety
+///
+/// name must be null or point to a nul terminated string, and buf must be null or valid for
+/// writes of len bytes
+#[no_mangle]
+pub unsafe extern "C" fn huge_global_alloc_ctl_read(name: *const c_char, buf: *mut c_char, len: usize) -> c_int {
+    let Some(name) = to_str(name) else {
+        return -1;
+    };
+
+    let value = match exported().map(|allocator| HugeGlobalAllocator::ctl_read(allocator, name)) {
+        Some(Ok(value)) => value,
+        _ => return -1,
+    };
+
+    let mut out = CBuffer::new(buf, len);
+    let _ = out.write_str(&value);
+
+    out.finish() as c_int
+}
+
+/// Converts a nul terminated C string to a str
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
@@ -0,0 +1,257 @@
+//! C malloc interface
+//!
+//! Exports malloc, calloc, realloc, free, posix_memalign, aligned_alloc, memalign and
+//! malloc_usable_size, routing large allocations through [MALLOC_ALLOCATOR] and everything else
+//! to the glibc allocator. Building the crate's shared library with the malloc-shim feature allows
+//! the allocator to be injected in to unmodified binaries:
+//!
+//! ```sh
+//! cargo build --release --features malloc-shim
+//! LD_PRELOAD=target/release/libhuge_global_alloc.so some_program
+//! ```
+//!
+//! Note that with this feature enabled any binary linking the crate has its malloc replaced.
+
+use std::alloc::Layout;
+use std::ffi::{c_int, c_void, CStr};
+use std::ptr::{copy_nonoverlapping, null_mut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::pagesize::base_page_size;
+use crate::{HugeGlobalAllocator, PASSTHROUGH};
+
+/// The allocator used by the C malloc interface. Can be used to configure the interface and read
+/// its statistics.
+pub static MALLOC_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+
+/// Alignment of blocks returned by malloc (alignof(max_align_t))
+const MALLOC_ALIGN: usize = 16;
+
+extern "C" {
+    fn __libc_malloc(size: usize) -> *mut c_void;
+    fn __libc_calloc(nmemb: usize, size: usize) -> *mut c_void;
+    fn __libc_realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
+    fn __libc_free(ptr: *mut c_void);
+    fn __libc_memalign(align: usize, size: usize) -> *mut c_void;
+}
+
+/// Allocates size bytes
+///
+/// # Safety
+///
+/// The returned block must be freed with free
+#[no_mangle]
+pub unsafe extern "C" fn malloc(size: usize) -> *mut c_void {
+    alloc(size, MALLOC_ALIGN, false)
+}
+
+/// Allocates a zeroed array of nmemb elements of size bytes
+///
+/// # Safety
+///
+/// The returned block must be freed with free
+#[no_mangle]
+pub unsafe extern "C" fn calloc(nmemb: usize, size: usize) -> *mut c_void {
+    match nmemb.checked_mul(size) {
+        Some(bytes) => alloc(bytes, MALLOC_ALIGN, true),
+        None => enomem(),
+    }
+}
+
+/// Resizes the block at ptr to size bytes
+///
+/// # Safety
+///
+/// ptr must be null or a block returned by this interface which has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+
+    if size == 0 {
+        free(ptr);
+        return null_mut();
+    }
+
+    let mapper = &MALLOC_ALLOCATOR.mapper;
+    let use_mapper = MALLOC_ALLOCATOR.use_mapper(layout(size, MALLOC_ALIGN));
+
+    match mapper.managed_size(ptr as *mut u8) {
+        Some(_) if use_mapper => {
+            // Old and new blocks are both mapped
+            let new_ptr = mapper.realloc(ptr as *mut u8, layout(size, MALLOC_ALIGN)) as *mut c_void;
+
+            if new_ptr.is_null() {
+                enomem()
+            } else {
+                new_ptr
+            }
+        }
+        Some(old_size) => {
+            // Old block is mapped, new block shouldn't be
+            move_block(ptr, old_size, __libc_malloc(size), size)
+        }
+        None if use_mapper => {
+            // Old block is from glibc, new block should be mapped
+            let old_size = malloc_usable_size(ptr);
+            let new_ptr = mapper.alloc(layout(size, MALLOC_ALIGN)) as *mut c_void;
+
+            move_block(ptr, old_size, new_ptr, size)
+        }
+        None => __libc_realloc(ptr, size),
+    }
+}
+
+/// Frees the block at ptr
+///
+/// # Safety
+///
+/// ptr must be null or a block returned by this interface which has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if !ptr.is_null() && (PASSTHROUGH || !MALLOC_ALLOCATOR.mapper.dealloc(ptr as *mut u8)) {
+        __libc_free(ptr);
+    }
+}
+
+/// Allocates size bytes aligned to align, storing the pointer in memptr
+///
+/// # Safety
+///
+/// memptr must be valid for writes. The returned block must be freed with free
+#[no_mangle]
+pub unsafe extern "C" fn posix_memalign(memptr: *mut *mut c_void, align: usize, size: usize) -> c_int {
+    if !align.is_power_of_two() || !align.is_multiple_of(size_of::<*mut c_void>()) {
+        return libc::EINVAL;
+    }
+
+    let ptr = alloc(size, align, false);
+
+    if ptr.is_null() {
+        libc::ENOMEM
+    } else {
+        *memptr = ptr;
+        0
+    }
+}
+
+/// Allocates size bytes aligned to align
+///
+/// # Safety
+///
+/// The returned block must be freed with free
+#[no_mangle]
+pub unsafe extern "C" fn aligned_alloc(align: usize, size: usize) -> *mut c_void {
+    if !align.is_power_of_two() {
+        *libc::__errno_location() = libc::EINVAL;
+        return null_mut();
+    }
+
+    alloc(size, align, false)
+}
+
+/// Allocates size bytes aligned to align
+///
+/// # Safety
+///
+/// The returned block must be freed with free
+#[no_mangle]
+pub unsafe extern "C" fn memalign(align: usize, size: usize) -> *mut c_void {
+    aligned_alloc(align, size)
+}
+
+/// Returns the usable size of the block at ptr
+///
+/// # Safety
+///
+/// ptr must be null or a block returned by this interface which has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+
+    match MALLOC_ALLOCATOR.mapper.managed_size(ptr as *mut u8) {
+        Some(size) => size,
+        None => match next_malloc_usable_size() {
+            Some(func) => func(ptr),
+            None => 0,
+        },
+    }
+}
+
+/// Allocates a block, mapping it if it is big enough. Mapped blocks are page aligned, so bigger
+/// alignments are left to glibc.
+unsafe fn alloc(size: usize, align: usize, zeroed: bool) -> *mut c_void {
+    let ptr = if MALLOC_ALLOCATOR.use_mapper(layout(size, align)) && align <= base_page_size() {
+        // Anonymous mem maps are zeroed already
+        MALLOC_ALLOCATOR.mapper.alloc(layout(size, align)) as *mut c_void
+    } else if zeroed {
+        __libc_calloc(1, size)
+    } else if align <= MALLOC_ALIGN {
+        __libc_malloc(size)
+    } else {
+        __libc_memalign(align, size)
+    };
+
+    if ptr.is_null() {
+        enomem()
+    } else {
+        ptr
+    }
+}
+
+/// Copies a block to a newly allocated one and frees the old block. Frees nothing if the new
+/// allocation failed.
+unsafe fn move_block(old_ptr: *mut c_void, old_size: usize, new_ptr: *mut c_void, new_size: usize) -> *mut c_void {
+    if new_ptr.is_null() {
+        return enomem();
+    }
+
+    copy_nonoverlapping(old_ptr as *const u8, new_ptr as *mut u8, old_size.min(new_size));
+
+    free(old_ptr);
+
+    new_ptr
+}
+
+/// Creates a layout, which can't fail for the sizes and alignments passed to the interface
+fn layout(size: usize, align: usize) -> Layout {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => HugeGlobalAllocator::alloc_error_layout("malloc: failed to create layout", Layout::new::<u8>()),
+    }
+}
+
+/// Sets errno to ENOMEM and returns null
+unsafe fn enomem() -> *mut c_void {
+    *libc::__errno_location() = libc::ENOMEM;
+    null_mut()
+}
+
+type MallocUsableSizeFn = unsafe extern "C" fn(*mut c_void) -> usize;
+
+/// Resolved address of the next malloc_usable_size (glibc's), or 1 if it couldn't be found
+static NEXT_MALLOC_USABLE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns glibc's malloc_usable_size, which glibc doesn't export under an internal name
+unsafe fn next_malloc_usable_size() -> Option<MallocUsableSizeFn> {
+    let mut func = NEXT_MALLOC_USABLE_SIZE.load(Ordering::Relaxed);
+
+    if func == 0 {
+        const NAME: &CStr = c"malloc_usable_size";
+
+        let sym = libc::dlsym(libc::RTLD_NEXT, NAME.as_ptr());
+
+        func = if sym.is_null() { 1 } else { sym as usize };
+
+        NEXT_MALLOC_USABLE_SIZE.store(func, Ordering::Relaxed);
+    }
+
+    if func == 1 {
+        None
+    } else {
+        Some(std::mem::transmute::<usize, MallocUsableSizeFn>(func))
+    }
+}
@@ -0,0 +1,109 @@
+//! C interface
+//!
+//! Every function callable from C lives in this module, and every type passed across the
+//! interface has a stable C layout. A C header declaring the interface can be generated with the
+//! c-header feature, which writes huge_global_alloc.h to the build script's output directory, and
+//! also to the path in the HUGE_GLOBAL_ALLOC_HEADER environment variable if set:
+//!
+//! ```sh
+//! HUGE_GLOBAL_ALLOC_HEADER=include/huge_global_alloc.h cargo build --release --features c-stats,c-header
+//! ```
+//!
+//! The header omits the malloc interface, which is declared by stdlib.h.
+//!
+//! | Module   | Feature     | Functions                                               |
+//! |----------|-------------|---------------------------------------------------------|
+//! | [malloc] | malloc-shim | malloc, calloc, realloc, free and friends               |
+//! | [stats]  | c-stats     | huge_global_alloc_stats, huge_global_alloc_stats_json   |
+//! | [ctl]    | c-stats     | huge_global_alloc_ctl, huge_global_alloc_ctl_read       |
+
+#[cfg(feature = "c-stats")]
+use std::ffi::c_char;
+#[cfg(feature = "c-stats")]
+use std::fmt;
+#[cfg(feature = "c-stats")]
+use std::ptr::{copy_nonoverlapping, null_mut};
+#[cfg(feature = "c-stats")]
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(feature = "c-stats")]
+use crate::HugeGlobalAllocator;
+
+#[cfg(feature = "c-stats")]
+pub mod ctl;
+#[cfg(all(feature = "malloc-shim", target_env = "gnu"))]
+pub mod malloc;
+#[cfg(feature = "c-stats")]
+pub mod stats;
+
+/// The allocator the C statistics and control functions operate on
+#[cfg(feature = "c-stats")]
+static EXPORTED: AtomicPtr<HugeGlobalAllocator> = AtomicPtr::new(null_mut());
+
+/// Sets the allocator the C statistics and control functions operate on
+#[cfg(feature = "c-stats")]
+pub(crate) fn export(allocator: &'static HugeGlobalAllocator) {
+    EXPORTED.store(allocator as *const HugeGlobalAllocator as *mut HugeGlobalAllocator, Ordering::Release);
+}
+
+/// Returns the allocator the C statistics and control functions operate on. This is the malloc
+/// interface's allocator if none has been exported and the malloc-shim feature is enabled.
+#[cfg(feature = "c-stats")]
+fn exported() -> Option<&'static HugeGlobalAllocator> {
+    let allocator = EXPORTED.load(Ordering::Acquire);
+
+    if allocator.is_null() {
+        #[cfg(all(feature = "malloc-shim", target_env = "gnu"))]
+        return Some(&malloc::MALLOC_ALLOCATOR);
+
+        #[cfg(not(all(feature = "malloc-shim", target_env = "gnu")))]
+        return None;
+    }
+
+    Some(unsafe { &*allocator })
+}
+
+/// Formats in to a caller provided C string buffer without allocating. Output is truncated to fit
+/// and nul terminated, and the full length is counted so callers can detect truncation as with
+/// snprintf.
+#[cfg(feature = "c-stats")]
+struct CBuffer {
+    /// Caller's buffer (may be null)
+    buf: *mut c_char,
+    /// Size of the caller's buffer in bytes, including the terminator
+    len: usize,
+    /// Length of the full output
+    written: usize,
+}
+
+#[cfg(feature = "c-stats")]
+impl CBuffer {
+    /// Creates a writer for buf, which must be null or valid for writes of len bytes
+    unsafe fn new(buf: *mut c_char, len: usize) -> Self {
+        Self { buf, len, written: 0 }
+    }
+
+    /// Nul terminates the buffer, returning the length of the full output
+    fn finish(self) -> usize {
+        if !self.buf.is_null() && self.len > 0 {
+            unsafe { self.buf.add(self.written.min(self.len - 1)).write(0) };
+        }
+
+        self.written
+    }
+}
+
+#[cfg(feature = "c-stats")]
+impl fmt::Write for CBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.buf.is_null() && self.written + 1 < self.len {
+            let copy = s.len().min(self.len - 1 - self.written);
+
+            unsafe { copy_nonoverlapping(s.as_ptr() as *const c_char, self.buf.add(self.written), copy) };
+        }
+
+        self.written += s.len();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! C statistics interface
+//!
+//! Exports huge_global_alloc_stats, which fills a C layout statistics structure for the allocator
+//! registered with [HugeGlobalAllocator::export_stats], so debuggers and sidecar agents can read
+//! allocator state from a live process without linking Rust. From gdb:
+//!
+//! ```text
+//! (gdb) set $stats = (HugeGlobalAllocatorCStats *) malloc(sizeof(HugeGlobalAllocatorCStats))
+//! (gdb) call huge_global_alloc_stats($stats)
+//! (gdb) print *$stats
+//! ```
+//!
+//! huge_global_alloc_stats_json writes the same statistics as a JSON object in to a caller
+//! provided buffer, so monitoring agents can poll allocator health without a JSON library:
+//!
+//! ```c
+//! char json[1024];
+//!
+//! if (huge_global_alloc_stats_json(json, sizeof(json)) < (int) sizeof(json))
+//!     puts(json);
+//! ```
+
+use std::ffi::{c_char, c_int};
+use std::fmt::{self, Write};
+
+use super::{exported, CBuffer};
+use crate::{HugeGlobalAllocator, HugeGlobalAllocatorStats};
+
+/// Allocator statistics with a C layout. See [HugeGlobalAllocatorStats] for descriptions of the
+/// fields. Durations are in nanoseconds.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HugeGlobalAllocatorCStats {
+    /// Total amount of memory allocated in bytes
+    pub alloc: u64,
+    /// Total amount of memory mapped in bytes
+    pub mapped: u64,
+    /// Total number of segments mapped
+    pub segments: u64,
+    /// Amount of memory allocated in default page size pages in bytes
+    pub default_alloc: u64,
+    /// Amount of memory mapped in default page size pages in bytes
+    pub default_mapped: u64,
+    /// Number of default page size segments mapped
+    pub default_segments: u64,
+    /// Amount of memory allocated in huge pages in bytes
+    pub huge_alloc: u64,
+    /// Amount of memory mapped in huge pages in bytes
+    pub huge_mapped: u64,
+    /// Number of huge page segments mapped
+    pub huge_segments: u64,
+    /// Number of allocations missed due to lack of huge pages
+    pub missed_allocs: u64,
+    /// Allocations missed due to lack of huge pages in total megabytes
+    pub missed_mb: f64,
+    /// Number of failed remaps
+    pub remaps_failed: u64,
+    /// Number of failed remaps which allocated a new segment and copied the contents
+    pub remaps_copied: u64,
+    /// Number of failed remaps which failed the reallocation, including reallocations of stable
+    /// address allocations which would have had to move
+    pub remaps_refused: u64,
+    /// Total bytes copied due to failed remaps
+    pub remap_copied_bytes: u64,
+    /// Number of huge page mapping attempts retried due to an exhausted huge page pool
+    pub huge_retries: u64,
+    /// Number of huge page segments which could not be pre-faulted
+    pub populate_failed: u64,
+    /// Total time spent pre-faulting huge page segments in nanoseconds
+    pub prefault_time_ns: u64,
+    /// Longest time spent pre-faulting a single huge page segment in nanoseconds
+    pub prefault_max_time_ns: u64,
+    /// Percentage of mapped memory used by allocations
+    pub efficiency: u64,
+    /// Number of segments which could not be unmapped
+    pub unmaps_failed: u64,
+    /// Total bytes leaked by segments which could not be unmapped
+    pub leaked_bytes: u64,
+    /// Number of allocations which exceeded the soft limit
+    pub soft_limit_exceeded: u64,
+    /// Mapped memory as a percentage of the soft limit (0 if no soft limit is set)
+    pub pressure: u64,
+    /// Segments mapped per second, averaged over the last minute
+    pub allocs_per_sec: f64,
+    /// Segments unmapped per second, averaged over the last minute
+    pub deallocs_per_sec: f64,
+    /// Segments resized per second, averaged over the last minute
+    pub remaps_per_sec: f64,
+    /// Number of default page size segments moved on to huge pages
+    pub promotions: u64,
+    /// Number of passes made by the maintenance worker
+    pub maintenance_passes: u64,
+    /// Number of default page size segments collapsed in to transparent huge pages
+    pub collapses: u64,
+    /// Number of default page size segments the kernel could not collapse
+    pub collapses_failed: u64,
+    /// Number of shrinking reallocations which kept the existing mapping
+    pub shrinks_deferred: u64,
+    /// Total bytes released by trimming segments kept mapped by deferred shrinks
+    pub trimmed_bytes: u64,
+    /// Number of allocations mapped with default size pages because huge page rounding would
+    /// have exceeded the maximum slack
+    pub exact_size_allocs: u64,
+    /// Total bytes copied with memcpy by reallocations which moved to a new segment
+    pub move_memcpy_bytes: u64,
+    /// Total bytes moved with mremap by reallocations which moved to a new segment
+    pub move_mremap_bytes: u64,
+    /// Total bytes moved with mremap and MREMAP_DONTUNMAP by reallocations which moved to a new
+    /// segment
+    pub move_dontunmap_bytes: u64,
+    /// Number of huge page segments demoted to default pages to make room for high priority
+    /// allocations
+    pub demotions: u64,
+    /// Number of huge page segments the demotion veto hook kept on huge pages
+    pub demotions_vetoed: u64,
+    /// Number of segments mapped at a random address in the placement window
+    pub randomized_placements: u64,
+    /// Number of segments placed by the kernel because the placement window had no free address
+    pub placement_fallbacks: u64,
+    /// Number of segments mapped with memory tagging
+    pub mte_segments: u64,
+    /// Number of memory tag check faults caught by the SIGSEGV handler
+    pub mte_tag_faults: u64,
+    /// Number of segments the security profile wiped on fork, excluded from core dumps and locked
+    /// in to memory
+    pub secure_segments: u64,
+    /// Number of segments the security profile could not fully secure, usually because locking them
+    /// would exceed RLIMIT_MEMLOCK
+    pub secure_failures: u64,
+    /// Number of new segments reused from the segment cache
+    pub segment_cache_hits: u64,
+    /// Number of new segments mapped because the segment cache held no segment of the size
+    pub segment_cache_misses: u64,
+    /// Number of times the allocator's locks were taken
+    pub lock_acquisitions: u64,
+    /// Number of lock acquisitions which had to wait for another thread
+    pub lock_contended: u64,
+    /// Total time spent waiting for the allocator's locks in nanoseconds
+    pub lock_wait_time_ns: u64,
+    /// Longest time spent waiting for one of the allocator's locks in nanoseconds
+    pub lock_max_wait_time_ns: u64,
+    /// Number of reallocations of the thread's most recently mapped or resized segment, which
+    /// skip the segment lookup
+    pub recent_segment_hits: u64,
+    /// Number of reallocations of memory mapped segments which had to be looked up
+    pub recent_segment_misses: u64,
+}
+
+impl From<&HugeGlobalAllocatorStats> for HugeGlobalAllocatorCStats {
+    fn from(stats: &HugeGlobalAllocatorStats) -> Self {
+        Self {
+            alloc: stats.alloc as u64,
+            mapped: stats.mapped as u64,
+            segments: stats.segments as u64,
+            default_alloc: stats.default_alloc as u64,
+            default_mapped: stats.default_mapped as u64,
+            default_segments: stats.default_segments as u64,
+            huge_alloc: stats.huge_alloc as u64,
+            huge_mapped: stats.huge_mapped as u64,
+            huge_segments: stats.huge_segments as u64,
+            missed_allocs: stats.missed_allocs as u64,
+            missed_mb: stats.missed_mb,
+            remaps_failed: stats.remaps_failed as u64,
+            remaps_copied: stats.remaps_copied as u64,
+            remaps_refused: stats.remaps_refused as u64,
+            remap_copied_bytes: stats.remap_copied_bytes as u64,
+            huge_retries: stats.huge_retries as u64,
+            populate_failed: stats.populate_failed as u64,
+            prefault_time_ns: stats.prefault_time.as_nanos() as u64,
+            prefault_max_time_ns: stats.prefault_max_time.as_nanos() as u64,
+            efficiency: stats.efficiency as u64,
+            unmaps_failed: stats.unmaps_failed as u64,
+            leaked_bytes: stats.leaked_bytes as u64,
+            soft_limit_exceeded: stats.soft_limit_exceeded as u64,
+            pressure: stats.pressure as u64,
+            allocs_per_sec: stats.allocs_per_sec,
+            deallocs_per_sec: stats.deallocs_per_sec,
+            remaps_per_sec: stats.remaps_per_sec,
+            promotions: stats.promotions as u64,
+            maintenance_passes: stats.maintenance_passes as u64,
+            collapses: stats.collapses as u64,
+            collapses_failed: stats.collapses_failed as u64,
+            shrinks_deferred: stats.shrinks_deferred as u64,
+            trimmed_bytes: stats.trimmed_bytes as u64,
+            exact_size_allocs: stats.exact_size_allocs as u64,
+            move_memcpy_bytes: stats.move_memcpy_bytes as u64,
+            move_mremap_bytes: stats.move_mremap_bytes as u64,
+            move_dontunmap_bytes: stats.move_dontunmap_bytes as u64,
+            demotions: stats.demotions as u64,
+            demotions_vetoed: stats.demotions_vetoed as u64,
+            randomized_placements: stats.randomized_placements as u64,
+            placement_fallbacks: stats.placement_fallbacks as u64,
+            mte_segments: stats.mte_segments as u64,
+            mte_tag_faults: stats.mte_tag_faults as u64,
+            secure_segments: stats.secure_segments as u64,
+            secure_failures: stats.secure_failures as u64,
+            segment_cache_hits: stats.segment_cache_hits as u64,
+            segment_cache_misses: stats.segment_cache_misses as u64,
+            lock_acquisitions: stats.lock_acquisitions as u64,
+            lock_contended: stats.lock_contended as u64,
+            lock_wait_time_ns: stats.lock_wait_time.as_nanos() as u64,
+            lock_max_wait_time_ns: stats.lock_max_wait_time.as_nanos() as u64,
+            recent_segment_hits: stats.recent_segment_hits as u64,
+            recent_segment_misses: stats.recent_segment_misses as u64,
+        }
+    }
+}
+
+impl HugeGlobalAllocatorCStats {
+    /// Writes the statistics as a single line JSON object
+    fn write_json(&self, w: &mut impl Write) -> fmt::Result {
+        macro_rules! fields {
+            ($first:ident, $($field:ident),*) => {
+                write!(w, "\"{}\":{}", stringify!($first), self.$first)?;
+                $(write!(w, ",\"{}\":{}", stringify!($field), self.$field)?;)*
+            };
+        }
+
+        w.write_char('{')?;
+
+        fields!(
+            alloc, mapped, segments,
+            default_alloc, default_mapped, default_segments,
+            huge_alloc, huge_mapped, huge_segments,
+            missed_allocs, missed_mb,
+            remaps_failed, remaps_copied, remaps_refused, remap_copied_bytes,
+            huge_retries, populate_failed, prefault_time_ns, prefault_max_time_ns,
+            efficiency, unmaps_failed, leaked_bytes, soft_limit_exceeded, pressure,
+            allocs_per_sec, deallocs_per_sec, remaps_per_sec,
+            promotions, maintenance_passes, collapses, collapses_failed,
+            shrinks_deferred, trimmed_bytes, exact_size_allocs,
+            move_memcpy_bytes, move_mremap_bytes, move_dontunmap_bytes,
+            demotions, demotions_vetoed,
+            randomized_placements, placement_fallbacks,
+            mte_segments, mte_tag_faults,
+            secure_segments, secure_failures,
+            segment_cache_hits, segment_cache_misses,
+            lock_acquisitions, lock_contended, lock_wait_time_ns, lock_max_wait_time_ns,
+            recent_segment_hits, recent_segment_misses
+        );
+
+        w.write_char('}')
+    }
+}
+
+/// Fills out with the statistics of the exported allocator. Returns 0 on success, or -1 if out is
+/// null, no allocator has been exported or the statistics could not be gathered.
+///
+/// # Safety
+///
+/// out must be null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn huge_global_alloc_stats(out: *mut HugeGlobalAllocatorCStats) -> c_int {
+    if out.is_null() {
+        return -1;
+    }
+
+    match exported().map(HugeGlobalAllocator::stats) {
+        Some(Ok(stats)) => {
+            out.write(HugeGlobalAllocatorCStats::from(&stats));
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Writes the statistics of the exported allocator in to buf as a nul terminated JSON object,
+/// truncating it to fit len bytes. No memory is allocated. Returns the length of the full JSON
+/// excluding the terminator, so a return value of len or more indicates truncation, or -1 if no
+/// allocator has been exported or the statistics could not be gathered.
+///
+/// # Safety
+///
+/// buf must be null or valid for writes of len bytes
+#[no_mangle]
+pub unsafe extern "C" fn huge_global_alloc_stats_json(buf: *mut c_char, len: usize) -> c_int {
+    match exported().map(HugeGlobalAllocator::stats) {
+        Some(Ok(stats)) => {
+            let mut out = CBuffer::new(buf, len);
+            let _ = HugeGlobalAllocatorCStats::from(&stats).write_json(&mut out);
+
+            out.finish() as c_int
+        }
+        _ => -1,
+    }
+}
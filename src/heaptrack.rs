@@ -0,0 +1,149 @@
+//! heaptrack trace output
+//!
+//! Converts the events drained from the [event ring](crate::ring) in to heaptrack's interpreted
+//! data format, so the behaviour of the memory mapped segments can be explored with heaptrack_gui
+//! or summarised with heaptrack_print. Allocations passed to the inner allocator are not traced.
+//! There are no backtraces: each segment is attributed to a pseudo function naming the page size
+//! backing it. Segments allocated before the writer was created are not traced.
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::io::BufWriter;
+//! use std::time::Duration;
+//! use huge_global_alloc::heaptrack::HeaptrackWriter;
+//! use huge_global_alloc::HugeGlobalAllocator;
+//!
+//! #[global_allocator]
+//! static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+//!
+//! GLOBAL_ALLOCATOR.event_ring().set_enabled(true);
+//!
+//! std::thread::spawn(|| {
+//!     let file = BufWriter::new(File::create("heaptrack.huge_global_alloc").unwrap());
+//!     let mut writer = HeaptrackWriter::new(file).unwrap();
+//!
+//!     loop {
+//!         writer.drain(GLOBAL_ALLOCATOR.event_ring()).unwrap();
+//!         std::thread::sleep(Duration::from_millis(10));
+//!     }
+//! });
+//! ````
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::{
+    events::{Backing, Operation},
+    ring::{now_ns, EventRing, RingEvent},
+};
+
+/// heaptrack version reported in the header (1.2.0)
+const HEAPTRACK_VERSION: u32 = 0x010200;
+
+/// heaptrack file format version written
+const FILE_FORMAT_VERSION: u32 = 2;
+
+/// Writes heaptrack's interpreted data format
+pub struct HeaptrackWriter<W: Write> {
+    out: W,
+    /// Time the trace started in CLOCK_MONOTONIC nanoseconds
+    start_ns: u64,
+    /// Last timestamp written in milliseconds
+    last_ms: u64,
+    /// Allocation info indices by size and backing
+    infos: HashMap<(usize, Backing), usize>,
+    /// Allocation info indices of the live segments by address
+    live: HashMap<usize, usize>,
+}
+
+impl<W: Write> HeaptrackWriter<W> {
+    /// Creates a writer, writing the trace header. Events are timed from now.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let cmdline: Vec<String> = std::env::args().collect();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0);
+        let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) }.max(0);
+
+        writeln!(out, "v {:x} {:x}", HEAPTRACK_VERSION, FILE_FORMAT_VERSION)?;
+        writeln!(out, "X {}", cmdline.join(" "))?;
+        writeln!(out, "I {:x} {:x}", page_size, pages)?;
+
+        // Strings (from 1): the module and a pseudo function for each backing
+        writeln!(out, "s huge_global_alloc")?;
+        writeln!(out, "s huge page segment")?;
+        writeln!(out, "s default page segment")?;
+
+        // Instruction pointers (from 1): address, module string and function string
+        writeln!(out, "i 1 1 2")?;
+        writeln!(out, "i 2 1 3")?;
+
+        // Traces (from 1): instruction pointer and parent trace
+        writeln!(out, "t 1 0")?;
+        writeln!(out, "t 2 0")?;
+
+        Ok(Self {
+            out,
+            start_ns: now_ns(),
+            last_ms: 0,
+            infos: HashMap::new(),
+            live: HashMap::new(),
+        })
+    }
+
+    /// Writes an event taken from the event ring
+    pub fn write_event(&mut self, event: &RingEvent) -> io::Result<()> {
+        let ms = event.timestamp_ns.saturating_sub(self.start_ns) / 1_000_000;
+
+        if ms > self.last_ms {
+            writeln!(self.out, "c {:x}", ms)?;
+            self.last_ms = ms;
+        }
+
+        // Reallocations are traced as a free followed by an allocation
+        if matches!(event.operation, Operation::Dealloc | Operation::Realloc) {
+            if let Some(info) = self.live.remove(&event.old_address) {
+                writeln!(self.out, "- {:x}", info)?;
+            }
+        }
+
+        if matches!(event.operation, Operation::Alloc | Operation::Realloc) {
+            let info = self.info(event.size, event.backing)?;
+            self.live.insert(event.address, info);
+            writeln!(self.out, "+ {:x}", info)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every event currently in the ring
+    pub fn drain(&mut self, ring: &EventRing) -> io::Result<()> {
+        while let Some(event) = ring.pop() {
+            self.write_event(&event)?;
+        }
+
+        self.out.flush()
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Returns the allocation info index for a size and backing, writing it if it is new
+    fn info(&mut self, size: usize, backing: Backing) -> io::Result<usize> {
+        if let Some(&info) = self.infos.get(&(size, backing)) {
+            return Ok(info);
+        }
+
+        let trace = match backing {
+            Backing::Huge => 1,
+            Backing::Default => 2,
+        };
+
+        writeln!(self.out, "a {:x} {:x}", size, trace)?;
+
+        let info = self.infos.len();
+        self.infos.insert((size, backing), info);
+
+        Ok(info)
+    }
+}
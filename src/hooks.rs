@@ -0,0 +1,56 @@
+//! Segment lifecycle hooks
+//!
+//! Buffer managers which track their memory in their own eviction or telemetry systems can
+//! register hooks which are called as the allocator maps, resizes and frees segments. The hooks
+//! run on the allocating thread with no allocator locks held, so they may allocate and query the
+//! allocator.
+
+use crate::mmap::MMap;
+
+/// Metadata of a segment passed to the lifecycle hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// Identifier of the allocation, unique for the life of the process and kept when a
+    /// reallocation moves the segment
+    pub id: u64,
+    /// Address of the segment
+    pub ptr: usize,
+    /// Requested size in bytes
+    pub size: usize,
+    /// Mapped size in bytes
+    pub alloc_size: usize,
+    /// Page size backing the segment
+    pub page_size: usize,
+    /// True if backed by huge pages
+    pub huge: bool,
+    /// Tag attached by the application
+    pub tag: Option<&'static str>,
+}
+
+impl SegmentInfo {
+    /// Returns the metadata of a segment
+    pub(crate) fn of(mmap: &MMap) -> Self {
+        Self {
+            id: mmap.id(),
+            ptr: mmap.ptr(),
+            size: mmap.size(),
+            alloc_size: mmap.alloc_size(),
+            page_size: mmap.page_size(),
+            huge: !mmap.is_default_page_size(),
+            tag: mmap.tag(),
+        }
+    }
+}
+
+/// Callbacks invoked as segments are created, resized and freed, registered with
+/// [HugeGlobalAllocator::set_segment_hooks](crate::HugeGlobalAllocator::set_segment_hooks)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SegmentHooks {
+    /// Called after a new segment has been mapped, before the allocation returns
+    pub on_map: Option<fn(segment: &SegmentInfo)>,
+    /// Called when a segment is freed, before its pages are unmapped
+    pub on_unmap: Option<fn(segment: &SegmentInfo)>,
+    /// Called after a reallocation has resized a segment in place or moved it, with the segment
+    /// before and after
+    pub on_remap: Option<fn(old: &SegmentInfo, new: &SegmentInfo)>,
+}
@@ -0,0 +1,92 @@
+//! Debug HTTP endpoint
+//!
+//! A minimal HTTP/1.0 server listening on the loopback interface only, for inspecting a running
+//! process with curl. Requests are served one at a time on a background thread:
+//!
+//! | Path      | Response                                                              |
+//! |-----------|-----------------------------------------------------------------------|
+//! | /stats    | The statistics as a JSON object, keyed by the stats.* ctl key names   |
+//! | /segments | The live memory mapped segments, as returned by dump_segments()       |
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::{
+    ctl::{stat, STAT_NAMES},
+    HugeGlobalAllocatorStats,
+};
+
+/// Time allowed for a client to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest request accepted, including headers
+const MAX_REQUEST: u64 = 8192;
+
+/// Binds a listener on the loopback interface and serves requests on a background thread. A port
+/// of 0 picks a free port. Returns the bound address.
+pub(crate) fn spawn<S, D>(port: u16, stats: S, segments: D) -> Result<SocketAddr, Box<dyn Error>>
+where
+    S: Fn() -> HugeGlobalAllocatorStats + Send + 'static,
+    D: Fn() -> String + Send + 'static,
+{
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let addr = listener.local_addr()?;
+
+    thread::Builder::new().name("huge_alloc_http".to_string()).spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // Errors only affect the one client
+            let _ = handle(stream, &stats, &segments);
+        }
+    })?;
+
+    Ok(addr)
+}
+
+/// Reads a request and writes the response
+fn handle(
+    mut stream: TcpStream,
+    stats: &impl Fn() -> HugeGlobalAllocatorStats,
+    segments: &impl Fn() -> String,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    // Consume the headers so closing the connection doesn't reset it with unread data
+    let mut header = String::new();
+
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = line.split_whitespace();
+
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/stats")) => ("200 OK", "application/json", json(&stats())),
+        (Some("GET"), Some("/segments")) => ("200 OK", "text/plain", segments()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.0 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Formats the statistics as a single line JSON object
+fn json(stats: &HugeGlobalAllocatorStats) -> String {
+    let fields: Vec<String> = STAT_NAMES
+        .iter()
+        .map(|name| format!("\"{name}\":{}", stat(stats, name).unwrap_or_default()))
+        .collect();
+
+    format!("{{{}}}\n", fields.join(","))
+}
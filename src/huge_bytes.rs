@@ -0,0 +1,210 @@
+//! Growable byte buffers in memory mapped segments for the bytes crate
+
+use std::alloc::Layout;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{copy, copy_nonoverlapping, NonNull};
+use std::slice;
+
+use bytes::buf::UninitSlice;
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::{DefaultPagePolicy, HugeGlobalAllocator};
+
+/// Smallest segment a buffer is created or grown with
+const MIN_CAPACITY: usize = 4096;
+
+/// A growable byte buffer in a memory mapped segment, implementing the bytes crate's [BufMut]
+/// and [Buf] traits so huge page backed buffers can be used with existing codec stacks. Bytes are
+/// written at the end with BufMut and consumed from the front with Buf. Returned by
+/// [HugeGlobalAllocator::bytes_mut](crate::HugeGlobalAllocator::bytes_mut). Growing remaps the
+/// segment, and the segment is freed when the buffer is dropped.
+pub struct HugeBytesMut<'a, A = std::alloc::System, P = DefaultPagePolicy> {
+    /// The allocator owning the segment
+    allocator: &'a HugeGlobalAllocator<A, P>,
+    /// Start of the segment
+    ptr: NonNull<u8>,
+    /// Size of the segment in bytes
+    cap: usize,
+    /// Offset of the first unconsumed byte
+    start: usize,
+    /// Offset of the end of the written bytes
+    end: usize,
+}
+
+// The buffer owns its segment
+unsafe impl<A: Sync, P: Sync> Send for HugeBytesMut<'_, A, P> {}
+unsafe impl<A: Sync, P: Sync> Sync for HugeBytesMut<'_, A, P> {}
+
+impl<'a, A, P> HugeBytesMut<'a, A, P> {
+    /// Maps a segment for a buffer with room for capacity bytes
+    #[track_caller]
+    pub(crate) fn with_capacity(allocator: &'a HugeGlobalAllocator<A, P>, capacity: usize) -> Option<Self> {
+        let cap = capacity.max(MIN_CAPACITY);
+        let block = allocator.alloc_raw(Layout::from_size_align(cap, 1).ok()?)?;
+
+        Some(Self {
+            allocator,
+            ptr: block.cast(),
+            cap,
+            start: 0,
+            end: 0,
+        })
+    }
+
+    /// Returns the number of unconsumed bytes
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns true if there are no unconsumed bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes the buffer can hold without growing
+    pub fn capacity(&self) -> usize {
+        self.cap - self.start
+    }
+
+    /// Discards the unconsumed bytes, keeping the segment
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.end = 0;
+    }
+
+    /// Makes room for at least additional more bytes to be written. Consumed bytes are reclaimed
+    /// first by moving the unconsumed bytes to the front, and the segment is grown, at least
+    /// doubling, if that isn't enough.
+    pub fn reserve(&mut self, additional: usize) {
+        if self.cap - self.end >= additional {
+            return;
+        }
+
+        let len = self.len();
+
+        if self.start > 0 {
+            unsafe { copy(self.ptr.as_ptr().add(self.start), self.ptr.as_ptr(), len) };
+
+            self.start = 0;
+            self.end = len;
+
+            if self.cap - len >= additional {
+                return;
+            }
+        }
+
+        let cap = len.checked_add(additional).expect("capacity overflow").max(self.cap * 2);
+        let layout = Layout::from_size_align(cap, 1).expect("capacity overflow");
+        let block = NonNull::slice_from_raw_parts(self.ptr, self.cap);
+
+        match unsafe { self.allocator.realloc_raw(block, layout) } {
+            Some(block) => {
+                self.ptr = block.cast();
+                self.cap = cap;
+            }
+            None => HugeGlobalAllocator::alloc_error_layout("HugeBytesMut::reserve: failed to grow segment", layout),
+        }
+    }
+
+    /// Returns true if the buffer is backed by huge pages
+    pub fn is_huge(&self) -> bool {
+        self.allocator.is_huge(self.ptr.as_ptr())
+    }
+}
+
+impl<A: Send + Sync + 'static, P: Send + Sync + 'static> HugeBytesMut<'static, A, P> {
+    /// Converts the unconsumed bytes in to an immutable [Bytes] which can be cloned and sliced
+    /// without copying. The segment is freed when the last reference is dropped.
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl<A, P> Deref for HugeBytesMut<'_, A, P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr().add(self.start), self.len()) }
+    }
+}
+
+impl<A, P> DerefMut for HugeBytesMut<'_, A, P> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr().add(self.start), self.len()) }
+    }
+}
+
+impl<A, P> AsRef<[u8]> for HugeBytesMut<'_, A, P> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<A, P> fmt::Debug for HugeBytesMut<'_, A, P> {
+    /// Formats the address and lengths rather than the contents
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HugeBytesMut")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<A, P> Buf for HugeBytesMut<'_, A, P> {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.len(), "cannot advance past the end of the buffer");
+
+        self.start += cnt;
+
+        // Start writing from the front again once everything has been consumed
+        if self.start == self.end {
+            self.clear();
+        }
+    }
+}
+
+unsafe impl<A, P> BufMut for HugeBytesMut<'_, A, P> {
+    fn remaining_mut(&self) -> usize {
+        isize::MAX as usize - self.end
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        assert!(cnt <= self.cap - self.end, "cannot advance past the end of the segment");
+
+        self.end += cnt;
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        if self.end == self.cap {
+            self.reserve(64);
+        }
+
+        unsafe { UninitSlice::from_raw_parts_mut(self.ptr.as_ptr().add(self.end), self.cap - self.end) }
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.reserve(src.len());
+
+        unsafe {
+            copy_nonoverlapping(src.as_ptr(), self.ptr.as_ptr().add(self.end), src.len());
+            self.advance_mut(src.len());
+        }
+    }
+}
+
+impl<A, P> Drop for HugeBytesMut<'_, A, P> {
+    /// Frees the segment on drop
+    fn drop(&mut self) {
+        unsafe { self.allocator.dealloc_raw(NonNull::slice_from_raw_parts(self.ptr, self.cap)) };
+    }
+}
@@ -0,0 +1,92 @@
+//! Huge page pool information read from sysfs
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const SYSFS_HUGEPAGES: &str = "/sys/kernel/mm/hugepages";
+
+/// Huge page pool information for a single huge page size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HugePageInfo {
+    /// Huge page size in bytes
+    pub page_size: usize,
+    /// Total number of huge pages in the pool
+    pub total: usize,
+    /// Number of free huge pages in the pool
+    pub free: usize,
+}
+
+impl HugePageInfo {
+    /// Returns the number of free bytes in the pool
+    pub fn free_bytes(&self) -> usize {
+        self.free * self.page_size
+    }
+
+    /// Returns the total number of bytes in the pool
+    pub fn total_bytes(&self) -> usize {
+        self.total * self.page_size
+    }
+}
+
+/// Returns the huge page pools available on the system, ordered by page size. Tests can use this
+/// to make assertions conditional on the number of huge pages actually available.
+///
+/// ```rust
+/// use huge_global_alloc::huge_pages;
+///
+/// for info in huge_pages().unwrap() {
+///     assert!(info.free <= info.total);
+///     println!("{}kb pages: {} free of {}", info.page_size / 1024, info.free, info.total);
+/// }
+/// ````
+pub fn huge_pages() -> Result<Vec<HugePageInfo>, Box<dyn Error>> {
+    let mut pools = Vec::new();
+
+    for entry in fs::read_dir(SYSFS_HUGEPAGES)? {
+        let entry = entry?;
+
+        if let Some(page_size) = parse_dir_name(&entry.file_name().to_string_lossy()) {
+            pools.push(read_pool(&entry.path(), page_size)?);
+        }
+    }
+
+    pools.sort_by_key(|info| info.page_size);
+
+    Ok(pools)
+}
+
+/// Returns the huge page pool information for the given page size in bytes
+///
+/// ```rust
+/// use huge_global_alloc::huge_page_info;
+///
+/// let info = huge_page_info(2 * 1024 * 1024).unwrap(); // 2mb
+/// assert_eq!(info.page_size, 2 * 1024 * 1024);
+/// ````
+pub fn huge_page_info(page_size: usize) -> Result<HugePageInfo, Box<dyn Error>> {
+    let path = Path::new(SYSFS_HUGEPAGES).join(format!("hugepages-{}kB", page_size / 1024));
+
+    read_pool(&path, page_size)
+}
+
+/// Parses the page size in bytes from a sysfs directory name (eg. hugepages-2048kB)
+fn parse_dir_name(name: &str) -> Option<usize> {
+    let kb = name.strip_prefix("hugepages-")?.strip_suffix("kB")?;
+
+    kb.parse::<usize>().ok().map(|kb| kb * 1024)
+}
+
+/// Reads the pool information from a sysfs huge page size directory
+fn read_pool(path: &Path, page_size: usize) -> Result<HugePageInfo, Box<dyn Error>> {
+    Ok(HugePageInfo {
+        page_size,
+        total: read_count(&path.join("nr_hugepages"))?,
+        free: read_count(&path.join("free_hugepages"))?,
+    })
+}
+
+/// Reads a page count from a sysfs file
+fn read_count(path: &Path) -> Result<usize, Box<dyn Error>> {
+    Ok(fs::read_to_string(path)?.trim().parse::<usize>()?)
+}
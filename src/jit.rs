@@ -0,0 +1,234 @@
+//! Dual mapped executable segments for JIT compilers
+//!
+//! A [JitSegment] maps the same memfd twice: a read write view the compiler emits code through,
+//! and a read execute view the code runs from. No page is ever writable and executable at once,
+//! so W^X is kept without changing protections between compiling and running, and the code cache
+//! is backed by huge pages when they are available, cutting instruction TLB misses for large
+//! caches. The two views are at unrelated addresses, so code must be position independent or
+//! linked against [JitSegment::as_exec_ptr].
+//!
+//! ```rust
+//! use std::alloc::Layout;
+//! use huge_global_alloc::jit::JitSegment;
+//!
+//! let layout = Layout::from_size_align(4 * 1024 * 1024, 64).unwrap();
+//! let segment = JitSegment::new(layout).unwrap();
+//!
+//! # #[cfg(target_arch = "x86_64")]
+//! # {
+//! // mov eax, 42; ret
+//! let code = [0xb8, 0x2a, 0x00, 0x00, 0x00, 0xc3];
+//!
+//! unsafe { segment.as_mut_ptr().copy_from_nonoverlapping(code.as_ptr(), code.len()) };
+//! segment.sync_icache(0, code.len());
+//!
+//! let func: extern "C" fn() -> i32 = unsafe { std::mem::transmute(segment.as_exec_ptr()) };
+//! assert_eq!(func(), 42);
+//! # }
+//! ````
+
+use std::alloc::Layout;
+use std::error::Error;
+use std::ffi::{c_uint, c_void};
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::ptr::null_mut;
+
+use nix::{
+    errno::Errno,
+    sys::mman::{self, MapFlags, ProtFlags},
+};
+
+use crate::{
+    pagesize::{base_page_size, huge_page_size, huge_size_flags},
+    raw::round_to_pages,
+};
+
+/// memfd_create flag asking for an executable memory file, required when the vm.memfd_noexec
+/// sysctl defaults memfds to non-executable (Linux 6.3 or later). Not defined by libc for every
+/// target.
+const MFD_EXEC: c_uint = 0x10;
+
+/// A memfd mapped once read write and once read execute
+#[derive(Debug)]
+pub struct JitSegment {
+    /// The memory file backing both views
+    fd: OwnedFd,
+    /// Raw pointer to the read write view
+    rw_ptr: usize,
+    /// Raw pointer to the read execute view
+    rx_ptr: usize,
+    /// Requested layout
+    layout: Layout,
+    /// Mapped size of each view (whole pages)
+    alloc_size: usize,
+    /// Page size
+    page_size: usize,
+}
+
+impl JitSegment {
+    /// Creates a new executable segment for the given layout, backed by huge pages if possible
+    pub fn new(layout: Layout) -> Result<JitSegment, Box<dyn Error>> {
+        let huge_page_size = huge_page_size();
+
+        if layout.size() == 0 || layout.align() > huge_page_size {
+            Err(format!("unsupported jit segment layout {layout:?}"))?
+        }
+
+        let huge_flags = libc::MFD_HUGETLB | huge_size_flags(huge_page_size) as c_uint;
+
+        match Self::create(layout, huge_page_size, huge_flags) {
+            Ok(segment) => Ok(segment),
+            Err(_) => Self::create(layout, base_page_size(), 0),
+        }
+    }
+
+    /// Returns the raw pointer to the read write view, for emitting code
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.rw_ptr as *mut u8
+    }
+
+    /// Returns the raw pointer to the read execute view, for running code
+    pub fn as_exec_ptr(&self) -> *const u8 {
+        self.rx_ptr as *const u8
+    }
+
+    /// Translates a pointer in to the read write view to the same byte in the read execute view
+    pub fn exec_ptr_for(&self, ptr: *const u8) -> Option<*const u8> {
+        let offset = (ptr as usize).checked_sub(self.rw_ptr).filter(|&offset| offset < self.alloc_size)?;
+
+        Some((self.rx_ptr + offset) as *const u8)
+    }
+
+    /// Makes code written through the read write view from offset for len bytes visible to
+    /// instruction fetches from the read execute view. Required on aarch64, where the instruction
+    /// cache isn't coherent with data writes; x86_64 needs nothing.
+    pub fn sync_icache(&self, offset: usize, len: usize) {
+        let len = len.min(self.alloc_size.saturating_sub(offset));
+
+        if len > 0 {
+            sync_icache(self.rw_ptr + offset, self.rx_ptr + offset, len);
+        }
+    }
+
+    /// Returns the requested layout of the segment
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the requested size of the segment
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns the total mapped size of each view
+    pub fn alloc_size(&self) -> usize {
+        self.alloc_size
+    }
+
+    /// Returns the page size backing the segment
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns true if the segment is backed by huge pages
+    pub fn is_huge(&self) -> bool {
+        self.page_size != base_page_size()
+    }
+
+    /// Creates a memfd with the given flags, sizes it and maps both views
+    fn create(layout: Layout, page_size: usize, flags: c_uint) -> Result<JitSegment, Box<dyn Error>> {
+        let alloc_size = round_to_pages(layout.size(), page_size);
+
+        let mut fd = unsafe { libc::memfd_create(c"huge_global_alloc.jit".as_ptr(), libc::MFD_CLOEXEC | MFD_EXEC | flags) };
+
+        if fd < 0 && Errno::last() == Errno::EINVAL {
+            // Kernels before 6.3 don't know MFD_EXEC, and their memfds are executable
+            fd = unsafe { libc::memfd_create(c"huge_global_alloc.jit".as_ptr(), libc::MFD_CLOEXEC | flags) };
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(Errno::result(fd)?) };
+
+        File::from(fd.try_clone()?).set_len(alloc_size as u64)?;
+
+        let rw_ptr = map_view(&fd, alloc_size, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)?;
+
+        let rx_ptr = match map_view(&fd, alloc_size, ProtFlags::PROT_READ | ProtFlags::PROT_EXEC) {
+            Ok(rx_ptr) => rx_ptr,
+            Err(e) => {
+                let _ = unsafe { mman::munmap(rw_ptr as *mut c_void, alloc_size) };
+                Err(e)?
+            }
+        };
+
+        Ok(JitSegment {
+            fd,
+            rw_ptr,
+            rx_ptr,
+            layout,
+            alloc_size,
+            page_size,
+        })
+    }
+}
+
+impl AsFd for JitSegment {
+    /// Borrows the memfd backing the segment, for example to map it in to a profiler
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl Drop for JitSegment {
+    /// Unmaps both views on drop, closing the memfd
+    fn drop(&mut self) {
+        let _ = unsafe { mman::munmap(self.rw_ptr as *mut c_void, self.alloc_size) };
+        let _ = unsafe { mman::munmap(self.rx_ptr as *mut c_void, self.alloc_size) };
+    }
+}
+
+/// Maps a view of a sized memory file with the given protection
+fn map_view(fd: &OwnedFd, alloc_size: usize, prot: ProtFlags) -> nix::Result<usize> {
+    let ptr = unsafe { mman::mmap(null_mut::<c_void>(), alloc_size, prot, MapFlags::MAP_SHARED, fd.as_raw_fd(), 0) }?;
+
+    Ok(ptr as usize)
+}
+
+/// Cleans the data cache lines written through the read write view to the point of unification,
+/// then invalidates the instruction cache lines of the read execute view
+#[cfg(target_arch = "aarch64")]
+fn sync_icache(rw: usize, rx: usize, len: usize) {
+    use std::arch::asm;
+
+    let ctr: u64;
+
+    unsafe { asm!("mrs {ctr}, ctr_el0", ctr = out(reg) ctr, options(nomem, nostack)) };
+
+    // Smallest line sizes in bytes, from log2 of the number of words
+    let dline = 4usize << ((ctr >> 16) & 0xf);
+    let iline = 4usize << (ctr & 0xf);
+
+    unsafe {
+        let mut line = rw & !(dline - 1);
+
+        while line < rw + len {
+            asm!("dc cvau, {line}", line = in(reg) line, options(nostack));
+            line += dline;
+        }
+
+        asm!("dsb ish", options(nostack));
+
+        let mut line = rx & !(iline - 1);
+
+        while line < rx + len {
+            asm!("ic ivau, {line}", line = in(reg) line, options(nostack));
+            line += iline;
+        }
+
+        asm!("dsb ish", "isb", options(nostack));
+    }
+}
+
+/// The instruction cache is coherent with data writes
+#[cfg(not(target_arch = "aarch64"))]
+fn sync_icache(_rw: usize, _rx: usize, _len: usize) {}
@@ -0,0 +1,101 @@
+//! The allocation paths shared by the allocator types. Each allocation is routed either to the
+//! mapper or to the inner allocator, and each pointer is only ever freed by the layer which
+//! allocated it.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr::copy_nonoverlapping;
+
+use crate::{mmapper::MMapper, HugeGlobalAllocator, PagePolicy, PASSTHROUGH};
+
+/// The layers of an allocator
+pub(crate) struct Layers<'a, A, P, F> {
+    /// The memory mapper serving big allocations
+    pub mapper: &'a MMapper,
+    /// The inner allocator serving everything else
+    pub inner: &'a A,
+    /// Chooses the page size of mapped allocations
+    pub policy: &'a P,
+    /// Returns true if an allocation with the given layout should be mapped
+    pub use_mapper: F,
+}
+
+impl<A: GlobalAlloc, P: PagePolicy, F: Fn(Layout) -> bool> Layers<'_, A, P, F> {
+    pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if (self.use_mapper)(layout) {
+            // Allocate the segment
+            self.mapper.alloc_with(layout, self.policy.page_size(layout))
+        } else {
+            // Revert to inner alloc
+            self.inner.alloc(layout)
+        }
+    }
+
+    pub unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if PASSTHROUGH || !self.mapper.dealloc(ptr) {
+            // Revert to inner dealloc
+            self.inner.dealloc(ptr, layout)
+        }
+    }
+
+    pub unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if (self.use_mapper)(layout) {
+            // Anonymous mem maps are zeroed already
+            self.mapper.alloc_with(layout, self.policy.page_size(layout))
+        } else {
+            // Revert to inner alloc
+            self.inner.alloc_zeroed(layout)
+        }
+    }
+
+    pub unsafe fn realloc(&self, old_ptr: *mut u8, old_layout: Layout, new_size: usize) -> *mut u8 {
+        // Create new layout
+        let new_layout = match Layout::from_size_align(new_size, old_layout.align()) {
+            Ok(layout) => layout,
+            Err(_) => HugeGlobalAllocator::alloc_error_layout("HugeGlobalAllocator::realloc: Failed to create layout", old_layout)
+        };
+
+        if !PASSTHROUGH && self.mapper.is_managed_realloc(old_ptr) {
+            // Old ptr is managed
+            if (self.use_mapper)(new_layout) || self.mapper.is_stable_ptr(old_ptr) {
+                // Old ptr is managed and new ptr should be too, or can't move
+                self.mapper.realloc(old_ptr, new_layout)
+            } else {
+                // Old ptr is managed but new ptr shouldn't be
+
+                // Allocate new segment using the inner allocator
+                let new_ptr = self.inner.alloc(new_layout);
+
+                if !new_ptr.is_null() {
+                    // Copy data from old segment to new, which may be larger or smaller
+                    copy_nonoverlapping(old_ptr, new_ptr, old_layout.size().min(new_size));
+
+                    // Free the old segment
+                    self.mapper.dealloc(old_ptr);
+                }
+
+                new_ptr
+            }
+        } else {
+            // Old ptr is not managed
+            if (self.use_mapper)(new_layout) {
+                // Old ptr is not managed but new ptr should be
+
+                // Allocate new segment
+                let new_ptr = self.mapper.alloc_with(new_layout, self.policy.page_size(new_layout));
+
+                if !new_ptr.is_null() {
+                    // Copy data from old segment to new, which may be larger or smaller
+                    copy_nonoverlapping(old_ptr, new_ptr, old_layout.size().min(new_size));
+
+                    // Free the old segment
+                    self.inner.dealloc(old_ptr, old_layout);
+                }
+
+                new_ptr
+            } else {
+                // Old ptr is not managed and new ptr shouldn't be - revert to inner realloc
+                self.inner.realloc(old_ptr, old_layout, new_size)
+            }
+        }
+    }
+}
@@ -2,16 +2,198 @@
 
 //! A global memory allocator which tries to use huge pages for big allocations
 
+mod arena;
+mod budget;
+mod buffer;
+mod cache;
+mod callsite;
+mod capacity;
+mod checkpoint;
+#[cfg(feature = "canary")]
+mod canary;
+mod const_threshold;
+mod counters;
+mod ctl;
+mod csv;
+mod diagnose;
+#[cfg(feature = "shared-segments")]
+pub mod fdpass;
+#[cfg(any(feature = "c-stats", all(feature = "malloc-shim", target_env = "gnu")))]
+pub mod ffi;
+#[cfg(feature = "debug-http")]
+mod http;
+mod hooks;
+#[cfg(feature = "bytes")]
+mod huge_bytes;
+mod hugepages;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod layers;
+mod local;
+mod maintenance;
 mod mmap;
 mod mmapper;
+mod mte;
+mod numa;
+#[cfg(feature = "otel")]
+mod otel;
+mod overcommit;
+mod pagesize;
+mod pkey;
+mod policy;
+mod probe;
+#[cfg(any(feature = "asan", feature = "valgrind"))]
+mod sanitizer;
+#[cfg(all(feature = "preload", target_env = "gnu"))]
+pub mod preload;
+pub mod raw;
+mod recent;
+mod registry;
+mod report;
+mod segmap;
+#[cfg(feature = "shared-segments")]
+pub mod shared;
+mod snapshot;
+mod sync;
+mod sys;
+mod thp;
+#[cfg(feature = "tracy")]
+mod tracy;
+#[cfg(any(debug_assertions, feature = "verify"))]
+mod verify;
+
+#[cfg(feature = "event-log")]
+pub mod events;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+#[cfg(feature = "heaptrack")]
+pub mod heaptrack;
+#[cfg(feature = "event-ring")]
+pub mod ring;
 
 use std::alloc::{handle_alloc_error, GlobalAlloc, Layout, System};
 use std::error::Error;
-use std::io::Write;
-use std::ptr::copy_nonoverlapping;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::panic::Location;
+use std::ops::Range;
+use std::path::Path;
+use std::ptr::{null_mut, NonNull};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
+pub use arena::RegionSource;
+pub use budget::Budget;
+pub use buffer::HugeBuffer;
+pub use callsite::{CallsiteStats, CALLSITE_CAPACITY};
+pub use capacity::HugeCapacity;
+pub use checkpoint::CheckpointSegment;
+pub use diagnose::{diagnose, Backing, Diagnosis, ThpMode};
+pub use const_threshold::HugeGlobalAllocatorConst;
+pub use hooks::{SegmentHooks, SegmentInfo};
+pub use hugepages::{huge_page_info, huge_pages, HugePageInfo};
+#[cfg(feature = "bytes")]
+pub use huge_bytes::HugeBytesMut;
+pub use local::{
+    set_thread_access_pattern, set_thread_high_priority, set_thread_max_slack, set_thread_noreserve, set_thread_tag,
+    set_thread_threshold, thread_access_pattern, thread_high_priority, thread_max_slack, thread_noreserve, thread_tag,
+    thread_threshold, with_thread_config, ThreadConfig, ThreadConfigGuard,
+};
+pub use overcommit::{overcommit, Overcommit, OvercommitMode, OvercommitReport};
+pub use pagesize::{
+    base_page_size, huge_page_size, set_base_page_size, set_huge_page_size, PageSizePref, DEFAULT_HUGE_PAGE_SIZE,
+};
+pub use pkey::{KeyAccess, ProtectionKey};
+pub use policy::{DefaultPagePolicy, PagePolicy, HUGE_ALIGNMENT};
+pub use probe::{Probe, ProbeReport};
+pub use registry::{merged_stats, registered_stats, REGISTRY_CAPACITY};
+#[cfg(any(debug_assertions, feature = "verify"))]
+pub use verify::{Problem, VerifyReport};
+use layers::Layers;
 use mmapper::MMapper;
+use sync::const_fn;
+
+/// Miri can't emulate the memory mapping system calls, so under Miri every allocation is passed
+/// through to the inner allocator. This lets crates using this allocator run their tests under Miri.
+/// The passthrough feature does the same in normal builds, for comparing runs with and without
+/// huge pages.
+const PASSTHROUGH: bool = cfg!(any(miri, feature = "passthrough"));
+
+/// Threshold used by the Default implementations (1mb)
+pub const DEFAULT_THRESHOLD: usize = 1024 * 1024;
+
+/// Callback invoked when the soft limit is exceeded. Receives the number of bytes that will be
+/// mapped after the pending allocation and the soft limit.
+pub type PressureCallback = fn(mapped: usize, limit: usize);
+
+/// Hook deciding whether a huge page segment may be demoted to default pages. Receives the
+/// segment's address, size and tag, and returns true to veto the demotion.
+pub type DemotionVeto = fn(ptr: *const u8, size: usize, tag: Option<&'static str>) -> bool;
+
+/// Action taken when a managed segment cannot be resized with mremap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemapFailure {
+    /// Allocate a new segment and copy the contents across (the default)
+    #[default]
+    Copy,
+    /// Fail the reallocation, leaving the original segment untouched
+    Fail,
+}
+
+/// How the contents of a managed segment are moved when a reallocation has to move it to a new
+/// segment. Pages are only moved between segments with the same page size where the new segment is
+/// at least as large as the whole old mapping. Otherwise, or if the kernel refuses the move, the
+/// contents are copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MoveStrategy {
+    /// Copy the contents with memcpy (the default)
+    #[default]
+    Memcpy,
+    /// Move the pages with mremap, unmapping the old address range
+    Mremap,
+    /// Move the pages with mremap and MREMAP_DONTUNMAP (Linux 5.7 or later). The old address range
+    /// stays mapped, empty, until the old segment is unmapped
+    DontUnmap,
+}
+
+/// The structure the allocator records its memory mapped segments in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MapStrategy {
+    /// A hash map, grown as segments are added (the default)
+    #[default]
+    Hash,
+    /// A vector sorted by address, searched by binary search. Smallest for a few large segments
+    Ordered,
+    /// A flat table sized for the given number of segments up front, so it is only rebuilt if the
+    /// count is exceeded. Suits many segments with a known peak
+    Fixed(usize),
+}
+
+/// How an allocation's memory will be accessed, advised to the kernel when its segment is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessPattern {
+    /// No advice (the default)
+    #[default]
+    Normal,
+    /// Read or written in order from start to end, advised MADV_SEQUENTIAL. Pages are faulted in
+    /// as they are reached.
+    Streaming,
+    /// Accessed at random, advised MADV_RANDOM. The whole segment is pre-faulted when it is
+    /// created or grown, so scattered first touches don't each take a page fault.
+    Random,
+}
+
+/// Action taken when a managed segment cannot be unmapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmapFailure {
+    /// Abort the process (the default)
+    #[default]
+    Abort,
+    /// Leak the segment and count it in the statistics
+    Leak,
+    /// Leak the segment, count it in the statistics and write a message to stderr
+    Log,
+}
 
 /// The global allocator
 ///
@@ -23,21 +205,105 @@ use mmapper::MMapper;
 /// #[global_allocator]
 /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
 /// ````
-pub struct HugeGlobalAllocator {
+///
+/// The allocator wraps an inner allocator, the system allocator by default, which serves the
+/// allocations below the threshold. Allocators can be chained, for example a counting wrapper
+/// around a huge page allocator wrapping jemalloc:
+///
+/// ```rust
+/// use std::alloc::System;
+/// use huge_global_alloc::HugeGlobalAllocator;
+///
+/// static ALLOCATOR: HugeGlobalAllocator<System> = HugeGlobalAllocator::with_inner(1024 * 1024, System);
+/// ````
+///
+/// Each pointer is owned by exactly one layer. Pointers to memory mapped segments are never passed
+/// to the inner allocator, and every other pointer is passed through to it unchanged. A
+/// reallocation which crosses the threshold allocates from the new owner, copies the contents and
+/// frees the block from the old owner, so the inner allocator only ever sees pointers it returned.
+pub struct HugeGlobalAllocator<A = System, P = DefaultPagePolicy> {
     mapper: MMapper,
     threshold: AtomicUsize,
+    inner: A,
+    policy: P,
 }
 
 impl HugeGlobalAllocator {
-    /// Creates a new allocator. The threshold defines the minimum number of bytes to consider a
-    /// huge page allocation.
-    pub const fn new(threshold: usize) -> Self {
-        Self {
-            mapper: MMapper::new(),
-            threshold: AtomicUsize::new(threshold),
+    const_fn! {
+        /// Creates a new allocator wrapping the system allocator. The threshold defines the minimum
+        /// number of bytes to consider a huge page allocation.
+        pub fn new(threshold: usize) -> Self {
+            Self::with_inner(threshold, System)
+        }
+    }
+
+    /// Makes this allocator the one the C statistics and control functions operate on. See the
+    /// [ffi] module.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.export_stats();
+    /// ````
+    #[cfg(feature = "c-stats")]
+    pub fn export_stats(&'static self) {
+        ffi::export(self);
+    }
+
+    /// Calls handle_alloc_error with a message and layout
+    fn alloc_error_layout(reason: &'static str, layout: Layout) -> ! {
+        HugeGlobalAllocator::log(reason);
+
+        handle_alloc_error(layout);
+    }
+
+    /// Writes a message to stderr
+    fn log(reason: &str) {
+        let mut stderr = std::io::stderr();
+
+        let _ = stderr.write_all(reason.as_bytes());
+        let _ = stderr.write_all("\n".as_bytes());
+    }
+}
+
+impl<A> HugeGlobalAllocator<A> {
+    const_fn! {
+        /// Creates a new allocator wrapping an inner allocator, which serves the allocations which
+        /// aren't memory mapped. The threshold defines the minimum number of bytes to consider a
+        /// huge page allocation.
+        pub fn with_inner(threshold: usize, inner: A) -> Self {
+            Self::with_policy(threshold, inner, DefaultPagePolicy)
+        }
+    }
+}
+
+impl<A, P> HugeGlobalAllocator<A, P> {
+    const_fn! {
+        /// Creates a new allocator wrapping an inner allocator, with a page size policy choosing
+        /// the page size of each memory mapped allocation. See [PagePolicy].
+        pub fn with_policy(threshold: usize, inner: A, policy: P) -> Self {
+            Self {
+                mapper: MMapper::new(),
+                threshold: AtomicUsize::new(threshold),
+                inner,
+                policy,
+            }
         }
     }
 
+    /// Returns the page size policy
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// Returns the inner allocator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
     /// Sets the minimum number of bytes to consider a huge page allocation.
     ///
     /// ```rust
@@ -65,158 +331,1655 @@ impl HugeGlobalAllocator {
         self.threshold.store(bytes, Ordering::Relaxed);
     }
 
-    /// Returns allocation statistics from the allocator
+    /// Returns the minimum number of bytes to consider a huge page allocation. Threads may
+    /// override this with [set_thread_threshold]
+    pub fn threshold(&self) -> usize {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    /// Sets the number of times a huge page mapping is retried when the huge page pool is
+    /// exhausted before falling back to default page size pages. The backoff delay is doubled
+    /// after each attempt. Retries are switched off by default.
     ///
     /// ```rust
+    /// use std::time::Duration;
     /// use huge_global_alloc::HugeGlobalAllocator;
     ///
     /// #[global_allocator]
     /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
     ///
-    /// let vec: Vec<u8> = Vec::with_capacity(1024); // 1kb
-    /// let stats = GLOBAL_ALLOCATOR.stats().unwrap();
-    /// assert_eq!(stats.segments, 0);
-    /// assert_eq!(stats.alloc, 0);
+    /// GLOBAL_ALLOCATOR.set_huge_retry(3, Duration::from_micros(100));
+    /// ````
+    pub fn set_huge_retry(&self, retries: usize, backoff: Duration) {
+        self.mapper.set_huge_retry(retries, backoff);
+    }
+
+    /// Enables SIGBUS protection for huge page segments. Huge pages can fail to be backed when
+    /// they are first touched (eg. when a hugetlb cgroup limit is reached), which raises SIGBUS.
+    /// When enabled, huge page segments are pre-faulted when they are mapped or grown and the
+    /// allocation falls back to default page size pages if the pre-fault fails.
+    /// Requires Linux 5.14 or later, and is switched off by default.
+    pub fn set_sigbus_protection(&self, enabled: bool) {
+        self.mapper.set_sigbus_protection(enabled);
+    }
+
+    /// Enables pre-touch validation of huge page segments. Newly mapped huge page segments are
+    /// faulted in page by page at allocation time, and the allocation fails (returns null) if any
+    /// page cannot be backed, rather than faulting at a random point later. Time spent pre-faulting
+    /// is reported in the prefault_time and prefault_max_time statistics.
+    /// Requires Linux 5.14 or later, and is switched off by default.
+    pub fn set_pretouch_validation(&self, enabled: bool) {
+        self.mapper.set_pretouch_validation(enabled);
+    }
+
+    /// Enables memory tagging of new segments on aarch64 Linux with the Memory Tagging Extension.
+    /// Segments are mapped with PROT_MTE and their payload tagged with a random tag carried in the
+    /// top byte of the returned pointer, so the hardware faults on accesses past the end of the
+    /// payload or through a pointer to a segment which has been freed or moved. Tag check faults
+    /// are synchronous and raise SIGSEGV; they are counted in the mte_tag_faults statistic before
+    /// the previous SIGSEGV handler runs. Tagged segments are counted in the mte_segments
+    /// statistic, and are never promoted or demoted.
     ///
-    /// let vec: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1mb
+    /// Tag checking is enabled for the calling thread and the threads it starts, so this should be
+    /// called before any other threads are started. Fails if the CPU or kernel doesn't support
+    /// memory tagging. Switched off by default.
+    ///
+    /// Experimental: this has not yet been run on hardware with MTE.
+    pub fn set_mte(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_mte(enabled)
+    }
+
+    /// Sets a soft limit on the number of bytes mapped by the allocator. When an allocation would
+    /// take the mapped total over the limit the callback is called before the allocation
+    /// continues, giving the application a chance to free memory (eg. drop caches). The callback
+    /// is called without any allocator locks held. A limit of 0 switches the soft limit off.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// fn pressure(mapped: usize, limit: usize) {
+    ///     eprintln!("{} bytes mapped, limit is {}", mapped, limit);
+    /// }
+    ///
+    /// GLOBAL_ALLOCATOR.set_soft_limit(4 * 1024 * 1024, Some(pressure)); // 4mb
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(8 * 1024 * 1024); // 8mb
     /// let stats = GLOBAL_ALLOCATOR.stats().unwrap();
-    /// assert_eq!(stats.segments, 1);
-    /// assert_eq!(stats.alloc, 1024 * 1024);
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// assert_eq!(stats.soft_limit_exceeded, 1);
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// assert!(stats.pressure >= 200);
     /// ````
-    pub fn stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
-        // Gather stats
-        self.mapper.stats()
+    pub fn set_soft_limit(&self, bytes: usize, callback: Option<PressureCallback>) {
+        self.mapper.set_soft_limit(bytes, callback);
     }
 
-    /// Calls handle_alloc_error with a message and null layout
-    fn alloc_error(reason: &'static str) -> ! {
-        let layout = unsafe { Layout::from_size_align_unchecked(0, 0) };
-        HugeGlobalAllocator::alloc_error_layout(reason, layout)
+    /// Draws the huge pages of segments mapped from now on from a budget, which can be shared by
+    /// several allocators to hold the process to one hard limit on huge page memory. Segments the
+    /// budget can't cover are mapped with default size pages and counted as missed allocations. A
+    /// budget can only be set once; later calls fail. See [Budget].
+    pub fn set_budget(&self, budget: &'static Budget) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_budget(budget)
     }
 
-    /// Calls handle_alloc_error with a message and layout
-    fn alloc_error_layout(reason: &'static str, layout: Layout) -> ! {
-        unsafe {
-            std::io::stderr().write(reason.as_bytes()).unwrap_unchecked();
-            std::io::stderr().write("\n".as_bytes()).unwrap_unchecked();
-        }
+    /// Returns the budget huge pages are drawn from, if set
+    pub fn budget(&self) -> Option<&'static Budget> {
+        self.mapper.budget()
+    }
 
-        handle_alloc_error(layout);
+    /// Registers hooks called as managed segments are mapped, freed, and resized or moved by a
+    /// reallocation, carrying the segment's metadata, so buffer managers can track regions the
+    /// moment they are created. The hooks are called on the allocating thread without any
+    /// allocator locks held, so they may allocate. Segments promoted, demoted or trimmed in place
+    /// are not reported. Hooks can only be registered once; later calls fail.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use huge_global_alloc::{HugeGlobalAllocator, SegmentHooks, SegmentInfo};
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// static REGISTERED: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// fn on_map(segment: &SegmentInfo) {
+    ///     REGISTERED.fetch_add(segment.size, Ordering::Relaxed);
+    /// }
+    ///
+    /// fn on_unmap(segment: &SegmentInfo) {
+    ///     REGISTERED.fetch_sub(segment.size, Ordering::Relaxed);
+    /// }
+    ///
+    /// let hooks = SegmentHooks { on_map: Some(on_map), on_unmap: Some(on_unmap), on_remap: None };
+    /// GLOBAL_ALLOCATOR.set_segment_hooks(hooks).unwrap();
+    /// assert!(GLOBAL_ALLOCATOR.set_segment_hooks(hooks).is_err());
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// assert_eq!(REGISTERED.load(Ordering::Relaxed), 4 * 1024 * 1024);
+    ///
+    /// drop(vec);
+    /// assert_eq!(REGISTERED.load(Ordering::Relaxed), 0);
+    /// ````
+    pub fn set_segment_hooks(&self, hooks: SegmentHooks) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_hooks(hooks)
     }
-}
 
-unsafe impl GlobalAlloc for HugeGlobalAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let size = layout.size();
-        let threshold = self.threshold.load(Ordering::Relaxed);
-
-        if threshold != 0 && size >= threshold {
-            // Allocate the segment
-            self.mapper.alloc(layout)
-        } else {
-            // Revert to system alloc
-            System.alloc(layout)
-        }
+    /// Sets the action taken when a managed segment cannot be resized with mremap. By default a
+    /// new segment is allocated and the contents copied, which can cause a latency spike for large
+    /// segments. The remaps_copied and remaps_refused statistics count which path was taken.
+    pub fn set_remap_failure(&self, action: RemapFailure) {
+        self.mapper.set_remap_failure(action);
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        if !self.mapper.dealloc(ptr) {
-            // Revert to system dealloc
-            System.dealloc(ptr, layout)
+    /// Sets how the contents of a managed segment are moved when a reallocation can't resize it
+    /// in place and has to move it to a new segment. Moving the pages with mremap avoids copying
+    /// large segments. The move_memcpy_bytes, move_mremap_bytes and move_dontunmap_bytes
+    /// statistics count the bytes moved by each strategy.
+    pub fn set_move_strategy(&self, strategy: MoveStrategy) {
+        self.mapper.set_move_strategy(strategy);
+    }
+
+    /// Sets the structure the allocator records its memory mapped segments in. The default hash
+    /// map suits most workloads. A process holding a few giant segments can use the smaller
+    /// ordered map, and one holding thousands of segments with a known peak can size a fixed table
+    /// for them up front so it is never rebuilt as segments come and go. Segments already
+    /// recorded are moved in to the new structure.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::{HugeGlobalAllocator, MapStrategy};
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(2 * 1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_map_strategy(MapStrategy::Fixed(4096));
+    /// ````
+    pub fn set_map_strategy(&self, strategy: MapStrategy) {
+        self.mapper.set_map_strategy(strategy);
+    }
+
+    /// Enables demotion of huge page segments which haven't been mapped or resized for at least
+    /// min_idle, or disables it if None (the default). When a high priority allocation (see
+    /// [set_thread_high_priority]) finds the huge page pool exhausted, the least recently resized
+    /// segments are moved on to default pages until enough huge pages have been freed. Addresses
+    /// don't change but the contents are copied. The veto hook is called without the segments
+    /// locked, before each segment is demoted; a segment freed or resized after the hook allowed it
+    /// is left alone. Demotions are counted in the demotions and demotions_vetoed statistics.
+    ///
+    /// # Safety
+    ///
+    /// Idle only means not mapped or resized recently, not unused. While demotion is enabled, no
+    /// segment which the veto hook allows to be demoted may be read or written by any thread other
+    /// than the high priority thread demoting it, or writes made during the copy are lost.
+    /// Disabling demotion is always safe.
+    pub unsafe fn set_demotion(&self, min_idle: Option<Duration>, veto: Option<DemotionVeto>) {
+        self.mapper.set_demotion(min_idle, veto);
+    }
+
+    /// Places new segments at random addresses within window instead of where the kernel would
+    /// put them, adding entropy to the addresses of the largest, most predictable allocations.
+    /// Each segment tries a few random aligned addresses which are free, without replacing any
+    /// existing mapping, before falling back to the kernel's placement. Segments moved by a
+    /// reallocation which can't resize in place are placed by the kernel. None (the default)
+    /// leaves all placement to the kernel. Placements are counted in the randomized_placements and
+    /// placement_fallbacks statistics.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let window = 0x5000_0000_0000..0x6000_0000_0000;
+    /// GLOBAL_ALLOCATOR.set_placement_window(Some(window.clone())).unwrap();
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(8 * 1024 * 1024); // 8mb
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// assert!(window.contains(&(vec.as_ptr() as usize)));
+    /// ````
+    pub fn set_placement_window(&self, window: Option<Range<usize>>) -> Result<(), Box<dyn Error>> {
+        if window.as_ref().is_some_and(Range::is_empty) {
+            return Err("placement window is empty".into());
         }
+
+        self.mapper.set_placement_window(window);
+
+        Ok(())
     }
 
-    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        // Anonymous mem maps are zeroed already
-        self.alloc(layout)
+    /// Sets the action taken when a managed segment cannot be unmapped. The default is to abort
+    /// the process. As the segment has already been freed by the application, leaking it is
+    /// usually a better trade-off for long running processes. Leaked segments are counted in the
+    /// unmaps_failed and leaked_bytes statistics.
+    pub fn set_unmap_failure(&self, action: UnmapFailure) {
+        self.mapper.set_unmap_failure(action);
     }
 
-    unsafe fn realloc(&self, old_ptr: *mut u8, old_layout: Layout, new_size: usize) -> *mut u8 {
-        // Create new layout
-        let new_layout = match Layout::from_size_align(new_size, old_layout.align()) {
-            Ok(layout) => layout,
-            Err(_) => Self::alloc_error_layout("HugeGlobalAllocator::realloc: Failed to create layout", old_layout)
-        };
-
-        if self.mapper.is_managed_ptr(old_ptr) {
-            // Old ptr is managed
-            if new_size >= self.threshold.load(Ordering::Relaxed) {
-                // Old ptr is managed and new ptr should be too
-                self.mapper.realloc(old_ptr, new_layout)
-            } else {
-                // Old ptr is managed but new ptr shouldn't be
-
-                // Allocate new segment using the system allocator
-                let new_ptr = System.alloc(new_layout);
-
-                if !new_ptr.is_null() {
-                    // Copy data from old segment to new
-                    copy_nonoverlapping(old_ptr, new_ptr, new_size);
-                }
+    /// Sets the largest percentage of a new segment's mapping which may be left unused by rounding
+    /// up to whole huge pages. Allocations which would waste more, such as 2mb + 1 byte mapped in
+    /// two 2mb pages, are mapped with default size pages instead, trading the TLB benefit for a
+    /// smaller resident size. These are counted in the exact_size_allocs statistic rather than as
+    /// missed allocations. A thread can override the percentage for its own allocations with
+    /// [set_thread_max_slack]. The default of 100 never falls back.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_max_slack(25);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024 + 1); // 2mb + 1 byte
+    /// assert!(!GLOBAL_ALLOCATOR.is_huge(vec.as_ptr()));
+    /// ````
+    pub fn set_max_slack(&self, percent: usize) {
+        self.mapper.set_max_slack(percent);
+    }
 
-                // Free the old segment
-                self.mapper.dealloc(old_ptr);
+    /// Sets the percentage of a segment's mapped size which a shrinking reallocation must drop
+    /// below before pages are released. Shrinks above it keep the mapping, so a buffer which is
+    /// repeatedly shrunk and grown (eg. Vec::shrink_to_fit after each pop) stays at the same
+    /// address without an mremap each time. The kept pages are released by [trim](Self::trim) or
+    /// the maintenance worker once the segment hasn't been resized for a maintenance interval.
+    /// Deferred shrinks are counted in the shrinks_deferred statistic. The default of 100 always
+    /// shrinks, and 0 never does.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_shrink_threshold(50);
+    ///
+    /// let mut vec: Vec<u8> = Vec::with_capacity(8 * 1024 * 1024); // 8mb
+    /// let ptr = vec.as_ptr();
+    ///
+    /// vec.shrink_to(6 * 1024 * 1024); // 6mb, still above half the mapped size
+    /// assert_eq!(vec.as_ptr(), ptr);
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// assert_eq!(GLOBAL_ALLOCATOR.stats().unwrap().shrinks_deferred, 1);
+    /// ````
+    pub fn set_shrink_threshold(&self, percent: usize) {
+        self.mapper.set_shrink_threshold(percent);
+    }
 
-                new_ptr
-            }
-        } else {
-            // Old ptr is not managed
-            if new_size >= self.threshold.load(Ordering::Relaxed) {
-                // Old ptr is not managed but new ptr should be
+    /// Keeps freed segments mapped for reuse in a cache shared by the threads on each NUMA node. A
+    /// later allocation with the same mapped size by a thread on the same node reuses one, zeroed,
+    /// without mapping anything, which suits worker threads freeing and allocating same sized
+    /// buffers in a loop. The cache is split in to 16 locked slots grouped by node, each thread
+    /// using one slot of its node's group, and each slot holds up to count segments totalling up
+    /// to bytes. A slot is shared by the threads using it, so with more threads than slots one
+    /// thread can evict or reuse another's segments. Segments with their own settings, such as
+    /// tagged or sensitive segments, aren't cached. Cached segments are released by
+    /// [trim](Self::trim) and by the maintenance worker once cached for a maintenance interval,
+    /// and calling this releases those already cached. At most 4 segments are cached per slot.
+    /// Reuses are counted in the segment_cache_hits statistic. The cache is disabled by default.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_segment_cache(2, 64 * 1024 * 1024);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    /// let ptr = vec.as_ptr();
+    /// drop(vec);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024);
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// assert_eq!(vec.as_ptr(), ptr);
+    /// ````
+    pub fn set_segment_cache(&self, count: usize, bytes: usize) {
+        self.mapper.set_segment_cache(count, bytes);
+    }
 
-                // Allocate new segment
-                let new_ptr = self.mapper.alloc(new_layout);
+    /// Probes the system calls and flags the allocator depends on with scratch mappings, for
+    /// containers whose seccomp filters block some of them and older kernels which reject newer
+    /// flags. Settings which would fail at runtime are switched off: a remap failure action of
+    /// fail without mremap, the mremap or dontunmap move strategies without their flags, and
+    /// pre-touch validation and SIGBUS protection without MADV_POPULATE_WRITE. Call it at startup
+    /// after configuring the allocator. The returned report lists each call's outcome and the
+    /// settings switched off, and displays as a table.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::{HugeGlobalAllocator, MoveStrategy};
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_move_strategy(MoveStrategy::DontUnmap);
+    ///
+    /// let report = GLOBAL_ALLOCATOR.probe();
+    /// assert!(report.available("mmap"));
+    ///
+    /// if !report.all_available() {
+    ///     eprint!("{report}");
+    /// }
+    /// ````
+    pub fn probe(&self) -> ProbeReport {
+        self.mapper.probe()
+    }
 
-                if !new_ptr.is_null() {
-                    // Copy data from old segment to new
-                    copy_nonoverlapping(old_ptr, new_ptr, old_layout.size());
-                }
+    /// Reads the kernel's overcommit policy and adjusts the allocator so allocations behave
+    /// predictably under it. Under strict overcommit (vm.overcommit_memory = 2) strict overcommit
+    /// behaviour is switched on (see [set_strict_overcommit](Self::set_strict_overcommit)). Call it
+    /// at startup after configuring the allocator. The returned report lists the settings changed
+    /// and warnings about what to expect, such as mappings failing at the commit limit or, with
+    /// overcommit always allowed, the process being killed when memory runs out, and displays as
+    /// text.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let report = GLOBAL_ALLOCATOR.adapt_to_overcommit();
+    ///
+    /// if !report.warnings.is_empty() {
+    ///     eprint!("{report}");
+    /// }
+    /// ````
+    pub fn adapt_to_overcommit(&self) -> OvercommitReport {
+        self.mapper.adapt_to_overcommit(overcommit())
+    }
 
-                // Free the old segment
-                System.dealloc(old_ptr, old_layout);
+    /// Switches on behaviour suited to strict overcommit, where every mapping is charged in full
+    /// against the commit limit when it is mapped. Thread requests for MAP_NORESERVE (see
+    /// [set_thread_noreserve]) are ignored, as the kernel ignores the flag, so the segments are
+    /// cached and pre-faulted like any other. New default page size segments, and their growth,
+    /// are pre-faulted in one call, as their pages are committed anyway, so first touches don't
+    /// each take a page fault. Huge page segments are unaffected. Switched off by default, and
+    /// switched on by [adapt_to_overcommit](Self::adapt_to_overcommit) when the kernel is in strict
+    /// mode.
+    pub fn set_strict_overcommit(&self, enabled: bool) {
+        self.mapper.set_strict_overcommit(enabled);
+    }
 
-                new_ptr
-            } else {
-                // Old ptr is not managed and new ptr shouldn't be - revert to system realloc
-                System.realloc(old_ptr, old_layout, new_size)
-            }
-        }
+    /// Tells the allocator that the memory mapped allocation starting at ptr is expected to grow to
+    /// expected_size bytes, for example a Vec which will end up at around 8gb. The segment is
+    /// grown to that size once, in place if the address range after it is free or otherwise by
+    /// the next reallocation, and reallocations up to that size then need no mremap. Shrinks keep
+    /// the reserved size mapped. Returns false if ptr is not the start of a memory mapped
+    /// allocation.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let mut vec: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024); // 2mb
+    /// assert!(GLOBAL_ALLOCATOR.reserve_hint(vec.as_ptr(), 64 * 1024 * 1024)); // 64mb
+    ///
+    /// vec.reserve_exact(8 * 1024 * 1024);
+    /// let ptr = vec.as_ptr();
+    ///
+    /// // Further growth within the reservation doesn't move the buffer
+    /// vec.reserve_exact(32 * 1024 * 1024);
+    /// assert_eq!(vec.as_ptr(), ptr);
+    /// ````
+    pub fn reserve_hint(&self, ptr: *const u8, expected_size: usize) -> bool {
+        self.mapper.reserve_hint(ptr as *mut u8, expected_size)
     }
-}
 
-/// Allocator performance statistics
-#[derive(Debug, Default)]
-pub struct HugeGlobalAllocatorStats {
-    /// Total amount of memory allocated in bytes
-    pub alloc: usize,
-    /// Total amount of memory mapped in bytes
-    pub mapped: usize,
-    /// Total number of segments mapped
-    pub segments: usize,
+    /// Releases the pages mapped after the end of the memory mapped allocation starting at ptr,
+    /// kept by deferred shrinks or a [reserve_hint](Self::reserve_hint), for a buffer which will
+    /// stay at a smaller size for a long time. The reservation is cancelled. Pages are released in
+    /// whole pages of the segment's page size, so up to one page of slack remains. Returns the
+    /// number of bytes released, or None if ptr is not the start of a memory mapped allocation.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let mut vec: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    /// GLOBAL_ALLOCATOR.reserve_hint(vec.as_ptr(), 64 * 1024 * 1024);
+    ///
+    /// vec.reserve_exact(16 * 1024 * 1024);
+    /// let released = GLOBAL_ALLOCATOR.trim_segment(vec.as_ptr()).unwrap();
+    /// assert_eq!(released, 48 * 1024 * 1024);
+    /// ````
+    pub fn trim_segment(&self, ptr: *const u8) -> Option<usize> {
+        self.mapper.trim_segment(ptr as *mut u8)
+    }
 
-    /// Amount of memory allocated in default page size pages in bytes
-    pub default_alloc: usize,
-    /// Amount of memory mapped in default page size pages in bytes
-    pub default_mapped: usize,
-    /// Number of default page size segments mapped
-    pub default_segments: usize,
+    /// Releases the pages kept mapped by deferred shrinks and the freed segments kept mapped by the
+    /// segment cache. Returns the number of bytes released.
+    pub fn trim(&self) -> usize {
+        self.mapper.trim(Duration::ZERO)
+    }
 
-    /// Amount of memory allocated in huge pages in bytes
-    pub huge_alloc: usize,
-    /// Amount of memory mapped in huge pages in bytes
-    pub huge_mapped: usize,
-    /// Number of huge page segments mapped
-    pub huge_segments: usize,
+    /// Enables or disables recording of the operations performed on memory mapped segments.
+    /// Recording is switched off by default. See the [events] module.
+    #[cfg(feature = "event-log")]
+    pub fn set_event_recording(&self, enabled: bool) {
+        self.mapper.set_event_recording(enabled);
+    }
 
-    /// Number of allocations missed due to lack of huge pages
-    pub missed_allocs: usize,
-    /// Allocations missed due to lack of huge pages in total megabytes
-    pub missed_mb: f64,
-    /// Number of failed remaps
-    pub remaps_failed: usize,
-    /// Percentage of mapped memory used by allocations
-    pub efficiency: usize,
-}
+    /// Returns the operations recorded on memory mapped segments in order
+    #[cfg(feature = "event-log")]
+    pub fn events(&self) -> Vec<events::Event> {
+        self.mapper.events()
+    }
 
-#[cfg(test)]
-mod tests;
+    /// Clears the recorded operations
+    #[cfg(feature = "event-log")]
+    pub fn clear_events(&self) {
+        self.mapper.clear_events();
+    }
+
+    /// Returns the ring of segment events, which a profiler or tracer can drain to build an
+    /// allocation timeline. The ring is disabled by default. See the [ring] module.
+    #[cfg(feature = "event-ring")]
+    pub fn event_ring(&self) -> &ring::EventRing {
+        self.mapper.event_ring()
+    }
+
+    /// Writes a setting addressed by a dotted key, so tooling can manipulate the allocator
+    /// uniformly without binding to each individual setter. Values are read and written as strings.
+    ///
+    /// | Key                          | Access | Value                                        |
+    /// |------------------------------|--------|----------------------------------------------|
+    /// | threshold                    | rw     | Bytes (k, m and g suffixes allowed)          |
+    /// | soft_limit                   | rw     | Bytes (k, m and g suffixes allowed)          |
+    /// | huge_retry.count             | rw     | Number of huge page mapping retries          |
+    /// | huge_retry.backoff_us        | rw     | Initial retry backoff in microseconds        |
+    /// | sigbus_protection            | rw     | true or false                                |
+    /// | pretouch_validation          | rw     | true or false                                |
+    /// | remap_failure                | rw     | copy or fail                                 |
+    /// | move_strategy                | rw     | memcpy, mremap or dontunmap                  |
+    /// | map_strategy                 | rw     | hash, ordered or fixed:segments              |
+    /// | unmap_failure                | rw     | abort, leak or log                           |
+    /// | shrink_threshold             | rw     | Percentage of mapped size (0 to 100)         |
+    /// | max_slack                    | rw     | Percentage of huge mapping (0 to 100)        |
+    /// | maintenance.interval_ms      | rw     | Started worker's pass interval (0 stops it)  |
+    /// | placement.window             | rw     | Hex address range (eg. 0x1000-0x2000) or off |
+    /// | mte                          | rw     | true or false (aarch64 with MTE only)        |
+    /// | secure_profile               | rw     | true or false                                |
+    /// | fork_shared                  | rw     | true or false                                |
+    /// | numa_local                   | rw     | true or false                                |
+    /// | strict_overcommit            | rw     | true or false                                |
+    /// | segment_cache.count          | rw     | Segments cached per slot (0 to 4)            |
+    /// | segment_cache.bytes          | rw     | Bytes (k, m and g suffixes allowed)          |
+    /// | stats.reset                  | w      | Any value. Resets the event counters         |
+    /// | stats.*                      | r      | Any HugeGlobalAllocatorStats field           |
+    ///
+    /// Durations in the statistics are read with an _ns suffix (eg. stats.prefault_time_ns).
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.ctl("threshold", "2m").unwrap();
+    /// GLOBAL_ALLOCATOR.ctl("remap_failure", "fail").unwrap();
+    /// GLOBAL_ALLOCATOR.ctl("stats.reset", "").unwrap();
+    ///
+    /// assert_eq!(GLOBAL_ALLOCATOR.threshold(), 2 * 1024 * 1024);
+    /// assert!(GLOBAL_ALLOCATOR.ctl("threshold", "lots").is_err());
+    /// ````
+    pub fn ctl(&self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        ctl::write(self, name, value)
+    }
+
+    /// Reads a setting or statistic addressed by a dotted key. See [HugeGlobalAllocator::ctl] for
+    /// the keys.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// assert_eq!(GLOBAL_ALLOCATOR.ctl_read("threshold").unwrap(), "1048576");
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1mb
+    /// assert_eq!(GLOBAL_ALLOCATOR.ctl_read("stats.segments").unwrap(), "1");
+    /// ````
+    pub fn ctl_read(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        ctl::read(self, name)
+    }
+
+    /// Returns allocation statistics from the allocator. With the no-stats feature only the segment
+    /// totals are reported and the event counters are always zero.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(1024); // 1kb
+    /// let stats = GLOBAL_ALLOCATOR.stats().unwrap();
+    /// assert_eq!(stats.segments, 0);
+    /// assert_eq!(stats.alloc, 0);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1mb
+    /// let stats = GLOBAL_ALLOCATOR.stats().unwrap();
+    /// assert_eq!(stats.segments, 1);
+    /// assert_eq!(stats.alloc, 1024 * 1024);
+    /// ````
+    pub fn stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
+        // Gather stats
+        self.mapper.stats()
+    }
+
+    /// Returns the same exact statistics as stats(), or an error straight away if another thread
+    /// holds the lock they need, for metric tasks on async runtimes with few worker threads which
+    /// must never block a worker. The caller can retry on its next tick, or fall back to
+    /// stats_snapshot().
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let stats = GLOBAL_ALLOCATOR.try_stats().unwrap_or_else(|_| GLOBAL_ALLOCATOR.stats_snapshot());
+    /// ````
+    pub fn try_stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
+        self.mapper.try_stats()
+    }
+
+    /// Returns allocation statistics without taking any of the locks used by the allocation paths,
+    /// so a monitoring thread can poll it without adding contention. Values are read from atomics
+    /// and may be slightly out of step with each other while other threads are allocating. Use
+    /// stats() for an exact view of the mapped segments.
+    /// With the no-stats feature the snapshot is always zero.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1mb
+    /// let stats = GLOBAL_ALLOCATOR.stats_snapshot();
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// assert_eq!(stats.segments, 1);
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// assert_eq!(stats.alloc, 1024 * 1024);
+    /// ````
+    pub fn stats_snapshot(&self) -> HugeGlobalAllocatorStats {
+        self.mapper.stats_snapshot()
+    }
+
+    /// Registers the allocator under a name, so its statistics are listed by name by
+    /// [registered_stats] and included in [merged_stats]. Processes running an allocator per
+    /// subsystem get separate limits and statistics for each, and a process wide total. Fails if
+    /// the allocator or the name is already registered, or if [REGISTRY_CAPACITY] allocators are.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::{merged_stats, registered_stats, HugeGlobalAllocator};
+    ///
+    /// static INGEST: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    /// static INDEX: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// INGEST.register("ingest").unwrap();
+    /// INDEX.register("index").unwrap();
+    /// assert_eq!(Some("index"), INDEX.name());
+    ///
+    /// for (name, stats) in registered_stats() {
+    ///     println!("{name}: {} bytes mapped", stats.mapped);
+    /// }
+    ///
+    /// println!("total: {} bytes mapped", merged_stats().mapped);
+    /// ````
+    pub fn register(&'static self, name: &'static str) -> Result<(), Box<dyn Error>> {
+        registry::register(name, &self.mapper)
+    }
+
+    /// Returns the name the allocator is registered under
+    pub fn name(&self) -> Option<&'static str> {
+        registry::name_of(&self.mapper)
+    }
+
+    /// Appends a snapshot of the statistics to a CSV file as a timestamped row, writing a header
+    /// row first if the file is empty. Calling this periodically builds a time series which can be
+    /// graphed with standard tooling. The columns are the time in seconds since the Unix epoch
+    /// followed by the stats.* ctl keys.
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// std::thread::spawn(|| loop {
+    ///     GLOBAL_ALLOCATOR.append_stats_csv("alloc_stats.csv").unwrap();
+    ///     std::thread::sleep(Duration::from_secs(1));
+    /// });
+    /// ````
+    pub fn append_stats_csv(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        csv::append(path.as_ref(), &self.stats_snapshot())
+    }
+
+    /// Registers the statistics as observable instruments on an OpenTelemetry meter, so they are
+    /// exported with the application's other metrics. Instruments are named huge_global_alloc.*
+    /// and read a lock free snapshot of the statistics each time the meter provider collects.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    /// use opentelemetry::global;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.register_metrics(&global::meter("allocator"));
+    /// ````
+    #[cfg(feature = "otel")]
+    pub fn register_metrics(&'static self, meter: &opentelemetry::metrics::Meter)
+    where
+        A: Sync,
+        P: Sync,
+    {
+        otel::register(meter, move || self.stats_snapshot());
+    }
+
+    /// Starts a background thread serving /stats (JSON) and /segments (the dump_segments()
+    /// listing) over HTTP on the loopback interface, for inspecting a running process with curl.
+    /// A port of 0 picks a free port. Returns the address listened on.
+    ///
+    /// ```rust,no_run
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let addr = GLOBAL_ALLOCATOR.serve_debug_http(9464).unwrap();
+    /// eprintln!("curl http://{addr}/stats");
+    /// ````
+    #[cfg(feature = "debug-http")]
+    pub fn serve_debug_http(&'static self, port: u16) -> Result<std::net::SocketAddr, Box<dyn Error>>
+    where
+        A: Sync,
+        P: Sync,
+    {
+        http::spawn(port, move || self.stats_snapshot(), move || self.dump_segments())
+    }
+
+    /// Returns true if ptr is the start of an allocation in a memory mapped segment, so
+    /// application code can check where a buffer was placed
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let small: Vec<u8> = Vec::with_capacity(1024); // 1kb
+    /// assert!(!GLOBAL_ALLOCATOR.is_managed(small.as_ptr()));
+    ///
+    /// let big: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1mb
+    /// assert!(GLOBAL_ALLOCATOR.is_managed(big.as_ptr()));
+    /// ````
+    pub fn is_managed(&self, ptr: *const u8) -> bool {
+        self.mapper.is_managed_ptr(ptr as *mut u8)
+    }
+
+    /// Returns true if ptr is the start of an allocation in a memory mapped segment backed by huge
+    /// pages
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let big: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1mb
+    ///
+    /// if !GLOBAL_ALLOCATOR.is_huge(big.as_ptr()) {
+    ///     println!("no huge pages available");
+    /// }
+    /// ````
+    pub fn is_huge(&self, ptr: *const u8) -> bool {
+        self.mapper.is_huge_ptr(ptr as *mut u8)
+    }
+
+    /// Returns the metadata of the memory mapped allocation containing ptr, which may point
+    /// anywhere in its segment, or None if ptr isn't in one. The id identifies the allocation for
+    /// the life of the process, and is also passed to the segment hooks, recorded in the event
+    /// logs and shown in the [dump_segments](Self::dump_segments) report, so logs and traces can
+    /// be joined on it.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let mut vec: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024); // 2mb
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// # {
+    /// let id = GLOBAL_ALLOCATOR.owner_of(vec.as_ptr()).unwrap().id;
+    ///
+    /// // Interior pointers find the same allocation, which keeps its id when it moves
+    /// assert_eq!(GLOBAL_ALLOCATOR.owner_of(vec.as_ptr().wrapping_add(1024)).map(|segment| segment.id), Some(id));
+    /// vec.reserve(64 * 1024 * 1024);
+    /// assert_eq!(GLOBAL_ALLOCATOR.owner_of(vec.as_ptr()).unwrap().id, id);
+    /// # }
+    /// ````
+    pub fn owner_of(&self, ptr: *const u8) -> Option<SegmentInfo> {
+        self.mapper.owner_of(ptr)
+    }
+
+    /// Attaches a tag to the memory mapped allocation starting at ptr, shown in the
+    /// [dump_segments](Self::dump_segments) report. The tag follows the allocation when it is
+    /// reallocated. Returns false if ptr is not the start of a memory mapped allocation.
+    pub fn tag(&self, ptr: *const u8, tag: &'static str) -> bool {
+        self.mapper.set_tag(ptr as *mut u8, tag)
+    }
+
+    /// Marks the memory mapped allocation starting at ptr as sensitive, for buffers holding key
+    /// material or other secrets. Before its pages are unmapped, or cut off by a shrinking
+    /// reallocation, the allocation is zeroed with explicit_bzero, which the compiler can't
+    /// optimise away. The flag follows the allocation when it is reallocated, and sensitive
+    /// allocations are never promoted or demoted, which would leave a copy behind. Returns false
+    /// if ptr is not the start of a memory mapped allocation.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let keys = vec![0u8; 4 * 1024 * 1024]; // 4mb
+    ///
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// assert!(GLOBAL_ALLOCATOR.set_sensitive(keys.as_ptr(), true));
+    ///
+    /// drop(keys); // Wiped, then unmapped
+    /// ````
+    pub fn set_sensitive(&self, ptr: *const u8, sensitive: bool) -> bool {
+        self.mapper.set_sensitive(ptr as *mut u8, sensitive)
+    }
+
+    /// Enables the security profile for applications handling secrets. Every new memory mapped
+    /// allocation is advised MADV_WIPEONFORK and MADV_DONTDUMP, so it reads as zeroes in forked
+    /// children and is left out of core dumps, and is locked in to memory with mlock so it is never
+    /// written to swap. Reallocations keep the profile, and secured allocations are never promoted
+    /// or demoted. Secured allocations are counted in the secure_segments statistic; allocations
+    /// which couldn't be locked, usually because RLIMIT_MEMLOCK is too low, keep the advice and are
+    /// counted in the secure_failures statistic instead. Existing allocations are unaffected.
+    /// Switched off by default.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_secure_profile(true);
+    ///
+    /// let secrets = vec![0u8; 4 * 1024 * 1024]; // 4mb, wiped on fork and not dumped
+    /// ````
+    pub fn set_secure_profile(&self, enabled: bool) {
+        self.mapper.set_secure_profile(enabled);
+    }
+
+    /// Maps new memory mapped allocations with MAP_SHARED instead of MAP_PRIVATE, for prefork
+    /// worker pools. A large read mostly dataset built in the parent before forking then shares its
+    /// physical pages with every child, instead of each child's pages being copied on its first
+    /// write, and writes by any process are seen by all of them. Each process still frees its own
+    /// mapping. Shared allocations are never promoted or demoted, and can't be moved with the
+    /// dontunmap move strategy, so reallocations which move them fall back to copying. Existing
+    /// allocations are unaffected. Switched off by default.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_fork_shared(true);
+    /// let dataset = vec![0u8; 64 * 1024 * 1024]; // 64mb, shared with children forked from here
+    /// GLOBAL_ALLOCATOR.set_fork_shared(false);
+    /// ````
+    pub fn set_fork_shared(&self, enabled: bool) {
+        self.mapper.set_fork_shared(enabled);
+    }
+
+    /// Binds new memory mapped allocations to the NUMA node of the thread allocating them, so on
+    /// multi-socket hosts a thread's buffers, including arena regions, are backed by memory local
+    /// to its socket. The node is preferred rather than required: when it has no free pages, or
+    /// its huge page pool is exhausted, pages come from other nodes. Segments in the segment
+    /// cache are reused by threads on the same node. Existing allocations are unaffected. Switched
+    /// off by default.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.set_numa_local(true);
+    /// let partition = vec![0u8; 64 * 1024 * 1024]; // 64mb on this thread's node
+    /// ````
+    pub fn set_numa_local(&self, enabled: bool) {
+        self.mapper.set_numa_local(enabled);
+    }
+
+    /// Marks the memory mapped allocation starting at ptr as having a stable address, for buffers
+    /// whose virtual and physical placement must stay fixed, such as key material guarded against
+    /// side channels or memory pinned for DMA. A stable allocation is only ever resized in place:
+    /// mremap is never allowed to move it, and a reallocation which can't be done in place fails,
+    /// returning null and leaving the allocation untouched, instead of copying it to a new
+    /// segment, whatever the remap failure action. Such failures are counted in the remaps_refused
+    /// statistic. Stable allocations are never promoted, demoted or collapsed in to transparent
+    /// huge pages. Returns false if ptr is not the start of a memory mapped allocation.
+    ///
+    /// ```rust
+    /// use std::alloc::{GlobalAlloc, Layout};
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let layout = Layout::from_size_align(4 * 1024 * 1024, 64).unwrap(); // 4mb
+    ///
+    /// unsafe {
+    ///     let ptr = GLOBAL_ALLOCATOR.alloc(layout);
+    ///     # #[cfg(not(feature = "passthrough"))]
+    ///     assert!(GLOBAL_ALLOCATOR.set_stable_address(ptr, true));
+    ///
+    ///     // Either grown in place or null, never moved
+    ///     let new_ptr = GLOBAL_ALLOCATOR.realloc(ptr, layout, 64 * 1024 * 1024);
+    ///     # #[cfg(not(feature = "passthrough"))]
+    ///     assert!(new_ptr.is_null() || new_ptr == ptr);
+    ///
+    ///     let (ptr, layout) = if new_ptr.is_null() {
+    ///         (ptr, layout)
+    ///     } else {
+    ///         (new_ptr, Layout::from_size_align(64 * 1024 * 1024, 64).unwrap())
+    ///     };
+    ///
+    ///     GLOBAL_ALLOCATOR.dealloc(ptr, layout);
+    /// }
+    /// ````
+    pub fn set_stable_address(&self, ptr: *const u8, stable: bool) -> bool {
+        self.mapper.set_stable_address(ptr as *mut u8, stable)
+    }
+
+    /// Associates the memory mapped allocation starting at ptr with a protection key, or the
+    /// default key if None. Each thread's access to the allocation can then be switched cheaply
+    /// with [ProtectionKey::set_access], for example to keep key material unreachable except while
+    /// it is in use. The key follows the allocation when it is reallocated. Reallocating or freeing
+    /// the allocation into the inner allocator copies it, so the calling thread needs read access.
+    /// Segments with a protection key are never promoted or demoted and have no canary. Fails if
+    /// ptr is not the start of a memory mapped allocation.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::{HugeGlobalAllocator, KeyAccess, ProtectionKey};
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let mut secret = vec![0u8; 4 * 1024 * 1024]; // 4mb
+    ///
+    /// // Protection keys need CPU and kernel support
+    /// if let Ok(key) = ProtectionKey::alloc(KeyAccess::ReadWrite) {
+    ///     # #[cfg(not(feature = "passthrough"))]
+    ///     GLOBAL_ALLOCATOR.set_protection_key(secret.as_ptr(), Some(key)).unwrap();
+    ///
+    ///     secret[0] = 42;
+    ///     key.set_access(KeyAccess::NoAccess); // Touching secret now faults
+    ///
+    ///     key.set_access(KeyAccess::ReadWrite);
+    ///     assert_eq!(42, secret[0]);
+    /// }
+    /// ````
+    pub fn set_protection_key(&self, ptr: *const u8, key: Option<ProtectionKey>) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_protection_key(ptr as *mut u8, key.map_or(0, ProtectionKey::as_raw))
+    }
+
+    /// Marks the memory mapped allocation starting at ptr to be skipped by checkpoints, or clears
+    /// the mark. Marked allocations are flagged in [checkpoint_segments](Self::checkpoint_segments)
+    /// and advised MADV_DONTDUMP, so they are also left out of core dumps, for data such as caches
+    /// which is cheaper to rebuild than to save. The mark follows the allocation when it is
+    /// reallocated, and marked allocations are never promoted or demoted. Fails if ptr is not the
+    /// start of a memory mapped allocation.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let cache: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// GLOBAL_ALLOCATOR.set_skip_checkpoint(cache.as_ptr(), true).unwrap();
+    /// ````
+    pub fn set_skip_checkpoint(&self, ptr: *const u8, skip: bool) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_skip_checkpoint(ptr as *mut u8, skip)
+    }
+
+    /// Controls kernel samepage merging (KSM) of the memory mapped allocation starting at ptr. With
+    /// mergeable true the allocation is advised MADV_MERGEABLE, so many identical large read only
+    /// tables, across processes too, can share pages once ksmd has scanned them. With false it is
+    /// advised MADV_UNMERGEABLE, keeping latency critical buffers out of KSM's scans and copy on
+    /// write faults even when merging has been enabled for the whole process. KSM only merges
+    /// default size pages, so huge page allocations are unaffected. The advice follows the
+    /// allocation when it is reallocated, and advised allocations are never promoted or demoted.
+    /// Fails if ptr is not the start of a memory mapped allocation or the kernel was built without
+    /// KSM.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let order_book: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    ///
+    /// // Never scanned or merged by KSM
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// GLOBAL_ALLOCATOR.set_mergeable(order_book.as_ptr(), false).ok();
+    /// ````
+    pub fn set_mergeable(&self, ptr: *const u8, mergeable: bool) -> Result<(), Box<dyn Error>> {
+        self.mapper.set_mergeable(ptr as *mut u8, mergeable)
+    }
+
+    /// Returns the live memory mapped segments ordered by address, in a form for checkpoint and
+    /// live migration tools such as CRIU: the address range of each mapping, the bytes in use,
+    /// the page size the restored mapping needs, whether it is shared and whether it has been
+    /// marked to be skipped with [set_skip_checkpoint](Self::set_skip_checkpoint)
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let cache: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    /// GLOBAL_ALLOCATOR.set_skip_checkpoint(cache.as_ptr(), true).ok();
+    ///
+    /// for segment in GLOBAL_ALLOCATOR.checkpoint_segments().iter().filter(|segment| !segment.skip) {
+    ///     eprintln!("save {segment}");
+    /// }
+    /// ````
+    pub fn checkpoint_segments(&self) -> Vec<CheckpointSegment> {
+        self.mapper.checkpoint_segments()
+    }
+
+    /// Returns a multi-line listing of the live memory mapped segments, with their address, size,
+    /// mapped size, page size, backing, age and tag, for dumping in to logs or bug reports
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let buffer: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    /// GLOBAL_ALLOCATOR.tag(buffer.as_ptr(), "frame buffer");
+    ///
+    /// let report = GLOBAL_ALLOCATOR.dump_segments();
+    /// assert!(report.contains("frame buffer"));
+    /// eprint!("{report}");
+    /// ````
+    pub fn dump_segments(&self) -> String {
+        report::format(&self.mapper.segments())
+    }
+
+    /// Checks the allocator's bookkeeping for corruption, for when the allocator itself is
+    /// suspected. Every tracked segment is checked to be stored under its own address, aligned,
+    /// mapped in whole pages, no smaller than its payload and clear of every other segment, and
+    /// its canary is checked with the `canary` feature. The running totals behind the statistics
+    /// are compared with the sums over the segments. Allocations made by other threads during the
+    /// check may show up as total mismatches, so call it while the allocator is quiet. Available
+    /// in debug builds, and in release builds with the `verify` feature.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let buffer: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+    ///
+    /// let report = GLOBAL_ALLOCATOR.verify();
+    /// assert!(report.is_ok(), "{report}");
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// assert_eq!(1, report.segments);
+    /// ````
+    #[cfg(any(debug_assertions, feature = "verify"))]
+    pub fn verify(&self) -> VerifyReport {
+        self.mapper.verify()
+    }
+
+    /// Retries huge page backing for segments which fell back to default size pages, for example
+    /// after the huge page pool has been refilled. A segment keeps its address, so it can only be
+    /// promoted if it starts on a huge page boundary and the address range up to the end of its
+    /// last huge page is free. Promoted segments are removed from the missed statistics and counted
+    /// in promotions. Returns the number of segments promoted.
+    ///
+    /// # Safety
+    ///
+    /// The contents of each segment are copied to the new pages, so no other thread may access or
+    /// free memory mapped allocations during the call.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024);
+    ///
+    /// // Once more huge pages have been reserved, with no other threads running
+    /// let promoted = unsafe { GLOBAL_ALLOCATOR.promote() };
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// assert_eq!(GLOBAL_ALLOCATOR.stats().unwrap().promotions, promoted);
+    /// ````
+    pub unsafe fn promote(&self) -> usize {
+        self.mapper.promote()
+    }
+
+    /// Asks the kernel to collapse default page size segments in to transparent huge pages in
+    /// place, using MADV_COLLAPSE. Unlike [promote](Self::promote) the memory isn't moved, so this
+    /// is safe while the segments are in use. Each segment is offered once, and again after it has
+    /// been grown. Requires Linux 6.1 or later with transparent huge pages enabled; segments the
+    /// kernel can't collapse are counted in collapses_failed. Returns the number of segments
+    /// collapsed.
+    pub fn collapse(&self) -> usize {
+        self.mapper.collapse()
+    }
+
+    /// Returns the bytes of default page size fallbacks which the kernel has since backed with
+    /// transparent huge pages. Allocations which wanted huge pages but fell back to default size
+    /// pages are aligned to 2mb and advised MADV_HUGEPAGE if they span at least 2mb, so khugepaged
+    /// can back them in the background without moving them. The count is read from
+    /// /proc/self/smaps each time, so it is meant for occasional monitoring. The kernel merges
+    /// adjacent mappings, so transparent huge pages of a neighbouring mapping advised
+    /// MADV_HUGEPAGE elsewhere may be included.
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let vec: Vec<u8> = Vec::with_capacity(64 * 1024 * 1024);
+    ///
+    /// if !GLOBAL_ALLOCATOR.is_huge(vec.as_ptr()) {
+    ///     eprintln!("{} bytes promoted by khugepaged", GLOBAL_ALLOCATOR.thp_promoted().unwrap());
+    /// }
+    /// ````
+    pub fn thp_promoted(&self) -> Result<usize, Box<dyn Error>> {
+        self.mapper.thp_promoted()
+    }
+
+    /// Starts a background thread which runs a maintenance pass every interval, collapsing
+    /// default page size segments in to transparent huge pages as [collapse](Self::collapse) does,
+    /// and trimming segments kept mapped by deferred shrinks which haven't been resized for an
+    /// interval.
+    /// Passes are counted in the maintenance_passes statistic. If the worker is already running
+    /// its interval is changed. The worker takes the same locks as an allocation and never
+    /// needs exclusive access, so it doesn't [promote](Self::promote) segments.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// GLOBAL_ALLOCATOR.start_maintenance(Duration::from_secs(10)).unwrap();
+    /// assert_eq!(GLOBAL_ALLOCATOR.ctl_read("maintenance.interval_ms").unwrap(), "10000");
+    ///
+    /// GLOBAL_ALLOCATOR.stop_maintenance();
+    /// ````
+    pub fn start_maintenance(&'static self, interval: Duration) -> Result<(), Box<dyn Error>>
+    where
+        A: Sync,
+        P: Sync,
+    {
+        self.mapper.maintenance().start(interval, move || self.mapper.maintain())
+    }
+
+    /// Stops the maintenance worker after any pass in progress
+    pub fn stop_maintenance(&self) {
+        self.mapper.maintenance().stop();
+    }
+
+    /// Maps a segment for the layout regardless of the threshold, overriding the page size policy
+    /// with the given preference. Huge page preferences ignore the maximum slack, and fall back to
+    /// smaller pages if the pool can't supply them; the segment keeps its preference if it moves.
+    /// The pointer is freed and resized through the GlobalAlloc interface like any other. Returns
+    /// null if the layout is zero sized or pre-touch validation fails. Passthrough builds use the
+    /// inner allocator. The allocation is attributed to the caller's source location in
+    /// [callsite_stats](Self::callsite_stats).
+    ///
+    /// ```rust
+    /// use std::alloc::{GlobalAlloc, Layout};
+    /// use huge_global_alloc::{HugeGlobalAllocator, PageSizePref};
+    ///
+    /// let allocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// // Small buffer which would otherwise go to the inner allocator
+    /// let layout = Layout::from_size_align(64 * 1024, 8).unwrap();
+    /// let ptr = allocator.alloc_with(PageSizePref::Huge, layout);
+    /// assert!(!ptr.is_null());
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// assert!(allocator.is_managed(ptr));
+    ///
+    /// unsafe { allocator.dealloc(ptr, layout) };
+    /// ````
+    #[track_caller]
+    pub fn alloc_with(&self, pref: PageSizePref, layout: Layout) -> *mut u8
+    where
+        A: GlobalAlloc,
+    {
+        if layout.size() == 0 {
+            return null_mut();
+        }
+
+        if PASSTHROUGH {
+            return unsafe { self.inner.alloc(layout) };
+        }
+
+        let ptr = self.mapper.alloc_with(layout, pref);
+
+        if !ptr.is_null() {
+            self.mapper.record_callsite(Location::caller(), layout.size());
+        }
+
+        ptr
+    }
+
+    /// Maps a segment for each layout regardless of the threshold, adding them all to the
+    /// allocator's bookkeeping under one lock acquisition, for pipelines which allocate many large
+    /// buffers at once. Each pointer is freed and resized through the GlobalAlloc interface with
+    /// its layout like any other. Entries are null where the layout is zero sized or pre-touch
+    /// validation fails. Passthrough builds use the inner allocator. The allocations are attributed
+    /// to the caller's source location in [callsite_stats](Self::callsite_stats).
+    ///
+    /// ```rust
+    /// use std::alloc::{GlobalAlloc, Layout};
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// let allocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let layouts = [Layout::from_size_align(4 * 1024 * 1024, 64).unwrap(); 8];
+    /// let ptrs = allocator.alloc_batch(&layouts);
+    /// assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+    ///
+    /// for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+    ///     unsafe { allocator.dealloc(ptr, layout) };
+    /// }
+    /// ````
+    #[track_caller]
+    pub fn alloc_batch(&self, layouts: &[Layout]) -> Vec<*mut u8>
+    where
+        A: GlobalAlloc,
+    {
+        if PASSTHROUGH {
+            return layouts
+                .iter()
+                .map(|&layout| if layout.size() == 0 { null_mut() } else { unsafe { self.inner.alloc(layout) } })
+                .collect();
+        }
+
+        let location = Location::caller();
+        let mapped: Vec<Layout> = layouts.iter().copied().filter(|layout| layout.size() != 0).collect();
+        let mut ptrs = self.mapper.alloc_batch(&mapped).into_iter();
+
+        layouts
+            .iter()
+            .map(|layout| {
+                if layout.size() == 0 {
+                    return null_mut();
+                }
+
+                let ptr = ptrs.next().unwrap_or(null_mut());
+
+                if !ptr.is_null() {
+                    self.mapper.record_callsite(location, layout.size());
+                }
+
+                ptr
+            })
+            .collect()
+    }
+
+    /// Reads a whole file in to a new buffer backed by huge pages if possible, regardless of the
+    /// threshold, for a one call path from a big file on disk to an in-memory copy. The buffer is
+    /// sized to the file, and its segment is freed when it is dropped. Fails if the file can't be
+    /// read or is truncated while it is being read. Passthrough builds use the inner
+    /// allocator. The allocation is attributed to the caller's source location in
+    /// [callsite_stats](Self::callsite_stats).
+    ///
+    /// ```rust
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let path = std::env::temp_dir().join("huge_global_alloc_read_to_huge");
+    /// std::fs::write(&path, vec![7u8; 3 * 1024 * 1024]).unwrap();
+    ///
+    /// let buffer = ALLOCATOR.read_to_huge(&path).unwrap();
+    /// assert_eq!(buffer.len(), 3 * 1024 * 1024);
+    /// assert!(buffer.iter().all(|&byte| byte == 7));
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ````
+    #[track_caller]
+    pub fn read_to_huge(&self, path: impl AsRef<Path>) -> Result<HugeBuffer<'_, A, P>, Box<dyn Error>>
+    where
+        A: GlobalAlloc,
+        P: PagePolicy,
+    {
+        let mut file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())?;
+
+        if len == 0 {
+            return Ok(unsafe { HugeBuffer::from_raw(self, NonNull::dangling(), 0) });
+        }
+
+        let layout = Layout::from_size_align(len, 1)?;
+        let ptr = NonNull::new(self.alloc_with(PageSizePref::Huge, layout)).ok_or("failed to allocate buffer")?;
+
+        // Memory from the inner allocator isn't zeroed like a mapped segment
+        if PASSTHROUGH {
+            unsafe { ptr.as_ptr().write_bytes(0, len) };
+        }
+
+        let mut buffer = unsafe { HugeBuffer::from_raw(self, ptr, len) };
+
+        file.read_exact(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    /// Creates a growable byte buffer in a memory mapped segment with room for at least capacity
+    /// bytes, regardless of the threshold. The buffer implements the bytes crate's Buf and BufMut
+    /// traits. Fails with the passthrough feature, which maps nothing.
+    ///
+    /// ```rust
+    /// use bytes::{Buf, BufMut};
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// # #[cfg(not(feature = "passthrough"))]
+    /// # {
+    /// let mut buf = ALLOCATOR.bytes_mut(64 * 1024).unwrap();
+    /// buf.put_u32(5);
+    /// buf.put_slice(b"hello");
+    ///
+    /// assert_eq!(5, buf.get_u32());
+    /// assert_eq!(&b"hello"[..], buf.freeze());
+    /// # }
+    /// ````
+    #[cfg(feature = "bytes")]
+    #[track_caller]
+    pub fn bytes_mut(&self, capacity: usize) -> Result<HugeBytesMut<'_, A, P>, Box<dyn Error>> {
+        HugeBytesMut::with_capacity(self, capacity).ok_or_else(|| "failed to map buffer".into())
+    }
+
+    /// Maps a segment for the layout regardless of the threshold, returning the whole mapping.
+    /// The returned slice's length is the mapped size, rounded up to a whole number of pages, so
+    /// the slack after the requested size can be used. The block must be freed with
+    /// [dealloc_raw](Self::dealloc_raw). Alignments larger than the page size are honoured by
+    /// mapping extra address space and unmapping the unaligned ends. Returns None if the layout is
+    /// zero sized or the segment could not be mapped. Always returns None under Miri. The
+    /// allocation is attributed to the caller's source location in
+    /// [callsite_stats](Self::callsite_stats).
+    ///
+    /// ```rust
+    /// use std::alloc::Layout;
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// let allocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let layout = Layout::from_size_align(3 * 1024 * 1024 + 1, 8).unwrap();
+    /// let block = allocator.alloc_raw(layout).unwrap();
+    /// assert!(block.len() >= layout.size());
+    ///
+    /// unsafe { allocator.dealloc_raw(block) };
+    /// ````
+    #[track_caller]
+    pub fn alloc_raw(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        if PASSTHROUGH || layout.size() == 0 {
+            return None;
+        }
+
+        let block = self.mapper.alloc_raw(layout)?;
+
+        self.mapper.record_callsite(Location::caller(), layout.size());
+
+        Some(block)
+    }
+
+    /// Returns the allocations made through the explicit allocation APIs such as
+    /// [alloc_raw](Self::alloc_raw), totalled by the source location of the call, largest total
+    /// first. Allocations through the GlobalAlloc interface are not attributed. Up to
+    /// [CALLSITE_CAPACITY] callsites are tracked. The totals are zeroed by the stats.reset ctl key.
+    ///
+    /// ```rust
+    /// use std::alloc::Layout;
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// let allocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let layout = Layout::from_size_align(2 * 1024 * 1024, 8).unwrap();
+    /// let block = allocator.alloc_raw(layout).unwrap();
+    /// unsafe { allocator.dealloc_raw(block) };
+    ///
+    /// # #[cfg(not(feature = "no-stats"))]
+    /// # {
+    /// let callsites = allocator.callsite_stats();
+    /// assert_eq!(callsites[0].location.file(), file!());
+    /// assert_eq!(callsites[0].bytes, 2 * 1024 * 1024);
+    /// # }
+    /// ````
+    pub fn callsite_stats(&self) -> Vec<CallsiteStats> {
+        self.mapper.callsite_stats()
+    }
+
+    /// Resizes a block allocated with [alloc_raw](Self::alloc_raw) to a new layout, which unlike
+    /// GlobalAlloc::realloc may change the alignment, for containers which reuse an allocation for
+    /// a different element type. The segment is remapped where possible, in place if the
+    /// alignment is larger than the page size, and otherwise moved to a new segment with the
+    /// contents up to the smaller size copied. Returns the whole new mapping, or None if the new
+    /// layout is zero sized or the remap failed with [RemapFailure::Fail], in which case the
+    /// block is unchanged.
+    ///
+    /// # Safety
+    ///
+    /// The block must have been returned by alloc_raw or realloc_raw on this allocator and not
+    /// already freed.
+    ///
+    /// ```rust
+    /// use std::alloc::Layout;
+    /// use huge_global_alloc::HugeGlobalAllocator;
+    ///
+    /// let allocator = HugeGlobalAllocator::new(1024 * 1024);
+    ///
+    /// let block = allocator.alloc_raw(Layout::from_size_align(1024 * 1024, 8).unwrap()).unwrap();
+    ///
+    /// let layout = Layout::from_size_align(8 * 1024 * 1024, 4 * 1024 * 1024).unwrap();
+    /// let block = unsafe { allocator.realloc_raw(block, layout) }.unwrap();
+    /// assert_eq!(block.as_ptr() as *mut u8 as usize % layout.align(), 0);
+    ///
+    /// unsafe { allocator.dealloc_raw(block) };
+    /// ````
+    pub unsafe fn realloc_raw(&self, block: NonNull<[u8]>, new_layout: Layout) -> Option<NonNull<[u8]>> {
+        if new_layout.size() == 0 {
+            return None;
+        }
+
+        self.mapper.realloc_raw(block.as_ptr() as *mut u8, new_layout)
+    }
+
+    /// Frees a block allocated with [alloc_raw](Self::alloc_raw)
+    ///
+    /// # Safety
+    ///
+    /// The block must have been returned by alloc_raw or realloc_raw on this allocator and not
+    /// already freed.
+    pub unsafe fn dealloc_raw(&self, block: NonNull<[u8]>) {
+        if !self.mapper.dealloc(block.as_ptr() as *mut u8) {
+            let layout = Layout::from_size_align_unchecked(block.len(), 1);
+
+            HugeGlobalAllocator::alloc_error_layout("HugeGlobalAllocator::dealloc_raw: block not found", layout);
+        }
+    }
+
+    /// Returns true if an allocation with the layout should be mapped
+    fn use_mapper(&self, layout: Layout) -> bool
+    where
+        P: PagePolicy,
+    {
+        if local::take_map_next() {
+            return !PASSTHROUGH;
+        }
+
+        let threshold = thread_threshold().unwrap_or_else(|| self.threshold());
+
+        !PASSTHROUGH && self.policy.maps(layout, threshold)
+    }
+
+    /// Returns the allocation paths for this allocator
+    fn layers(&self) -> Layers<'_, A, P, impl Fn(Layout) -> bool + '_>
+    where
+        P: PagePolicy,
+    {
+        Layers {
+            mapper: &self.mapper,
+            inner: &self.inner,
+            policy: &self.policy,
+            use_mapper: |layout| self.use_mapper(layout),
+        }
+    }
+}
+
+impl<A: Default, P: Default> Default for HugeGlobalAllocator<A, P> {
+    /// Creates an allocator with a threshold of [DEFAULT_THRESHOLD] wrapping the default inner
+    /// allocator, with the default page size policy
+    fn default() -> Self {
+        Self::with_policy(DEFAULT_THRESHOLD, A::default(), P::default())
+    }
+}
+
+unsafe impl<A: GlobalAlloc, P: PagePolicy> GlobalAlloc for HugeGlobalAllocator<A, P> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.layers().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.layers().dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.layers().alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, old_ptr: *mut u8, old_layout: Layout, new_size: usize) -> *mut u8 {
+        self.layers().realloc(old_ptr, old_layout, new_size)
+    }
+}
+
+/// Allocator performance statistics
+#[derive(Debug, Default)]
+pub struct HugeGlobalAllocatorStats {
+    /// Total amount of memory allocated in bytes
+    pub alloc: usize,
+    /// Total amount of memory mapped in bytes
+    pub mapped: usize,
+    /// Total number of segments mapped
+    pub segments: usize,
+
+    /// Amount of memory allocated in default page size pages in bytes
+    pub default_alloc: usize,
+    /// Amount of memory mapped in default page size pages in bytes
+    pub default_mapped: usize,
+    /// Number of default page size segments mapped
+    pub default_segments: usize,
+
+    /// Amount of memory allocated in huge pages in bytes
+    pub huge_alloc: usize,
+    /// Amount of memory mapped in huge pages in bytes
+    pub huge_mapped: usize,
+    /// Number of huge page segments mapped
+    pub huge_segments: usize,
+
+    /// Number of allocations missed due to lack of huge pages
+    pub missed_allocs: usize,
+    /// Allocations missed due to lack of huge pages in total megabytes
+    pub missed_mb: f64,
+    /// Number of failed remaps
+    pub remaps_failed: usize,
+    /// Number of failed remaps which allocated a new segment and copied the contents
+    pub remaps_copied: usize,
+    /// Number of failed remaps which failed the reallocation, including reallocations of stable
+    /// address allocations which would have had to move
+    pub remaps_refused: usize,
+    /// Total bytes copied due to failed remaps
+    pub remap_copied_bytes: usize,
+    /// Number of huge page mapping attempts retried due to an exhausted huge page pool
+    pub huge_retries: usize,
+    /// Number of huge page segments which could not be pre-faulted
+    pub populate_failed: usize,
+    /// Total time spent pre-faulting huge page segments
+    pub prefault_time: Duration,
+    /// Longest time spent pre-faulting a single huge page segment
+    pub prefault_max_time: Duration,
+    /// Percentage of mapped memory used by allocations
+    pub efficiency: usize,
+    /// Number of segments which could not be unmapped
+    pub unmaps_failed: usize,
+    /// Total bytes leaked by segments which could not be unmapped
+    pub leaked_bytes: usize,
+    /// Number of allocations which exceeded the soft limit
+    pub soft_limit_exceeded: usize,
+    /// Mapped memory as a percentage of the soft limit (0 if no soft limit is set)
+    pub pressure: usize,
+
+    /// Segments mapped per second, averaged over the last minute
+    pub allocs_per_sec: f64,
+    /// Segments unmapped per second, averaged over the last minute
+    pub deallocs_per_sec: f64,
+    /// Segments resized per second, averaged over the last minute
+    pub remaps_per_sec: f64,
+
+    /// Number of default page size segments moved on to huge pages by promote()
+    pub promotions: usize,
+    /// Number of passes made by the maintenance worker
+    pub maintenance_passes: usize,
+    /// Number of default page size segments collapsed in to transparent huge pages
+    pub collapses: usize,
+    /// Number of default page size segments the kernel could not collapse
+    pub collapses_failed: usize,
+    /// Number of shrinking reallocations which kept the existing mapping
+    pub shrinks_deferred: usize,
+    /// Total bytes released by trimming segments kept mapped by deferred shrinks
+    pub trimmed_bytes: usize,
+    /// Number of allocations mapped with default size pages because huge page rounding would
+    /// have exceeded the maximum slack
+    pub exact_size_allocs: usize,
+    /// Total bytes copied with memcpy by reallocations which moved to a new segment
+    pub move_memcpy_bytes: usize,
+    /// Total bytes moved with mremap by reallocations which moved to a new segment
+    pub move_mremap_bytes: usize,
+    /// Total bytes moved with mremap and MREMAP_DONTUNMAP by reallocations which moved to a new
+    /// segment
+    pub move_dontunmap_bytes: usize,
+    /// Number of huge page segments demoted to default pages to make room for high priority
+    /// allocations
+    pub demotions: usize,
+    /// Number of huge page segments the demotion veto hook kept on huge pages
+    pub demotions_vetoed: usize,
+    /// Number of segments mapped at a random address in the placement window
+    pub randomized_placements: usize,
+    /// Number of segments placed by the kernel because the placement window had no free address
+    pub placement_fallbacks: usize,
+    /// Number of segments mapped with memory tagging
+    pub mte_segments: usize,
+    /// Number of memory tag check faults caught by the SIGSEGV handler
+    pub mte_tag_faults: usize,
+    /// Number of segments the security profile wiped on fork, excluded from core dumps and locked
+    /// in to memory
+    pub secure_segments: usize,
+    /// Number of segments the security profile could not fully secure, usually because locking them
+    /// would exceed RLIMIT_MEMLOCK
+    pub secure_failures: usize,
+    /// Number of new segments reused from the segment cache
+    pub segment_cache_hits: usize,
+    /// Number of new segments mapped because the segment cache held no segment of the size
+    pub segment_cache_misses: usize,
+    /// Number of times the allocator's locks were taken
+    pub lock_acquisitions: usize,
+    /// Number of lock acquisitions which had to wait for another thread
+    pub lock_contended: usize,
+    /// Total time spent waiting for the allocator's locks
+    pub lock_wait_time: Duration,
+    /// Longest time spent waiting for one of the allocator's locks
+    pub lock_max_wait_time: Duration,
+    /// Number of reallocations of the thread's most recently mapped or resized segment, which
+    /// skip the segment lookup
+    pub recent_segment_hits: usize,
+    /// Number of reallocations of memory mapped segments which had to be looked up
+    pub recent_segment_misses: usize,
+}
+
+impl HugeGlobalAllocatorStats {
+    /// Adds another allocator's statistics to these. Totals and counts are summed, the maximum
+    /// times and pressure are the larger of the two, and the efficiency is recalculated from the
+    /// merged totals. Memory tag check faults are counted for the whole process, so aren't summed.
+    pub fn merge(&mut self, other: &HugeGlobalAllocatorStats) {
+        self.alloc += other.alloc;
+        self.mapped += other.mapped;
+        self.segments += other.segments;
+        self.default_alloc += other.default_alloc;
+        self.default_mapped += other.default_mapped;
+        self.default_segments += other.default_segments;
+        self.huge_alloc += other.huge_alloc;
+        self.huge_mapped += other.huge_mapped;
+        self.huge_segments += other.huge_segments;
+        self.missed_allocs += other.missed_allocs;
+        self.missed_mb += other.missed_mb;
+        self.remaps_failed += other.remaps_failed;
+        self.remaps_copied += other.remaps_copied;
+        self.remaps_refused += other.remaps_refused;
+        self.remap_copied_bytes += other.remap_copied_bytes;
+        self.huge_retries += other.huge_retries;
+        self.populate_failed += other.populate_failed;
+        self.prefault_time += other.prefault_time;
+        self.prefault_max_time = self.prefault_max_time.max(other.prefault_max_time);
+        self.unmaps_failed += other.unmaps_failed;
+        self.leaked_bytes += other.leaked_bytes;
+        self.soft_limit_exceeded += other.soft_limit_exceeded;
+        self.pressure = self.pressure.max(other.pressure);
+        self.allocs_per_sec += other.allocs_per_sec;
+        self.deallocs_per_sec += other.deallocs_per_sec;
+        self.remaps_per_sec += other.remaps_per_sec;
+        self.promotions += other.promotions;
+        self.maintenance_passes += other.maintenance_passes;
+        self.collapses += other.collapses;
+        self.collapses_failed += other.collapses_failed;
+        self.shrinks_deferred += other.shrinks_deferred;
+        self.trimmed_bytes += other.trimmed_bytes;
+        self.exact_size_allocs += other.exact_size_allocs;
+        self.move_memcpy_bytes += other.move_memcpy_bytes;
+        self.move_mremap_bytes += other.move_mremap_bytes;
+        self.move_dontunmap_bytes += other.move_dontunmap_bytes;
+        self.demotions += other.demotions;
+        self.demotions_vetoed += other.demotions_vetoed;
+        self.randomized_placements += other.randomized_placements;
+        self.placement_fallbacks += other.placement_fallbacks;
+        self.mte_segments += other.mte_segments;
+        self.mte_tag_faults = self.mte_tag_faults.max(other.mte_tag_faults);
+        self.secure_segments += other.secure_segments;
+        self.secure_failures += other.secure_failures;
+        self.segment_cache_hits += other.segment_cache_hits;
+        self.segment_cache_misses += other.segment_cache_misses;
+        self.lock_acquisitions += other.lock_acquisitions;
+        self.lock_contended += other.lock_contended;
+        self.lock_wait_time += other.lock_wait_time;
+        self.lock_max_wait_time = self.lock_max_wait_time.max(other.lock_max_wait_time);
+        self.recent_segment_hits += other.recent_segment_hits;
+        self.recent_segment_misses += other.recent_segment_misses;
+
+        self.efficiency = (self.alloc * 100).checked_div(self.mapped).unwrap_or(100).min(100);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests;
+
+#[cfg(all(test, loom))]
+#[path = "tests/loom.rs"]
+mod loom_tests;
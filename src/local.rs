@@ -0,0 +1,264 @@
+//! Per-thread settings
+//!
+//! A thread can override the threshold of every [HugeGlobalAllocator](crate::HugeGlobalAllocator)
+//! it allocates from, so one thread can route smaller buffers to huge pages while the rest of the
+//! process keeps the allocator's threshold. [HugeGlobalAllocatorConst](crate::HugeGlobalAllocatorConst)
+//! thresholds are fixed at compile time and are not affected. The maximum huge page slack and
+//! allocation priority can be set the same way, for both allocator types.
+//!
+//! ```rust
+//! use huge_global_alloc::{set_thread_threshold, HugeGlobalAllocator};
+//!
+//! #[global_allocator]
+//! static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+//!
+//! std::thread::spawn(|| {
+//!     set_thread_threshold(Some(256 * 1024)); // 256kb on this thread only
+//!
+//!     let buffer: Vec<u8> = Vec::with_capacity(512 * 1024);
+//!     assert!(GLOBAL_ALLOCATOR.is_managed(buffer.as_ptr()));
+//! })
+//! .join()
+//! .unwrap();
+//!
+//! let buffer: Vec<u8> = Vec::with_capacity(512 * 1024);
+//! assert!(!GLOBAL_ALLOCATOR.is_managed(buffer.as_ptr()));
+//! ````
+//!
+//! Settings can also be changed for the duration of a scope with [with_thread_config] or the
+//! guard returned by [ThreadConfig::apply], for phases such as loading which allocate differently
+//! from the steady state:
+//!
+//! ```rust
+//! use huge_global_alloc::{thread_threshold, with_thread_config};
+//!
+//! let tables = with_thread_config(
+//!     |config| config.threshold = Some(64 * 1024),
+//!     || {
+//!         assert_eq!(thread_threshold(), Some(64 * 1024));
+//!         vec![0u8; 128 * 1024]
+//!     },
+//! );
+//!
+//! assert_eq!(thread_threshold(), None);
+//! ````
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::AccessPattern;
+
+thread_local! {
+    /// Threshold override for the current thread. Const initialised without a destructor so it
+    /// can be used from the allocator at any point in the thread's life
+    static THRESHOLD: Cell<Option<usize>> = const { Cell::new(None) };
+
+    /// Maximum huge page slack override for the current thread
+    static MAX_SLACK: Cell<Option<usize>> = const { Cell::new(None) };
+
+    /// True if the current thread's allocations may demote other segments
+    static HIGH_PRIORITY: Cell<bool> = const { Cell::new(false) };
+
+    /// Access pattern of the current thread's new segments
+    static ACCESS_PATTERN: Cell<AccessPattern> = const { Cell::new(AccessPattern::Normal) };
+
+    /// True if the current thread's new default page size segments are mapped without reserving swap
+    static NORESERVE: Cell<bool> = const { Cell::new(false) };
+
+    /// Name of the current thread's role, for page size policies
+    static TAG: Cell<Option<&'static str>> = const { Cell::new(None) };
+
+    /// True if the current thread's next allocation is mapped whatever its size
+    static MAP_NEXT: Cell<bool> = const { Cell::new(false) };
+
+    /// Index of the current thread, usize::MAX until assigned
+    static INDEX: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// Next thread index to hand out
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns an index for the current thread, assigned in the order threads first ask, for spreading
+/// threads over per-thread structures. Returns None if the thread is exiting.
+pub(crate) fn thread_index() -> Option<usize> {
+    INDEX
+        .try_with(|index| {
+            if index.get() == usize::MAX {
+                index.set(NEXT_INDEX.fetch_add(1, Ordering::Relaxed));
+            }
+
+            index.get()
+        })
+        .ok()
+}
+
+/// Overrides the threshold of every HugeGlobalAllocator for the current thread, returning the
+/// previous override. A threshold of Some(0) switches huge page allocations off for the thread,
+/// and None removes the override.
+pub fn set_thread_threshold(threshold: Option<usize>) -> Option<usize> {
+    THRESHOLD.with(|cell| cell.replace(threshold))
+}
+
+/// Returns the current thread's threshold override
+pub fn thread_threshold() -> Option<usize> {
+    THRESHOLD.try_with(Cell::get).ok().flatten()
+}
+
+/// Overrides the maximum huge page slack percentage of every allocator for the current thread,
+/// returning the previous override. New segments whose huge page rounding would leave more than
+/// this percentage of the mapping unused are mapped with default size pages. None removes the
+/// override.
+pub fn set_thread_max_slack(percent: Option<usize>) -> Option<usize> {
+    MAX_SLACK.with(|cell| cell.replace(percent))
+}
+
+/// Returns the current thread's maximum huge page slack override
+pub fn thread_max_slack() -> Option<usize> {
+    MAX_SLACK.try_with(Cell::get).ok().flatten()
+}
+
+/// Marks the current thread's allocations as high priority, returning the previous setting. When
+/// demotion is enabled, a high priority allocation which finds the huge page pool exhausted demotes
+/// idle huge page segments to default pages to make room.
+pub fn set_thread_high_priority(high_priority: bool) -> bool {
+    HIGH_PRIORITY.with(|cell| cell.replace(high_priority))
+}
+
+/// Returns true if the current thread's allocations are high priority
+pub fn thread_high_priority() -> bool {
+    HIGH_PRIORITY.try_with(Cell::get).unwrap_or(false)
+}
+
+/// Sets the access pattern advised for the current thread's new memory mapped allocations,
+/// returning the previous pattern. Reallocations keep the pattern their segment was created with.
+pub fn set_thread_access_pattern(pattern: AccessPattern) -> AccessPattern {
+    ACCESS_PATTERN.with(|cell| cell.replace(pattern))
+}
+
+/// Returns the access pattern advised for the current thread's new allocations
+pub fn thread_access_pattern() -> AccessPattern {
+    ACCESS_PATTERN.try_with(Cell::get).unwrap_or_default()
+}
+
+/// Maps the current thread's new default page size segments with MAP_NORESERVE, returning the
+/// previous setting. No swap space is reserved and the segment isn't charged against the commit
+/// limit, so giant sparsely touched mappings such as hash tables can be larger than the commit
+/// limit allows. Touching more of them than there is memory for gets the process killed rather
+/// than an allocation failure. Huge page segments reserve from the huge page pool and are
+/// unaffected, and the kernel ignores MAP_NORESERVE when overcommit is disabled
+/// (vm.overcommit_memory = 2). Reallocations keep the setting their segment was created with.
+pub fn set_thread_noreserve(noreserve: bool) -> bool {
+    NORESERVE.with(|cell| cell.replace(noreserve))
+}
+
+/// Returns true if the current thread's new default page size segments are mapped with
+/// MAP_NORESERVE
+pub fn thread_noreserve() -> bool {
+    NORESERVE.try_with(Cell::get).unwrap_or(false)
+}
+
+/// Tags the current thread with the name of its role, eg. "gc", returning the previous tag. Page
+/// size policies can read the tag with [thread_tag] to treat the thread's allocations differently
+/// (see [PagePolicy](crate::PagePolicy)). None removes the tag.
+pub fn set_thread_tag(tag: Option<&'static str>) -> Option<&'static str> {
+    TAG.with(|cell| cell.replace(tag))
+}
+
+/// Returns the current thread's tag
+pub fn thread_tag() -> Option<&'static str> {
+    TAG.try_with(Cell::get).ok().flatten()
+}
+
+/// Sets whether the current thread's next allocation is mapped whatever its size
+pub(crate) fn set_map_next(map: bool) {
+    let _ = MAP_NEXT.try_with(|cell| cell.set(map));
+}
+
+/// Returns true, once, if the current thread's next allocation is to be mapped whatever its size.
+/// Allocations made while mapping it see the flag cleared.
+pub(crate) fn take_map_next() -> bool {
+    MAP_NEXT.try_with(|cell| cell.replace(false)).unwrap_or(false)
+}
+
+/// The current thread's settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct ThreadConfig {
+    /// Threshold override (see [set_thread_threshold])
+    pub threshold: Option<usize>,
+    /// Maximum huge page slack override (see [set_thread_max_slack])
+    pub max_slack: Option<usize>,
+    /// High priority allocations (see [set_thread_high_priority])
+    pub high_priority: bool,
+    /// Access pattern of new allocations (see [set_thread_access_pattern])
+    pub access_pattern: AccessPattern,
+    /// Map default page size segments without reserving swap (see [set_thread_noreserve])
+    pub noreserve: bool,
+    /// Role of the thread (see [set_thread_tag])
+    pub tag: Option<&'static str>,
+}
+
+impl ThreadConfig {
+    /// Returns the current thread's settings
+    pub fn current() -> Self {
+        Self {
+            threshold: thread_threshold(),
+            max_slack: thread_max_slack(),
+            high_priority: thread_high_priority(),
+            access_pattern: thread_access_pattern(),
+            noreserve: thread_noreserve(),
+            tag: thread_tag(),
+        }
+    }
+
+    /// Applies the settings to the current thread until the returned guard is dropped, when the
+    /// previous settings are restored
+    pub fn apply(self) -> ThreadConfigGuard {
+        let previous = Self::current();
+
+        self.set();
+
+        ThreadConfigGuard {
+            previous,
+            _thread: PhantomData,
+        }
+    }
+
+    /// Makes these the current thread's settings
+    fn set(self) {
+        set_thread_threshold(self.threshold);
+        set_thread_max_slack(self.max_slack);
+        set_thread_high_priority(self.high_priority);
+        set_thread_access_pattern(self.access_pattern);
+        set_thread_noreserve(self.noreserve);
+        set_thread_tag(self.tag);
+    }
+}
+
+/// Restores the previous thread settings when dropped. Guards must be dropped on the thread which
+/// created them, and nested guards should be dropped in reverse order.
+#[must_use = "the settings are restored when the guard is dropped"]
+#[derive(Debug)]
+pub struct ThreadConfigGuard {
+    previous: ThreadConfig,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for ThreadConfigGuard {
+    fn drop(&mut self) {
+        self.previous.set();
+    }
+}
+
+/// Runs f with the current thread's settings changed by configure, restoring them afterwards even
+/// if f panics
+pub fn with_thread_config<R>(configure: impl FnOnce(&mut ThreadConfig), f: impl FnOnce() -> R) -> R {
+    let mut config = ThreadConfig::current();
+
+    configure(&mut config);
+
+    let _guard = config.apply();
+
+    f()
+}
@@ -0,0 +1,104 @@
+//! Background maintenance worker
+//!
+//! An optional thread which wakes periodically and runs a maintenance pass over the mapper. The
+//! interval can be changed while the worker runs, and an interval of zero stops it at its next
+//! wake up. The worker only takes the locks an allocation would, so it never needs exclusive
+//! access to the segments.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// State shared between the allocator and its maintenance worker
+pub(crate) struct Maintenance {
+    /// Time between passes in milliseconds (0 when stopped)
+    interval_ms: AtomicU64,
+    /// True while a worker thread exists
+    running: AtomicBool,
+}
+
+impl Maintenance {
+    /// Creates the state for a stopped worker
+    pub(crate) const fn new() -> Self {
+        Self {
+            interval_ms: AtomicU64::new(0),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets the interval between passes, starting a worker thread to run pass if there isn't one
+    pub(crate) fn start<F>(&'static self, interval: Duration, pass: F) -> Result<(), Box<dyn Error>>
+    where
+        F: Fn() + Send + 'static,
+    {
+        if interval.is_zero() {
+            return Err("maintenance interval must be non-zero".into());
+        }
+
+        self.interval_ms.store((interval.as_millis() as u64).max(1), Ordering::Relaxed);
+
+        if self.running.swap(true, Ordering::AcqRel) {
+            // Already running with the new interval
+            return Ok(());
+        }
+
+        let spawned = thread::Builder::new().name("huge_alloc_maint".to_string()).spawn(move || {
+            while let Some(interval) = self.next_interval() {
+                thread::sleep(interval);
+
+                if self.interval_ms.load(Ordering::Relaxed) != 0 {
+                    pass();
+                }
+            }
+        });
+
+        if let Err(e) = spawned {
+            self.running.store(false, Ordering::Release);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Stops the worker thread after any pass in progress
+    pub(crate) fn stop(&self) {
+        self.interval_ms.store(0, Ordering::Relaxed);
+    }
+
+    /// Changes the interval between passes of a started worker. An interval of zero stops it.
+    pub(crate) fn set_interval(&self, interval: Duration) -> Result<(), Box<dyn Error>> {
+        if interval.is_zero() {
+            self.stop();
+        } else if self.interval().is_zero() {
+            return Err("maintenance worker not started".into());
+        } else {
+            self.interval_ms.store((interval.as_millis() as u64).max(1), Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the interval between passes, or zero if the worker is stopped
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.load(Ordering::Relaxed))
+    }
+
+    /// Returns the time to sleep before the next pass, or None if the worker should exit
+    fn next_interval(&self) -> Option<Duration> {
+        loop {
+            let interval = self.interval();
+
+            if !interval.is_zero() {
+                return Some(interval);
+            }
+
+            self.running.store(false, Ordering::Release);
+
+            // Restarted between reading the interval and clearing running, without a new thread
+            if self.interval().is_zero() || self.running.swap(true, Ordering::AcqRel) {
+                return None;
+            }
+        }
+    }
+}
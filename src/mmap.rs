@@ -1,34 +1,39 @@
 use std::alloc::Layout;
-use std::ffi::c_void;
-use std::ptr::null_mut;
-
-use lazy_static::lazy_static;
+use std::ffi::{c_int, c_void};
+use std::mem::{forget, size_of, take};
+use std::ops::Range;
+use std::ptr::copy_nonoverlapping;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
 use nix::{
-    sys::mman::{mmap, mremap, munmap, MRemapFlags, MapFlags, ProtFlags},
-    unistd::{sysconf, SysconfVar},
+    errno::Errno,
+    sys::mman::{MRemapFlags, MapFlags, ProtFlags},
 };
 
-use crate::HugeGlobalAllocator;
-
-lazy_static! {
-    /// The default page size for the platform
-    static ref DEFAULT_PAGE_SIZE: usize = {
-        let layout = unsafe { Layout::from_size_align_unchecked(0, 0) };
+#[cfg(feature = "canary")]
+use crate::canary;
+#[cfg(any(feature = "asan", feature = "valgrind"))]
+use crate::sanitizer;
+use crate::{
+    budget::Budget,
+    mte,
+    pagesize::{base_page_size, huge_page_size, huge_size_flags, PageSizePref},
+    pkey,
+    raw::round_to_pages,
+    sys::Syscalls,
+    thp::THP_SIZE,
+    AccessPattern, HugeGlobalAllocator,
+};
 
-        match sysconf(SysconfVar::PAGE_SIZE) {
-            Ok(val) => match val {
-                Some(val) => val as usize,
-                None => HugeGlobalAllocator::alloc_error_layout("sysconf PAGE_SIZE no value", layout)
-            }
-            Err(_) => HugeGlobalAllocator::alloc_error_layout("sysconf PAGE_SIZE failed", layout)
-        }
-    };
-}
+/// Identifier of the next allocation
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 
 /// Descriptor for anonymous memory mapped segments
 #[derive(Debug)]
 pub struct MMap {
+    /// Identifier of the allocation, kept when the segment moves
+    id: u64,
     /// Raw pointer to memory mapped section
     ptr: usize,
     /// Requested layout
@@ -37,27 +42,70 @@ pub struct MMap {
     alloc_size: usize,
     /// Page size
     page_size: usize,
+    /// True if mapped with huge pages
+    huge: bool,
+    /// System calls used to manage the mapping
+    sys: &'static dyn Syscalls,
+    /// Time the segment was mapped
+    created: Instant,
+    /// Time the segment was last resized
+    resized: Instant,
+    /// Mapped size the segment is expected to grow to, kept when it is resized
+    reserve: usize,
+    /// Label attached by the application
+    tag: Option<&'static str>,
+    /// True once the segment has been offered to the kernel for collapsing in to transparent huge
+    /// pages
+    collapse_tried: bool,
+    /// Memory tag of the payload's granules, or 0 if the segment isn't tagged
+    mte_tag: u8,
+    /// Number of bytes from the start of the segment tagged with the memory tag
+    mte_len: usize,
+    /// Protection key of the mapping (0 is the default key)
+    pkey: c_int,
+    /// True if the payload is wiped before its pages are unmapped
+    sensitive: bool,
+    /// True if the mapping is wiped in forked children and left out of core dumps
+    secure: bool,
+    /// True if the segment must never move or be copied, so it is only ever resized in place
+    stable: bool,
+    /// True if the pages are shared with forked children instead of copied on write
+    shared: bool,
+    /// True if checkpoints should skip the segment, which is left out of core dumps
+    skip_checkpoint: bool,
+    /// Page size requested for the segment, kept when it moves
+    page_pref: PageSizePref,
+    /// Access pattern advised for the segment
+    access_pattern: AccessPattern,
+    /// Whether KSM has been advised it may merge the segment's pages, if advised either way
+    mergeable: Option<bool>,
+    /// True if mapped with MAP_NORESERVE, so no swap or commit charge is reserved for it
+    noreserve: bool,
+    /// True if aligned for and advised MADV_HUGEPAGE so khugepaged can back it with transparent
+    /// huge pages
+    thp: bool,
+    /// Budget the segment's huge pages are charged to
+    budget: Option<&'static Budget>,
+    /// Bytes charged to the budget
+    charged: usize,
+    /// Allocations and bytes of the segment counted in the missed statistics, taken back if it is
+    /// promoted
+    missed: (usize, usize),
+    /// True if a canary has been written after the payload
+    #[cfg(feature = "canary")]
+    canary: bool,
 }
 
 impl MMap {
-    /// Creates a new anonymous memory mapped segment. A huge page allocation is tried initially.
-    /// If that fails a default page size allocation is tried.
-    pub fn new(layout: Layout) -> nix::Result<MMap> {
-        // Try and map a 2mb page size segment first
-        match Self::map_2mb(layout) {
-            Ok(mmap) => Ok(mmap),
-            Err(_) => Self::map_default(layout),
-        }
-    }
-
     // Returns the pointer as a usize
     pub fn ptr(&self) -> usize {
         self.ptr
     }
 
-    /// Returns the raw pointer to the memory mapped segment
+    /// Returns the raw pointer to the memory mapped segment, carrying the memory tag if the
+    /// segment is tagged
     pub fn as_ptr(&self) -> *mut u8 {
-        self.ptr as *mut u8
+        mte::tagged(self.ptr, self.mte_tag) as *mut u8
     }
 
     /// Returns the allocation size of the segment
@@ -75,31 +123,415 @@ impl MMap {
         self.alloc_size
     }
 
+    /// Returns the page size backing the segment
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns the time the segment was mapped
+    pub fn created(&self) -> Instant {
+        self.created
+    }
+
+    /// Returns the time the segment was last resized, or mapped if it hasn't been resized
+    pub fn resized(&self) -> Instant {
+        self.resized
+    }
+
+    /// Returns the number of bytes mapped beyond the last page of the allocation and any
+    /// reservation, kept by deferred shrinks
+    pub fn trimmable(&self) -> usize {
+        self.alloc_size
+            .saturating_sub(round_to_pages(self.size(), self.page_size).max(self.reserve))
+    }
+
+    /// Sets the size the segment is expected to grow to, so growing up to it doesn't remap. The
+    /// mapping is grown in place straight away if the address range after it is free, otherwise
+    /// it is grown to the reserved size by the next remap. Returns true if the reserved size is
+    /// mapped.
+    pub fn reserve(&mut self, size: usize) -> bool {
+        self.reserve = round_to_pages(size, self.page_size);
+
+        if self.reserve <= self.alloc_size {
+            return true;
+        }
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        let grown = unsafe {
+            self.sys
+                .mremap(self.ptr as *mut c_void, self.alloc_size, self.reserve, MRemapFlags::empty())
+        }
+        .is_ok();
+
+        if grown {
+            self.alloc_size = self.reserve;
+            self.collapse_tried = false;
+        }
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(self);
+
+        grown
+    }
+
+    /// Returns the identifier of the allocation
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sets the identifier of the allocation, for a segment taking over another's contents
+    pub fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+
+    /// Returns the segment's tag
+    pub fn tag(&self) -> Option<&'static str> {
+        self.tag
+    }
+
+    /// Sets the segment's tag
+    pub fn set_tag(&mut self, tag: Option<&'static str>) {
+        self.tag = tag;
+    }
+
     /// Returns true if the mapping uses the default page size
     pub fn is_default_page_size(&self) -> bool {
-        self.page_size == *DEFAULT_PAGE_SIZE
+        !self.huge
     }
 
-    /// Remaps a memory section
-    pub fn remap(&mut self, new_layout: Layout) -> bool {
+    /// Returns true if the segment is a default page size segment which hasn't been offered to the
+    /// kernel for collapsing in to transparent huge pages since it was mapped or last grown
+    pub fn is_collapsible(&self) -> bool {
+        !self.huge && !self.collapse_tried && !self.stable && !self.noreserve
+    }
+
+    /// Records that the segment has been offered for collapsing
+    pub fn set_collapse_tried(&mut self) {
+        self.collapse_tried = true;
+    }
+
+    /// Returns the memory tag of the payload, or 0 if the segment isn't tagged
+    pub fn mte_tag(&self) -> u8 {
+        self.mte_tag
+    }
+
+    /// Returns the number of bytes from the start of the segment carrying the memory tag
+    pub fn mte_len(&self) -> usize {
+        self.mte_len
+    }
+
+    /// Records the memory tag of the payload and the number of bytes tagged with it
+    pub fn set_mte(&mut self, tag: u8, len: usize) {
+        self.mte_tag = tag;
+        self.mte_len = len;
+    }
+
+    /// Enables memory tag checking on the whole mapping
+    pub fn protect_mte(&self) -> nix::Result<()> {
+        let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE | unsafe { ProtFlags::from_bits_unchecked(mte::PROT_MTE) };
+
+        unsafe { self.sys.mprotect(self.ptr as *mut c_void, self.alloc_size, prot) }
+    }
+
+    /// Returns the protection key of the mapping
+    pub fn pkey(&self) -> c_int {
+        self.pkey
+    }
+
+    /// Associates the whole mapping with a protection key
+    pub fn set_pkey(&mut self, pkey: c_int) -> nix::Result<()> {
+        let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+
+        unsafe { self.sys.pkey_mprotect(self.ptr as *mut c_void, self.alloc_size, prot, pkey) }?;
+
+        self.pkey = pkey;
+
+        Ok(())
+    }
+
+    /// Returns true if the payload is wiped before its pages are unmapped
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+
+    /// Sets whether the payload is wiped before its pages are unmapped
+    pub fn set_sensitive(&mut self, sensitive: bool) {
+        self.sensitive = sensitive;
+    }
+
+    /// Returns true if the mapping is wiped in forked children and left out of core dumps
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Advises the kernel to wipe the mapping in forked children and leave it out of core dumps,
+    /// then locks its pages in to memory so they are never swapped out. The segment counts as
+    /// secure once the advice has been taken, even if locking fails.
+    pub fn secure(&mut self) -> nix::Result<()> {
+        let ptr = self.ptr as *mut c_void;
+
+        unsafe {
+            self.sys.madvise(ptr, self.alloc_size, libc::MADV_WIPEONFORK)?;
+            self.sys.madvise(ptr, self.alloc_size, libc::MADV_DONTDUMP)?;
+        }
+
+        self.secure = true;
+
+        unsafe { self.sys.mlock(ptr, self.alloc_size) }
+    }
+
+    /// Returns true if the pages are shared with forked children
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Returns true if checkpoints should skip the segment
+    pub fn skips_checkpoint(&self) -> bool {
+        self.skip_checkpoint
+    }
+
+    /// Marks the segment to be skipped by checkpoints, advising the kernel to leave it out of core
+    /// dumps, or clears the mark, advising the kernel to dump it again unless it is secure
+    pub fn set_skip_checkpoint(&mut self, skip: bool) -> nix::Result<()> {
+        if skip || !self.secure {
+            let advice = if skip { libc::MADV_DONTDUMP } else { libc::MADV_DODUMP };
+
+            unsafe { self.sys.madvise(self.ptr as *mut c_void, self.alloc_size, advice) }?;
+        }
+
+        self.skip_checkpoint = skip;
+
+        Ok(())
+    }
+
+    /// Returns whether KSM has been advised it may merge the segment's pages, or None if it hasn't
+    /// been advised either way
+    pub fn mergeable(&self) -> Option<bool> {
+        self.mergeable
+    }
+
+    /// Advises KSM that it may merge the segment's pages with identical pages, or that it must not
+    pub fn set_mergeable(&mut self, mergeable: bool) -> nix::Result<()> {
+        let advice = if mergeable { libc::MADV_MERGEABLE } else { libc::MADV_UNMERGEABLE };
+
+        unsafe { self.sys.madvise(self.ptr as *mut c_void, self.alloc_size, advice) }?;
+
+        self.mergeable = Some(mergeable);
+
+        Ok(())
+    }
+
+    /// Returns true if the segment was mapped with MAP_NORESERVE
+    pub fn is_noreserve(&self) -> bool {
+        self.noreserve
+    }
+
+    /// Returns true if the segment was aligned for and advised MADV_HUGEPAGE
+    pub fn is_thp(&self) -> bool {
+        self.thp
+    }
+
+    /// Returns the access pattern advised for the segment
+    pub fn access_pattern(&self) -> AccessPattern {
+        self.access_pattern
+    }
+
+    /// Advises the kernel how the segment will be accessed
+    pub fn set_access_pattern(&mut self, pattern: AccessPattern) -> nix::Result<()> {
+        let advice = match pattern {
+            AccessPattern::Normal => libc::MADV_NORMAL,
+            AccessPattern::Streaming => libc::MADV_SEQUENTIAL,
+            AccessPattern::Random => libc::MADV_RANDOM,
+        };
+
+        unsafe { self.sys.madvise(self.ptr as *mut c_void, self.alloc_size, advice) }?;
+
+        self.access_pattern = pattern;
+
+        Ok(())
+    }
+
+    /// Faults in the pages of the segment from the given offset to the end of the mapping in one
+    /// call
+    pub fn prefault(&self, from: usize) -> nix::Result<()> {
+        if from >= self.alloc_size {
+            return Ok(());
+        }
+
+        unsafe {
+            self.sys.madvise(
+                (self.ptr + from) as *mut c_void,
+                self.alloc_size - from,
+                libc::MADV_POPULATE_WRITE,
+            )
+        }
+    }
+
+    /// Returns the page size requested for the segment
+    pub fn page_pref(&self) -> PageSizePref {
+        self.page_pref
+    }
+
+    /// Sets the page size requested for the segment
+    pub fn set_page_pref(&mut self, page_pref: PageSizePref) {
+        self.page_pref = page_pref;
+    }
+
+    /// Records an allocation of bytes which missed huge pages against the segment
+    pub fn add_missed(&mut self, bytes: usize) {
+        self.missed.0 += 1;
+        self.missed.1 += bytes;
+    }
+
+    /// Returns the allocations and bytes recorded as missed against the segment, clearing them
+    pub fn take_missed(&mut self) -> (usize, usize) {
+        take(&mut self.missed)
+    }
+
+    /// Sets the budget the segment's huge pages are charged to, with the bytes already charged
+    pub fn set_budget(&mut self, budget: &'static Budget, charged: usize) {
+        self.budget = Some(budget);
+        self.charged = charged;
+    }
+
+    /// Charges bytes of huge pages the segment is about to grow by to its budget. Returns false if
+    /// the budget can't cover them.
+    fn charge(&mut self, bytes: usize) -> bool {
+        match self.budget {
+            Some(budget) if bytes > 0 => {
+                if !budget.charge(bytes) {
+                    return false;
+                }
+
+                self.charged += bytes;
+
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Refunds the budget any bytes charged beyond the segment's mapped huge pages
+    fn settle(&mut self) {
+        let huge_bytes = if self.huge { self.alloc_size } else { 0 };
+
+        if let Some(budget) = self.budget.filter(|_| self.charged > huge_bytes) {
+            budget.refund(self.charged - huge_bytes);
+            self.charged = huge_bytes;
+        }
+    }
+
+    /// Refunds the budget everything charged for the segment once it is unmapped
+    fn refund(&mut self) {
+        if let Some(budget) = self.budget {
+            budget.refund(self.charged);
+            self.charged = 0;
+        }
+    }
+
+    /// Readies a freed segment for reuse by a new allocation with the layout, which must fit in the
+    /// mapping
+    pub fn recycle(&mut self, layout: Layout) {
+        let now = Instant::now();
+
+        self.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        self.layout = layout;
+        self.created = now;
+        self.resized = now;
+        self.reserve = 0;
+        self.tag = None;
+        self.missed = (0, 0);
+
+        #[cfg(feature = "canary")]
+        {
+            self.canary = false;
+        }
+    }
+
+    /// Returns true if the segment must never move or be copied
+    pub fn is_stable(&self) -> bool {
+        self.stable
+    }
+
+    /// Sets whether the segment must never move or be copied
+    pub fn set_stable(&mut self, stable: bool) {
+        self.stable = stable;
+    }
+
+    /// Zeroes the payload from an offset to its end if the segment is sensitive, in a way the
+    /// compiler can't optimise away
+    fn wipe(&self, from: usize) {
+        let size = self.layout.size();
+
+        if !self.sensitive || from >= size {
+            return;
+        }
+
+        let wipe = || unsafe { libc::explicit_bzero(self.as_ptr().add(from) as *mut c_void, size - from) };
+
+        // The calling thread may have no access to the segment
+        if self.pkey != 0 {
+            pkey::with_access(self.pkey, wipe);
+        } else {
+            wipe();
+        }
+    }
+
+    /// Returns true if a canary has been written after the payload
+    #[cfg(feature = "canary")]
+    pub fn has_canary(&self) -> bool {
+        self.canary
+    }
+
+    /// Records whether a canary has been written after the payload
+    #[cfg(feature = "canary")]
+    pub fn set_canary(&mut self, canary: bool) {
+        self.canary = canary;
+    }
+
+    /// Remaps a memory section. A shrink which leaves the new size at least shrink_percent of the
+    /// mapped size keeps the existing mapping, so the address stays stable and growing again is
+    /// free.
+    pub fn remap(&mut self, new_layout: Layout, shrink_percent: usize) -> bool {
         let new_size = new_layout.size();
-        let new_alloc_size = Self::calc_alloc_size(new_size, self.page_size);
+        let mut new_alloc_size = round_to_pages(new_size, self.page_size).max(self.reserve);
+
+        if new_alloc_size < self.alloc_size
+            && new_size.saturating_mul(100) >= self.alloc_size.saturating_mul(shrink_percent)
+        {
+            // Defer the shrink
+            new_alloc_size = self.alloc_size;
+        }
+
+        // Growing huge pages must fit in the budget
+        if self.huge && !self.charge(new_alloc_size.saturating_sub(self.alloc_size)) {
+            return false;
+        }
+
+        // The bytes cut off may be unmapped, or reused when growing again. They are gone even if the
+        // remap fails.
+        self.wipe(new_size);
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        // A move could break an alignment larger than the page size, and stable segments never move
+        let flags = if new_layout.align() > self.page_size || self.stable {
+            MRemapFlags::empty()
+        } else {
+            MRemapFlags::MREMAP_MAYMOVE
+        };
 
         let ok = if self.alloc_size != new_alloc_size {
             // Try and remap
-            match unsafe {
-                mremap(
-                    self.ptr as *mut c_void,
-                    self.alloc_size,
-                    new_alloc_size,
-                    MRemapFlags::MREMAP_MAYMOVE,
-                    None,
-                )
-            } {
+            match unsafe { self.sys.mremap(self.ptr as *mut c_void, self.alloc_size, new_alloc_size, flags) } {
                 Ok(ptr) => {
                     // Success
                     self.ptr = ptr as usize;
                     self.alloc_size = new_alloc_size;
+                    self.collapse_tried = false;
 
                     true
                 }
@@ -114,70 +546,477 @@ impl MMap {
 
         if ok {
             self.layout = new_layout;
+            self.resized = Instant::now();
         }
 
+        self.settle();
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(self);
+
         ok
     }
 
-    /// Tries to map an anonymous read write segment with default page size
-    fn map_default(layout: Layout) -> nix::Result<MMap> {
-        let page_size = *DEFAULT_PAGE_SIZE;
-        let alloc_size = Self::calc_alloc_size(layout.size(), page_size);
+    /// Unmaps the pages kept after the allocation by deferred shrinks. Shrinking never moves the
+    /// mapping. Returns the number of bytes released.
+    pub fn trim(&mut self) -> nix::Result<usize> {
+        let trimmable = self.trimmable();
 
-        let ptr = Self::map_anon(alloc_size, MapFlags::empty())?;
+        if trimmable == 0 {
+            return Ok(0);
+        }
 
-        Ok(MMap {
-            ptr: ptr as usize,
-            layout,
-            alloc_size,
-            page_size,
-        })
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        let res = unsafe {
+            self.sys.mremap(
+                self.ptr as *mut c_void,
+                self.alloc_size,
+                self.alloc_size - trimmable,
+                MRemapFlags::empty(),
+            )
+        };
+
+        if res.is_ok() {
+            self.alloc_size -= trimmable;
+            self.settle();
+        }
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(self);
+
+        res.map(|_| trimmable)
+    }
+
+    /// Gives up the pages kept after the allocation by deferred shrinks without unmapping them,
+    /// returning their address range, which the caller must unmap. Lets a segment which other
+    /// threads can see be trimmed with the unmap done after it is unlocked. Returns None if there
+    /// is nothing to trim.
+    pub fn detach_tail(&mut self) -> Option<Range<usize>> {
+        let trimmable = self.trimmable();
+
+        if trimmable == 0 {
+            return None;
+        }
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        self.alloc_size -= trimmable;
+        self.settle();
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(self);
+
+        Some(self.ptr + self.alloc_size..self.ptr + self.alloc_size + trimmable)
+    }
+
+    /// Moves a default page size segment on to huge pages without changing its address. The
+    /// segment must start on a huge page boundary, and the address range up to the next huge page
+    /// boundary after it must be free. The contents are copied, so the segment must not be
+    /// accessed during the move. On failure the segment is left as it was.
+    pub fn promote(&mut self) -> nix::Result<()> {
+        let page_size = huge_page_size();
+
+        // Tags, keys and advice would be lost by the move, the copy needs access, the old pages
+        // wouldn't be wiped, stable segments keep their pages, and forked children would lose
+        // sight of shared segments
+        if self.huge
+            || self.mte_tag != 0
+            || self.pkey != 0
+            || self.sensitive
+            || self.secure
+            || self.stable
+            || self.shared
+            || self.skip_checkpoint
+            || self.access_pattern != AccessPattern::Normal
+            || self.mergeable.is_some()
+            || self.noreserve
+            || !self.ptr.is_multiple_of(page_size)
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let alloc_size = round_to_pages(self.layout.size(), page_size);
+
+        // The huge pages must fit in the budget
+        if !self.charge(alloc_size) {
+            return Err(Errno::ENOMEM);
+        }
+
+        let res = self.promote_to(alloc_size, page_size);
+
+        self.settle();
+
+        res
+    }
+
+    /// Moves the segment on to huge pages of page_size, mapping alloc_size bytes
+    fn promote_to(&mut self, alloc_size: usize, page_size: usize) -> nix::Result<()> {
+        let ptr = self.ptr as *mut c_void;
+
+        // Claim the rest of the last huge page by growing the segment in place
+        if alloc_size > self.alloc_size {
+            unsafe { self.sys.mremap(ptr, self.alloc_size, alloc_size, MRemapFlags::empty()) }?;
+        }
+
+        let shrink = || {
+            if alloc_size > self.alloc_size {
+                let _ = unsafe { self.sys.mremap(ptr, alloc_size, self.alloc_size, MRemapFlags::empty()) };
+            }
+        };
+
+        let flags = MapFlags::MAP_HUGETLB | unsafe { MapFlags::from_bits_unchecked(huge_size_flags(page_size)) };
+
+        let huge = match unsafe { self.sys.mmap(alloc_size, flags) } {
+            Ok(huge) => huge,
+            Err(e) => {
+                shrink();
+                return Err(e);
+            }
+        };
+
+        unsafe { copy_nonoverlapping(ptr as *const u8, huge as *mut u8, self.alloc_size) };
+
+        // Replace the segment with the huge page mapping
+        if let Err(e) = unsafe { self.sys.mremap_fixed(huge, alloc_size, ptr, false) } {
+            let _ = unsafe { self.sys.munmap(huge, alloc_size) };
+            shrink();
+            return Err(e);
+        }
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        self.alloc_size = alloc_size;
+        self.page_size = page_size;
+        self.huge = true;
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(self);
+
+        Ok(())
+    }
+
+    /// Returns true if the segment is a huge page segment which can be demoted. Segments with
+    /// settings tied to their pages can't be.
+    pub fn is_demotable(&self) -> bool {
+        self.huge
+            && self.mte_tag == 0
+            && self.pkey == 0
+            && !self.sensitive
+            && !self.secure
+            && !self.stable
+            && !self.shared
+            && !self.skip_checkpoint
+            && self.access_pattern == AccessPattern::Normal
+            && self.mergeable.is_none()
+    }
+
+    /// Maps the default page size pages a huge page segment of alloc_size bytes is demoted on to
+    pub fn map_demotion(sys: &dyn Syscalls, alloc_size: usize) -> nix::Result<*mut c_void> {
+        unsafe { sys.mmap(alloc_size, MapFlags::empty()) }
+    }
+
+    /// Moves a huge page segment on to the default size pages mapped by [MMap::map_demotion] for
+    /// its mapped size, without changing its address, returning its huge pages to the pool. The
+    /// contents are copied, so the segment must not be accessed during the move. On success the
+    /// default pages have become the segment's. On failure the segment is left as it was, and the
+    /// default pages must be unmapped by the caller.
+    pub fn demote(&mut self, default: *mut c_void) -> nix::Result<()> {
+        if !self.is_demotable() {
+            return Err(Errno::EINVAL);
+        }
+
+        let ptr = self.ptr as *mut c_void;
+
+        // Keep the canary after the payload
+        #[cfg(feature = "canary")]
+        let len = self.layout.size() + canary::len(self);
+        #[cfg(not(feature = "canary"))]
+        let len = self.layout.size();
+
+        unsafe { copy_nonoverlapping(ptr as *const u8, default as *mut u8, len) };
+
+        // Replace the segment with the default page mapping
+        unsafe { self.sys.mremap_fixed(default, self.alloc_size, ptr, false) }?;
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        self.page_size = base_page_size();
+        self.huge = false;
+        self.settle();
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(self);
+
+        Ok(())
+    }
+
+    /// Faults in the pages of the segment from the given offset to the end of the mapping, one page
+    /// at a time. Pages which cannot be backed are reported as an error instead of raising SIGBUS
+    /// on first touch.
+    pub fn populate(&self, from: usize) -> nix::Result<()> {
+        let mut offset = from;
+
+        while offset < self.alloc_size {
+            unsafe {
+                self.sys.madvise(
+                    (self.ptr + offset) as *mut c_void,
+                    self.page_size,
+                    libc::MADV_POPULATE_WRITE,
+                )
+            }?;
+
+            offset += self.page_size;
+        }
+
+        Ok(())
     }
 
-    /// Tries to map an anonymous read write segment with 2mb page size
-    fn map_2mb(layout: Layout) -> nix::Result<MMap> {
-        let page_size = 2 * 1024 * 1024;
-        let alloc_size = Self::calc_alloc_size(layout.size(), page_size);
+    /// Tries to map an anonymous read write segment with the base page size, at a random address in
+    /// the placement window if there is one. Shared segments are shared with forked children,
+    /// noreserve segments are mapped with MAP_NORESERVE, and thp segments are aligned to the
+    /// transparent huge page size and advised MADV_HUGEPAGE. The advice is only a hint, so the
+    /// segment is kept if the kernel refuses it.
+    pub fn map_default(
+        sys: &'static dyn Syscalls,
+        layout: Layout,
+        window: Option<Range<usize>>,
+        shared: bool,
+        noreserve: bool,
+        thp: bool,
+    ) -> nix::Result<MMap> {
+        let page_size = base_page_size();
+        let alloc_size = round_to_pages(layout.size(), page_size);
+
+        let mut flags = sharing_flags(shared);
+
+        if noreserve {
+            flags |= MapFlags::MAP_NORESERVE;
+        }
+
+        let align = if thp { layout.align().max(THP_SIZE) } else { layout.align().max(page_size) };
+
+        let ptr = unsafe { map_placed(sys, alloc_size, align, flags, window) }?;
+
+        let mut mmap = MMap::new(ptr, layout, alloc_size, page_size, false, sys);
+        mmap.shared = shared;
+        mmap.noreserve = noreserve;
+        mmap.thp = thp && unsafe { sys.madvise(ptr, alloc_size, libc::MADV_HUGEPAGE) }.is_ok();
+
+        Ok(mmap)
+    }
+
+    /// Tries to map an anonymous read write segment with the given huge page size, at a random
+    /// address in the placement window if there is one. Shared segments are shared with forked
+    /// children.
+    pub fn map_huge(
+        sys: &'static dyn Syscalls,
+        layout: Layout,
+        window: Option<Range<usize>>,
+        shared: bool,
+        page_size: usize,
+    ) -> nix::Result<MMap> {
+        let alloc_size = round_to_pages(layout.size(), page_size);
+
+        // The huge page size bits aren't all named by MapFlags
+        let flags = MapFlags::MAP_HUGETLB
+            | unsafe { MapFlags::from_bits_unchecked(huge_size_flags(page_size)) }
+            | sharing_flags(shared);
 
-        let ptr = Self::map_anon(alloc_size, MapFlags::MAP_HUGETLB | MapFlags::MAP_HUGE_2MB)?;
+        let ptr = unsafe { map_placed(sys, alloc_size, layout.align().max(page_size), flags, window) }?;
 
-        Ok(MMap {
+        let mut mmap = MMap::new(ptr, layout, alloc_size, page_size, true, sys);
+        mmap.shared = shared;
+
+        Ok(mmap)
+    }
+
+    /// Creates the descriptor for a newly mapped segment
+    fn new(ptr: *mut c_void, layout: Layout, alloc_size: usize, page_size: usize, huge: bool, sys: &'static dyn Syscalls) -> MMap {
+        let mmap = MMap {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             ptr: ptr as usize,
             layout,
             alloc_size,
             page_size,
-        })
-    }
-
-    /// Maps an anonymous read write segment with given flags
-    fn map_anon(size: usize, flags: MapFlags) -> nix::Result<*mut c_void> {
-        let ptr = unsafe {
-            mmap(
-                null_mut::<c_void>(),
-                size,
-                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-                MapFlags::MAP_ANON | MapFlags::MAP_PRIVATE | flags,
-                0,
-                0,
-            )
+            huge,
+            sys,
+            created: Instant::now(),
+            resized: Instant::now(),
+            reserve: 0,
+            tag: None,
+            collapse_tried: false,
+            mte_tag: 0,
+            mte_len: 0,
+            pkey: 0,
+            sensitive: false,
+            secure: false,
+            stable: false,
+            shared: false,
+            skip_checkpoint: false,
+            page_pref: PageSizePref::Policy,
+            access_pattern: AccessPattern::Normal,
+            mergeable: None,
+            noreserve: false,
+            thp: false,
+            budget: None,
+            charged: 0,
+            missed: (0, 0),
+            #[cfg(feature = "canary")]
+            canary: false,
+        };
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::mapped(&mmap);
+
+        mmap
+    }
+
+    /// Unmaps the segment, returning the error if the unmap fails. The segment is not unmapped
+    /// again when dropped.
+    pub fn unmap(mut self) -> nix::Result<()> {
+        let res = self.munmap();
+
+        // Leaked huge pages stay charged
+        if res.is_ok() {
+            self.refund();
+        }
+
+        forget(self);
+
+        res
+    }
+
+    /// Moves the pages of the whole mapping to the start of another segment, without copying. If
+    /// keep_source is false the segment's address range is unmapped by the move and the segment must
+    /// be forgotten with [MMap::discard]. Otherwise it stays mapped, empty, until unmapped.
+    pub fn move_pages(&self, dest: &MMap, keep_source: bool) -> nix::Result<()> {
+        unsafe {
+            self.sys.mremap_fixed(self.ptr as *mut c_void, self.alloc_size, dest.ptr as *mut c_void, keep_source)
         }?;
 
-        Ok(ptr)
+        Ok(())
+    }
+
+    /// Forgets a segment whose address range has already been unmapped by moving its pages
+    pub fn discard(mut self) {
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(&self);
+
+        self.refund();
+
+        forget(self);
     }
 
-    /// Calculates the allocation size (whole pages) required for the size required
-    fn calc_alloc_size(size: usize, page_size: usize) -> usize {
-        (((size - 1) / page_size) + 1) * page_size
+    /// Unmaps the anonymous memory mapped segment, wiping it first if it is sensitive
+    fn munmap(&self) -> nix::Result<()> {
+        self.wipe(0);
+
+        #[cfg(any(feature = "asan", feature = "valgrind"))]
+        sanitizer::unmapping(self);
+
+        unsafe { self.sys.munmap(self.ptr as *mut c_void, self.alloc_size) }
     }
 }
 
 impl Drop for MMap {
     /// Unmaps the anonymous memory mapped segment on drop
     fn drop(&mut self) {
-        let size = self.alloc_size();
-
-        if unsafe { munmap(self.ptr as *mut c_void, size) }.is_err() {
+        if self.munmap().is_err() {
             HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: failed to unmap", self.layout);
         }
+
+        self.refund();
     }
 }
+
+/// Number of random addresses tried in the placement window before leaving placement to the kernel
+const PLACEMENT_ATTEMPTS: usize = 8;
+
+/// Maps alloc_size bytes aligned to align, at a random free address in window if there is one.
+/// Falls back to the kernel's placement if no free address is found.
+unsafe fn map_placed(
+    sys: &dyn Syscalls,
+    alloc_size: usize,
+    align: usize,
+    flags: MapFlags,
+    window: Option<Range<usize>>,
+) -> nix::Result<*mut c_void> {
+    if let Some(window) = window {
+        // Aligned start addresses which keep the whole mapping inside the window
+        let first = window.start.checked_next_multiple_of(align);
+        let last = window.end.checked_sub(alloc_size).map(|last| last - last % align);
+
+        if let (Some(first), Some(last)) = (first, last) {
+            if first <= last {
+                let slots = (last - first) / align + 1;
+
+                for _ in 0..PLACEMENT_ATTEMPTS {
+                    let Some(random) = random() else { break };
+                    let addr = first + (random % slots as u64) as usize * align;
+
+                    match sys.mmap_at(addr as *mut c_void, alloc_size, flags) {
+                        Ok(ptr) => return Ok(ptr),
+                        Err(Errno::EEXIST) => continue,
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+    }
+
+    map_aligned(sys, alloc_size, align, flags)
+}
+
+/// Returns the extra mapping flags for a segment shared with forked children or not
+fn sharing_flags(shared: bool) -> MapFlags {
+    if shared {
+        MapFlags::MAP_SHARED
+    } else {
+        MapFlags::empty()
+    }
+}
+
+/// Returns 64 random bits from the kernel, or None if its entropy pool isn't ready
+fn random() -> Option<u64> {
+    let mut random = 0u64;
+
+    let res = unsafe {
+        libc::syscall(libc::SYS_getrandom, &mut random as *mut u64 as *mut c_void, size_of::<u64>(), libc::GRND_NONBLOCK)
+    };
+
+    (res == size_of::<u64>() as libc::c_long).then_some(random)
+}
+
+/// Maps alloc_size bytes aligned to align. Mappings are page aligned, so larger alignments are met
+/// by mapping an extra align bytes and unmapping the unaligned head and the tail.
+unsafe fn map_aligned(sys: &dyn Syscalls, alloc_size: usize, align: usize, flags: MapFlags) -> nix::Result<*mut c_void> {
+    let ptr = sys.mmap(alloc_size, flags)?;
+
+    if (ptr as usize).is_multiple_of(align) {
+        return Ok(ptr);
+    }
+
+    sys.munmap(ptr, alloc_size)?;
+
+    let over_size = alloc_size.checked_add(align).ok_or(Errno::ENOMEM)?;
+    let base = sys.mmap(over_size, flags)? as usize;
+
+    let aligned = base.next_multiple_of(align);
+    let head = aligned - base;
+    let tail = over_size - head - alloc_size;
+
+    if head > 0 {
+        sys.munmap(base as *mut c_void, head)?;
+    }
+
+    if tail > 0 {
+        sys.munmap((aligned + alloc_size) as *mut c_void, tail)?;
+    }
+
+    Ok(aligned as *mut c_void)
+}
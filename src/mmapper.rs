@@ -1,160 +1,1966 @@
 use std::{
     alloc::Layout,
-    collections::HashMap,
     error::Error,
-    ptr::copy_nonoverlapping,
-    sync::{Mutex, MutexGuard},
+    ffi::{c_int, c_void},
+    iter,
+    mem,
+    ops::Range,
+    panic::Location,
+    ptr::{copy_nonoverlapping, null_mut, write_bytes, NonNull},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+        OnceLock,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
 };
 
-use crate::{mmap::MMap, HugeGlobalAllocator, HugeGlobalAllocatorStats};
+use nix::errno::Errno;
+
+#[cfg(feature = "canary")]
+use crate::canary;
+#[cfg(feature = "event-log")]
+use crate::events::{Event, EventLog, Operation};
+#[cfg(feature = "event-ring")]
+use crate::ring::EventRing;
+#[cfg(feature = "tracy")]
+use crate::tracy;
+#[cfg(any(debug_assertions, feature = "verify"))]
+use crate::verify::{self, Entry, VerifyReport};
+use crate::{
+    budget::Budget,
+    cache::NodeCaches,
+    callsite::{Callsites, CallsiteStats},
+    checkpoint::CheckpointSegment,
+    counters::{Churn, ChurnWindow, Gauges, LockStats, ShardedStats, Timer},
+    hooks::{SegmentHooks, SegmentInfo},
+    maintenance::Maintenance,
+    mmap::MMap,
+    mte,
+    numa,
+    overcommit::{Overcommit, OvercommitMode, OvercommitReport},
+    local::{thread_access_pattern, thread_high_priority, thread_max_slack, thread_noreserve},
+    pagesize::{base_page_size, huge_page_size, PageSizePref, GIGANTIC_PAGE_SIZE},
+    probe::{self, ProbeReport},
+    raw::round_to_pages,
+    recent::RecentSegments,
+    report::Segment,
+    segmap::{PtrMap, SegmentMap},
+    snapshot::{SnapshotEntry, Snapshots},
+    sync::{const_fn, Mutex, MutexGuard},
+    sys::{LinuxSyscalls, Syscalls, MADV_COLLAPSE},
+    thp::{self, THP_SIZE},
+    AccessPattern, DemotionVeto, HugeGlobalAllocator, HugeGlobalAllocatorStats, MapStrategy, MoveStrategy, PressureCallback, RemapFailure, UnmapFailure};
+
+/// Initial capacity of the pointer map
+const MAP_INITIAL_CAPACITY: usize = 16;
+
 
 /// A collection of tracked memory mapped segments
 pub struct MMapper {
-    ptr_map: Mutex<Option<HashMap<usize, MMap>>>,
-    stats: Mutex<MMapperStats>,
+    sys: &'static dyn Syscalls,
+    ptr_map: Mutex<Option<PtrMap>>,
+    map_strategy: AtomicU8,
+    map_fixed_capacity: AtomicUsize,
+    stats: ShardedStats,
+    churn: ChurnWindow,
+    callsites: Callsites,
+    default_gauges: Gauges,
+    huge_gauges: Gauges,
+    huge_retries: AtomicUsize,
+    huge_retry_backoff_us: AtomicUsize,
+    sigbus_protection: AtomicBool,
+    pretouch_validation: AtomicBool,
+    mapped: AtomicUsize,
+    soft_limit: AtomicUsize,
+    pressure_callback: Mutex<Option<PressureCallback>>,
+    remap_failure: AtomicU8,
+    move_strategy: AtomicU8,
+    demote_min_idle_ms: AtomicUsize,
+    demotion_veto: Mutex<Option<DemotionVeto>>,
+    hooks: OnceLock<SegmentHooks>,
+    /// Budget huge page segments are charged to
+    budget: OnceLock<&'static Budget>,
+    cache: NodeCaches,
+    snapshots: Snapshots,
+    recent: RecentSegments,
+    placement_start: AtomicUsize,
+    placement_end: AtomicUsize,
+    mte: AtomicBool,
+    mte_used: AtomicBool,
+    secure_profile: AtomicBool,
+    fork_shared: AtomicBool,
+    numa_local: AtomicBool,
+    strict_overcommit: AtomicBool,
+    unmap_failure: AtomicU8,
+    shrink_threshold: AtomicUsize,
+    max_slack: AtomicUsize,
+    maintenance: Maintenance,
+    #[cfg(feature = "event-log")]
+    events: EventLog,
+    #[cfg(feature = "event-ring")]
+    ring: EventRing,
 }
 
-impl MMapper {
-    /// Create a new memory mappings container
-    pub const fn new() -> Self {
-        Self {
-            ptr_map: Mutex::new(None),
-            stats: Mutex::new(MMapperStats::new()),
-        }
+impl MMapper {
+    const_fn! {
+        /// Create a new memory mappings container
+        pub fn new() -> Self {
+            Self::with_syscalls(&LinuxSyscalls)
+        }
+    }
+
+    const_fn! {
+        /// Create a new memory mappings container using the given system calls
+        pub fn with_syscalls(sys: &'static dyn Syscalls) -> Self {
+            Self {
+                sys,
+                ptr_map: Mutex::new(None),
+                map_strategy: AtomicU8::new(0),
+                map_fixed_capacity: AtomicUsize::new(0),
+                stats: ShardedStats::new(),
+                churn: ChurnWindow::new(),
+                callsites: Callsites::new(),
+                default_gauges: Gauges::new(),
+                huge_gauges: Gauges::new(),
+                huge_retries: AtomicUsize::new(0),
+                huge_retry_backoff_us: AtomicUsize::new(0),
+                sigbus_protection: AtomicBool::new(false),
+                pretouch_validation: AtomicBool::new(false),
+                mapped: AtomicUsize::new(0),
+                soft_limit: AtomicUsize::new(0),
+                pressure_callback: Mutex::new(None),
+                remap_failure: AtomicU8::new(RemapFailure::Copy as u8),
+                move_strategy: AtomicU8::new(MoveStrategy::Memcpy as u8),
+                demote_min_idle_ms: AtomicUsize::new(usize::MAX),
+                demotion_veto: Mutex::new(None),
+                hooks: OnceLock::new(),
+                budget: OnceLock::new(),
+                cache: NodeCaches::new(),
+                snapshots: Snapshots::new(),
+                recent: RecentSegments::new(),
+                placement_start: AtomicUsize::new(0),
+                placement_end: AtomicUsize::new(0),
+                mte: AtomicBool::new(false),
+                mte_used: AtomicBool::new(false),
+                secure_profile: AtomicBool::new(false),
+                fork_shared: AtomicBool::new(false),
+                numa_local: AtomicBool::new(false),
+                strict_overcommit: AtomicBool::new(false),
+                unmap_failure: AtomicU8::new(UnmapFailure::Abort as u8),
+                shrink_threshold: AtomicUsize::new(100),
+                max_slack: AtomicUsize::new(100),
+                maintenance: Maintenance::new(),
+                #[cfg(feature = "event-log")]
+                events: EventLog::new(),
+                #[cfg(feature = "event-ring")]
+                ring: EventRing::new(),
+            }
+        }
+    }
+
+    /// Sets the action to take when a segment cannot be unmapped
+    pub fn set_unmap_failure(&self, action: UnmapFailure) {
+        self.unmap_failure.store(action as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the action to take when a segment cannot be unmapped
+    pub fn unmap_failure(&self) -> UnmapFailure {
+        match self.unmap_failure.load(Ordering::Relaxed) {
+            x if x == UnmapFailure::Leak as u8 => UnmapFailure::Leak,
+            x if x == UnmapFailure::Log as u8 => UnmapFailure::Log,
+            _ => UnmapFailure::Abort,
+        }
+    }
+
+    /// Sets the percentage of the mapped size a shrinking reallocation must drop below to release
+    /// pages
+    pub fn set_shrink_threshold(&self, percent: usize) {
+        self.shrink_threshold.store(percent.min(100), Ordering::Relaxed);
+    }
+
+    /// Returns the percentage of the mapped size a shrinking reallocation must drop below to
+    /// release pages
+    pub fn shrink_threshold(&self) -> usize {
+        self.shrink_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Sets the largest percentage of a new huge page mapping which may be left unused by rounding
+    pub fn set_max_slack(&self, percent: usize) {
+        self.max_slack.store(percent.min(100), Ordering::Relaxed);
+    }
+
+    /// Returns the largest percentage of a new huge page mapping which may be left unused by
+    /// rounding
+    pub fn max_slack(&self) -> usize {
+        self.max_slack.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if mapping size bytes with huge pages would leave more than the maximum slack
+    /// unused, taking the current thread's override in to account
+    fn exceeds_max_slack(&self, size: usize) -> bool {
+        let percent = thread_max_slack().unwrap_or_else(|| self.max_slack());
+        let alloc_size = round_to_pages(size, huge_page_size());
+
+        (alloc_size - size).saturating_mul(100) > alloc_size.saturating_mul(percent)
+    }
+
+    /// Registers the segment lifecycle hooks. Fails if hooks have already been registered.
+    pub fn set_hooks(&self, hooks: SegmentHooks) -> Result<(), Box<dyn Error>> {
+        self.hooks.set(hooks).map_err(|_| "segment hooks already registered".into())
+    }
+
+    /// Sets the budget huge page segments mapped from now on are charged to. Fails if a budget has
+    /// already been set.
+    pub fn set_budget(&self, budget: &'static Budget) -> Result<(), Box<dyn Error>> {
+        self.budget.set(budget).map_err(|_| "huge page budget already set".into())
+    }
+
+    /// Returns the budget huge page segments are charged to, if set
+    pub fn budget(&self) -> Option<&'static Budget> {
+        self.budget.get().copied()
+    }
+
+    /// Returns the segment lifecycle hooks if registered
+    fn hooks(&self) -> Option<&SegmentHooks> {
+        self.hooks.get()
+    }
+
+    /// Invokes the remap hook if registered. Must be called without any locks held.
+    fn on_remap(&self, old: &SegmentInfo, new: &SegmentInfo) {
+        if let Some(on_remap) = self.hooks().and_then(|hooks| hooks.on_remap) {
+            on_remap(old, new);
+        }
+    }
+
+    /// Sets the most segments and bytes of freed segments kept mapped for reuse in each segment
+    /// cache slot, releasing the segments already cached. A count or bytes of zero disables the
+    /// cache.
+    pub fn set_segment_cache(&self, count: usize, bytes: usize) {
+        self.cache.set_limits(count, bytes);
+        self.cache.flush(Duration::ZERO, |mmap| self.release(mmap));
+    }
+
+    /// Returns the most segments and bytes of freed segments kept mapped for reuse in each segment
+    /// cache slot
+    pub fn segment_cache(&self) -> (usize, usize) {
+        self.cache.limits()
+    }
+
+    /// Sets the action to take when a segment cannot be remapped
+    pub fn set_remap_failure(&self, action: RemapFailure) {
+        self.remap_failure.store(action as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the action to take when a segment cannot be remapped
+    pub fn remap_failure(&self) -> RemapFailure {
+        match self.remap_failure.load(Ordering::Relaxed) {
+            x if x == RemapFailure::Fail as u8 => RemapFailure::Fail,
+            _ => RemapFailure::Copy,
+        }
+    }
+
+    /// Sets how the contents of a segment are moved when a reallocation moves it
+    pub fn set_move_strategy(&self, strategy: MoveStrategy) {
+        self.move_strategy.store(strategy as u8, Ordering::Relaxed);
+    }
+
+    /// Returns how the contents of a segment are moved when a reallocation moves it
+    pub fn move_strategy(&self) -> MoveStrategy {
+        match self.move_strategy.load(Ordering::Relaxed) {
+            x if x == MoveStrategy::Mremap as u8 => MoveStrategy::Mremap,
+            x if x == MoveStrategy::DontUnmap as u8 => MoveStrategy::DontUnmap,
+            _ => MoveStrategy::Memcpy,
+        }
+    }
+
+    /// Sets the structure the segments are recorded in, moving any recorded segments in to a new
+    /// map of that structure
+    pub fn set_map_strategy(&self, strategy: MapStrategy) {
+        let (kind, fixed) = match strategy {
+            MapStrategy::Hash => (0, 0),
+            MapStrategy::Ordered => (1, 0),
+            MapStrategy::Fixed(capacity) => (2, capacity),
+        };
+
+        self.map_fixed_capacity.store(fixed, Ordering::Relaxed);
+        self.map_strategy.store(kind, Ordering::Relaxed);
+
+        // Rebuild the map, or enlarge a fixed table, unless it hasn't been created yet
+        loop {
+            let len = match self.lock_map().as_ref() {
+                Some(ptr_map) if !ptr_map.is(self.map_strategy()) || ptr_map.capacity() < fixed => ptr_map.len(),
+                _ => break,
+            };
+
+            self.map_grow((len + MAP_INITIAL_CAPACITY).max(fixed));
+        }
+    }
+
+    /// Returns the structure the segments are recorded in
+    pub fn map_strategy(&self) -> MapStrategy {
+        match self.map_strategy.load(Ordering::Relaxed) {
+            1 => MapStrategy::Ordered,
+            2 => MapStrategy::Fixed(self.map_fixed_capacity.load(Ordering::Relaxed)),
+            _ => MapStrategy::Hash,
+        }
+    }
+
+    /// Enables demotion of huge page segments idle for at least min_idle, or disables it if None,
+    /// and sets the hook which can veto demoting a segment
+    pub fn set_demotion(&self, min_idle: Option<Duration>, veto: Option<DemotionVeto>) {
+        *self.lock_demotion_veto() = veto;
+
+        let ms = min_idle.map_or(usize::MAX, |min_idle| (min_idle.as_millis() as usize).min(usize::MAX - 1));
+
+        self.demote_min_idle_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// Returns the idle time after which huge page segments may be demoted, or None if demotion is
+    /// disabled
+    pub fn demotion(&self) -> Option<Duration> {
+        match self.demote_min_idle_ms.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        }
+    }
+
+    /// Sets the address range new segments are placed in at random, or None to leave placement to
+    /// the kernel
+    pub fn set_placement_window(&self, window: Option<Range<usize>>) {
+        let window = window.unwrap_or(0..0);
+
+        // Disable while the range changes
+        self.placement_end.store(0, Ordering::Relaxed);
+        self.placement_start.store(window.start, Ordering::Relaxed);
+        self.placement_end.store(window.end, Ordering::Relaxed);
+    }
+
+    /// Returns the address range new segments are placed in at random, if any
+    pub fn placement_window(&self) -> Option<Range<usize>> {
+        let end = self.placement_end.load(Ordering::Relaxed);
+        let start = self.placement_start.load(Ordering::Relaxed);
+
+        (start < end).then_some(start..end)
+    }
+
+    /// Enables or disables memory tagging of new segments. Fails if memory tagging isn't supported.
+    pub fn set_mte(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        if enabled {
+            mte::enable()?;
+
+            // Segments tagged from now on keep their tags when tagging is switched off again
+            self.mte_used.store(true, Ordering::Relaxed);
+        }
+
+        self.mte.store(enabled, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Returns true if new segments are given memory tags
+    pub fn mte(&self) -> bool {
+        self.mte.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables the security profile, which wipes new segments in forked children,
+    /// leaves them out of core dumps and locks them in to memory
+    pub fn set_secure_profile(&self, enabled: bool) {
+        self.secure_profile.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if the security profile is enabled
+    pub fn secure_profile(&self) -> bool {
+        self.secure_profile.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables mapping new segments with MAP_SHARED, so forked children share their
+    /// pages instead of copying them on write
+    pub fn set_fork_shared(&self, enabled: bool) {
+        self.fork_shared.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if new segments are shared with forked children
+    pub fn fork_shared(&self) -> bool {
+        self.fork_shared.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables binding new segments to the NUMA node of the thread mapping them
+    pub fn set_numa_local(&self, enabled: bool) {
+        self.numa_local.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if new segments are bound to the mapping thread's NUMA node
+    pub fn numa_local(&self) -> bool {
+        self.numa_local.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables behaviour suited to strict overcommit: MAP_NORESERVE requests are
+    /// ignored and new default page size segments are pre-faulted
+    pub fn set_strict_overcommit(&self, enabled: bool) {
+        self.strict_overcommit.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if the mapper behaves as suits strict overcommit
+    pub fn strict_overcommit(&self) -> bool {
+        self.strict_overcommit.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the current thread's new segments are to be mapped with MAP_NORESERVE. The
+    /// kernel ignores it under strict overcommit, so the segments are treated as any other.
+    fn noreserve(&self) -> bool {
+        thread_noreserve() && !self.strict_overcommit()
+    }
+
+    /// Sets the soft limit on mapped bytes and the callback to invoke when it is exceeded
+    pub fn set_soft_limit(&self, bytes: usize, callback: Option<PressureCallback>) {
+        *self.lock_pressure_callback() = callback;
+        self.soft_limit.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Sets the soft limit on mapped bytes, leaving the callback unchanged
+    pub fn set_soft_limit_bytes(&self, bytes: usize) {
+        self.soft_limit.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the soft limit on mapped bytes
+    pub fn soft_limit(&self) -> usize {
+        self.soft_limit.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables pre-faulting of new huge page segments, failing the allocation if the
+    /// segment cannot be backed
+    pub fn set_pretouch_validation(&self, enabled: bool) {
+        self.pretouch_validation.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if pre-touch validation is enabled
+    pub fn pretouch_validation(&self) -> bool {
+        self.pretouch_validation.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables pre-faulting of huge page segments when they are mapped or grown
+    pub fn set_sigbus_protection(&self, enabled: bool) {
+        self.sigbus_protection.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if SIGBUS protection is enabled
+    pub fn sigbus_protection(&self) -> bool {
+        self.sigbus_protection.load(Ordering::Relaxed)
+    }
+
+    /// Sets the number of times a huge page mapping is retried when the huge page pool is
+    /// exhausted, and the initial delay between attempts (doubled after each attempt)
+    pub fn set_huge_retry(&self, retries: usize, backoff: Duration) {
+        self.huge_retries.store(retries, Ordering::Relaxed);
+        self.huge_retry_backoff_us.store(backoff.as_micros() as usize, Ordering::Relaxed);
+    }
+
+    /// Returns the number of huge page mapping retries and the initial delay between attempts
+    pub fn huge_retry(&self) -> (usize, Duration) {
+        (
+            self.huge_retries.load(Ordering::Relaxed),
+            Duration::from_micros(self.huge_retry_backoff_us.load(Ordering::Relaxed) as u64),
+        )
+    }
+
+    /// Enables or disables recording of segment operations in the event log
+    #[cfg(feature = "event-log")]
+    pub fn set_event_recording(&self, enabled: bool) {
+        self.events.set_enabled(enabled);
+    }
+
+    /// Returns the recorded segment operations
+    #[cfg(feature = "event-log")]
+    pub fn events(&self) -> Vec<Event> {
+        self.events.events()
+    }
+
+    /// Clears the recorded segment operations
+    #[cfg(feature = "event-log")]
+    pub fn clear_events(&self) {
+        self.events.clear();
+    }
+
+    /// Returns the ring of segment events for external consumers
+    #[cfg(feature = "event-ring")]
+    pub fn event_ring(&self) -> &EventRing {
+        &self.ring
+    }
+
+    /// Allocates an anonymous memory mapped segment. Returns null if pre-touch validation is
+    /// enabled and the segment could not be backed.
+    #[cfg(any(test, all(feature = "malloc-shim", target_env = "gnu")))]
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_with(layout, PageSizePref::Policy)
+    }
+
+    /// Allocates an anonymous memory mapped segment with the requested page size. Returns null if
+    /// pre-touch validation is enabled and the segment could not be backed.
+    pub fn alloc_with(&self, layout: Layout, pref: PageSizePref) -> *mut u8 {
+        match self.alloc_raw_with(layout, pref) {
+            Some(block) => {
+                let ptr = self.tag_memory(block.as_ptr() as *mut u8);
+
+                #[cfg(feature = "canary")]
+                self.write_canary(ptr);
+
+                ptr
+            }
+            None => null_mut(),
+        }
+    }
+
+    /// Allocates an anonymous memory mapped segment, returning the whole mapping. Returns None if
+    /// pre-touch validation is enabled and the segment could not be backed.
+    pub fn alloc_raw(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        self.alloc_raw_with(layout, PageSizePref::Policy)
+    }
+
+    /// Allocates an anonymous memory mapped segment with the requested page size, returning the
+    /// whole mapping. Returns None if pre-touch validation is enabled and the segment could not be
+    /// backed.
+    pub fn alloc_raw_with(&self, layout: Layout, pref: PageSizePref) -> Option<NonNull<[u8]>> {
+        let mmap = self.alloc_segment(layout, true, pref, thread_access_pattern(), self.noreserve())?;
+
+        #[cfg(feature = "event-log")]
+        self.events.record(Operation::Alloc, 0, mmap.size(), &mmap);
+
+        #[cfg(feature = "event-ring")]
+        self.ring.record(Operation::Alloc, 0, 0, mmap.as_ptr() as usize, mmap.size(), &mmap);
+
+        #[cfg(feature = "tracy")]
+        tracy::alloc(mmap.as_ptr(), mmap.size());
+
+        self.churn.record(Churn::Alloc);
+
+        // Get raw pointer and mapped length
+        let block = NonNull::slice_from_raw_parts(NonNull::new(mmap.as_ptr())?, mmap.alloc_size());
+        let info = SegmentInfo::of(&mmap);
+
+        // Insert in to hash map
+        self.map_add(mmap);
+
+        if let Some(on_map) = self.hooks().and_then(|hooks| hooks.on_map) {
+            on_map(&info);
+        }
+
+        Some(block)
+    }
+
+    /// Allocates an anonymous memory mapped segment for each layout, adding them all to the
+    /// pointer map under one lock acquisition. Entries are null where pre-touch validation is
+    /// enabled and the segment could not be backed.
+    pub fn alloc_batch(&self, layouts: &[Layout]) -> Vec<*mut u8> {
+        let access = thread_access_pattern();
+        let noreserve = self.noreserve();
+        let on_map = self.hooks().and_then(|hooks| hooks.on_map);
+        let mut ptrs = Vec::with_capacity(layouts.len());
+        let mut mmaps = Vec::with_capacity(layouts.len());
+        let mut infos = Vec::with_capacity(if on_map.is_some() { layouts.len() } else { 0 });
+
+        for &layout in layouts {
+            let Some(mut mmap) = self.alloc_segment(layout, true, PageSizePref::Policy, access, noreserve) else {
+                ptrs.push(null_mut());
+                continue;
+            };
+
+            #[cfg(feature = "event-log")]
+            self.events.record(Operation::Alloc, 0, mmap.size(), &mmap);
+
+            #[cfg(feature = "event-ring")]
+            self.ring.record(Operation::Alloc, 0, 0, mmap.as_ptr() as usize, mmap.size(), &mmap);
+
+            #[cfg(feature = "tracy")]
+            tracy::alloc(mmap.as_ptr(), mmap.size());
+
+            self.churn.record(Churn::Alloc);
+
+            if on_map.is_some() {
+                infos.push(SegmentInfo::of(&mmap));
+            }
+
+            // Nothing else can see the segment yet, so it is tagged before it is added
+            ptrs.push(self.tag_segment(&mut mmap));
+
+            #[cfg(feature = "canary")]
+            canary::write(&mut mmap);
+
+            mmaps.push(mmap);
+        }
+
+        // Insert in to hash map
+        self.map_add_all(mmaps.into_iter());
+
+        if let Some(on_map) = on_map {
+            infos.iter().for_each(on_map);
+        }
+
+        ptrs
+    }
+
+    /// Maps an anonymous memory mapped segment with the requested page size and access pattern,
+    /// with MAP_NORESERVE if noreserve is true and it is mapped with default size pages. If strict
+    /// is false, segments failing pre-touch validation are replaced with default page size segments
+    /// instead of failing.
+    fn alloc_segment(&self, layout: Layout, strict: bool, pref: PageSizePref, access: AccessPattern, noreserve: bool) -> Option<MMap> {
+        let size = layout.size();
+
+        // Reuse a segment freed by this thread if there is one, which maps nothing new. Cached
+        // segments are reserved, so aren't used for noreserve segments.
+        if let Some(mut mmap) = (!noreserve).then(|| self.reuse_segment(layout, pref)).flatten() {
+            if access != AccessPattern::Normal {
+                self.advise_access(&mut mmap, access);
+            }
+
+            return Some(mmap);
+        }
+
+        // Check the soft limit before mapping
+        self.check_soft_limit(size);
+
+        // Create the anon memory map
+        let mut mmap = match self.map_segment(layout, strict, pref, noreserve) {
+            Ok(mmap) => mmap?,
+            Err(_) => HugeGlobalAllocator::alloc_error_layout("MMapper::alloc: failed to map segment", layout)
+        };
+
+        if self.secure_profile() {
+            self.secure(&mut mmap);
+        }
+
+        self.advise_access(&mut mmap, access);
+
+        mmap.set_page_pref(pref);
+
+        if mmap.is_default_page_size() && self.wants_huge(size, pref) {
+            // Log missed allocation
+            self.add_missed(&mut mmap, size);
+        }
+
+        if let Some(window) = self.placement_window() {
+            if window.contains(&mmap.ptr()) {
+                self.stats.randomized_placements.add(1);
+            } else {
+                self.stats.placement_fallbacks.add(1);
+            }
+        }
+
+        Some(mmap)
+    }
+
+    /// Deallocates an anonymous memory mapped segment
+    pub fn dealloc(&self, ptr: *mut u8) -> bool {
+        // Remove from the map
+        match self.map_remove(ptr) {
+            Some(mmap) => {
+                #[cfg(feature = "canary")]
+                canary::verify(&mmap);
+
+                #[cfg(feature = "event-log")]
+                self.events.record(Operation::Dealloc, mmap.size(), 0, &mmap);
+
+                #[cfg(feature = "event-ring")]
+                self.ring.record(Operation::Dealloc, mmap.ptr(), mmap.size(), 0, 0, &mmap);
+
+                #[cfg(feature = "tracy")]
+                tracy::free(mmap.ptr() as *const u8);
+
+                self.churn.record(Churn::Dealloc);
+
+                if let Some(on_unmap) = self.hooks().and_then(|hooks| hooks.on_unmap) {
+                    on_unmap(&SegmentInfo::of(&mmap));
+                }
+
+                if self.cacheable(&mmap) {
+                    self.cache.put(mmap, |mmap| self.release(mmap));
+                } else {
+                    self.release(mmap);
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reallocates an anonymous memory mapped segment
+    pub fn realloc(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        match self.realloc_raw(ptr, layout) {
+            Some(block) => {
+                let ptr = self.tag_memory(block.as_ptr() as *mut u8);
+
+                #[cfg(feature = "canary")]
+                self.write_canary(ptr);
+
+                ptr
+            }
+            None => null_mut(),
+        }
+    }
+
+    /// Tags the payload of the segment at ptr if memory tagging is enabled or the segment is
+    /// already tagged, returning the pointer carrying the tag. Returns ptr unchanged if the segment
+    /// isn't tagged, without locking if memory tagging has never been enabled.
+    fn tag_memory(&self, ptr: *mut u8) -> *mut u8 {
+        if !self.mte_used.load(Ordering::Relaxed) {
+            return ptr;
+        }
+
+        let mut lock = self.lock_map();
+
+        match lock.as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            Some(mmap) => self.tag_segment(mmap),
+            None => ptr,
+        }
+    }
+
+    /// Tags the payload of a segment if memory tagging is enabled or the segment is already
+    /// tagged, returning the pointer to hand out
+    fn tag_segment(&self, mmap: &mut MMap) -> *mut u8 {
+        let ptr = mmap.as_ptr();
+        let tagged = mmap.mte_tag() != 0;
+
+        if !tagged && !self.mte() {
+            return ptr;
+        }
+
+        match mte::tag(mmap) {
+            Ok(()) => {
+                if !tagged {
+                    self.stats.mte_segments.add(1);
+                }
+
+                mmap.as_ptr()
+            }
+            Err(_) => ptr,
+        }
+    }
+
+    /// Writes a canary after the payload of the segment at ptr
+    #[cfg(feature = "canary")]
+    fn write_canary(&self, ptr: *mut u8) {
+        if let Some(mmap) = self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            canary::write(mmap);
+        }
+    }
+
+    /// Reallocates an anonymous memory mapped segment, returning the whole mapping. The new layout
+    /// may have a different alignment. Returns None if the remap failure action is Fail and the
+    /// segment could not be remapped.
+    pub fn realloc_raw(&self, ptr: *mut u8, layout: Layout) -> Option<NonNull<[u8]>> {
+        let new_size = layout.size();
+
+        // Remove existing map entry
+        if let Some(mut mmap) = self.map_remove(ptr) {
+            // The whole mapping is returned, so the caller may overwrite the slack
+            #[cfg(feature = "canary")]
+            let had_canary = {
+                canary::verify(&mmap);
+                mmap.has_canary()
+            };
+            #[cfg(feature = "canary")]
+            mmap.set_canary(false);
+
+            let was_default = mmap.is_default_page_size();
+            let old_info = SegmentInfo::of(&mmap);
+            #[cfg(any(feature = "event-ring", feature = "tracy"))]
+            let old_address = mmap.ptr();
+            let old_size = mmap.size();
+            let old_alloc_size = mmap.alloc_size();
+
+            if new_size > old_size {
+                // Check the soft limit before growing
+                self.check_soft_limit(new_size);
+            }
+
+            // A segment which doesn't meet a larger new alignment has to move
+            let misaligned = !mmap.ptr().is_multiple_of(layout.align());
+
+            // Do the reallocate
+            let remapped = !misaligned && mmap.remap(layout, self.shrink_threshold());
+
+            // A stable segment which grew in place is kept even if its new pages couldn't be
+            // pre-faulted, as it can't move
+            if remapped && (self.protect(&mmap, old_alloc_size) || mmap.is_stable()) {
+                if new_size < old_size && mmap.trimmable() > 0 {
+                    self.stats.shrinks_deferred.add(1);
+                }
+
+                // The grown pages of pre-faulted segments need faulting in too
+                if self.prefaults(&mmap) {
+                    let _ = mmap.prefault(old_alloc_size);
+                }
+
+                // Get raw pointer
+                let ptr = mmap.as_ptr();
+
+                if was_default {
+                    // Was default
+                    if new_size > old_size {
+                        // Add extra space as missed
+                        self.add_missed(&mut mmap, new_size - old_size);
+                    }
+                } else if mmap.is_default_page_size() {
+                    // Was huge and is now not
+                    self.add_missed(&mut mmap, new_size);
+                }
+
+                #[cfg(feature = "event-log")]
+                self.events.record(Operation::Realloc, old_size, new_size, &mmap);
+
+                #[cfg(feature = "event-ring")]
+                self.ring.record(Operation::Realloc, old_address, old_size, mmap.ptr(), new_size, &mmap);
+
+                #[cfg(feature = "tracy")]
+                {
+                    tracy::free(old_address as *const u8);
+                    tracy::alloc(mmap.ptr() as *const u8, new_size);
+                }
+
+                self.churn.record(Churn::Remap);
+
+                // Get the whole mapping and insert it back in to the hash map
+                let block = NonNull::slice_from_raw_parts(NonNull::new(ptr)?, mmap.alloc_size());
+                let info = SegmentInfo::of(&mmap);
+                self.map_add(mmap);
+
+                self.on_remap(&old_info, &info);
+
+                Some(block)
+            } else if !remapped && (mmap.is_stable() || !misaligned && self.remap_failure() == RemapFailure::Fail) {
+                // Failed to remap, or a stable segment would have to move - fail the reallocation
+                // leaving the segment untouched
+                if !misaligned {
+                    self.stats.remaps_failed.add(1);
+                }
+
+                self.stats.remaps_refused.add(1);
+
+                #[cfg(feature = "canary")]
+                mmap.set_canary(had_canary);
+
+                // Insert it back in to the hash map
+                self.map_add(mmap);
+
+                None
+            } else {
+                // Failed to remap (or to pre-fault the remapped segment), or misaligned - copy to
+                // a new segment
+                if !misaligned {
+                    self.stats.remaps_failed.add(1);
+                    self.stats.remaps_copied.add(1);
+                    self.stats.remap_copied_bytes.add(old_size.min(new_size) as u64);
+                }
+
+                // Allocate new segment. The old segment may already have moved so this can't
+                // fail validation
+                let mut new_mmap = match self.alloc_segment(layout, false, mmap.page_pref(), mmap.access_pattern(), mmap.is_noreserve()) {
+                    Some(new_mmap) => new_mmap,
+                    None => HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: failed to map segment", layout)
+                };
+
+                new_mmap.set_id(mmap.id());
+                new_mmap.set_tag(mmap.tag());
+                new_mmap.set_sensitive(mmap.is_sensitive());
+
+                if mmap.is_secure() && !new_mmap.is_secure() {
+                    self.secure(&mut new_mmap);
+                }
+
+                if mmap.skips_checkpoint() && new_mmap.set_skip_checkpoint(true).is_err() {
+                    HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: failed to advise segment", layout);
+                }
+
+                if let Some(mergeable) = mmap.mergeable() {
+                    if new_mmap.set_mergeable(mergeable).is_err() {
+                        HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: failed to advise segment", layout);
+                    }
+                }
+
+                let pkey = mmap.pkey();
+
+                #[cfg(feature = "event-log")]
+                self.events.record(Operation::Realloc, old_size, new_size, &new_mmap);
+
+                #[cfg(feature = "event-ring")]
+                self.ring.record(Operation::Realloc, old_address, old_size, new_mmap.as_ptr() as usize, new_size, &new_mmap);
+
+                #[cfg(feature = "tracy")]
+                {
+                    tracy::free(old_address as *const u8);
+                    tracy::alloc(new_mmap.as_ptr(), new_size);
+                }
+
+                self.churn.record(Churn::Remap);
+
+                // Move data from old segment to new and free the old segment
+                let new_ptr = new_mmap.as_ptr();
+                self.move_segment(mmap, &new_mmap, old_size.min(new_size));
+
+                // Keep the segment's protection key once the contents are in place
+                if pkey != 0 && new_mmap.set_pkey(pkey).is_err() {
+                    HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: failed to set protection key", layout);
+                }
+
+                // Insert the new segment in to the hash map
+                let block = NonNull::slice_from_raw_parts(NonNull::new(new_ptr)?, new_mmap.alloc_size());
+                let info = SegmentInfo::of(&new_mmap);
+                self.map_add(new_mmap);
+
+                self.on_remap(&old_info, &info);
+
+                Some(block)
+            }
+        } else {
+            HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: ptr not found", layout);
+        }
+    }
+
+    /// Moves the first bytes of a segment to the start of a new segment with the move strategy and
+    /// frees the old segment. Falls back to copying if the pages can't be moved.
+    fn move_segment(&self, mut mmap: MMap, new_mmap: &MMap, bytes: usize) {
+        let strategy = self.move_strategy();
+
+        // Moved pages would keep their memory tags
+        if strategy != MoveStrategy::Memcpy
+            && mmap.mte_tag() == 0
+            && mmap.page_size() == new_mmap.page_size()
+            && mmap.alloc_size() <= new_mmap.alloc_size()
+        {
+            let keep_source = strategy == MoveStrategy::DontUnmap;
+
+            if mmap.move_pages(new_mmap, keep_source).is_ok() {
+                if keep_source {
+                    self.stats.move_dontunmap_bytes.add(mmap.alloc_size() as u64);
+
+                    // The pages have gone, so there is nothing to wipe
+                    mmap.set_sensitive(false);
+                    self.release(mmap);
+                } else {
+                    self.stats.move_mremap_bytes.add(mmap.alloc_size() as u64);
+                    mmap.discard();
+                }
+
+                return;
+            }
+        }
+
+        unsafe {
+            copy_nonoverlapping(mmap.as_ptr(), new_mmap.as_ptr(), bytes);
+        }
+
+        self.stats.move_memcpy_bytes.add(bytes as u64);
+        self.release(mmap);
+    }
+
+    /// Takes a segment with the mapped size and page size a new segment for the layout would have
+    /// from the current thread's cache, zeroed as a new mapping would be. A segment which fell back
+    /// to default pages can be reused for an allocation wanting huge pages, which would most likely
+    /// fall back too.
+    fn reuse_segment(&self, layout: Layout, pref: PageSizePref) -> Option<MMap> {
+        if !self.cache.enabled() || self.secure_profile() || pref == PageSizePref::Huge1G {
+            return None;
+        }
+
+        let size = layout.size();
+        let take = |page_size| self.cache.take(round_to_pages(size, page_size), page_size, layout.align());
+
+        let wants_huge = self.wants_huge(size, pref);
+
+        let mmap = if wants_huge {
+            take(huge_page_size()).or_else(|| take(base_page_size()))
+        } else {
+            take(base_page_size())
+        };
+
+        let Some(mut mmap) = mmap else {
+            self.stats.segment_cache_misses.add(1);
+            return None;
+        };
+
+        self.stats.segment_cache_hits.add(1);
+
+        unsafe { write_bytes(mmap.as_ptr(), 0, mmap.alloc_size()) };
+
+        mmap.recycle(layout);
+        mmap.set_page_pref(pref);
+
+        if wants_huge && mmap.is_default_page_size() {
+            self.add_missed(&mut mmap, size);
+        }
+
+        Some(mmap)
+    }
+
+    /// Returns true if a freed segment can be cached for reuse. Segments with settings a new
+    /// segment wouldn't have are unmapped instead.
+    fn cacheable(&self, mmap: &MMap) -> bool {
+        self.cache.enabled()
+            && mmap.mte_tag() == 0
+            && mmap.pkey() == 0
+            && !mmap.is_sensitive()
+            && !mmap.is_secure()
+            && !mmap.is_stable()
+            && mmap.is_shared() == self.fork_shared()
+            && !mmap.skips_checkpoint()
+            && mmap.access_pattern() == AccessPattern::Normal
+            && mmap.mergeable().is_none()
+            && !mmap.is_noreserve()
+    }
+
+    /// Advises the kernel of a new segment's access pattern, then pre-faults it if it should be.
+    /// Advice is only a hint, so the segment is kept if the kernel refuses it.
+    fn advise_access(&self, mmap: &mut MMap, pattern: AccessPattern) {
+        if pattern != AccessPattern::Normal && mmap.set_access_pattern(pattern).is_err() {
+            return;
+        }
+
+        if self.prefaults(mmap) {
+            let _ = mmap.prefault(0);
+        }
+    }
+
+    /// Returns true if a segment's pages are faulted in when it is mapped or grown. Random access
+    /// segments are, so scattered first touches don't each take a page fault, as are default page
+    /// size segments under strict overcommit, whose pages are committed when mapped anyway.
+    /// Segments mapped with MAP_NORESERVE are expected to be sparsely touched, so never are.
+    fn prefaults(&self, mmap: &MMap) -> bool {
+        !mmap.is_noreserve()
+            && (mmap.access_pattern() == AccessPattern::Random || (mmap.is_default_page_size() && self.strict_overcommit()))
+    }
+
+    /// Returns true if a segment of size bytes with the requested page size should be mapped with
+    /// huge pages
+    fn wants_huge(&self, size: usize, pref: PageSizePref) -> bool {
+        match pref {
+            PageSizePref::Policy => !self.exceeds_max_slack(size),
+            PageSizePref::Base => false,
+            PageSizePref::Huge | PageSizePref::Huge1G => true,
+        }
+    }
+
+    /// Creates a new anonymous memory mapped segment. A huge page allocation is tried initially,
+    /// retrying with backoff if the huge page pool is exhausted. If that fails a default page size
+    /// allocation is tried, with MAP_NORESERVE if noreserve is true. 1gb pages are tried first if
+    /// requested. Returns None if strict is true and pre-touch validation fails.
+    fn map_segment(&self, layout: Layout, strict: bool, pref: PageSizePref, noreserve: bool) -> nix::Result<Option<MMap>> {
+        if !self.wants_huge(layout.size(), pref) {
+            if pref == PageSizePref::Policy {
+                self.stats.exact_size_allocs.add(1);
+            }
+
+            return self.map_default(layout, noreserve, false).map(Some);
+        }
+
+        if pref == PageSizePref::Huge1G && huge_page_size() != GIGANTIC_PAGE_SIZE {
+            if let Ok(mmap) = self.map_huge(layout, GIGANTIC_PAGE_SIZE) {
+                if self.protect(&mmap, 0) {
+                    return Ok(Some(mmap));
+                }
+
+                self.release(mmap);
+            }
+        }
+
+        let mut retries = self.huge_retries.load(Ordering::Relaxed);
+        let mut backoff = self.huge_retry_backoff_us.load(Ordering::Relaxed) as u64;
+        let mut demoted = false;
+
+        loop {
+            // Try and map a huge page size segment first
+            match self.map_huge(layout, huge_page_size()) {
+                Ok(mmap) if self.protect(&mmap, 0) => break Ok(Some(mmap)),
+                Ok(mmap) => {
+                    // Could not back the segment with huge pages
+                    self.release(mmap);
+
+                    if strict && self.pretouch_validation.load(Ordering::Relaxed) {
+                        break Ok(None);
+                    }
+
+                    break self.map_fallback(layout, noreserve).map(Some);
+                }
+                Err(Errno::ENOMEM) if retries > 0 => {
+                    // Huge page pool exhausted - wait and try again
+                    self.stats.huge_retries.add(1);
+
+                    if backoff > 0 {
+                        sleep(Duration::from_micros(backoff));
+                        backoff = backoff.saturating_mul(2);
+                    }
+
+                    retries -= 1;
+                }
+                Err(Errno::ENOMEM) if !demoted && thread_high_priority() => {
+                    // Huge page pool exhausted - make room by demoting idle segments
+                    demoted = true;
+
+                    if self.demote(round_to_pages(layout.size(), huge_page_size())) == 0 {
+                        break self.map_fallback(layout, noreserve).map(Some);
+                    }
+                }
+                Err(_) => break self.map_fallback(layout, noreserve).map(Some),
+            }
+        }
+    }
+
+    /// Maps a segment with the given huge page size, placed as configured. Fails with ENOMEM, as
+    /// though the huge page pool were exhausted, if the budget can't cover the segment.
+    fn map_huge(&self, layout: Layout, page_size: usize) -> nix::Result<MMap> {
+        let budget = self.budget();
+        let charge = round_to_pages(layout.size(), page_size);
+
+        if budget.is_some_and(|budget| !budget.charge(charge)) {
+            return Err(Errno::ENOMEM);
+        }
+
+        match MMap::map_huge(self.sys, layout, self.placement_window(), self.fork_shared(), page_size) {
+            Ok(mut mmap) => {
+                if let Some(budget) = budget {
+                    mmap.set_budget(budget, charge);
+                }
+
+                Ok(self.bind(mmap))
+            }
+            Err(e) => {
+                if let Some(budget) = budget {
+                    budget.refund(charge);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Maps the default page size segment for an allocation which couldn't get huge pages. If it
+    /// spans a transparent huge page it is aligned for and advised MADV_HUGEPAGE, so khugepaged can
+    /// back it with transparent huge pages later, unless it is mapped with MAP_NORESERVE and
+    /// expected to be sparsely touched.
+    fn map_fallback(&self, layout: Layout, noreserve: bool) -> nix::Result<MMap> {
+        let thp = !noreserve && round_to_pages(layout.size(), base_page_size()) >= THP_SIZE;
+
+        self.map_default(layout, noreserve, thp)
+    }
+
+    /// Maps a segment with the base page size, placed as configured. The segment draws from the
+    /// budget if it is promoted.
+    fn map_default(&self, layout: Layout, noreserve: bool, thp: bool) -> nix::Result<MMap> {
+        let window = self.placement_window();
+
+        MMap::map_default(self.sys, layout, window, self.fork_shared(), noreserve, thp).map(|mut mmap| {
+            if let Some(budget) = self.budget() {
+                mmap.set_budget(budget, 0);
+            }
+
+            self.bind(mmap)
+        })
+    }
+
+    /// Binds a newly mapped segment to the current thread's NUMA node if enabled, before any of its
+    /// pages are faulted in. Binding is a preference, so failures are ignored.
+    fn bind(&self, mmap: MMap) -> MMap {
+        if self.numa_local() {
+            let _ = unsafe { self.sys.mbind(mmap.ptr() as *mut c_void, mmap.alloc_size(), numa::running_node()) };
+        }
+
+        mmap
+    }
+
+    /// Demotes eligible huge page segments to default pages, least recently resized first, until
+    /// bytes of huge pages have been freed. Returns the number of bytes freed.
+    fn demote(&self, bytes: usize) -> usize {
+        let Some(min_idle) = self.demotion() else { return 0 };
+        let veto = *self.lock_demotion_veto();
+        let now = Instant::now();
+        let mut freed = 0;
+        let mut last = None;
+
+        while freed < bytes {
+            // Find the least recently resized idle segment not yet visited
+            let next = self.lock_map().as_ref().and_then(|ptr_map| {
+                ptr_map
+                    .values()
+                    .filter(|mmap| mmap.is_demotable() && now.saturating_duration_since(mmap.resized()) >= min_idle)
+                    .map(|mmap| ((mmap.resized(), mmap.ptr()), mmap.as_ptr(), mmap.size(), mmap.alloc_size(), mmap.tag()))
+                    .filter(|&(key, ..)| last.is_none_or(|last| key > last))
+                    .min_by_key(|&(key, ..)| key)
+            });
+
+            let Some((key, ptr, size, alloc_size, tag)) = next else { break };
+            last = Some(key);
+
+            if veto.is_some_and(|veto| veto(ptr, size, tag)) {
+                self.stats.demotions_vetoed.add(1);
+                continue;
+            }
+
+            // Map the default pages before locking
+            let Ok(default) = MMap::map_demotion(self.sys, alloc_size) else { break };
+
+            // Hold the lock while the contents move so the segment can't be freed or resized. A
+            // segment resized since it was chosen, or freed and replaced, is left alone.
+            let demoted = self.lock_map().as_mut().is_some_and(|ptr_map| {
+                ptr_map
+                    .get_mut(&key.1)
+                    .filter(|mmap| mmap.resized() == key.0 && mmap.alloc_size() == alloc_size)
+                    .is_some_and(|mmap| self.demote_locked(mmap, default))
+            });
+
+            if demoted {
+                self.stats.demotions.add(1);
+
+                freed += alloc_size;
+            } else {
+                let _ = unsafe { self.sys.munmap(default, alloc_size) };
+            }
+        }
+
+        freed
+    }
+
+    /// Moves a segment on to the default pages mapped for it, keeping the gauges and snapshots up
+    /// to date and counting the segment as missed. Must be called with the pointer map locked.
+    /// Returns false if the move failed.
+    fn demote_locked(&self, mmap: &mut MMap, default: *mut c_void) -> bool {
+        self.gauges(mmap).sub(mmap);
+        let res = mmap.demote(default);
+        self.gauges(mmap).add(mmap);
+
+        self.snapshots.insert(SnapshotEntry::of(mmap));
+
+        if res.is_ok() {
+            self.add_missed(mmap, mmap.size());
+        }
+
+        res.is_ok()
+    }
+
+    /// Applies the security profile's advice and memory lock to a new segment. Failures, usually
+    /// from exceeding RLIMIT_MEMLOCK, are counted and leave the segment usable.
+    fn secure(&self, mmap: &mut MMap) {
+        if mmap.secure().is_ok() {
+            self.stats.secure_segments.add(1);
+        } else {
+            self.stats.secure_failures.add(1);
+        }
+    }
+
+    /// Unmaps a segment, applying the unmap failure policy if the unmap fails
+    fn release(&self, mmap: MMap) {
+        let layout = mmap.layout();
+        let alloc_size = mmap.alloc_size();
+
+        if mmap.unmap().is_err() {
+            let action = self.unmap_failure();
+
+            if action == UnmapFailure::Abort {
+                HugeGlobalAllocator::alloc_error_layout("MMapper::release: failed to unmap", layout);
+            }
+
+            self.stats.unmaps_failed.add(1);
+            self.stats.leaked_bytes.add(alloc_size as u64);
+
+            if action == UnmapFailure::Log {
+                HugeGlobalAllocator::log("MMapper::release: failed to unmap, segment leaked");
+            }
+        }
+    }
+
+    /// Pre-faults huge pages in a segment from the given offset if SIGBUS protection or pre-touch
+    /// validation is enabled. Returns false if the huge page pool could not back the segment.
+    fn protect(&self, mmap: &MMap, from: usize) -> bool {
+        if mmap.is_default_page_size()
+            || !(self.sigbus_protection.load(Ordering::Relaxed) || self.pretouch_validation.load(Ordering::Relaxed))
+        {
+            return true;
+        }
+
+        let timer = Timer::start();
+        let ok = mmap.populate(from).is_ok();
+        let elapsed = timer.elapsed_ns();
+
+        self.stats.prefault_time_ns.add(elapsed);
+        self.stats.prefault_max_time_ns.max(elapsed);
+
+        if !ok {
+            self.stats.populate_failed.add(1);
+        }
+
+        ok
+    }
+
+    /// Invokes the pressure callback if mapping the given number of bytes would exceed the soft
+    /// limit. Must be called without any locks held as the callback may free memory.
+    fn check_soft_limit(&self, bytes: usize) {
+        let limit = self.soft_limit.load(Ordering::Relaxed);
+
+        if limit == 0 || self.mapped.load(Ordering::Relaxed) + bytes <= limit {
+            return;
+        }
+
+        self.stats.soft_limit_exceeded.add(1);
+
+        let callback = *self.lock_pressure_callback();
+
+        if let Some(callback) = callback {
+            callback(self.mapped.load(Ordering::Relaxed) + bytes, limit);
+        }
+    }
+
+    /// Returns statistics for the mapper
+    pub(crate) fn stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
+        // Lock the ptr_map
+        Ok(self.stats_locked(&self.lock_map()))
+    }
+
+    /// Returns statistics for the mapper if the pointer map isn't locked by another thread,
+    /// without waiting
+    pub(crate) fn try_stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
+        match self.ptr_map.try_lock() {
+            Some(ptr_map) => Ok(self.stats_locked(&ptr_map)),
+            None => Err("MMapper::try_stats: pointer map is locked".into()),
+        }
+    }
+
+    /// Returns statistics for the mapper from the locked pointer map
+    fn stats_locked(&self, ptr_map: &Option<PtrMap>) -> HugeGlobalAllocatorStats {
+        let mut out_stats = HugeGlobalAllocatorStats::default();
+
+        if let Some(ptr_map) = ptr_map.as_ref() {
+            for mmap in ptr_map.values() {
+                out_stats.alloc += mmap.size();
+                out_stats.mapped += mmap.alloc_size();
+                out_stats.segments += 1;
+
+                if mmap.is_default_page_size() {
+                    out_stats.default_alloc += mmap.size();
+                    out_stats.default_mapped += mmap.alloc_size();
+                    out_stats.default_segments += 1;
+                } else {
+                    out_stats.huge_alloc += mmap.size();
+                    out_stats.huge_mapped += mmap.alloc_size();
+                    out_stats.huge_segments += 1;
+                }
+            }
+        }
+
+        self.stats.read(&mut out_stats);
+        self.lock_stats().for_each(|stats| stats.read(&mut out_stats));
+        self.churn.read(&mut out_stats);
+        self.derive_stats(&mut out_stats);
+
+        out_stats
+    }
+
+    /// Returns statistics for the mapper without taking any locks. The segment totals are kept in
+    /// atomics alongside the pointer map, so a snapshot taken while other threads are allocating
+    /// may mix values from either side of an operation.
+    pub(crate) fn stats_snapshot(&self) -> HugeGlobalAllocatorStats {
+        let mut out_stats = HugeGlobalAllocatorStats::default();
+
+        (out_stats.default_alloc, out_stats.default_mapped, out_stats.default_segments) = self.default_gauges.read();
+        (out_stats.huge_alloc, out_stats.huge_mapped, out_stats.huge_segments) = self.huge_gauges.read();
+
+        out_stats.alloc = out_stats.default_alloc + out_stats.huge_alloc;
+        out_stats.mapped = out_stats.default_mapped + out_stats.huge_mapped;
+        out_stats.segments = out_stats.default_segments + out_stats.huge_segments;
+
+        self.stats.read(&mut out_stats);
+        self.lock_stats().for_each(|stats| stats.read(&mut out_stats));
+        self.churn.read(&mut out_stats);
+        self.derive_stats(&mut out_stats);
+
+        out_stats
+    }
+
+    /// Calculates the statistics derived from the segment totals
+    fn derive_stats(&self, out_stats: &mut HugeGlobalAllocatorStats) {
+        out_stats.efficiency = (out_stats.alloc * 100).checked_div(out_stats.mapped).unwrap_or(100).min(100);
+        out_stats.pressure = (out_stats.mapped * 100)
+            .checked_div(self.soft_limit.load(Ordering::Relaxed))
+            .unwrap_or(0);
+    }
+
+    /// Resets the event counters. Statistics describing the mapped segments are unaffected
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+        self.lock_stats().for_each(LockStats::reset);
+        self.callsites.reset();
     }
 
-    /// Allocates an anonymous memory mapped segment
-    pub fn alloc(&self, layout: Layout) -> *mut u8 {
-        let size = layout.size();
+    /// Moves default page size segments which start on a huge page boundary on to huge pages.
+    /// Returns the number of segments moved.
+    ///
+    /// # Safety
+    ///
+    /// The segments are copied, so no other thread may access or free memory mapped allocations
+    /// during the call.
+    pub unsafe fn promote(&self) -> usize {
+        let huge_page_size = huge_page_size();
+        let mut promoted = 0;
+        let mut next = 0;
 
-        // Create the anon memory map
-        let mmap = match MMap::new(layout) {
-            Ok(mmap) => mmap,
-            Err(_) => HugeGlobalAllocator::alloc_error_layout("MMapper::alloc: failed to map segment", layout)
-        };
+        loop {
+            // Find the lowest eligible segment not yet visited
+            let ptr = self.lock_map().as_ref().and_then(|ptr_map| {
+                ptr_map
+                    .values()
+                    .filter(|mmap| mmap.is_default_page_size() && mmap.ptr().is_multiple_of(huge_page_size) && mmap.ptr() >= next)
+                    .map(MMap::ptr)
+                    .min()
+            });
 
-        if mmap.is_default_page_size() {
-            // Log missed allocation
-            self.add_missed(size);
-        }
+            let Some(ptr) = ptr else { break };
+            next = ptr + 1;
 
-        // Get raw pointer
-        let ptr = mmap.as_ptr();
+            let Some(mut mmap) = self.map_remove(ptr as *mut u8) else { continue };
 
-        // Insert in to hash map
-        self.map_add(mmap);
+            if mmap.promote().is_ok() {
+                // Take back what was counted as missed for the segment
+                let (allocs, bytes) = mmap.take_missed();
 
-        ptr
-    }
+                self.stats.promotions.add(1);
+                self.stats.missed_allocs.sub(allocs as u64);
+                self.stats.missed_bytes.sub(bytes as u64);
 
-    /// Deallocates an anonymous memory mapped segment
-    pub fn dealloc(&self, ptr: *mut u8) -> bool {
-        // Remove from the map
-        self.map_remove(ptr).is_some()
-    }
+                promoted += 1;
+            }
 
-    /// Reallocates an anonymous memory mapped segment
-    pub fn realloc(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
-        let new_size = layout.size();
+            self.map_add(mmap);
+        }
 
-        // Remove existing map entry
-        if let Some(mut mmap) = self.map_remove(ptr) {
-            let was_default = mmap.is_default_page_size();
-            let old_size = mmap.size();
+        promoted
+    }
 
-            // Do the reallocate
-            if mmap.remap(layout) {
-                // Get raw pointer
-                let ptr = mmap.as_ptr();
+    /// Probes the system calls the mapper depends on, switching off enabled settings which would
+    /// fail at runtime because a call is unavailable
+    pub fn probe(&self) -> ProbeReport {
+        let probes = probe::run(self.sys);
+        let available = |name| probes.iter().any(|probe| probe.name == name && probe.available());
+        let mut disabled = Vec::new();
 
-                if was_default {
-                    // Was default
-                    if new_size > old_size {
-                        // Add extra space as missed
-                        self.add_missed(new_size - old_size);
-                    }
-                } else if mmap.is_default_page_size() {
-                    // Was huge and is now not
-                    self.add_missed(new_size);
+        if !available(probe::MREMAP) && self.remap_failure() == RemapFailure::Fail {
+            // Every growing reallocation would fail instead of copying
+            self.set_remap_failure(RemapFailure::Copy);
+            disabled.push("remap_failure=fail");
+        }
+
+        match self.move_strategy() {
+            MoveStrategy::DontUnmap if !available(probe::MREMAP_DONTUNMAP) => {
+                if available(probe::MREMAP_FIXED) {
+                    self.set_move_strategy(MoveStrategy::Mremap);
+                } else {
+                    self.set_move_strategy(MoveStrategy::Memcpy);
                 }
 
-                // Insert it back in to the hash map
-                self.map_add(mmap);
+                disabled.push("move_strategy=dontunmap");
+            }
+            MoveStrategy::Mremap if !available(probe::MREMAP_FIXED) => {
+                self.set_move_strategy(MoveStrategy::Memcpy);
+                disabled.push("move_strategy=mremap");
+            }
+            _ => (),
+        }
 
-                ptr
-            } else {
-                // Failed to remap
-                let mut stats = self.lock_stats();
+        if !available(probe::MADV_POPULATE_WRITE) {
+            // Pre-faulting would fail every huge page segment
+            if self.pretouch_validation() {
+                self.set_pretouch_validation(false);
+                disabled.push("pretouch_validation=true");
+            }
 
-                stats.remaps_failed += 1;
+            if self.sigbus_protection() {
+                self.set_sigbus_protection(false);
+                disabled.push("sigbus_protection=true");
+            }
+        }
 
-                drop(stats);
+        ProbeReport { probes, disabled }
+    }
 
-                // Allocate new segment
-                let new_ptr = self.alloc(layout);
+    /// Adjusts the settings to the kernel's overcommit policy, reporting what to expect from
+    /// allocations under it
+    pub fn adapt_to_overcommit(&self, overcommit: Option<Overcommit>) -> OvercommitReport {
+        let mut adjusted = Vec::new();
+        let mut warnings = Vec::new();
 
-                // Copy data from old segment to new
-                unsafe {
-                    copy_nonoverlapping(mmap.as_ptr(), new_ptr, old_size);
+        match overcommit.map(|overcommit| overcommit.mode) {
+            None => warnings.push("overcommit policy unreadable, settings left unchanged".to_string()),
+            Some(OvercommitMode::Never) => {
+                if !self.strict_overcommit() {
+                    self.set_strict_overcommit(true);
+                    adjusted.push("strict_overcommit=true");
                 }
 
-                new_ptr
+                let available = match overcommit.and_then(|overcommit| overcommit.available()) {
+                    Some(available) => format!("{available} bytes"),
+                    None => "an unknown number of bytes".to_string(),
+                };
+
+                warnings.push(format!(
+                    "strict overcommit: mappings are charged in full against the commit limit, which has {available} \
+                     left, and fail once it is reached"
+                ));
+                warnings.push("strict overcommit: MAP_NORESERVE is ignored, sparse mappings are charged in full".to_string());
             }
-        } else {
-            HugeGlobalAllocator::alloc_error_layout("MMapper::realloc: ptr not found", layout);
+            Some(OvercommitMode::Always) => warnings.push(
+                "overcommit always allowed: mappings never fail, running out of memory kills the process when pages are \
+                 touched"
+                    .to_string(),
+            ),
+            Some(OvercommitMode::Heuristic) => (),
+        }
+
+        OvercommitReport {
+            overcommit,
+            adjusted,
+            warnings,
         }
     }
 
-    /// Returns statistics for the mapper
-    pub(crate) fn stats(&self) -> Result<HugeGlobalAllocatorStats, Box<dyn Error>> {
-        let mut out_stats = HugeGlobalAllocatorStats::default();
+    /// Asks the kernel to collapse each default page size segment not yet offered in to
+    /// transparent huge pages in place. Addresses and contents are unchanged, so this is safe while
+    /// other threads use the segments. Returns the number of segments collapsed.
+    pub fn collapse(&self) -> usize {
+        let mut collapsed = 0;
+        let mut next = 0;
 
-        // Lock the ptr_map
-        if let Some(ptr_map) = self.lock_map().as_ref() {
-            for mmap in ptr_map.values() {
-                out_stats.alloc += mmap.size();
-                out_stats.mapped += mmap.alloc_size();
-                out_stats.segments += 1;
+        loop {
+            // Find the lowest eligible segment not yet visited
+            let segment = self.lock_map().as_ref().and_then(|ptr_map| {
+                ptr_map
+                    .values()
+                    .filter(|mmap| mmap.is_collapsible() && mmap.ptr() >= next)
+                    .map(|mmap| (mmap.ptr(), mmap.alloc_size()))
+                    .min()
+            });
 
-                if mmap.is_default_page_size() {
-                    out_stats.default_alloc += mmap.size();
-                    out_stats.default_mapped += mmap.alloc_size();
-                    out_stats.default_segments += 1;
-                } else {
-                    out_stats.huge_alloc += mmap.size();
-                    out_stats.huge_mapped += mmap.alloc_size();
-                    out_stats.huge_segments += 1;
+            let Some((ptr, alloc_size)) = segment else { break };
+            next = ptr + 1;
+
+            // Collapsing can take a while so it is done unlocked. If the segment is freed meanwhile
+            // the advice just fails.
+            match unsafe { self.sys.madvise(ptr as *mut c_void, alloc_size, MADV_COLLAPSE) } {
+                Ok(()) => {
+                    self.stats.collapses.add(1);
+                    collapsed += 1;
                 }
+                Err(_) => self.stats.collapses_failed.add(1),
+            }
+
+            if let Some(mmap) = self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&ptr)) {
+                mmap.set_collapse_tried();
             }
         }
 
-        let stats = self.lock_stats();
+        collapsed
+    }
 
-        out_stats.missed_allocs = stats.missed_allocs;
-        out_stats.missed_mb = stats.missed_mb as f64 + (stats.missed_bytes as f64 / (1024 * 1024) as f64);
-        out_stats.remaps_failed = stats.remaps_failed;
+    /// Records the size the segment at ptr is expected to grow to, mapping it now if it can be
+    /// grown in place. Returns false if the pointer is not managed.
+    pub fn reserve_hint(&self, ptr: *mut u8, size: usize) -> bool {
+        // Check the soft limit before growing
+        self.check_soft_limit(size);
 
-        drop(stats);
+        let Some(mut mmap) = self.map_remove(ptr) else { return false };
+        let old_alloc_size = mmap.alloc_size();
 
-        if out_stats.mapped == 0 {
-            out_stats.efficiency = 100;
-        } else {
-            out_stats.efficiency = (out_stats.alloc * 100) / out_stats.mapped;
+        if mmap.reserve(size) && !self.protect(&mmap, old_alloc_size) {
+            // The reserved huge pages couldn't be backed - give them back
+            mmap.reserve(0);
+            let _ = mmap.trim();
+        }
+
+        self.map_add(mmap);
+
+        true
+    }
+
+    /// Releases the pages kept by deferred shrinks in segments not resized for at least min_age,
+    /// and the freed segments cached for at least min_age. Returns the number of bytes released.
+    pub fn trim(&self, min_age: Duration) -> usize {
+        // Release segments cached for at least min_age
+        let mut trimmed = self.cache.flush(min_age, |mmap| self.release(mmap));
+
+        self.stats.trimmed_bytes.add(trimmed as u64);
+
+        let mut next = 0;
+
+        loop {
+            // Detach the pages of the lowest eligible segment not yet visited
+            let tail = self.lock_map().as_mut().and_then(|ptr_map| {
+                let ptr = ptr_map
+                    .values()
+                    .filter(|mmap| mmap.trimmable() > 0 && mmap.resized().elapsed() >= min_age && mmap.ptr() >= next)
+                    .map(MMap::ptr)
+                    .min()?;
+
+                next = ptr + 1;
+
+                self.detach_tail(ptr_map.get_mut(&ptr)?)
+            });
+
+            let Some(tail) = tail else { break };
+
+            trimmed += self.unmap_tail(tail);
+        }
+
+        trimmed
+    }
+
+    /// Releases the pages kept after the allocation in the segment at ptr by deferred shrinks or a
+    /// reservation, cancelling the reservation. Returns the number of bytes released, or None if
+    /// the pointer is not managed.
+    pub fn trim_segment(&self, ptr: *mut u8) -> Option<usize> {
+        let tail = {
+            let mut lock = self.lock_map();
+            let mmap = lock.as_mut()?.get_mut(&key(ptr))?;
+
+            mmap.reserve(0);
+
+            self.detach_tail(mmap)
+        };
+
+        Some(tail.map_or(0, |tail| self.unmap_tail(tail)))
+    }
+
+    /// Detaches the pages kept after the allocation in a segment held in the pointer map, updating
+    /// the totals. The returned address range must be unmapped with [unmap_tail](Self::unmap_tail)
+    /// once the map is unlocked.
+    fn detach_tail(&self, mmap: &mut MMap) -> Option<Range<usize>> {
+        self.gauges(mmap).sub(mmap);
+
+        let tail = mmap.detach_tail();
+
+        self.gauges(mmap).add(mmap);
+
+        if let Some(tail) = &tail {
+            self.mapped.fetch_sub(tail.len(), Ordering::Relaxed);
+        }
+
+        tail
+    }
+
+    /// Unmaps pages detached from a segment. Must be called without the map locked. The pages
+    /// are no longer part of any segment, so they are leaked if the unmap fails. Returns the
+    /// number of bytes released.
+    fn unmap_tail(&self, tail: Range<usize>) -> usize {
+        let bytes = tail.len();
+
+        if unsafe { self.sys.munmap(tail.start as *mut c_void, bytes) }.is_err() {
+            self.stats.unmaps_failed.add(1);
+            self.stats.leaked_bytes.add(bytes as u64);
+
+            return 0;
         }
 
-        Ok(out_stats)
+        self.stats.trimmed_bytes.add(bytes as u64);
+
+        bytes
+    }
+
+    /// Runs one maintenance pass
+    pub fn maintain(&self) {
+        self.stats.maintenance_passes.add(1);
+        self.trim(self.maintenance.interval());
+        self.collapse();
+    }
+
+    /// Returns the maintenance worker state
+    pub(crate) fn maintenance(&self) -> &Maintenance {
+        &self.maintenance
+    }
+
+    /// Attributes an explicit allocation to the callsite which requested it
+    pub fn record_callsite(&self, location: &'static Location<'static>, size: usize) {
+        self.callsites.record(location, size);
+    }
+
+    /// Returns the allocations attributed to each callsite
+    pub fn callsite_stats(&self) -> Vec<CallsiteStats> {
+        self.callsites.stats()
     }
 
     /// Returns true if the passed pointer is managed by the mapper
     pub(crate) fn is_managed_ptr(&self, ptr: *mut u8) -> bool {
+        // Look in the snapshot rather than locking the ptr_map
+        self.snapshots.get(key(ptr)).is_some()
+    }
+
+    /// Returns true if the passed pointer, about to be reallocated, is managed by the mapper. The
+    /// current thread's most recent segment is recognised without a lookup.
+    pub(crate) fn is_managed_realloc(&self, ptr: *mut u8) -> bool {
+        if self.recent.contains(key(ptr)) {
+            self.stats.recent_segment_hits.add(1);
+            return true;
+        }
+
+        let managed = self.is_managed_ptr(ptr);
+
+        if managed {
+            self.stats.recent_segment_misses.add(1);
+        }
+
+        managed
+    }
+
+    /// Returns true if the passed pointer is managed by the mapper and backed by huge pages
+    pub(crate) fn is_huge_ptr(&self, ptr: *mut u8) -> bool {
+        self.snapshots.get(key(ptr)).is_some_and(|entry| entry.huge)
+    }
+
+    /// Returns true if the passed pointer is managed by the mapper and has a stable address
+    pub(crate) fn is_stable_ptr(&self, ptr: *mut u8) -> bool {
+        self.snapshots.get(key(ptr)).is_some_and(|entry| entry.stable)
+    }
+
+    /// Checks the pointer map and the running totals for inconsistencies
+    #[cfg(any(debug_assertions, feature = "verify"))]
+    pub fn verify(&self) -> VerifyReport {
+        let mut capacity = 0;
+
+        loop {
+            // Allocate outside the lock, as the allocation may itself be mapped
+            let mut entries = Vec::with_capacity(capacity);
+
+            let lock = self.lock_map();
+            let len = lock.as_ref().map_or(0, SegmentMap::len);
+
+            if len > entries.capacity() {
+                capacity = len + MAP_INITIAL_CAPACITY;
+
+                drop(lock);
+                continue;
+            }
+
+            if let Some(ptr_map) = lock.as_ref() {
+                entries.extend(ptr_map.iter().map(|(&key, mmap)| Entry {
+                    key,
+                    ptr: mmap.ptr(),
+                    size: mmap.size(),
+                    align: mmap.layout().align(),
+                    alloc_size: mmap.alloc_size(),
+                    page_size: mmap.page_size(),
+                    huge: !mmap.is_default_page_size(),
+                    #[cfg(feature = "canary")]
+                    canary: if mmap.has_canary() { canary::check(mmap) } else { None },
+                    #[cfg(not(feature = "canary"))]
+                    canary: None,
+                }));
+            }
+
+            // Read the totals while the map can't change
+            let default = self.default_gauges.read();
+            let huge = self.huge_gauges.read();
+            let mapped = self.mapped.load(Ordering::Relaxed);
+
+            drop(lock);
+
+            return verify::check(entries, default, huge, mapped);
+        }
+    }
+
+    /// Returns the allocation size of the segment if the passed pointer is managed by the mapper
+    #[cfg(all(feature = "malloc-shim", target_env = "gnu"))]
+    pub(crate) fn managed_size(&self, ptr: *mut u8) -> Option<usize> {
         // Lock the ptr_map
-        if let Some(ptr_map) = self.lock_map().as_ref() {
-            // Look for map entry
-            ptr_map.contains_key(&(ptr as usize))
-        } else {
-            // ptr_map does not exist
-            false
+        self.lock_map().as_mut()?.get_mut(&key(ptr)).map(|mmap| mmap.size())
+    }
+
+    /// Attaches a tag to the segment starting at ptr. Returns false if the pointer is not managed
+    pub fn set_tag(&self, ptr: *mut u8, tag: &'static str) -> bool {
+        // Lock the ptr_map
+        match self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            Some(mmap) => {
+                mmap.set_tag(Some(tag));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the segment starting at ptr as sensitive, so it is wiped before its pages are unmapped.
+    /// Returns false if the pointer is not managed
+    pub fn set_sensitive(&self, ptr: *mut u8, sensitive: bool) -> bool {
+        // Lock the ptr_map
+        match self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            Some(mmap) => {
+                mmap.set_sensitive(sensitive);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the segment starting at ptr as stable, so it is only ever resized in place and never
+    /// moved or copied. Returns false if the pointer is not managed
+    pub fn set_stable_address(&self, ptr: *mut u8, stable: bool) -> bool {
+        let mut lock = self.lock_map();
+        let Some(ptr_map) = lock.as_mut() else { return false };
+
+        match ptr_map.get_mut(&key(ptr)) {
+            Some(mmap) => {
+                mmap.set_stable(stable);
+                self.snapshots.insert(SnapshotEntry::of(mmap));
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Associates the segment starting at ptr with a protection key (0 for the default key)
+    pub fn set_protection_key(&self, ptr: *mut u8, pkey: c_int) -> Result<(), Box<dyn Error>> {
+        // Lock the ptr_map
+        match self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            Some(mmap) => Ok(mmap.set_pkey(pkey)?),
+            None => Err("pointer is not managed".into()),
+        }
+    }
+
+    /// Marks the segment starting at ptr to be skipped by checkpoints, or clears the mark
+    pub fn set_skip_checkpoint(&self, ptr: *mut u8, skip: bool) -> Result<(), Box<dyn Error>> {
+        match self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            Some(mmap) => Ok(mmap.set_skip_checkpoint(skip)?),
+            None => Err("pointer is not managed".into()),
+        }
+    }
+
+    /// Advises KSM that it may merge the pages of the segment starting at ptr, or that it must not
+    pub fn set_mergeable(&self, ptr: *mut u8, mergeable: bool) -> Result<(), Box<dyn Error>> {
+        match self.lock_map().as_mut().and_then(|ptr_map| ptr_map.get_mut(&key(ptr))) {
+            Some(mmap) => Ok(mmap.set_mergeable(mergeable)?),
+            None => Err("pointer is not managed".into()),
+        }
+    }
+
+    /// Returns the live segments as seen by a checkpoint, ordered by address
+    pub fn checkpoint_segments(&self) -> Vec<CheckpointSegment> {
+        let mut capacity = 0;
+
+        let mut segments = loop {
+            // Allocate outside the lock, as the allocation may itself be mapped
+            let mut segments = Vec::with_capacity(capacity);
+
+            let lock = self.lock_map();
+            let ptr_map = match lock.as_ref() {
+                Some(ptr_map) => ptr_map,
+                None => break segments,
+            };
+
+            if ptr_map.len() > segments.capacity() {
+                capacity = ptr_map.len() + MAP_INITIAL_CAPACITY;
+
+                drop(lock);
+                continue;
+            }
+
+            segments.extend(ptr_map.values().map(|mmap| CheckpointSegment {
+                start: mmap.ptr(),
+                end: mmap.ptr() + mmap.alloc_size(),
+                size: mmap.size(),
+                page_size: mmap.page_size(),
+                huge: !mmap.is_default_page_size(),
+                shared: mmap.is_shared(),
+                skip: mmap.skips_checkpoint(),
+                tag: mmap.tag(),
+            }));
+
+            break segments;
+        };
+
+        segments.sort_by_key(|segment| segment.start);
+
+        segments
+    }
+
+    /// Returns the metadata of the segment containing ptr, which may point anywhere in the
+    /// mapping. Pointers which aren't the start of a segment are found by scanning the segments.
+    pub fn owner_of(&self, ptr: *const u8) -> Option<SegmentInfo> {
+        let addr = key(ptr as *mut u8);
+        let mut lock = self.lock_map();
+        let ptr_map = lock.as_mut()?;
+
+        if let Some(mmap) = ptr_map.get_mut(&addr) {
+            return Some(SegmentInfo::of(mmap));
+        }
+
+        let owner = ptr_map.values().find(|mmap| (mmap.ptr()..mmap.ptr() + mmap.alloc_size()).contains(&addr));
+
+        owner.map(SegmentInfo::of)
+    }
+
+    /// Returns the bytes of the segments advised MADV_HUGEPAGE which the kernel has backed with
+    /// transparent huge pages, read from /proc/self/smaps
+    pub fn thp_promoted(&self) -> Result<usize, Box<dyn Error>> {
+        let mut capacity = 0;
+
+        let ranges = loop {
+            // Allocate outside the lock, as the allocation may itself be mapped
+            let mut ranges = Vec::with_capacity(capacity);
+
+            let lock = self.lock_map();
+            let Some(ptr_map) = lock.as_ref() else { break ranges };
+
+            if ptr_map.len() > ranges.capacity() {
+                capacity = ptr_map.len() + MAP_INITIAL_CAPACITY;
+
+                drop(lock);
+                continue;
+            }
+
+            ranges.extend(ptr_map.values().filter(|mmap| mmap.is_thp()).map(|mmap| mmap.ptr()..mmap.ptr() + mmap.alloc_size()));
+
+            break ranges;
+        };
+
+        if ranges.is_empty() {
+            return Ok(0);
         }
+
+        Ok(thp::anon_huge_bytes(&ranges)?)
+    }
+
+    /// Returns a snapshot of the live segments, ordered by address
+    pub fn segments(&self) -> Vec<Segment> {
+        let now = Instant::now();
+        let mut capacity = 0;
+
+        let mut segments = loop {
+            // Allocate outside the lock, as the allocation may itself be mapped
+            let mut segments = Vec::with_capacity(capacity);
+
+            let lock = self.lock_map();
+            let ptr_map = match lock.as_ref() {
+                Some(ptr_map) => ptr_map,
+                None => break segments,
+            };
+
+            if ptr_map.len() > segments.capacity() {
+                capacity = ptr_map.len() + MAP_INITIAL_CAPACITY;
+
+                drop(lock);
+                continue;
+            }
+
+            segments.extend(ptr_map.values().map(|mmap| Segment {
+                id: mmap.id(),
+                ptr: mmap.ptr(),
+                size: mmap.size(),
+                alloc_size: mmap.alloc_size(),
+                page_size: mmap.page_size(),
+                huge: !mmap.is_default_page_size(),
+                age: now.saturating_duration_since(mmap.created()),
+                tag: mmap.tag(),
+            }));
+
+            break segments;
+        };
+
+        segments.sort_by_key(|segment| segment.ptr);
+
+        segments
     }
 
     /// Removes an entry from the pointer map
@@ -162,87 +1968,165 @@ impl MMapper {
         // Lock the ptr_map
         if let Some(ptr_map) = self.lock_map().as_mut() {
             // Remove map entry
-            ptr_map.remove(&(ptr as usize))
+            let mmap = ptr_map.remove(&key(ptr));
+
+            if let Some(mmap) = &mmap {
+                self.mapped.fetch_sub(mmap.alloc_size(), Ordering::Relaxed);
+                self.gauges(mmap).sub(mmap);
+                self.snapshots.remove(mmap.ptr());
+                self.recent.removed();
+            }
+
+            mmap
         } else {
             // ptr_map does not exist
             None
         }
     }
 
-    /// Adds an entry from the pointer map
+    /// Adds an entry to the pointer map
     fn map_add(&self, mmap: MMap) {
-        let layout = mmap.layout();
+        self.map_add_all(iter::once(mmap));
+    }
 
-        // Lock the ptr_map
-        let mut lock = self.lock_map_for_insert();
-        let ptr_map = lock.as_mut().unwrap();
+    /// Adds entries to the pointer map under one lock acquisition
+    fn map_add_all(&self, mmaps: impl ExactSizeIterator<Item = MMap>) {
+        let count = mmaps.len();
+
+        loop {
+            // Lock the ptr_map
+            let mut lock = self.lock_map();
+
+            let capacity = match lock.as_mut() {
+                Some(ptr_map) if ptr_map.len() + count <= ptr_map.capacity().min(self.snapshots.capacity()) => {
+                    // Add map entries. There is room in the map and the snapshots so this won't allocate
+                    let mut last = None;
 
-        // Add map entry
-        if ptr_map.insert(mmap.ptr(), mmap).is_some() {
-            HugeGlobalAllocator::alloc_error_layout("MMapper::map_add: map already exists", layout);
+                    for mmap in mmaps {
+                        let layout = mmap.layout();
+                        last = Some(mmap.ptr());
+
+                        self.mapped.fetch_add(mmap.alloc_size(), Ordering::Relaxed);
+                        self.gauges(&mmap).add(&mmap);
+                        self.snapshots.insert(SnapshotEntry::of(&mmap));
+
+                        if ptr_map.insert(mmap.ptr(), mmap).is_some() {
+                            HugeGlobalAllocator::alloc_error_layout("MMapper::map_add: map already exists", layout);
+                        }
+                    }
+
+                    // The last segment added is the one most likely to be reallocated next
+                    if let Some(ptr) = last {
+                        self.recent.record(ptr);
+                    }
+
+                    break;
+                }
+                Some(ptr_map) if ptr_map.len() + count <= ptr_map.capacity() => ptr_map.capacity(),
+                Some(ptr_map) => (ptr_map.capacity() * 2).max(ptr_map.len() + count),
+                None => MAP_INITIAL_CAPACITY.max(count),
+            };
+
+            drop(lock);
+
+            self.map_grow(capacity);
         }
     }
 
-    /// Locks the ptr_map for insertion, creating if necessary
-    fn lock_map_for_insert(&self) -> MutexGuard<Option<HashMap<usize, MMap>>> {
-        let mut map = self.lock_map();
+    /// Grows the ptr_map and the snapshots to at least the given capacity, rebuilding the map if
+    /// the map strategy has changed. Growing allocates and frees memory through the global
+    /// allocator, which would deadlock if done with the map locked, so the new map and tables are
+    /// created and the old ones dropped outside the lock
+    fn map_grow(&self, capacity: usize) {
+        let strategy = self.map_strategy();
+        let mut new_map = Some(PtrMap::with_capacity(strategy, capacity));
+        let mut new_tables = Some([Snapshots::alloc_table(capacity), Snapshots::alloc_table(capacity)]);
+        let mut old_tables = None;
+
+        let mut lock = self.lock_map();
+
+        match (lock.as_mut(), new_map.as_mut()) {
+            (Some(ptr_map), _) if ptr_map.is(strategy) && ptr_map.capacity() >= capacity => (), // Grown by another thread
+            (Some(ptr_map), Some(map)) if ptr_map.len() <= map.capacity() && self.map_strategy() == strategy => {
+                ptr_map.drain_into(|key, mmap| {
+                    map.insert(key, mmap);
+                });
+                mem::swap(ptr_map, map);
+            }
+            (Some(_), _) => (), // Filled or changed strategy since, retried by the caller
+            (None, _) => *lock = new_map.take(),
+        }
 
-        if map.is_none() {
-            *map = Some(HashMap::new());
+        if self.snapshots.capacity() < capacity {
+            if let (Some(ptr_map), Some(tables)) = (lock.as_ref(), new_tables.take()) {
+                old_tables = Some(self.snapshots.publish_grown(tables, || ptr_map.values()));
+            }
         }
 
-        map
+        drop(lock);
+
+        // Old map and tables, or the unused new tables, freed here
+        drop(new_map);
+        drop(new_tables);
+        drop(old_tables);
+    }
+
+    /// Returns true if the ptr_map is locked
+    #[cfg(test)]
+    pub(crate) fn is_map_locked(&self) -> bool {
+        self.ptr_map.is_locked()
+    }
+
+    /// Calls f with the ptr_map locked
+    #[cfg(test)]
+    pub(crate) fn with_map_locked<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _lock = self.lock_map();
+
+        f()
     }
 
     /// Locks the ptr_map for removal
-    fn lock_map(&self) -> MutexGuard<Option<HashMap<usize, MMap>>> {
+    fn lock_map(&self) -> MutexGuard<'_, Option<PtrMap>> {
         // Lock the ptr_map
-        match self.ptr_map.lock() {
-            Ok(ptr_map) => ptr_map,
-            _ => HugeGlobalAllocator::alloc_error("MMapper::lock_map: unable to lock ptr_map"),
-        }
+        self.ptr_map.lock()
+    }
+
+    /// Returns the contention counters of the mapper's locks
+    fn lock_stats(&self) -> impl Iterator<Item = &LockStats> {
+        [self.ptr_map.stats(), self.pressure_callback.stats(), self.demotion_veto.stats()]
+            .into_iter()
+            .chain(self.cache.lock_stats())
     }
 
-    /// Locks statistics
-    fn lock_stats(&self) -> MutexGuard<MMapperStats> {
-        // Lock stats
-        match self.stats.lock() {
-            Ok(stats) => stats,
-            _ => HugeGlobalAllocator::alloc_error("MMapper::lock_stats: unable to lock stats"),
+    /// Returns the segment totals for a segment's page size
+    fn gauges(&self, mmap: &MMap) -> &Gauges {
+        if mmap.is_default_page_size() {
+            &self.default_gauges
+        } else {
+            &self.huge_gauges
         }
     }
 
-    /// Add statistics about missed huge allocations
-    fn add_missed(&self, bytes: usize) {
-        let mut stats = self.lock_stats();
+    /// Locks the pressure callback
+    fn lock_pressure_callback(&self) -> MutexGuard<'_, Option<PressureCallback>> {
+        self.pressure_callback.lock()
+    }
 
-        stats.missed_allocs += 1;
+    /// Locks the demotion veto hook
+    fn lock_demotion_veto(&self) -> MutexGuard<'_, Option<DemotionVeto>> {
+        self.demotion_veto.lock()
+    }
 
-        stats.missed_bytes += bytes;
+    /// Add statistics about missed huge allocations, recording them against the segment
+    fn add_missed(&self, mmap: &mut MMap, bytes: usize) {
+        mmap.add_missed(bytes);
 
-        if stats.missed_bytes > (1024 * 1024) {
-            let mb = stats.missed_bytes / (1024 * 1024);
-            stats.missed_bytes -= mb * (1024 * 1024);
-            stats.missed_mb += mb;
-        }
+        self.stats.missed_allocs.add(1);
+        self.stats.missed_bytes.add(bytes as u64);
     }
 }
 
-#[derive(Default)]
-struct MMapperStats {
-    missed_allocs: usize,
-    missed_bytes: usize,
-    missed_mb: usize,
-    remaps_failed: usize,
-}
-
-impl MMapperStats {
-    const fn new() -> Self {
-        Self {
-            missed_allocs: 0,
-            missed_bytes: 0,
-            missed_mb: 0,
-            remaps_failed: 0,
-        }
-    }
+/// Returns the pointer map key of a pointer, which may carry a memory tag
+fn key(ptr: *mut u8) -> usize {
+    mte::untagged(ptr as usize)
 }
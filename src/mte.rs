@@ -0,0 +1,234 @@
+//! ARM Memory Tagging Extension support
+//!
+//! On aarch64 Linux with MTE, segments can be mapped with PROT_MTE and the 16 byte granules of each
+//! payload tagged with a random tag, which is also placed in the top byte of the returned pointer.
+//! The slack after the payload keeps tag 0, so overflows in to it, and accesses through pointers to
+//! the part of a payload released by a shrinking reallocation, raise a synchronous tag check fault
+//! (SIGSEGV). Faults are counted by a SIGSEGV handler which then hands the signal on to the
+//! previous handler.
+//!
+//! Tag checking is a per thread setting inherited by new threads, so memory tagging should be
+//! enabled before other threads are started. On other targets memory tagging is unsupported, and
+//! pointers are never tagged.
+
+use std::error::Error;
+
+use crate::{counters::Counter, mmap::MMap};
+
+/// Size of a tag granule
+pub(crate) const GRANULE: usize = 16;
+
+/// Bit position of the tag in a pointer
+const TAG_SHIFT: u32 = 56;
+
+/// Number of tag check faults seen by the SIGSEGV handler
+static TAG_FAULTS: Counter = Counter::new();
+
+/// Returns the address of a tagged pointer with the tag removed
+pub(crate) fn untagged(ptr: usize) -> usize {
+    if cfg!(all(target_arch = "aarch64", target_os = "linux")) {
+        ptr & ((1 << TAG_SHIFT) - 1)
+    } else {
+        ptr
+    }
+}
+
+/// Returns an address with a tag in the top byte
+pub(crate) fn tagged(addr: usize, tag: u8) -> usize {
+    addr | (tag as usize) << TAG_SHIFT
+}
+
+/// Returns the number of tag check faults seen since memory tagging was enabled or the statistics
+/// were last reset
+pub(crate) fn tag_faults() -> usize {
+    TAG_FAULTS.get() as usize
+}
+
+/// Zeroes the tag check fault count
+pub(crate) fn reset_tag_faults() {
+    TAG_FAULTS.reset();
+}
+
+#[cfg(all(target_arch = "aarch64", target_os = "linux"))]
+mod imp {
+    use std::arch::asm;
+    use std::cell::UnsafeCell;
+    use std::error::Error;
+    use std::ffi::{c_int, c_ulong, c_void};
+    use std::mem::zeroed;
+    use std::ptr::null_mut;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::{tagged, GRANULE, TAG_FAULTS};
+
+    // Not all defined by libc for every aarch64 target
+    const HWCAP2_MTE: c_ulong = 1 << 18;
+    const PR_SET_TAGGED_ADDR_CTRL: c_int = 55;
+    const PR_TAGGED_ADDR_ENABLE: c_ulong = 1;
+    const PR_MTE_TCF_SYNC: c_ulong = 1 << 1;
+    const PR_MTE_TAG_SHIFT: c_ulong = 3;
+    const SEGV_MTEAERR: c_int = 8;
+    const SEGV_MTESERR: c_int = 9;
+
+    /// Protection flag enabling tag checks on a mapping
+    pub(crate) const PROT_MTE: c_int = 0x20;
+
+    /// The SIGSEGV action replaced by the fault counting handler
+    struct PreviousAction(UnsafeCell<libc::sigaction>);
+
+    // Only written before the handler is installed
+    unsafe impl Sync for PreviousAction {}
+
+    static PREVIOUS: PreviousAction = PreviousAction(UnsafeCell::new(unsafe { zeroed() }));
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+    pub(crate) fn supported() -> bool {
+        unsafe { libc::getauxval(libc::AT_HWCAP2) & HWCAP2_MTE != 0 }
+    }
+
+    pub(crate) fn enable() -> Result<(), Box<dyn Error>> {
+        // Synchronous tag check faults, generating any tag except 0
+        let ctrl = PR_TAGGED_ADDR_ENABLE | PR_MTE_TCF_SYNC | (0xfffe << PR_MTE_TAG_SHIFT);
+
+        if unsafe { libc::prctl(PR_SET_TAGGED_ADDR_CTRL, ctrl, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        if !INSTALLED.swap(true, Ordering::AcqRel) {
+            unsafe {
+                let mut action: libc::sigaction = zeroed();
+                action.sa_sigaction = on_sigsegv as *const () as usize;
+                action.sa_flags = libc::SA_SIGINFO;
+
+                if libc::sigaction(libc::SIGSEGV, &action, PREVIOUS.0.get()) != 0 {
+                    INSTALLED.store(false, Ordering::Release);
+                    return Err(std::io::Error::last_os_error().into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts tag check faults, then restores the previous action so the faulting access raises
+    /// the signal again for it when the handler returns
+    extern "C" fn on_sigsegv(_signal: c_int, info: *mut libc::siginfo_t, _context: *mut c_void) {
+        let code = unsafe { (*info).si_code };
+
+        if code == SEGV_MTESERR || code == SEGV_MTEAERR {
+            TAG_FAULTS.add(1);
+        }
+
+        unsafe { libc::sigaction(libc::SIGSEGV, PREVIOUS.0.get(), null_mut()) };
+    }
+
+    pub(crate) fn random_tag() -> u8 {
+        let ptr: usize;
+
+        // Exclude tag 0, which untagged memory has
+        unsafe {
+            asm!(
+                ".arch_extension memtag",
+                "irg {ptr}, {ptr}, {exclude}",
+                ptr = inout(reg) 0usize => ptr,
+                exclude = in(reg) 1usize,
+                options(nomem, nostack),
+            );
+        }
+
+        (ptr >> super::TAG_SHIFT) as u8 & 0xf
+    }
+
+    pub(crate) unsafe fn set_tags(addr: usize, len: usize, tag: u8) {
+        let mut granule = tagged(addr, tag);
+        let end = granule + len;
+
+        while granule < end {
+            asm!(
+                ".arch_extension memtag",
+                "stg {granule}, [{granule}]",
+                granule = in(reg) granule,
+                options(nostack),
+            );
+
+            granule += GRANULE;
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "aarch64", target_os = "linux")))]
+mod imp {
+    use std::error::Error;
+    use std::ffi::c_int;
+
+    pub(crate) const PROT_MTE: c_int = 0;
+
+    pub(crate) fn supported() -> bool {
+        false
+    }
+
+    pub(crate) fn enable() -> Result<(), Box<dyn Error>> {
+        Err("memory tagging is not supported".into())
+    }
+
+    pub(crate) fn random_tag() -> u8 {
+        0
+    }
+
+    pub(crate) unsafe fn set_tags(_addr: usize, _len: usize, _tag: u8) {}
+}
+
+pub(crate) use imp::PROT_MTE;
+
+/// Returns true if the CPU and kernel support memory tagging
+pub(crate) fn supported() -> bool {
+    imp::supported()
+}
+
+/// Enables tag checking for the calling thread and the threads it starts, and installs the tag
+/// fault counting handler
+pub(crate) fn enable() -> Result<(), Box<dyn Error>> {
+    if !supported() {
+        return Err("memory tagging is not supported".into());
+    }
+
+    imp::enable()
+}
+
+/// Returns a random non-zero tag
+pub(crate) fn random_tag() -> u8 {
+    imp::random_tag()
+}
+
+/// Sets the tag of the granules from addr for len bytes, both multiples of the granule size. The
+/// mapping must have been made with PROT_MTE.
+pub(crate) unsafe fn set_tags(addr: usize, len: usize, tag: u8) {
+    imp::set_tags(addr, len, tag)
+}
+
+/// Tags the granules of a segment's payload. A segment which isn't tagged yet is given PROT_MTE
+/// and a random tag, otherwise granules gained by growing are given its tag and granules released
+/// by shrinking are returned to tag 0.
+pub(crate) fn tag(mmap: &mut MMap) -> nix::Result<()> {
+    if mmap.mte_tag() == 0 {
+        mmap.protect_mte()?;
+        mmap.set_mte(random_tag(), 0);
+    }
+
+    let tag = mmap.mte_tag();
+    let len = mmap.size().next_multiple_of(GRANULE);
+    // Granules beyond a shrunk mapping are gone
+    let tagged = mmap.mte_len().min(mmap.alloc_size());
+
+    unsafe {
+        if len > tagged {
+            set_tags(mmap.ptr() + tagged, len - tagged, tag);
+        } else {
+            set_tags(mmap.ptr() + len, tagged - len, 0);
+        }
+    }
+
+    mmap.set_mte(tag, len);
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! NUMA node of the calling thread
+//!
+//! On multi-socket hosts each socket reaches its own memory node faster than the others, and
+//! cache lines bounce between sockets when threads on different nodes write them. Per-thread
+//! structures, such as the statistics shards and segment cache slots, are split in to one group per
+//! node, and threads pick a shard from the group of the node they are running on, so threads on
+//! one socket share cache lines with each other rather than with other sockets. New segments can
+//! also be bound to the calling thread's node (see [set_numa_local](crate::HugeGlobalAllocator::set_numa_local)).
+//!
+//! The node is found with getcpu and cached per thread, refreshed every few hundred lookups as the
+//! scheduler seldom moves a thread between nodes. Without NUMA support everything is on node 0.
+
+use std::cell::Cell;
+use std::ffi::{c_uint, c_ulong, c_void};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::local::thread_index;
+
+/// mbind mode preferring a node, falling back to others when it is full. Not defined by libc.
+pub(crate) const MPOL_PREFERRED: c_ulong = 1;
+
+/// get_mempolicy flag returning the nodes the process may use. Not defined by libc.
+const MPOL_F_MEMS_ALLOWED: c_ulong = 4;
+
+/// Most nodes counted
+const MAX_NODES: usize = 1024;
+
+/// Number of lookups between refreshes of a thread's cached node
+const REFRESH: u32 = 256;
+
+/// Number of nodes the process may use, 0 until counted
+static NODES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The current thread's node and the number of lookups before it is refreshed. Const
+    /// initialised without a destructor so it can be used from the allocator at any point in the
+    /// thread's life
+    static NODE: Cell<(usize, u32)> = const { Cell::new((0, 0)) };
+}
+
+/// Returns the node the current thread last ran on, refreshing it every [REFRESH] lookups
+pub(crate) fn current_node() -> usize {
+    NODE.try_with(|cell| match cell.get() {
+        (_, 0) => refresh(cell),
+        (node, lookups) => {
+            cell.set((node, lookups - 1));
+            node
+        }
+    })
+    .unwrap_or(0)
+}
+
+/// Returns the node the current thread is running on now, refreshing the cached node
+pub(crate) fn running_node() -> usize {
+    NODE.try_with(refresh).unwrap_or_else(|_| getcpu_node())
+}
+
+/// Returns the number of nodes the process may use, 1 without NUMA support
+pub(crate) fn node_count() -> usize {
+    match NODES.load(Ordering::Relaxed) {
+        0 => {
+            let nodes = count_nodes();
+            NODES.store(nodes, Ordering::Relaxed);
+            nodes
+        }
+        nodes => nodes,
+    }
+}
+
+/// Returns the current thread's shard out of shards, from the group of its node. Returns None if
+/// the thread is exiting.
+pub(crate) fn node_shard(shards: usize) -> Option<usize> {
+    Some(shard_for(current_node(), node_count(), thread_index()?, shards))
+}
+
+/// Returns the shard for a thread on a node, splitting the shards in to a group per node. Nodes
+/// share groups when there are more nodes than shards.
+pub(crate) fn shard_for(node: usize, nodes: usize, thread: usize, shards: usize) -> usize {
+    let groups = nodes.clamp(1, shards);
+    let per_group = shards / groups;
+
+    (node % groups) * per_group + thread % per_group
+}
+
+/// Looks up the current thread's node and caches it
+fn refresh(cell: &Cell<(usize, u32)>) -> usize {
+    let node = getcpu_node();
+
+    cell.set((node, REFRESH));
+
+    node
+}
+
+/// Returns the node the current thread is running on, 0 if getcpu fails
+fn getcpu_node() -> usize {
+    let mut cpu: c_uint = 0;
+    let mut node: c_uint = 0;
+
+    let result = unsafe { libc::syscall(libc::SYS_getcpu, &mut cpu, &mut node, null_mut::<c_void>()) };
+
+    if result == 0 {
+        node as usize
+    } else {
+        0
+    }
+}
+
+/// Counts the nodes up to the highest the process may use, 1 if get_mempolicy fails
+fn count_nodes() -> usize {
+    const WORDS: usize = MAX_NODES / c_ulong::BITS as usize;
+
+    let mut mask: [c_ulong; WORDS] = [0; WORDS];
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_get_mempolicy,
+            null_mut::<c_void>(),
+            mask.as_mut_ptr(),
+            MAX_NODES as c_ulong,
+            null_mut::<c_void>(),
+            MPOL_F_MEMS_ALLOWED,
+        )
+    };
+
+    if result != 0 {
+        return 1;
+    }
+
+    mask.iter()
+        .enumerate()
+        .rev()
+        .find(|(_, word)| **word != 0)
+        .map_or(1, |(index, word)| index * c_ulong::BITS as usize + (c_ulong::BITS - word.leading_zeros()) as usize)
+}
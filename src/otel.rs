@@ -0,0 +1,161 @@
+//! OpenTelemetry metrics
+//!
+//! Registers the allocator statistics as observable instruments on an OpenTelemetry [Meter], so
+//! they are exported alongside the application's own metrics. Segment totals, efficiency, pressure
+//! and rates are gauges; event counts are counters. Every instrument name is prefixed with
+//! huge_global_alloc. and values are read with a lock free statistics snapshot each time the meter
+//! provider collects.
+//!
+//! Counters restart from zero when the statistics are reset with the stats.reset ctl key, which
+//! exporters treat as a counter reset.
+
+use opentelemetry::metrics::Meter;
+
+use crate::HugeGlobalAllocatorStats;
+
+/// Prefix of every instrument name
+const PREFIX: &str = "huge_global_alloc.";
+
+/// Whether a statistic is exported as a gauge or a monotonic counter
+#[derive(Clone, Copy)]
+enum Kind {
+    Gauge,
+    Counter,
+}
+
+/// How a statistic is read from a snapshot
+#[derive(Clone, Copy)]
+enum Value {
+    U64(fn(&HugeGlobalAllocatorStats) -> u64),
+    F64(fn(&HugeGlobalAllocatorStats) -> f64),
+}
+
+/// An exported statistic
+struct Metric {
+    name: &'static str,
+    unit: &'static str,
+    description: &'static str,
+    kind: Kind,
+    value: Value,
+}
+
+macro_rules! metric {
+    ($name:literal, $unit:literal, $kind:ident, $ty:ident, $get:expr, $description:literal) => {
+        Metric {
+            name: $name,
+            unit: $unit,
+            description: $description,
+            kind: Kind::$kind,
+            value: Value::$ty($get),
+        }
+    };
+}
+
+/// The exported statistics
+const METRICS: &[Metric] = &[
+    metric!("alloc", "By", Gauge, U64, |s| s.alloc as u64, "Memory allocated in mapped segments"),
+    metric!("mapped", "By", Gauge, U64, |s| s.mapped as u64, "Memory mapped"),
+    metric!("segments", "{segment}", Gauge, U64, |s| s.segments as u64, "Segments mapped"),
+    metric!("default.alloc", "By", Gauge, U64, |s| s.default_alloc as u64, "Memory allocated in default page size pages"),
+    metric!("default.mapped", "By", Gauge, U64, |s| s.default_mapped as u64, "Memory mapped in default page size pages"),
+    metric!("default.segments", "{segment}", Gauge, U64, |s| s.default_segments as u64, "Default page size segments mapped"),
+    metric!("huge.alloc", "By", Gauge, U64, |s| s.huge_alloc as u64, "Memory allocated in huge pages"),
+    metric!("huge.mapped", "By", Gauge, U64, |s| s.huge_mapped as u64, "Memory mapped in huge pages"),
+    metric!("huge.segments", "{segment}", Gauge, U64, |s| s.huge_segments as u64, "Huge page segments mapped"),
+    metric!("efficiency", "%", Gauge, U64, |s| s.efficiency as u64, "Percentage of mapped memory used by allocations"),
+    metric!("pressure", "%", Gauge, U64, |s| s.pressure as u64, "Mapped memory as a percentage of the soft limit"),
+    metric!("prefault.max_time", "s", Gauge, F64, |s| s.prefault_max_time.as_secs_f64(), "Longest time spent pre-faulting a huge page segment"),
+    metric!("allocs.rate", "{segment}/s", Gauge, F64, |s| s.allocs_per_sec, "Segments mapped per second over the last minute"),
+    metric!("deallocs.rate", "{segment}/s", Gauge, F64, |s| s.deallocs_per_sec, "Segments unmapped per second over the last minute"),
+    metric!("remaps.rate", "{segment}/s", Gauge, F64, |s| s.remaps_per_sec, "Segments resized per second over the last minute"),
+    metric!("missed.allocs", "{allocation}", Counter, U64, |s| s.missed_allocs as u64, "Allocations which missed huge pages"),
+    metric!("missed.bytes", "By", Counter, U64, |s| (s.missed_mb * 1024.0 * 1024.0) as u64, "Bytes allocated which missed huge pages"),
+    metric!("remaps.failed", "{remap}", Counter, U64, |s| s.remaps_failed as u64, "Failed remaps"),
+    metric!("remaps.copied", "{remap}", Counter, U64, |s| s.remaps_copied as u64, "Failed remaps which copied to a new segment"),
+    metric!("remaps.refused", "{remap}", Counter, U64, |s| s.remaps_refused as u64, "Failed remaps which failed the reallocation"),
+    metric!("remaps.copied_bytes", "By", Counter, U64, |s| s.remap_copied_bytes as u64, "Bytes copied due to failed remaps"),
+    metric!("huge.retries", "{retry}", Counter, U64, |s| s.huge_retries as u64, "Huge page mappings retried"),
+    metric!("prefault.failed", "{segment}", Counter, U64, |s| s.populate_failed as u64, "Huge page segments which could not be pre-faulted"),
+    metric!("prefault.time", "s", Counter, F64, |s| s.prefault_time.as_secs_f64(), "Time spent pre-faulting huge page segments"),
+    metric!("unmaps.failed", "{segment}", Counter, U64, |s| s.unmaps_failed as u64, "Segments which could not be unmapped"),
+    metric!("leaked", "By", Counter, U64, |s| s.leaked_bytes as u64, "Bytes leaked by segments which could not be unmapped"),
+    metric!("promotions", "{segment}", Counter, U64, |s| s.promotions as u64, "Default page size segments moved on to huge pages"),
+    metric!("maintenance.passes", "{pass}", Counter, U64, |s| s.maintenance_passes as u64, "Passes made by the maintenance worker"),
+    metric!("collapses", "{segment}", Counter, U64, |s| s.collapses as u64, "Default page size segments collapsed in to transparent huge pages"),
+    metric!("collapses.failed", "{segment}", Counter, U64, |s| s.collapses_failed as u64, "Default page size segments which could not be collapsed"),
+    metric!("shrinks.deferred", "{remap}", Counter, U64, |s| s.shrinks_deferred as u64, "Shrinking reallocations which kept the existing mapping"),
+    metric!("trimmed", "By", Counter, U64, |s| s.trimmed_bytes as u64, "Bytes released by trimming segments kept mapped by deferred shrinks"),
+    metric!("exact_size.allocs", "{allocation}", Counter, U64, |s| s.exact_size_allocs as u64, "Allocations mapped with default size pages to limit huge page slack"),
+    metric!("moved.memcpy", "By", Counter, U64, |s| s.move_memcpy_bytes as u64, "Bytes copied with memcpy by reallocations which moved segments"),
+    metric!("moved.mremap", "By", Counter, U64, |s| s.move_mremap_bytes as u64, "Bytes moved with mremap by reallocations which moved segments"),
+    metric!("moved.dontunmap", "By", Counter, U64, |s| s.move_dontunmap_bytes as u64, "Bytes moved with mremap and MREMAP_DONTUNMAP by reallocations which moved segments"),
+    metric!("demotions", "{segment}", Counter, U64, |s| s.demotions as u64, "Huge page segments demoted to default pages for high priority allocations"),
+    metric!("demotions.vetoed", "{segment}", Counter, U64, |s| s.demotions_vetoed as u64, "Huge page segments kept on huge pages by the demotion veto hook"),
+    metric!("placements.randomized", "{segment}", Counter, U64, |s| s.randomized_placements as u64, "Segments mapped at a random address in the placement window"),
+    metric!("placements.fallback", "{segment}", Counter, U64, |s| s.placement_fallbacks as u64, "Segments placed by the kernel because the placement window had no free address"),
+    metric!("mte.segments", "{segment}", Counter, U64, |s| s.mte_segments as u64, "Segments mapped with memory tagging"),
+    metric!("mte.tag_faults", "{fault}", Counter, U64, |s| s.mte_tag_faults as u64, "Memory tag check faults caught by the SIGSEGV handler"),
+    metric!("secure.segments", "{segment}", Counter, U64, |s| s.secure_segments as u64, "Segments secured by the security profile"),
+    metric!("secure.failures", "{segment}", Counter, U64, |s| s.secure_failures as u64, "Segments the security profile could not fully secure"),
+    metric!("segment_cache.hits", "{segment}", Counter, U64, |s| s.segment_cache_hits as u64, "New segments reused from the segment cache"),
+    metric!("segment_cache.misses", "{segment}", Counter, U64, |s| s.segment_cache_misses as u64, "New segments mapped on a segment cache miss"),
+    metric!("locks.acquisitions", "{acquisition}", Counter, U64, |s| s.lock_acquisitions as u64, "Acquisitions of the allocator's locks"),
+    metric!("locks.contended", "{acquisition}", Counter, U64, |s| s.lock_contended as u64, "Lock acquisitions which waited for another thread"),
+    metric!("locks.wait_time", "s", Counter, F64, |s| s.lock_wait_time.as_secs_f64(), "Time spent waiting for the allocator's locks"),
+    metric!("locks.max_wait_time", "s", Gauge, F64, |s| s.lock_max_wait_time.as_secs_f64(), "Longest wait for one of the allocator's locks"),
+    metric!("recent_segment.hits", "{reallocation}", Counter, U64, |s| s.recent_segment_hits as u64, "Reallocations of the thread's most recent segment"),
+    metric!("recent_segment.misses", "{reallocation}", Counter, U64, |s| s.recent_segment_misses as u64, "Reallocations of segments which had to be looked up"),
+    metric!("soft_limit.exceeded", "{allocation}", Counter, U64, |s| s.soft_limit_exceeded as u64, "Allocations which exceeded the soft limit"),
+];
+
+/// Registers an observable instrument for each statistic, reading values with snapshot
+pub(crate) fn register<S>(meter: &Meter, snapshot: S)
+where
+    S: Fn() -> HugeGlobalAllocatorStats + Clone + Send + Sync + 'static,
+{
+    for metric in METRICS {
+        let name = format!("{PREFIX}{}", metric.name);
+        let snapshot = snapshot.clone();
+
+        match (metric.kind, metric.value) {
+            (Kind::Gauge, Value::U64(get)) => {
+                meter
+                    .u64_observable_gauge(name)
+                    .with_unit(metric.unit)
+                    .with_description(metric.description)
+                    .with_callback(move |observer| observer.observe(get(&snapshot()), &[]))
+                    .build();
+            }
+            (Kind::Gauge, Value::F64(get)) => {
+                meter
+                    .f64_observable_gauge(name)
+                    .with_unit(metric.unit)
+                    .with_description(metric.description)
+                    .with_callback(move |observer| observer.observe(get(&snapshot()), &[]))
+                    .build();
+            }
+            (Kind::Counter, Value::U64(get)) => {
+                meter
+                    .u64_observable_counter(name)
+                    .with_unit(metric.unit)
+                    .with_description(metric.description)
+                    .with_callback(move |observer| observer.observe(get(&snapshot()), &[]))
+                    .build();
+            }
+            (Kind::Counter, Value::F64(get)) => {
+                meter
+                    .f64_observable_counter(name)
+                    .with_unit(metric.unit)
+                    .with_description(metric.description)
+                    .with_callback(move |observer| observer.observe(get(&snapshot()), &[]))
+                    .build();
+            }
+        }
+    }
+}
+
+/// Returns the names of the registered instruments
+#[cfg(test)]
+pub(crate) fn names() -> impl Iterator<Item = String> {
+    METRICS.iter().map(|metric| format!("{PREFIX}{}", metric.name))
+}
@@ -0,0 +1,129 @@
+//! Detection of the kernel's memory overcommit policy
+//!
+//! vm.overcommit_memory decides what happens when memory runs short. With the default heuristic
+//! and with overcommit always allowed, mappings succeed and running out gets the process killed
+//! when pages are touched. With strict overcommit every private writable mapping is charged in
+//! full against the commit limit when it is mapped, MAP_NORESERVE is ignored, and large sparse
+//! allocations fail even if most of them would never be touched. [overcommit] reads the policy,
+//! and [HugeGlobalAllocator::adapt_to_overcommit](crate::HugeGlobalAllocator::adapt_to_overcommit)
+//! adjusts the allocator to it.
+
+use std::fmt::{self, Display};
+use std::fs;
+
+/// Overcommit mode, from /proc/sys/vm/overcommit_memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OvercommitMode {
+    /// Obvious overcommits are refused, everything else succeeds (0, the default)
+    Heuristic,
+    /// Every mapping succeeds (1)
+    Always,
+    /// Mappings beyond the commit limit are refused (2)
+    Never,
+}
+
+/// The kernel's overcommit policy, returned by [overcommit]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overcommit {
+    /// The overcommit mode
+    pub mode: OvercommitMode,
+    /// Percentage of physical memory counted towards the commit limit in strict mode
+    pub ratio: usize,
+    /// Bytes of physical memory counted towards the commit limit in strict mode instead of the
+    /// ratio, zero if the ratio is used
+    pub kbytes: usize,
+    /// The commit limit in bytes, None if /proc/meminfo is unreadable
+    pub commit_limit: Option<usize>,
+    /// Bytes committed by every process, None if /proc/meminfo is unreadable
+    pub committed: Option<usize>,
+}
+
+impl Overcommit {
+    /// Returns the bytes which can still be committed before strict mode refuses mappings
+    pub fn available(&self) -> Option<usize> {
+        Some(self.commit_limit?.saturating_sub(self.committed?))
+    }
+}
+
+/// The outcome of adapting an allocator to the overcommit policy, returned by
+/// [HugeGlobalAllocator::adapt_to_overcommit](crate::HugeGlobalAllocator::adapt_to_overcommit)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OvercommitReport {
+    /// The policy detected, None if it couldn't be read
+    pub overcommit: Option<Overcommit>,
+    /// The ctl keys of the settings changed, with their new values
+    pub adjusted: Vec<&'static str>,
+    /// What to expect from allocations under the policy
+    pub warnings: Vec<String>,
+}
+
+impl Display for OvercommitReport {
+    /// Formats the report as the policy followed by the settings changed and the warnings
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.overcommit {
+            Some(overcommit) => writeln!(f, "overcommit: {:?}, ratio {}%", overcommit.mode, overcommit.ratio)?,
+            None => writeln!(f, "overcommit: unknown")?,
+        }
+
+        if self.adjusted.is_empty() {
+            writeln!(f, "nothing adjusted")?;
+        } else {
+            writeln!(f, "adjusted: {}", self.adjusted.join(", "))?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(f, "warning: {warning}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the kernel's overcommit policy, None if it can't be read, for example without a mounted
+/// /proc
+///
+/// ```rust
+/// use huge_global_alloc::{overcommit, OvercommitMode};
+///
+/// if let Some(overcommit) = overcommit() {
+///     if overcommit.mode == OvercommitMode::Never {
+///         eprintln!("strict overcommit, {:?} bytes left to commit", overcommit.available());
+///     }
+/// }
+/// ````
+pub fn overcommit() -> Option<Overcommit> {
+    let mode = parse_mode(&fs::read_to_string("/proc/sys/vm/overcommit_memory").ok()?)?;
+    let ratio = fs::read_to_string("/proc/sys/vm/overcommit_ratio").ok()?.trim().parse().ok()?;
+    let kbytes = fs::read_to_string("/proc/sys/vm/overcommit_kbytes")
+        .ok()
+        .and_then(|kbytes| kbytes.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+
+    Some(Overcommit {
+        mode,
+        ratio,
+        kbytes: kbytes * 1024,
+        commit_limit: parse_meminfo(&meminfo, "CommitLimit"),
+        committed: parse_meminfo(&meminfo, "Committed_AS"),
+    })
+}
+
+/// Parses the contents of /proc/sys/vm/overcommit_memory
+pub(crate) fn parse_mode(mode: &str) -> Option<OvercommitMode> {
+    match mode.trim() {
+        "0" => Some(OvercommitMode::Heuristic),
+        "1" => Some(OvercommitMode::Always),
+        "2" => Some(OvercommitMode::Never),
+        _ => None,
+    }
+}
+
+/// Returns the named field of /proc/meminfo in bytes
+pub(crate) fn parse_meminfo(meminfo: &str, name: &str) -> Option<usize> {
+    let value = meminfo.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?;
+    let kb = value.trim().strip_suffix("kB")?.trim().parse::<usize>().ok()?;
+
+    kb.checked_mul(1024)
+}
@@ -0,0 +1,157 @@
+//! Page sizes assumed by the allocator
+//!
+//! The base page size is read with sysconf and huge pages are assumed to be 2mb. Either can be
+//! overridden where detection is unreliable, for example under emulation or in containers, or on
+//! platforms with a different huge page size. The sizes are fixed the first time either is read,
+//! which every allocator does before mapping its first segment, so overrides must be made at the
+//! start of main before anything is allocated. Later overrides fail.
+//!
+//! ```rust
+//! use huge_global_alloc::{huge_page_size, set_huge_page_size};
+//!
+//! // Fails if a segment has already been mapped
+//! if set_huge_page_size(1024 * 1024 * 1024).is_ok() {
+//!     assert_eq!(huge_page_size(), 1024 * 1024 * 1024);
+//! }
+//!
+//! assert!(set_huge_page_size(2 * 1024 * 1024).is_err());
+//! ````
+
+use std::alloc::Layout;
+use std::error::Error;
+use std::ffi::c_int;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
+
+use nix::unistd::{sysconf, SysconfVar};
+
+use crate::HugeGlobalAllocator;
+
+/// Huge page size used unless overridden
+pub const DEFAULT_HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Size of gigantic huge pages
+pub(crate) const GIGANTIC_PAGE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Page size requested for an individual allocation with
+/// [HugeGlobalAllocator::alloc_with](crate::HugeGlobalAllocator::alloc_with), overriding the
+/// allocator's policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageSizePref {
+    /// Huge pages unless that would leave more slack than the maximum slack allows, falling back
+    /// to base size pages, as for any other allocation
+    #[default]
+    Policy,
+    /// Base size pages only
+    Base,
+    /// Huge pages of the configured huge page size whatever the slack, falling back to base size
+    /// pages
+    Huge,
+    /// 1gb huge pages, falling back to the configured huge page size and then to base size pages
+    Huge1G,
+}
+
+/// Shift of the log2 page size in the mmap and memfd_create huge page size flags
+const HUGE_SHIFT: u32 = 26;
+
+lazy_static! {
+    /// The base page size reported by the platform
+    static ref DETECTED_PAGE_SIZE: usize = {
+        let layout = unsafe { Layout::from_size_align_unchecked(0, 1) };
+
+        match sysconf(SysconfVar::PAGE_SIZE) {
+            Ok(val) => match val {
+                Some(val) => val as usize,
+                None => HugeGlobalAllocator::alloc_error_layout("sysconf PAGE_SIZE no value", layout)
+            }
+            Err(_) => HugeGlobalAllocator::alloc_error_layout("sysconf PAGE_SIZE failed", layout)
+        }
+    };
+}
+
+/// Base page size override (0 for the detected size)
+static BASE_PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Huge page size override (0 for the default)
+static HUGE_PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Set once either page size has been read, after which the overrides can't change
+static FIXED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the base page size assumed by the allocator, fixing the page sizes
+pub fn base_page_size() -> usize {
+    fix();
+
+    base()
+}
+
+/// Returns the huge page size assumed by the allocator, fixing the page sizes
+pub fn huge_page_size() -> usize {
+    fix();
+
+    huge()
+}
+
+/// Overrides the base page size detected with sysconf. The size must be a power of two no smaller
+/// than the detected size and smaller than the huge page size. A size of 0 restores the detected
+/// size. Fails once the page sizes have been fixed.
+pub fn set_base_page_size(size: usize) -> Result<(), Box<dyn Error>> {
+    if size != 0 && (!size.is_power_of_two() || size < *DETECTED_PAGE_SIZE || size >= huge()) {
+        Err(format!("invalid base page size {size}"))?
+    }
+
+    set(&BASE_PAGE_SIZE, size)
+}
+
+/// Overrides the huge page size. The size must be a power of two larger than the base page size,
+/// and must be a huge page size supported by the kernel for huge page mappings to succeed. A size
+/// of 0 restores the default of 2mb. Fails once the page sizes have been fixed.
+pub fn set_huge_page_size(size: usize) -> Result<(), Box<dyn Error>> {
+    if size != 0 && (!size.is_power_of_two() || size <= base()) {
+        Err(format!("invalid huge page size {size}"))?
+    }
+
+    set(&HUGE_PAGE_SIZE, size)
+}
+
+/// Returns the base page size without fixing it
+fn base() -> usize {
+    match BASE_PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => *DETECTED_PAGE_SIZE,
+        size => size,
+    }
+}
+
+/// Returns the huge page size without fixing it
+fn huge() -> usize {
+    match HUGE_PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => DEFAULT_HUGE_PAGE_SIZE,
+        size => size,
+    }
+}
+
+/// Fixes the page sizes, so no segment is mapped with a size which later changes
+#[inline]
+fn fix() {
+    if !FIXED.load(Ordering::Relaxed) {
+        FIXED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Stores a page size override unless the page sizes have been fixed
+fn set(size: &AtomicUsize, value: usize) -> Result<(), Box<dyn Error>> {
+    if FIXED.load(Ordering::Relaxed) {
+        Err("page sizes can't be changed once they have been used")?
+    }
+
+    size.store(value, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Returns the flag bits selecting a huge page size for mmap (MAP_HUGE_*) and memfd_create
+/// (MFD_HUGE_*), which share the same encoding
+pub(crate) fn huge_size_flags(page_size: usize) -> c_int {
+    (page_size.trailing_zeros() << HUGE_SHIFT) as c_int
+}
@@ -0,0 +1,140 @@
+//! Memory protection keys
+//!
+//! On x86_64 CPUs with protection keys, a managed segment can be associated with a key allocated
+//! from the kernel. Each thread's rights to the pages of every segment with the key are then
+//! switched by writing the thread's PKRU register, without a system call, and without the TLB
+//! shootdowns changing page protections with mprotect costs. New threads and signal handlers start
+//! with no access to any allocated key.
+//!
+//! On other targets, or CPUs without protection keys, allocating a key fails.
+
+use std::error::Error;
+use std::ffi::c_int;
+
+/// pkey_alloc access rights. Not defined by libc for every target.
+const PKEY_DISABLE_ACCESS: u32 = 0x1;
+const PKEY_DISABLE_WRITE: u32 = 0x2;
+
+/// Access a thread has to the segments associated with a protection key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAccess {
+    /// The segments can be read and written
+    ReadWrite,
+    /// The segments can be read but writes fault
+    ReadOnly,
+    /// Reads and writes fault
+    NoAccess,
+}
+
+impl KeyAccess {
+    /// Returns the access rights bits for the access
+    fn rights(self) -> u32 {
+        match self {
+            KeyAccess::ReadWrite => 0,
+            KeyAccess::ReadOnly => PKEY_DISABLE_WRITE,
+            KeyAccess::NoAccess => PKEY_DISABLE_ACCESS,
+        }
+    }
+
+    /// Returns the access for access rights bits
+    fn from_rights(rights: u32) -> Self {
+        if rights & PKEY_DISABLE_ACCESS != 0 {
+            KeyAccess::NoAccess
+        } else if rights & PKEY_DISABLE_WRITE != 0 {
+            KeyAccess::ReadOnly
+        } else {
+            KeyAccess::ReadWrite
+        }
+    }
+}
+
+/// A memory protection key allocated from the kernel. Associate segments with the key with
+/// [HugeGlobalAllocator::set_protection_key](crate::HugeGlobalAllocator::set_protection_key).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtectionKey(c_int);
+
+impl ProtectionKey {
+    /// Allocates a protection key, giving the calling thread the access to segments associated
+    /// with it. Fails if protection keys aren't supported or all keys are allocated.
+    pub fn alloc(access: KeyAccess) -> Result<Self, Box<dyn Error>> {
+        let pkey = unsafe { libc::syscall(libc::SYS_pkey_alloc, 0, access.rights()) };
+
+        if pkey < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Self(pkey as c_int))
+    }
+
+    /// Returns the key to the kernel. Segments still associated with the key keep it, and gain
+    /// the access of any key allocated later with the same number, so they should be given the
+    /// default key first.
+    pub fn free(self) -> Result<(), Box<dyn Error>> {
+        if unsafe { libc::syscall(libc::SYS_pkey_free, self.0) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    /// Sets the calling thread's access to the segments associated with the key
+    pub fn set_access(self, access: KeyAccess) {
+        let shift = self.0 as u32 * 2;
+        let pkru = read_pkru() & !((PKEY_DISABLE_ACCESS | PKEY_DISABLE_WRITE) << shift);
+
+        write_pkru(pkru | access.rights() << shift);
+    }
+
+    /// Returns the calling thread's access to the segments associated with the key
+    pub fn access(self) -> KeyAccess {
+        KeyAccess::from_rights(read_pkru() >> (self.0 as u32 * 2))
+    }
+
+    /// Returns the kernel's number for the key
+    pub fn as_raw(self) -> c_int {
+        self.0
+    }
+}
+
+/// Runs f with the calling thread given read write access to the segments associated with a key,
+/// restoring its access afterwards
+pub(crate) fn with_access<R>(pkey: c_int, f: impl FnOnce() -> R) -> R {
+    let key = ProtectionKey(pkey);
+    let access = key.access();
+
+    key.set_access(KeyAccess::ReadWrite);
+    let res = f();
+    key.set_access(access);
+
+    res
+}
+
+/// Returns the calling thread's protection key rights register
+#[cfg(target_arch = "x86_64")]
+fn read_pkru() -> u32 {
+    let pkru: u32;
+
+    // Only reached with an allocated key, so the CPU and kernel support protection keys
+    unsafe {
+        std::arch::asm!("rdpkru", in("ecx") 0, out("eax") pkru, out("edx") _, options(nomem, nostack));
+    }
+
+    pkru
+}
+
+/// Sets the calling thread's protection key rights register
+#[cfg(target_arch = "x86_64")]
+fn write_pkru(pkru: u32) {
+    // Not nomem, so memory accesses aren't moved across the change of rights
+    unsafe {
+        std::arch::asm!("wrpkru", in("eax") pkru, in("ecx") 0, in("edx") 0, options(nostack));
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn read_pkru() -> u32 {
+    0
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn write_pkru(_pkru: u32) {}
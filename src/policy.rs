@@ -0,0 +1,104 @@
+//! Compile time allocation policies
+//!
+//! [HugeGlobalAllocator](crate::HugeGlobalAllocator) takes a page size policy as a type
+//! parameter, which decides from each allocation's layout whether it is memory mapped and with
+//! which page size. The policy's methods are resolved at compile time, so a custom policy costs no
+//! more than the default and needs no runtime configuration.
+//!
+//! ```rust
+//! use std::alloc::{Layout, System};
+//! use huge_global_alloc::{HugeGlobalAllocator, PagePolicy, PageSizePref};
+//!
+//! /// Puts page aligned tables on 1gb pages and everything else on base size pages
+//! struct Tables;
+//!
+//! impl PagePolicy for Tables {
+//!     fn page_size(&self, layout: Layout) -> PageSizePref {
+//!         if layout.align() >= 4096 {
+//!             PageSizePref::Huge1G
+//!         } else {
+//!             PageSizePref::Base
+//!         }
+//!     }
+//! }
+//!
+//! #[global_allocator]
+//! static GLOBAL_ALLOCATOR: HugeGlobalAllocator<System, Tables> =
+//!     HugeGlobalAllocator::with_policy(1024 * 1024, System, Tables);
+//!
+//! let vec: Vec<u8> = Vec::with_capacity(4 * 1024 * 1024); // 4mb
+//! assert!(!GLOBAL_ALLOCATOR.is_huge(vec.as_ptr()));
+//! ````
+//!
+//! The decision to map an allocation at all can look beyond its size, at its alignment or at the
+//! role of the allocating thread (see [set_thread_tag](crate::set_thread_tag)):
+//!
+//! ```rust
+//! use std::alloc::{GlobalAlloc, Layout, System};
+//! use huge_global_alloc::{set_thread_tag, thread_tag, HugeGlobalAllocator, PagePolicy};
+//!
+//! /// Maps big or huge page aligned allocations, except on the garbage collector thread
+//! struct NotOnGc;
+//!
+//! impl PagePolicy for NotOnGc {
+//!     fn maps(&self, layout: Layout, threshold: usize) -> bool {
+//!         thread_tag() != Some("gc") && (layout.size() >= threshold || layout.align() >= 2 * 1024 * 1024)
+//!     }
+//! }
+//!
+//! static ALLOCATOR: HugeGlobalAllocator<System, NotOnGc> = HugeGlobalAllocator::with_policy(1024 * 1024, System, NotOnGc);
+//!
+//! let aligned = Layout::from_size_align(4096, 2 * 1024 * 1024).unwrap();
+//! let ptr = unsafe { ALLOCATOR.alloc(aligned) };
+//! # #[cfg(not(feature = "passthrough"))]
+//! assert!(ALLOCATOR.is_managed(ptr));
+//! unsafe { ALLOCATOR.dealloc(ptr, aligned) };
+//!
+//! set_thread_tag(Some("gc"));
+//!
+//! let big = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+//! let ptr = unsafe { ALLOCATOR.alloc(big) };
+//! assert!(!ALLOCATOR.is_managed(ptr));
+//! unsafe { ALLOCATOR.dealloc(ptr, big) };
+//! ````
+
+use std::alloc::Layout;
+
+use crate::{PageSizePref, DEFAULT_HUGE_PAGE_SIZE};
+
+/// Alignment from which the default policy treats a layout as a request for huge pages (2mb).
+/// Collections which can only express what they need through their layout, such as aligned
+/// vectors, can ask for huge pages on stable Rust by aligning their buffer to it.
+pub const HUGE_ALIGNMENT: usize = DEFAULT_HUGE_PAGE_SIZE;
+
+/// Decides which allocations are memory mapped, and the page size they are mapped with
+pub trait PagePolicy {
+    /// Returns true if an allocation with the layout should be memory mapped rather than passed
+    /// to the inner allocator. The threshold is the allocator's, or the current thread's override.
+    /// By default allocations of at least the threshold, or aligned to at least [HUGE_ALIGNMENT]
+    /// whatever their size, are mapped, and a threshold of zero maps nothing.
+    fn maps(&self, layout: Layout, threshold: usize) -> bool {
+        threshold != 0 && (layout.size() >= threshold || layout.align() >= HUGE_ALIGNMENT)
+    }
+
+    /// Returns the page size to map an allocation with. Only allocations the policy maps are
+    /// passed. Segments keep the page size preference they were created with when a reallocation
+    /// moves them. By default allocations aligned to at least [HUGE_ALIGNMENT] get huge pages
+    /// whatever the slack, falling back to base size pages, and others follow the allocator's
+    /// maximum slack.
+    fn page_size(&self, layout: Layout) -> PageSizePref {
+        if layout.align() >= HUGE_ALIGNMENT {
+            PageSizePref::Huge
+        } else {
+            PageSizePref::Policy
+        }
+    }
+}
+
+/// The default page size policy: allocations of at least the threshold are mapped, with huge
+/// pages unless that would leave more slack than the maximum slack allows, and allocations aligned
+/// to at least [HUGE_ALIGNMENT] are mapped with huge pages whatever their size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultPagePolicy;
+
+impl PagePolicy for DefaultPagePolicy {}
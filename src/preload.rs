@@ -0,0 +1,103 @@
+//! LD_PRELOAD support
+//!
+//! The preload feature builds a shared library which can be injected in to unmodified binaries.
+//! The malloc interface's allocator is const initialised so it works for allocations made before
+//! any constructors run, and is then configured from the environment by a constructor:
+//!
+//! | Variable                          | Setting                                            |
+//! |-----------------------------------|----------------------------------------------------|
+//! | HUGE_ALLOC_THRESHOLD              | Threshold in bytes (k, m and g suffixes allowed)   |
+//! | HUGE_ALLOC_SOFT_LIMIT             | Soft limit in bytes (k, m and g suffixes allowed)  |
+//! | HUGE_ALLOC_HUGE_RETRIES           | Number of huge page mapping retries                |
+//! | HUGE_ALLOC_HUGE_RETRY_BACKOFF_US  | Initial retry backoff in microseconds              |
+//! | HUGE_ALLOC_SIGBUS_PROTECTION      | 1 to enable SIGBUS protection                      |
+//! | HUGE_ALLOC_PRETOUCH_VALIDATION    | 1 to enable pre-touch validation                   |
+//! | HUGE_ALLOC_REMAP_FAILURE          | copy or fail                                       |
+//! | HUGE_ALLOC_UNMAP_FAILURE          | abort, leak or log                                 |
+//! | HUGE_ALLOC_PAGE_SIZE              | Base page size override in bytes                   |
+//! | HUGE_ALLOC_HUGE_PAGE_SIZE         | Huge page size override in bytes                   |
+//! | HUGE_ALLOC_STATS                  | 1 to write the statistics to stderr at exit        |
+//!
+//! ```sh
+//! cargo build --release --features preload
+//! HUGE_ALLOC_THRESHOLD=2m HUGE_ALLOC_STATS=1 LD_PRELOAD=target/release/libhuge_global_alloc.so some_program
+//! ```
+
+use std::error::Error;
+use std::ffi::{c_char, CStr};
+
+use crate::ctl::{parse_bool, parse_size};
+use crate::ffi::malloc::MALLOC_ALLOCATOR;
+use crate::{set_base_page_size, set_huge_page_size, HugeGlobalAllocator};
+
+/// Constructor run by the dynamic loader when the library is loaded
+#[used]
+#[link_section = ".init_array"]
+static INIT: extern "C" fn() = init;
+
+extern "C" fn init() {
+    configure(&MALLOC_ALLOCATOR, getenv);
+
+    if getenv(c"HUGE_ALLOC_STATS").and_then(parse_bool) == Some(true) {
+        unsafe { libc::atexit(print_stats) };
+    }
+}
+
+/// Writes the malloc interface's statistics to stderr
+extern "C" fn print_stats() {
+    if let Ok(stats) = MALLOC_ALLOCATOR.stats() {
+        eprintln!("{:?}", stats);
+    }
+}
+
+/// Returns the value of an environment variable without allocating
+fn getenv(name: &CStr) -> Option<&'static str> {
+    let value = unsafe { libc::getenv(name.as_ptr()) };
+
+    if value.is_null() {
+        None
+    } else {
+        unsafe { CStr::from_ptr(value as *const c_char) }.to_str().ok()
+    }
+}
+
+/// Environment variables and the page size overrides they set, which must be made before the page
+/// sizes are first read
+const PAGE_SIZES: [(&CStr, fn(usize) -> Result<(), Box<dyn Error>>); 2] = [
+    (c"HUGE_ALLOC_PAGE_SIZE", set_base_page_size),
+    (c"HUGE_ALLOC_HUGE_PAGE_SIZE", set_huge_page_size),
+];
+
+/// Environment variables and the ctl keys they set
+const SETTINGS: [(&CStr, &str); 8] = [
+    (c"HUGE_ALLOC_THRESHOLD", "threshold"),
+    (c"HUGE_ALLOC_SOFT_LIMIT", "soft_limit"),
+    (c"HUGE_ALLOC_HUGE_RETRIES", "huge_retry.count"),
+    (c"HUGE_ALLOC_HUGE_RETRY_BACKOFF_US", "huge_retry.backoff_us"),
+    (c"HUGE_ALLOC_SIGBUS_PROTECTION", "sigbus_protection"),
+    (c"HUGE_ALLOC_PRETOUCH_VALIDATION", "pretouch_validation"),
+    (c"HUGE_ALLOC_REMAP_FAILURE", "remap_failure"),
+    (c"HUGE_ALLOC_UNMAP_FAILURE", "unmap_failure"),
+];
+
+/// Configures an allocator from variables returned by the lookup function. Invalid values are
+/// reported on stderr and ignored.
+pub(crate) fn configure(allocator: &HugeGlobalAllocator, var: impl Fn(&CStr) -> Option<&'static str>) {
+    for (name, set) in PAGE_SIZES {
+        if let Some(value) = var(name) {
+            let result = parse_size(value).ok_or_else(|| format!("invalid size {value}").into()).and_then(set);
+
+            if let Err(e) = result {
+                HugeGlobalAllocator::log(&format!("huge_global_alloc: ignoring {}: {}", name.to_string_lossy(), e));
+            }
+        }
+    }
+
+    for (name, key) in SETTINGS {
+        if let Some(value) = var(name) {
+            if let Err(e) = allocator.ctl(key, value) {
+                HugeGlobalAllocator::log(&format!("huge_global_alloc: ignoring {}: {}", name.to_string_lossy(), e));
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Probe of the system calls and flags the allocator depends on
+//!
+//! Hardened containers often block system calls or flags with seccomp filters, and older kernels
+//! reject newer flags. The probe tries each call the allocator relies on against scratch mappings,
+//! so strategies which would fail at runtime can be switched off at startup and the reason
+//! reported.
+
+use std::ffi::c_void;
+use std::fmt::{self, Display};
+
+use nix::{
+    errno::Errno,
+    sys::mman::{MRemapFlags, MapFlags},
+};
+
+use crate::{
+    pagesize::{base_page_size, huge_page_size, huge_size_flags},
+    sys::Syscalls,
+};
+
+/// Outcome of probing one system call or flag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    /// The system call and flag probed
+    pub name: &'static str,
+    /// What the allocator uses the call for
+    pub used_for: &'static str,
+    /// The errno the call failed with, or None if it worked
+    pub errno: Option<i32>,
+}
+
+impl Probe {
+    /// Returns true if the call worked
+    pub fn available(&self) -> bool {
+        self.errno.is_none()
+    }
+
+    /// Returns true if the call was refused outright, as seccomp filters do, rather than failing
+    /// for lack of resources or kernel support
+    pub fn blocked(&self) -> bool {
+        matches!(self.errno.map(Errno::from_i32), Some(Errno::EPERM | Errno::EACCES | Errno::ENOSYS))
+    }
+}
+
+/// Results of probing the allocator's system calls, and the settings switched off because they
+/// would fail
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// The calls probed
+    pub probes: Vec<Probe>,
+    /// The ctl keys of the settings switched off, with the values they had
+    pub disabled: Vec<&'static str>,
+}
+
+impl ProbeReport {
+    /// Returns the probe of the named call
+    pub fn get(&self, name: &str) -> Option<&Probe> {
+        self.probes.iter().find(|probe| probe.name == name)
+    }
+
+    /// Returns true if the named call worked
+    pub fn available(&self, name: &str) -> bool {
+        self.get(name).is_some_and(Probe::available)
+    }
+
+    /// Returns true if every call worked
+    pub fn all_available(&self) -> bool {
+        self.probes.iter().all(Probe::available)
+    }
+}
+
+impl Display for ProbeReport {
+    /// Formats the report as a table of calls followed by the settings switched off
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<28} {:<44} used for", "call", "status")?;
+
+        for probe in &self.probes {
+            let status = match probe.errno {
+                None => "ok".to_string(),
+                Some(errno) => {
+                    let errno = Errno::from_i32(errno);
+                    let reason = if probe.blocked() { "blocked" } else { "failed" };
+
+                    format!("{reason}: {errno:?} ({})", errno.desc())
+                }
+            };
+
+            writeln!(f, "{:<28} {:<44} {}", probe.name, status, probe.used_for)?;
+        }
+
+        if self.disabled.is_empty() {
+            writeln!(f, "nothing disabled")
+        } else {
+            writeln!(f, "disabled: {}", self.disabled.join(", "))
+        }
+    }
+}
+
+/// Names of the probes
+pub(crate) const MMAP: &str = "mmap";
+pub(crate) const MMAP_HUGETLB: &str = "mmap MAP_HUGETLB";
+pub(crate) const MREMAP: &str = "mremap MREMAP_MAYMOVE";
+pub(crate) const MREMAP_FIXED: &str = "mremap MREMAP_FIXED";
+pub(crate) const MREMAP_DONTUNMAP: &str = "mremap MREMAP_DONTUNMAP";
+pub(crate) const MADV_POPULATE_WRITE: &str = "madvise MADV_POPULATE_WRITE";
+pub(crate) const MADV_WIPEONFORK: &str = "madvise MADV_WIPEONFORK";
+pub(crate) const MLOCK: &str = "mlock";
+
+/// Tries each system call the allocator depends on against scratch mappings, unmapping them
+/// afterwards
+pub(crate) fn run(sys: &dyn Syscalls) -> Vec<Probe> {
+    let page_size = base_page_size();
+    let mut probes = Vec::new();
+
+    let mut record = |name, used_for, res: nix::Result<()>| {
+        probes.push(Probe {
+            name,
+            used_for,
+            errno: res.err().map(|errno| errno as i32),
+        });
+    };
+
+    record(MMAP, "all segments", with_page(sys, |_| Ok(())));
+
+    // The huge page size bits aren't all named by MapFlags
+    let huge_flags = MapFlags::MAP_HUGETLB | unsafe { MapFlags::from_bits_unchecked(huge_size_flags(huge_page_size())) };
+
+    record(
+        MMAP_HUGETLB,
+        "huge page segments",
+        unsafe { sys.mmap(huge_page_size(), huge_flags) }.and_then(|ptr| unsafe { sys.munmap(ptr, huge_page_size()) }),
+    );
+
+    record(
+        MREMAP,
+        "resizing segments",
+        unsafe { sys.mmap(page_size, MapFlags::empty()) }.and_then(|ptr| {
+            match unsafe { sys.mremap(ptr, page_size, page_size * 2, MRemapFlags::MREMAP_MAYMOVE) } {
+                Ok(ptr) => unsafe { sys.munmap(ptr, page_size * 2) },
+                Err(e) => {
+                    let _ = unsafe { sys.munmap(ptr, page_size) };
+                    Err(e)
+                }
+            }
+        }),
+    );
+
+    record(MREMAP_FIXED, "move strategy mremap, demotion", move_page(sys, false));
+    record(MREMAP_DONTUNMAP, "move strategy dontunmap", move_page(sys, true));
+
+    record(
+        MADV_POPULATE_WRITE,
+        "sigbus protection, pre-touch validation",
+        with_page(sys, |ptr| unsafe { sys.madvise(ptr, page_size, libc::MADV_POPULATE_WRITE) }),
+    );
+
+    record(
+        MADV_WIPEONFORK,
+        "security profile",
+        with_page(sys, |ptr| unsafe { sys.madvise(ptr, page_size, libc::MADV_WIPEONFORK) }),
+    );
+
+    record(MLOCK, "security profile", with_page(sys, |ptr| unsafe { sys.mlock(ptr, page_size) }));
+
+    probes
+}
+
+/// Maps a scratch page, runs f on it and unmaps it
+fn with_page(sys: &dyn Syscalls, f: impl FnOnce(*mut c_void) -> nix::Result<()>) -> nix::Result<()> {
+    let ptr = unsafe { sys.mmap(base_page_size(), MapFlags::empty()) }?;
+
+    let res = f(ptr);
+
+    let _ = unsafe { sys.munmap(ptr, base_page_size()) };
+
+    res
+}
+
+/// Moves a scratch page on to another with mremap, keeping the source mapped if keep_source is true
+fn move_page(sys: &dyn Syscalls, keep_source: bool) -> nix::Result<()> {
+    let page_size = base_page_size();
+
+    with_page(sys, |dest| {
+        let ptr = unsafe { sys.mmap(page_size, MapFlags::empty()) }?;
+
+        let res = unsafe { sys.mremap_fixed(ptr, page_size, dest, keep_source) };
+
+        // The source is gone if it moved without MREMAP_DONTUNMAP
+        if res.is_err() || keep_source {
+            let _ = unsafe { sys.munmap(ptr, page_size) };
+        }
+
+        res.map(drop)
+    })
+}
@@ -0,0 +1,126 @@
+//! Raw memory mapping primitives
+//!
+//! Thin wrappers around the mmap, mremap, munmap and madvise system calls used by the allocator,
+//! for applications which want huge page mappings without installing a global allocator. Nothing
+//! here is tracked by an allocator: regions are plain slices covering whole pages, and the caller
+//! is responsible for unmapping them. Faults injected with the fault-injection feature apply to
+//! these functions too.
+//!
+//! ```rust
+//! use huge_global_alloc::raw;
+//!
+//! // Try huge pages first, falling back to default size pages
+//! let (region, page_size) = match raw::map_huge(3 * 1024 * 1024) {
+//!     Ok(region) => (region, raw::HUGE_PAGE_SIZE),
+//!     Err(_) => (raw::map_default(3 * 1024 * 1024).unwrap(), raw::page_size()),
+//! };
+//! assert!(region.len() >= 3 * 1024 * 1024);
+//!
+//! unsafe {
+//!     (region.as_ptr() as *mut u8).write(42);
+//!
+//!     let region = raw::remap(region, 5 * 1024 * 1024, page_size).unwrap();
+//!     assert_eq!(*(region.as_ptr() as *mut u8), 42);
+//!
+//!     raw::unmap(region).unwrap();
+//! }
+//! ````
+
+use std::ffi::{c_int, c_void};
+use std::ptr::NonNull;
+
+use nix::{
+    errno::Errno,
+    sys::mman::{MRemapFlags, MapFlags},
+};
+
+use crate::{
+    pagesize::base_page_size,
+    sys::{LinuxSyscalls, Syscalls},
+};
+
+/// Size of the huge pages mapped by [map_huge]
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Returns the base page size, which is the platform's page size unless overridden with
+/// [set_base_page_size](crate::set_base_page_size)
+pub fn page_size() -> usize {
+    base_page_size()
+}
+
+/// Rounds size up to a whole number of pages
+pub fn round_to_pages(size: usize, page_size: usize) -> usize {
+    size.div_ceil(page_size) * page_size
+}
+
+/// Maps an anonymous read write region of at least size bytes backed by 2mb huge pages. Fails
+/// with ENOMEM if the huge page pool can't back the region.
+pub fn map_huge(size: usize) -> nix::Result<NonNull<[u8]>> {
+    map(size, HUGE_PAGE_SIZE, MapFlags::MAP_HUGETLB | MapFlags::MAP_HUGE_2MB)
+}
+
+/// Maps an anonymous read write region of at least size bytes backed by default size pages
+pub fn map_default(size: usize) -> nix::Result<NonNull<[u8]>> {
+    map(size, page_size(), MapFlags::empty())
+}
+
+/// Resizes a region to at least new_size bytes, moving it if necessary. On failure the original
+/// region is left mapped.
+///
+/// # Safety
+///
+/// The region must have been returned by this module's functions and not unmapped, and page_size
+/// must be the page size it was mapped with. If the region moves, pointers in to the old region
+/// are invalidated.
+pub unsafe fn remap(region: NonNull<[u8]>, new_size: usize, page_size: usize) -> nix::Result<NonNull<[u8]>> {
+    if new_size == 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let new_len = round_to_pages(new_size, page_size);
+
+    let ptr = LinuxSyscalls.mremap(region.as_ptr() as *mut c_void, region.len(), new_len, MRemapFlags::MREMAP_MAYMOVE)?;
+
+    region_from(ptr, new_len)
+}
+
+/// Unmaps a region
+///
+/// # Safety
+///
+/// The region must have been returned by this module's functions and not already unmapped. No
+/// pointers in to the region may be used afterwards.
+pub unsafe fn unmap(region: NonNull<[u8]>) -> nix::Result<()> {
+    LinuxSyscalls.munmap(region.as_ptr() as *mut c_void, region.len())
+}
+
+/// Gives the kernel advice about the use of a region, for example libc::MADV_DONTNEED to release
+/// its pages or libc::MADV_POPULATE_WRITE to fault them in
+///
+/// # Safety
+///
+/// The region must be mapped, and the advice must not invalidate memory the caller relies on
+/// (MADV_DONTNEED discards the region's contents).
+pub unsafe fn advise(region: NonNull<[u8]>, advice: c_int) -> nix::Result<()> {
+    LinuxSyscalls.madvise(region.as_ptr() as *mut c_void, region.len(), advice)
+}
+
+/// Maps a region of whole pages with the given flags
+fn map(size: usize, page_size: usize, flags: MapFlags) -> nix::Result<NonNull<[u8]>> {
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let len = round_to_pages(size, page_size);
+
+    let ptr = unsafe { LinuxSyscalls.mmap(len, flags) }?;
+
+    region_from(ptr, len)
+}
+
+/// Builds a region slice from a mapping returned by the kernel
+fn region_from(ptr: *mut c_void, len: usize) -> nix::Result<NonNull<[u8]>> {
+    let ptr = NonNull::new(ptr as *mut u8).ok_or(Errno::ENOMEM)?;
+
+    Ok(NonNull::slice_from_raw_parts(ptr, len))
+}
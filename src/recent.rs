@@ -0,0 +1,58 @@
+//! Per-thread record of the most recently allocated segment
+//!
+//! A vector which is allocated and then grown straight away, or grown repeatedly, is reallocated
+//! by the thread which allocated it. Each thread remembers the segment it last mapped or resized
+//! so the reallocation can tell the pointer is managed without looking it up.
+//!
+//! The record can't be cleared when another thread frees the segment, so it carries the number of
+//! segments removed from the mapper when it was made, and only answers while no segment has been
+//! removed since. Otherwise a freed segment's address reused by the inner allocator would be taken
+//! for a managed one.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    /// The mapper, segment address and removal count of the current thread's most recent segment.
+    /// Const initialised without a destructor so it can be used from the allocator at any point in
+    /// the thread's life
+    static RECENT: Cell<(usize, usize, usize)> = const { Cell::new((0, 0, 0)) };
+}
+
+/// The most recent segment of each thread allocating from a mapper
+pub(crate) struct RecentSegments {
+    /// Number of segments removed from the mapper
+    removals: AtomicUsize,
+}
+
+impl RecentSegments {
+    pub const fn new() -> Self {
+        Self {
+            removals: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records the segment at ptr as the current thread's most recent
+    pub fn record(&self, ptr: usize) {
+        let removals = self.removals.load(Ordering::Acquire);
+
+        let _ = RECENT.try_with(|recent| recent.set((self.id(), ptr, removals)));
+    }
+
+    /// Counts a segment removed from the mapper, invalidating every thread's record
+    pub fn removed(&self) {
+        self.removals.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns true if the segment at ptr is the current thread's most recent and is still managed
+    pub fn contains(&self, ptr: usize) -> bool {
+        let Ok((id, recent, removals)) = RECENT.try_with(Cell::get) else { return false };
+
+        id == self.id() && recent == ptr && removals == self.removals.load(Ordering::Acquire)
+    }
+
+    /// Identifies the mapper in the thread's record
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+}
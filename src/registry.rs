@@ -0,0 +1,98 @@
+//! Named allocator instances
+//!
+//! A process can run several allocators, one per subsystem, each with its own threshold, limits
+//! and statistics. Registering an allocator under a name adds it to a fixed size lock free table
+//! so its statistics can be listed by name with [registered_stats], or merged in to one process
+//! wide view with [merged_stats]. Registering never allocates.
+
+use std::error::Error;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use crate::{mmapper::MMapper, HugeGlobalAllocatorStats};
+
+/// Maximum number of named allocators
+pub const REGISTRY_CAPACITY: usize = 16;
+
+/// A registry table entry. The name is published by the ready flag once the entry is claimed.
+struct Entry {
+    mapper: AtomicPtr<MMapper>,
+    name_ptr: AtomicPtr<u8>,
+    name_len: AtomicUsize,
+    ready: AtomicBool,
+}
+
+static REGISTRY: [Entry; REGISTRY_CAPACITY] = [const {
+    Entry {
+        mapper: AtomicPtr::new(null_mut()),
+        name_ptr: AtomicPtr::new(null_mut()),
+        name_len: AtomicUsize::new(0),
+        ready: AtomicBool::new(false),
+    }
+}; REGISTRY_CAPACITY];
+
+impl Entry {
+    /// Returns the name and mapper of a registered allocator
+    fn get(&self) -> Option<(&'static str, &'static MMapper)> {
+        if !self.ready.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let bytes = unsafe {
+            std::slice::from_raw_parts(self.name_ptr.load(Ordering::Relaxed), self.name_len.load(Ordering::Relaxed))
+        };
+
+        Some((unsafe { std::str::from_utf8_unchecked(bytes) }, unsafe { &*self.mapper.load(Ordering::Relaxed) }))
+    }
+}
+
+/// Iterates over the registered allocators in registration order
+fn entries() -> impl Iterator<Item = (&'static str, &'static MMapper)> {
+    REGISTRY.iter().filter_map(Entry::get)
+}
+
+/// Registers an allocator's mapper under a name
+pub(crate) fn register(name: &'static str, mapper: &'static MMapper) -> Result<(), Box<dyn Error>> {
+    if entries().any(|(registered, other)| registered == name || std::ptr::eq(other, mapper)) {
+        Err(format!("allocator already registered or name {name:?} in use"))?
+    }
+
+    let key = mapper as *const MMapper as *mut MMapper;
+
+    for entry in &REGISTRY {
+        if entry.mapper.compare_exchange(null_mut(), key, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            entry.name_ptr.store(name.as_ptr() as *mut u8, Ordering::Relaxed);
+            entry.name_len.store(name.len(), Ordering::Relaxed);
+            entry.ready.store(true, Ordering::Release);
+
+            return Ok(());
+        }
+    }
+
+    Err(format!("no room to register {name:?}, at most {REGISTRY_CAPACITY} allocators can be named"))?
+}
+
+/// Returns the name an allocator's mapper is registered under
+pub(crate) fn name_of(mapper: &MMapper) -> Option<&'static str> {
+    entries().find(|(_, other)| std::ptr::eq(*other, mapper)).map(|(name, _)| name)
+}
+
+/// Returns a lock free snapshot of the statistics of each named allocator, in registration order.
+/// See [HugeGlobalAllocator::register](crate::HugeGlobalAllocator::register).
+/// With the no-stats feature the snapshots are always zero.
+pub fn registered_stats() -> Vec<(&'static str, HugeGlobalAllocatorStats)> {
+    entries().map(|(name, mapper)| (name, mapper.stats_snapshot())).collect()
+}
+
+/// Returns the statistics of every named allocator merged in to one. See
+/// [HugeGlobalAllocatorStats::merge].
+pub fn merged_stats() -> HugeGlobalAllocatorStats {
+    let mut merged = HugeGlobalAllocatorStats {
+        efficiency: 100,
+        ..Default::default()
+    };
+
+    entries().for_each(|(_, mapper)| merged.merge(&mapper.stats_snapshot()));
+
+    merged
+}
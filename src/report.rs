@@ -0,0 +1,64 @@
+//! Human readable report of the live memory mapped segments
+
+use std::fmt::Write;
+use std::time::Duration;
+
+/// A snapshot of a live segment
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Segment {
+    /// Identifier of the allocation
+    pub id: u64,
+    /// Address of the segment
+    pub ptr: usize,
+    /// Requested size in bytes
+    pub size: usize,
+    /// Mapped size in bytes
+    pub alloc_size: usize,
+    /// Page size backing the segment
+    pub page_size: usize,
+    /// True if backed by huge pages
+    pub huge: bool,
+    /// Time since the segment was mapped
+    pub age: Duration,
+    /// Tag attached by the application
+    pub tag: Option<&'static str>,
+}
+
+/// Formats the segments as a table with a totals line
+pub(crate) fn format(segments: &[Segment]) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "{:>10} {:<18} {:>14} {:>14} {:>10} {:<7} {:>12} tag",
+        "id", "address", "size", "mapped", "page size", "backing", "age"
+    );
+
+    for segment in segments {
+        let _ = writeln!(
+            out,
+            "{:>10} {:<#18x} {:>14} {:>14} {:>10} {:<7} {:>12} {}",
+            segment.id,
+            segment.ptr,
+            segment.size,
+            segment.alloc_size,
+            segment.page_size,
+            if segment.huge { "huge" } else { "default" },
+            format!("{:.3?}", segment.age),
+            segment.tag.unwrap_or("-")
+        );
+    }
+
+    let size: usize = segments.iter().map(|segment| segment.size).sum();
+    let mapped: usize = segments.iter().map(|segment| segment.alloc_size).sum();
+
+    let _ = writeln!(
+        out,
+        "{} segments, {} bytes allocated, {} bytes mapped",
+        segments.len(),
+        size,
+        mapped
+    );
+
+    out
+}
@@ -0,0 +1,240 @@
+//! Lock free ring of segment events for external consumers
+//!
+//! When enabled, the allocator pushes an event for every allocation, reallocation and deallocation
+//! of a memory mapped segment in to a bounded ring, which a consumer thread such as a profiler or
+//! tracer drains to build an allocation timeline. Pushing never locks or allocates. When the ring
+//! is full new events are dropped and counted rather than waiting for the consumer. Timestamps are
+//! CLOCK_MONOTONIC nanoseconds, so they can be correlated with other monotonic timestamps in the
+//! process.
+//!
+//! ```rust
+//! use std::alloc::{GlobalAlloc, Layout};
+//! use huge_global_alloc::events::Operation;
+//! use huge_global_alloc::HugeGlobalAllocator;
+//!
+//! let allocator = HugeGlobalAllocator::new(1024 * 1024);
+//! allocator.event_ring().set_enabled(true);
+//!
+//! let layout = Layout::from_size_align(2 * 1024 * 1024, 8).unwrap();
+//! let ptr = unsafe { allocator.alloc(layout) };
+//! unsafe { allocator.dealloc(ptr, layout) };
+//!
+//! let mut events = Vec::new();
+//! allocator.event_ring().drain(|event| events.push(event));
+//!
+//! assert_eq!(events.len(), 2);
+//! assert_eq!(events[0].operation, Operation::Alloc);
+//! assert_eq!(events[0].address, ptr as usize);
+//! assert_eq!(events[1].operation, Operation::Dealloc);
+//! assert_eq!(events[1].old_address, ptr as usize);
+//! assert!(events[1].timestamp_ns >= events[0].timestamp_ns);
+//! ````
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::{
+    events::{Backing, Operation},
+    mmap::MMap,
+};
+
+/// Number of events the ring holds
+pub const CAPACITY: usize = 1024;
+
+/// An event taken from the ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingEvent {
+    /// The operation performed
+    pub operation: Operation,
+    /// Identifier of the allocation (see [SegmentInfo::id](crate::SegmentInfo::id))
+    pub id: u64,
+    /// Address of the allocation before the operation (0 for allocations)
+    pub old_address: usize,
+    /// Size of the segment before the operation in bytes (0 for allocations)
+    pub old_size: usize,
+    /// Address of the allocation after the operation (0 for deallocations)
+    pub address: usize,
+    /// Size of the segment after the operation in bytes (0 for deallocations)
+    pub size: usize,
+    /// Page size backing the segment
+    pub backing: Backing,
+    /// CLOCK_MONOTONIC time of the operation in nanoseconds
+    pub timestamp_ns: u64,
+}
+
+impl RingEvent {
+    const EMPTY: RingEvent = RingEvent {
+        operation: Operation::Alloc,
+        id: 0,
+        old_address: 0,
+        old_size: 0,
+        address: 0,
+        size: 0,
+        backing: Backing::Default,
+        timestamp_ns: 0,
+    };
+}
+
+/// A slot in the ring. The sequence number says whether the slot is ready to be written for a
+/// given lap of the ring, or holds an event ready to be read.
+struct Slot {
+    seq: AtomicUsize,
+    event: UnsafeCell<RingEvent>,
+}
+
+/// A bounded multi producer ring of segment events
+pub struct EventRing {
+    enabled: AtomicBool,
+    /// Position of the next event to write
+    head: AtomicUsize,
+    /// Position of the next event to read
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+    slots: [Slot; CAPACITY],
+}
+
+// Slot events are only accessed by the thread which claimed the slot through its sequence number
+unsafe impl Sync for EventRing {}
+
+impl EventRing {
+    /// Creates a new, disabled ring
+    pub(crate) const fn new() -> Self {
+        let mut slots = [const {
+            Slot {
+                seq: AtomicUsize::new(0),
+                event: UnsafeCell::new(RingEvent::EMPTY),
+            }
+        }; CAPACITY];
+
+        let mut i = 0;
+
+        while i < CAPACITY {
+            slots[i].seq = AtomicUsize::new(i);
+            i += 1;
+        }
+
+        Self {
+            enabled: AtomicBool::new(false),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            slots,
+        }
+    }
+
+    /// Enables or disables pushing events in to the ring. The ring is disabled by default.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if events are being pushed in to the ring
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of events dropped because the ring was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Takes the oldest event from the ring
+    pub fn pop(&self) -> Option<RingEvent> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[pos % CAPACITY];
+            let lap = slot.seq.load(Ordering::Acquire).wrapping_sub(pos.wrapping_add(1)) as isize;
+
+            if lap == 0 {
+                match self.tail.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let event = unsafe { *slot.event.get() };
+                        slot.seq.store(pos.wrapping_add(CAPACITY), Ordering::Release);
+                        return Some(event);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lap < 0 {
+                // Not written yet
+                return None;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Takes every event currently in the ring, oldest first
+    pub fn drain(&self, mut f: impl FnMut(RingEvent)) {
+        while let Some(event) = self.pop() {
+            f(event);
+        }
+    }
+
+    /// Pushes an operation on a segment if the ring is enabled
+    pub(crate) fn record(
+        &self,
+        operation: Operation,
+        old_address: usize,
+        old_size: usize,
+        address: usize,
+        size: usize,
+        mmap: &MMap,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let backing = if mmap.is_default_page_size() {
+            Backing::Default
+        } else {
+            Backing::Huge
+        };
+
+        self.push(RingEvent {
+            operation,
+            id: mmap.id(),
+            old_address,
+            old_size,
+            address,
+            size,
+            backing,
+            timestamp_ns: now_ns(),
+        });
+    }
+
+    /// Pushes an event, dropping it if the ring is full
+    fn push(&self, event: RingEvent) {
+        let mut pos = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[pos % CAPACITY];
+            let lap = slot.seq.load(Ordering::Acquire).wrapping_sub(pos) as isize;
+
+            if lap == 0 {
+                match self.head.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe { *slot.event.get() = event };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return;
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lap < 0 {
+                // Still holding an event from the previous lap
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Returns the CLOCK_MONOTONIC time in nanoseconds
+pub(crate) fn now_ns() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
@@ -0,0 +1,129 @@
+//! AddressSanitizer and Valgrind annotations for memory mapped segments
+//!
+//! With the valgrind feature segments are reported to Valgrind as malloc-like blocks so errors are
+//! attributed to the right allocation. The slack between the end of an allocation and the end of
+//! its last page is marked inaccessible to Valgrind, and to AddressSanitizer with the asan feature,
+//! apart from any bytes kept for a canary with the canary feature.
+//! Valgrind client requests are no-ops when not running under Valgrind, and are only issued on
+//! x86_64. The asan feature requires the process to be built with -Zsanitizer=address.
+
+#[cfg(feature = "canary")]
+use crate::canary;
+use crate::mmap::MMap;
+
+/// Called after a segment has been mapped or resized
+pub fn mapped(mmap: &MMap) {
+    let (slack, slack_size) = slack(mmap);
+
+    valgrind::malloclike_block(mmap.ptr(), mmap.size());
+    valgrind::make_mem_noaccess(slack, slack_size);
+
+    asan::poison(slack, slack_size);
+}
+
+/// Called before a segment is unmapped or resized
+pub fn unmapping(mmap: &MMap) {
+    let (slack, slack_size) = slack(mmap);
+
+    valgrind::freelike_block(mmap.ptr());
+
+    // Leave the shadow memory clean in case the address range is reused
+    asan::unpoison(slack, slack_size);
+}
+
+/// Returns the address and size of the slack, leaving out the bytes kept for a canary
+fn slack(mmap: &MMap) -> (usize, usize) {
+    #[cfg(feature = "canary")]
+    let used = mmap.size() + canary::len(mmap);
+    #[cfg(not(feature = "canary"))]
+    let used = mmap.size();
+
+    (mmap.ptr() + used, mmap.alloc_size() - used)
+}
+
+#[cfg(feature = "asan")]
+mod asan {
+    //! AddressSanitizer interface. The process must be built with -Zsanitizer=address
+
+    use std::ffi::c_void;
+
+    extern "C" {
+        fn __asan_poison_memory_region(addr: *const c_void, size: usize);
+        fn __asan_unpoison_memory_region(addr: *const c_void, size: usize);
+    }
+
+    /// Marks a region as inaccessible
+    pub fn poison(addr: usize, size: usize) {
+        unsafe { __asan_poison_memory_region(addr as *const c_void, size) };
+    }
+
+    /// Marks a region as accessible
+    pub fn unpoison(addr: usize, size: usize) {
+        unsafe { __asan_unpoison_memory_region(addr as *const c_void, size) };
+    }
+}
+
+#[cfg(not(feature = "asan"))]
+mod asan {
+    pub fn poison(_addr: usize, _size: usize) {}
+
+    pub fn unpoison(_addr: usize, _size: usize) {}
+}
+
+#[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+mod valgrind {
+    //! Valgrind client requests (see valgrind.h)
+
+    use std::arch::asm;
+
+    const MALLOCLIKE_BLOCK: usize = 0x1301;
+    const FREELIKE_BLOCK: usize = 0x1302;
+    const MAKE_MEM_NOACCESS: usize = 0x4d43_0000;
+
+    /// Reports a block allocated at addr. Anonymous mappings are zeroed so the block is defined
+    pub fn malloclike_block(addr: usize, size: usize) {
+        request([MALLOCLIKE_BLOCK, addr, size, 0, 1, 0]);
+    }
+
+    /// Reports the block allocated at addr as freed
+    pub fn freelike_block(addr: usize) {
+        request([FREELIKE_BLOCK, addr, 0, 0, 0, 0]);
+    }
+
+    /// Marks a region as inaccessible
+    pub fn make_mem_noaccess(addr: usize, size: usize) {
+        if size != 0 {
+            request([MAKE_MEM_NOACCESS, addr, size, 0, 0, 0]);
+        }
+    }
+
+    /// Issues a client request. The special instruction sequence is a no-op on real hardware
+    fn request(args: [usize; 6]) -> usize {
+        let result;
+
+        unsafe {
+            asm!(
+                "rol rdi, 3",
+                "rol rdi, 13",
+                "rol rdi, 61",
+                "rol rdi, 51",
+                "xchg rbx, rbx",
+                inout("rdx") 0usize => result,
+                in("rax") args.as_ptr(),
+                inout("rdi") 0usize => _,
+                options(nostack),
+            );
+        }
+
+        result
+    }
+}
+
+#[cfg(not(all(feature = "valgrind", target_arch = "x86_64")))]
+mod valgrind {
+    pub fn malloclike_block(_addr: usize, _size: usize) {}
+
+    pub fn freelike_block(_addr: usize) {}
+
+    pub fn make_mem_noaccess(_addr: usize, _size: usize) {}
+}
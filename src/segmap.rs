@@ -0,0 +1,356 @@
+//! Pointer map strategies
+//!
+//! The pointer map records every managed segment by address. A hash map suits most workloads,
+//! but the best structure depends on the segments: a process with a handful of giant segments is
+//! served as well by a sorted vector, which is smaller and keeps the segments in address order,
+//! while one with thousands of 2mb segments and a known peak can size a flat table once and never
+//! rehash it. Each structure implements [SegmentMap], and [PtrMap] holds the one selected by the
+//! [MapStrategy].
+//!
+//! Inserting must never allocate while there is spare capacity, as the map is locked and an
+//! allocation could re-enter the allocator. Maps are grown, or replaced when the strategy
+//! changes, by building the new map outside the lock and moving the segments in to it.
+
+use std::collections::{hash_map, HashMap};
+use std::iter;
+use std::slice;
+
+use crate::{mmap::MMap, MapStrategy};
+
+/// A map of managed segments keyed by address
+pub(crate) trait SegmentMap {
+    /// Iterator over the segments and their addresses
+    type Iter<'a>: Iterator<Item = (&'a usize, &'a MMap)>
+    where
+        Self: 'a;
+
+    /// Returns the number of segments
+    fn len(&self) -> usize;
+
+    /// Returns the number of segments the map can hold without allocating
+    fn capacity(&self) -> usize;
+
+    /// Returns the segment at an address for updating
+    fn get_mut(&mut self, key: &usize) -> Option<&mut MMap>;
+
+    /// Inserts a segment, returning the segment already at the address if there was one. Doesn't
+    /// allocate while the map is below its capacity.
+    fn insert(&mut self, key: usize, mmap: MMap) -> Option<MMap>;
+
+    /// Removes and returns the segment at an address
+    fn remove(&mut self, key: &usize) -> Option<MMap>;
+
+    /// Iterates over the segments and their addresses
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// Removes every segment, passing each to f
+    fn drain_into(&mut self, f: impl FnMut(usize, MMap));
+
+    /// Iterates over the segments
+    fn values(&self) -> impl Iterator<Item = &MMap> {
+        self.iter().map(|(_, mmap)| mmap)
+    }
+}
+
+impl SegmentMap for HashMap<usize, MMap> {
+    type Iter<'a> = hash_map::Iter<'a, usize, MMap>;
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        HashMap::capacity(self)
+    }
+
+    fn get_mut(&mut self, key: &usize) -> Option<&mut MMap> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: usize, mmap: MMap) -> Option<MMap> {
+        HashMap::insert(self, key, mmap)
+    }
+
+    fn remove(&mut self, key: &usize) -> Option<MMap> {
+        HashMap::remove(self, key)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        HashMap::iter(self)
+    }
+
+    fn drain_into(&mut self, mut f: impl FnMut(usize, MMap)) {
+        self.drain().for_each(|(key, mmap)| f(key, mmap));
+    }
+}
+
+/// Segments in a vector sorted by address, found by binary search. Inserting and removing move
+/// the segments above, which is cheap for a few segments.
+pub(crate) struct OrderedMap {
+    entries: Vec<(usize, MMap)>,
+}
+
+impl OrderedMap {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the index of the segment at an address, or where it would be inserted
+    fn search(&self, key: usize) -> Result<usize, usize> {
+        self.entries.binary_search_by_key(&key, |(ptr, _)| *ptr)
+    }
+}
+
+/// Splits a map entry in to its parts
+fn entry_ref((key, mmap): &(usize, MMap)) -> (&usize, &MMap) {
+    (key, mmap)
+}
+
+impl SegmentMap for OrderedMap {
+    type Iter<'a> = iter::Map<slice::Iter<'a, (usize, MMap)>, fn(&(usize, MMap)) -> (&usize, &MMap)>;
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    fn get_mut(&mut self, key: &usize) -> Option<&mut MMap> {
+        self.search(*key).ok().map(|index| &mut self.entries[index].1)
+    }
+
+    fn insert(&mut self, key: usize, mmap: MMap) -> Option<MMap> {
+        match self.search(key) {
+            Ok(index) => Some(std::mem::replace(&mut self.entries[index].1, mmap)),
+            Err(index) => {
+                self.entries.insert(index, (key, mmap));
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &usize) -> Option<MMap> {
+        self.search(*key).ok().map(|index| self.entries.remove(index).1)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.entries.iter().map(entry_ref)
+    }
+
+    fn drain_into(&mut self, mut f: impl FnMut(usize, MMap)) {
+        self.entries.drain(..).for_each(|(key, mmap)| f(key, mmap));
+    }
+}
+
+/// Splits an occupied table slot in to its parts
+fn slot_ref(slot: &Option<(usize, MMap)>) -> Option<(&usize, &MMap)> {
+    slot.as_ref().map(entry_ref)
+}
+
+/// A flat open addressing table with a fixed number of slots, twice the capacity so probe
+/// sequences stay short. The slots are allocated once, and the table is never rehashed until it
+/// is replaced by a larger one.
+pub(crate) struct FixedTable {
+    slots: Vec<Option<(usize, MMap)>>,
+    len: usize,
+}
+
+impl FixedTable {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut slots = Vec::new();
+        slots.resize_with((capacity.max(1) * 2).next_power_of_two(), || None);
+
+        Self { slots, len: 0 }
+    }
+
+    /// Returns the slot a segment address hashes to
+    fn home(&self, key: usize) -> usize {
+        // Fibonacci hashing of the page number, keeping the top bits
+        let hash = ((key >> 12) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+        (hash >> (64 - self.slots.len().trailing_zeros())) as usize
+    }
+
+    /// Returns the slot holding a segment address
+    fn find(&self, key: usize) -> Option<usize> {
+        let mask = self.slots.len() - 1;
+        let mut index = self.home(key);
+
+        loop {
+            match &self.slots[index] {
+                Some((ptr, _)) if *ptr == key => return Some(index),
+                Some(_) => index = (index + 1) & mask,
+                None => return None,
+            }
+        }
+    }
+}
+
+impl SegmentMap for FixedTable {
+    type Iter<'a> = iter::FilterMap<slice::Iter<'a, Option<(usize, MMap)>>, fn(&Option<(usize, MMap)>) -> Option<(&usize, &MMap)>>;
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    fn get_mut(&mut self, key: &usize) -> Option<&mut MMap> {
+        let index = self.find(*key)?;
+
+        self.slots[index].as_mut().map(|(_, mmap)| mmap)
+    }
+
+    fn insert(&mut self, key: usize, mmap: MMap) -> Option<MMap> {
+        if let Some(index) = self.find(key) {
+            return self.slots[index].replace((key, mmap)).map(|(_, old)| old);
+        }
+
+        assert!(self.len < self.capacity(), "fixed pointer table full");
+
+        let mask = self.slots.len() - 1;
+        let mut index = self.home(key);
+
+        while self.slots[index].is_some() {
+            index = (index + 1) & mask;
+        }
+
+        self.slots[index] = Some((key, mmap));
+        self.len += 1;
+
+        None
+    }
+
+    fn remove(&mut self, key: &usize) -> Option<MMap> {
+        let mask = self.slots.len() - 1;
+        let mut hole = self.find(*key)?;
+        let (_, mmap) = self.slots[hole].take()?;
+
+        self.len -= 1;
+
+        // Shift later entries of the probe sequence back in to the hole so lookups still find them
+        let mut index = (hole + 1) & mask;
+
+        while let Some((ptr, _)) = &self.slots[index] {
+            let home = self.home(*ptr);
+
+            // Move the entry if its home slot isn't between the hole and where it is now
+            if (index.wrapping_sub(home) & mask) >= (index.wrapping_sub(hole) & mask) {
+                self.slots[hole] = self.slots[index].take();
+                hole = index;
+            }
+
+            index = (index + 1) & mask;
+        }
+
+        Some(mmap)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.slots.iter().filter_map(slot_ref)
+    }
+
+    fn drain_into(&mut self, mut f: impl FnMut(usize, MMap)) {
+        self.slots.iter_mut().filter_map(Option::take).for_each(|(key, mmap)| f(key, mmap));
+        self.len = 0;
+    }
+}
+
+/// The pointer map of the selected strategy
+pub(crate) enum PtrMap {
+    Hash(HashMap<usize, MMap>),
+    Ordered(OrderedMap),
+    Fixed(FixedTable),
+}
+
+impl PtrMap {
+    /// Creates an empty map of the strategy which can hold capacity segments without allocating.
+    /// A fixed table is given at least its configured capacity.
+    pub fn with_capacity(strategy: MapStrategy, capacity: usize) -> Self {
+        match strategy {
+            MapStrategy::Hash => Self::Hash(HashMap::with_capacity(capacity)),
+            MapStrategy::Ordered => Self::Ordered(OrderedMap::with_capacity(capacity)),
+            MapStrategy::Fixed(fixed) => Self::Fixed(FixedTable::with_capacity(capacity.max(fixed))),
+        }
+    }
+
+    /// Returns true if the map was created for the strategy
+    pub fn is(&self, strategy: MapStrategy) -> bool {
+        matches!(
+            (self, strategy),
+            (Self::Hash(_), MapStrategy::Hash) | (Self::Ordered(_), MapStrategy::Ordered) | (Self::Fixed(_), MapStrategy::Fixed(_))
+        )
+    }
+}
+
+/// Iterator over the segments of a [PtrMap]
+pub(crate) enum PtrMapIter<'a> {
+    Hash(<HashMap<usize, MMap> as SegmentMap>::Iter<'a>),
+    Ordered(<OrderedMap as SegmentMap>::Iter<'a>),
+    Fixed(<FixedTable as SegmentMap>::Iter<'a>),
+}
+
+impl<'a> Iterator for PtrMapIter<'a> {
+    type Item = (&'a usize, &'a MMap);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Hash(iter) => iter.next(),
+            Self::Ordered(iter) => iter.next(),
+            Self::Fixed(iter) => iter.next(),
+        }
+    }
+}
+
+/// Forwards a call to the map of the selected strategy
+macro_rules! dispatch {
+    ($self:ident, $map:ident => $call:expr) => {
+        match $self {
+            PtrMap::Hash($map) => $call,
+            PtrMap::Ordered($map) => $call,
+            PtrMap::Fixed($map) => $call,
+        }
+    };
+}
+
+impl SegmentMap for PtrMap {
+    type Iter<'a> = PtrMapIter<'a>;
+
+    fn len(&self) -> usize {
+        dispatch!(self, map => map.len())
+    }
+
+    fn capacity(&self) -> usize {
+        dispatch!(self, map => map.capacity())
+    }
+
+    fn get_mut(&mut self, key: &usize) -> Option<&mut MMap> {
+        dispatch!(self, map => SegmentMap::get_mut(map, key))
+    }
+
+    fn insert(&mut self, key: usize, mmap: MMap) -> Option<MMap> {
+        dispatch!(self, map => SegmentMap::insert(map, key, mmap))
+    }
+
+    fn remove(&mut self, key: &usize) -> Option<MMap> {
+        dispatch!(self, map => SegmentMap::remove(map, key))
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        match self {
+            Self::Hash(map) => PtrMapIter::Hash(SegmentMap::iter(map)),
+            Self::Ordered(map) => PtrMapIter::Ordered(map.iter()),
+            Self::Fixed(map) => PtrMapIter::Fixed(map.iter()),
+        }
+    }
+
+    fn drain_into(&mut self, f: impl FnMut(usize, MMap)) {
+        dispatch!(self, map => map.drain_into(f))
+    }
+}
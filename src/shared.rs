@@ -0,0 +1,356 @@
+//! Memory segments backed by a memfd, which can be shared with other processes
+//!
+//! A [SharedSegment] is a read write shared mapping of an anonymous memory file. Huge pages are
+//! tried first, falling back to default page size pages if none are available. The segment's file
+//! descriptor can be passed to another process (see the [fdpass](crate::fdpass) module), which
+//! maps the same memory with [SharedSegment::from_fd].
+//!
+//! ```rust
+//! use std::alloc::Layout;
+//! use huge_global_alloc::shared::SharedSegment;
+//!
+//! let layout = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+//! let segment = SharedSegment::new(layout).unwrap();
+//!
+//! unsafe { *segment.as_ptr() = 1 };
+//! assert!(segment.alloc_size() >= 4 * 1024 * 1024);
+//! ````
+//!
+//! Segments can also be created with a name and attached by name from other processes, read only
+//! or read write. Named segments are files in a hugetlbfs mount if one is available, otherwise in
+//! /dev/shm, and persist until removed with [SharedSegment::unlink_named]. An attached segment's
+//! layout covers the whole mapping, aligned to the page size.
+//!
+//! ```rust
+//! use std::alloc::Layout;
+//! use huge_global_alloc::shared::{Access, SharedSegment};
+//!
+//! let layout = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+//! let producer = SharedSegment::create_named("doc_example", layout).unwrap();
+//! unsafe { *producer.as_ptr() = 42 };
+//!
+//! // Typically in another process
+//! let consumer = SharedSegment::open_named("doc_example", Access::ReadOnly).unwrap();
+//! assert!(!consumer.is_writable());
+//! assert_eq!(unsafe { *consumer.as_ptr() }, 42);
+//!
+//! SharedSegment::unlink_named("doc_example").unwrap();
+//! ````
+
+use std::alloc::Layout;
+use std::error::Error;
+use std::ffi::c_void;
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::ptr::null_mut;
+
+use nix::{
+    errno::Errno,
+    sys::mman::{self, MapFlags, ProtFlags},
+};
+
+use crate::pagesize::base_page_size;
+
+/// Huge page size used for shared segments
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Directory holding named segments when no hugetlbfs mount is available
+const SHM_DIR: &str = "/dev/shm";
+
+/// File name prefix for named segments
+const NAME_PREFIX: &str = "huge_global_alloc.";
+
+/// Permissions for named segment files
+const NAMED_MODE: u32 = 0o600;
+
+/// Access to an attached named segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The segment is mapped read only
+    ReadOnly,
+    /// The segment is mapped read write
+    ReadWrite,
+}
+
+/// A shared mapping of a memfd or named memory file
+#[derive(Debug)]
+pub struct SharedSegment {
+    /// The memory file backing the segment
+    fd: OwnedFd,
+    /// Raw pointer to the mapping
+    ptr: usize,
+    /// Requested layout
+    layout: Layout,
+    /// Mapped size (whole pages)
+    alloc_size: usize,
+    /// Page size
+    page_size: usize,
+    /// True if the segment is mapped read write
+    writable: bool,
+}
+
+impl SharedSegment {
+    /// Creates a new shared segment for the given layout, backed by 2mb huge pages if possible
+    pub fn new(layout: Layout) -> Result<SharedSegment, Box<dyn Error>> {
+        if layout.size() == 0 || layout.align() > HUGE_PAGE_SIZE {
+            Err(format!("unsupported shared segment layout {layout:?}"))?
+        }
+
+        match Self::create(layout, HUGE_PAGE_SIZE, libc::MFD_HUGETLB | libc::MFD_HUGE_2MB) {
+            Ok(segment) => Ok(segment),
+            Err(_) => Self::create(layout, base_page_size(), 0),
+        }
+    }
+
+    /// Maps a shared segment from a memfd received from another process. The layout and page
+    /// size must be those of the segment which created the memfd.
+    pub fn from_fd(fd: OwnedFd, layout: Layout, page_size: usize) -> Result<SharedSegment, Box<dyn Error>> {
+        if layout.size() == 0 || page_size == 0 || !page_size.is_power_of_two() || layout.align() > page_size {
+            Err(format!("invalid shared segment layout {layout:?} for page size {page_size}"))?
+        }
+
+        let alloc_size = calc_alloc_size(layout.size(), page_size);
+
+        // Check the memory file is big enough, otherwise accesses past its end raise SIGBUS
+        let file_size = File::from(fd.try_clone()?).metadata()?.len();
+
+        if file_size < alloc_size as u64 {
+            Err(format!("shared segment memfd is {file_size} bytes, expected at least {alloc_size}"))?
+        }
+
+        Self::map(fd, layout, alloc_size, page_size, true)
+    }
+
+    /// Creates a new named shared segment for the given layout, which other processes can attach
+    /// to with [SharedSegment::open_named]. Fails if a segment with the name already exists.
+    pub fn create_named(name: &str, layout: Layout) -> Result<SharedSegment, Box<dyn Error>> {
+        if layout.size() == 0 || layout.align() > HUGE_PAGE_SIZE {
+            Err(format!("unsupported shared segment layout {layout:?}"))?
+        }
+
+        let paths = named_paths(name)?;
+
+        if let Some(path) = paths.iter().find(|(path, _)| path.exists()) {
+            Err(format!("shared segment {} already exists", path.0.display()))?
+        }
+
+        let mut last_err = None;
+
+        for (path, page_size) in paths {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .mode(NAMED_MODE)
+                .open(&path)?;
+
+            let alloc_size = calc_alloc_size(layout.size(), page_size);
+
+            let res = file
+                .set_len(alloc_size as u64)
+                .map_err(|e| e.into())
+                .and_then(|_| Self::map(file.into(), layout, alloc_size, page_size, true));
+
+            match res {
+                Ok(segment) => return Ok(segment),
+                Err(e) => {
+                    // Huge pages unavailable - remove the file and try the next directory
+                    let _ = fs::remove_file(&path);
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| "no directory for shared segment".into()))
+    }
+
+    /// Attaches to a named shared segment created by [SharedSegment::create_named]
+    pub fn open_named(name: &str, access: Access) -> Result<SharedSegment, Box<dyn Error>> {
+        let writable = access == Access::ReadWrite;
+
+        for (path, _) in named_paths(name)? {
+            let file = match OpenOptions::new().read(true).write(writable).open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => Err(e)?,
+            };
+
+            let alloc_size = file.metadata()?.len() as usize;
+            let page_size = fs_page_size(&file)?;
+
+            if alloc_size == 0 || !alloc_size.is_multiple_of(page_size) {
+                Err(format!("shared segment {} has invalid size {alloc_size}", path.display()))?
+            }
+
+            let layout = Layout::from_size_align(alloc_size, page_size.min(HUGE_PAGE_SIZE))?;
+
+            return Self::map(file.into(), layout, alloc_size, page_size, writable);
+        }
+
+        Err(format!("shared segment {name} not found"))?
+    }
+
+    /// Removes a named shared segment. Processes with the segment mapped keep their mappings
+    pub fn unlink_named(name: &str) -> Result<(), Box<dyn Error>> {
+        for (path, _) in named_paths(name)? {
+            match fs::remove_file(&path) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => Err(e)?,
+            }
+        }
+
+        Err(format!("shared segment {name} not found"))?
+    }
+
+    /// Returns the raw pointer to the segment. Writing through the pointer is only permitted if
+    /// the segment is writable
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr as *mut u8
+    }
+
+    /// Returns the requested layout of the segment
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the requested size of the segment
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns the total mapped size of the segment
+    pub fn alloc_size(&self) -> usize {
+        self.alloc_size
+    }
+
+    /// Returns the page size backing the segment
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns true if the segment is backed by huge pages
+    pub fn is_huge(&self) -> bool {
+        self.page_size != base_page_size()
+    }
+
+    /// Returns true if the segment is mapped read write
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Creates a memfd with the given flags, sizes it and maps it
+    fn create(layout: Layout, page_size: usize, flags: libc::c_uint) -> Result<SharedSegment, Box<dyn Error>> {
+        let alloc_size = calc_alloc_size(layout.size(), page_size);
+
+        let fd = unsafe { libc::memfd_create(c"huge_global_alloc".as_ptr(), libc::MFD_CLOEXEC | flags) };
+        let fd = unsafe { OwnedFd::from_raw_fd(Errno::result(fd)?) };
+
+        File::from(fd.try_clone()?).set_len(alloc_size as u64)?;
+
+        Self::map(fd, layout, alloc_size, page_size, true)
+    }
+
+    /// Maps a sized memory file
+    fn map(fd: OwnedFd, layout: Layout, alloc_size: usize, page_size: usize, writable: bool) -> Result<SharedSegment, Box<dyn Error>> {
+        let prot = if writable {
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+        } else {
+            ProtFlags::PROT_READ
+        };
+
+        let ptr = unsafe {
+            mman::mmap(
+                null_mut::<c_void>(),
+                alloc_size,
+                prot,
+                MapFlags::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        }?;
+
+        Ok(SharedSegment {
+            fd,
+            ptr: ptr as usize,
+            layout,
+            alloc_size,
+            page_size,
+            writable,
+        })
+    }
+}
+
+impl AsFd for SharedSegment {
+    /// Borrows the memfd backing the segment
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl Drop for SharedSegment {
+    /// Unmaps the segment on drop. The memfd is closed when the last process mapping it drops it
+    fn drop(&mut self) {
+        let _ = unsafe { mman::munmap(self.ptr as *mut c_void, self.alloc_size) };
+    }
+}
+
+/// Calculates the allocation size (whole pages) required for the size required
+fn calc_alloc_size(size: usize, page_size: usize) -> usize {
+    size.div_ceil(page_size) * page_size
+}
+
+/// Returns the candidate paths and page sizes for a named segment, hugetlbfs first
+fn named_paths(name: &str) -> Result<Vec<(PathBuf, usize)>, Box<dyn Error>> {
+    if name.is_empty() || name.contains('/') || name.contains('\0') {
+        Err(format!("invalid shared segment name {name:?}"))?
+    }
+
+    let file_name = format!("{NAME_PREFIX}{name}");
+    let mut paths = Vec::new();
+
+    if let Some(dir) = hugetlbfs_dir() {
+        paths.push((dir.join(&file_name), HUGE_PAGE_SIZE));
+    }
+
+    paths.push((PathBuf::from(SHM_DIR).join(&file_name), base_page_size()));
+
+    Ok(paths)
+}
+
+/// Finds a hugetlbfs mount with 2mb pages
+fn hugetlbfs_dir() -> Option<PathBuf> {
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+
+    mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        match fields[..] {
+            [_, dir, "hugetlbfs", options, ..] => {
+                let page_size = options.split(',').find_map(|opt| opt.strip_prefix("pagesize="));
+
+                matches!(page_size, None | Some("2M")).then(|| PathBuf::from(dir))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Returns the page size of the filesystem holding a memory file
+fn fs_page_size(file: &File) -> Result<usize, Box<dyn Error>> {
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+
+    Errno::result(unsafe { libc::fstatfs(file.as_raw_fd(), buf.as_mut_ptr()) })?;
+
+    let statfs = unsafe { buf.assume_init() };
+
+    if statfs.f_type == libc::HUGETLBFS_MAGIC {
+        Ok(statfs.f_bsize as usize)
+    } else {
+        Ok(base_page_size())
+    }
+}
@@ -0,0 +1,264 @@
+//! Wait-free snapshots of the managed segments
+//!
+//! Every reallocation asks whether its pointer is managed, and other threads rarely change the
+//! answer. Lookups read an immutable sorted snapshot of the segments instead of locking the
+//! pointer map, so they never wait for a thread mapping or freeing a segment.
+//!
+//! There are two snapshot tables. Writers, which are serialised by the pointer map lock, apply
+//! each change to the table readers aren't using, switch readers over to it, wait for readers
+//! still in the old table to leave, and then apply the same change to the old table, as in the
+//! left-right technique. Readers announce themselves on one of two counters, which lets the writer
+//! wait for them to drain while new readers arrive on the other, so readers never wait and writers
+//! wait at most for a lookup in flight. The tables are hash tables, so a change costs the same
+//! however many segments there are. They only allocate when they are grown, which is done before
+//! the pointer map is locked.
+
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::thread::yield_now;
+
+use crate::mmap::MMap;
+
+/// A managed segment as seen by lookups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SnapshotEntry {
+    /// Address of the segment, without any memory tag
+    pub ptr: usize,
+    /// True if backed by huge pages
+    pub huge: bool,
+    /// True if the segment has a stable address
+    pub stable: bool,
+}
+
+impl SnapshotEntry {
+    /// Returns the entry for a segment
+    pub fn of(mmap: &MMap) -> Self {
+        Self {
+            ptr: mmap.ptr(),
+            huge: !mmap.is_default_page_size(),
+            stable: mmap.is_stable(),
+        }
+    }
+}
+
+/// An open addressing hash table of segments keyed by address, with linear probing. The table is
+/// kept at most half full so probe sequences stay short.
+pub(crate) struct Table {
+    slots: Vec<Option<SnapshotEntry>>,
+}
+
+impl Table {
+    /// Returns the number of segments the table can hold
+    fn capacity(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// Returns the slot a segment address hashes to
+    fn home(&self, ptr: usize) -> usize {
+        // Segments are page aligned, so the low bits carry no information
+        let hash = ((ptr >> 12) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+        (hash >> 32) as usize & (self.slots.len() - 1)
+    }
+
+    /// Returns the index of the slot holding the segment starting at ptr, or of the empty slot
+    /// ending its probe sequence
+    fn find(&self, ptr: usize) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut index = self.home(ptr);
+
+        while let Some(entry) = self.slots[index] {
+            if entry.ptr == ptr {
+                break;
+            }
+
+            index = (index + 1) & mask;
+        }
+
+        index
+    }
+
+    /// Returns the segment starting at ptr
+    fn get(&self, ptr: usize) -> Option<SnapshotEntry> {
+        self.slots[self.find(ptr)]
+    }
+
+    /// Adds a segment, or replaces the entry for a segment at the same address
+    fn insert(&mut self, entry: SnapshotEntry) {
+        let index = self.find(entry.ptr);
+
+        self.slots[index] = Some(entry);
+    }
+
+    /// Removes the segment starting at ptr, moving later entries of the probe sequence back in to
+    /// the gap so lookups never pass an empty slot before reaching their entry
+    fn remove(&mut self, ptr: usize) {
+        let mask = self.slots.len() - 1;
+        let mut hole = self.find(ptr);
+
+        if self.slots[hole].is_none() {
+            return;
+        }
+
+        let mut index = hole;
+
+        loop {
+            index = (index + 1) & mask;
+
+            let Some(entry) = self.slots[index] else { break };
+
+            // Move the entry back if the hole lies between its home slot and where it is
+            if index.wrapping_sub(self.home(entry.ptr)) & mask >= index.wrapping_sub(hole) & mask {
+                self.slots[hole] = Some(entry);
+                hole = index;
+            }
+        }
+
+        self.slots[hole] = None;
+    }
+
+    /// Replaces the contents with the segments, which must fit in the table's capacity
+    fn fill<'a>(&mut self, segments: impl Iterator<Item = &'a MMap>) {
+        self.slots.fill(None);
+
+        for mmap in segments {
+            self.insert(SnapshotEntry::of(mmap));
+        }
+    }
+}
+
+/// A pair of snapshot tables readable without locking
+pub(crate) struct Snapshots {
+    /// The tables, null until first grown
+    tables: [AtomicPtr<Table>; 2],
+    /// Index of the table readers use
+    active: AtomicUsize,
+    /// Index of the counter new readers announce themselves on
+    version: AtomicUsize,
+    /// Number of readers announced on each counter
+    readers: [AtomicUsize; 2],
+}
+
+impl Snapshots {
+    pub const fn new() -> Self {
+        Self {
+            tables: [AtomicPtr::new(null_mut()), AtomicPtr::new(null_mut())],
+            active: AtomicUsize::new(0),
+            version: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+        }
+    }
+
+    /// Looks up the segment starting at ptr without waiting
+    pub fn get(&self, ptr: usize) -> Option<SnapshotEntry> {
+        let version = self.version.load(Ordering::SeqCst);
+        self.readers[version].fetch_add(1, Ordering::SeqCst);
+
+        let table = self.tables[self.active.load(Ordering::SeqCst)].load(Ordering::SeqCst);
+
+        // The writer doesn't change or free the table until this reader has left
+        let entry = unsafe { table.as_ref() }.and_then(|table| table.get(ptr));
+
+        self.readers[version].fetch_sub(1, Ordering::SeqCst);
+
+        entry
+    }
+
+    /// Returns the number of segments both tables can hold without allocating. Must be called with
+    /// the pointer map locked.
+    pub fn capacity(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|table| unsafe { table.load(Ordering::SeqCst).as_ref() }.map_or(0, Table::capacity))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Allocates a table for capacity segments, for [publish_grown](Self::publish_grown). Must be
+    /// called without the pointer map locked, as the allocation may itself be mapped.
+    pub fn alloc_table(capacity: usize) -> Box<Table> {
+        Box::new(Table { slots: vec![None; (capacity.max(1) * 2).next_power_of_two()] })
+    }
+
+    /// Adds a segment, or updates the entry for a segment whose page size or stability has
+    /// changed. The segments must fit in the tables' [capacity](Self::capacity). Must be called
+    /// with the pointer map locked.
+    pub fn insert(&self, entry: SnapshotEntry) {
+        self.update(|table| table.insert(entry));
+    }
+
+    /// Removes the segment starting at ptr. Must be called with the pointer map locked.
+    pub fn remove(&self, ptr: usize) {
+        self.update(|table| table.remove(ptr));
+    }
+
+    /// Applies a change to the table readers aren't using, switches readers over to it, then
+    /// applies the change to the other table once readers have left it
+    fn update(&self, change: impl Fn(&mut Table)) {
+        let inactive = self.active.load(Ordering::SeqCst) ^ 1;
+
+        // No reader can be in the inactive table, and other writers are locked out
+        if let Some(table) = unsafe { self.tables[inactive].load(Ordering::SeqCst).as_mut() } {
+            change(table);
+        }
+
+        self.switch(inactive);
+
+        if let Some(table) = unsafe { self.tables[inactive ^ 1].load(Ordering::SeqCst).as_mut() } {
+            change(table);
+        }
+    }
+
+    /// Replaces both tables with larger ones holding the segments, returning the old tables to be
+    /// freed once the pointer map is unlocked. Must be called with the pointer map locked.
+    pub fn publish_grown<'a, I>(&self, new_tables: [Box<Table>; 2], segments: impl Fn() -> I) -> [Option<Box<Table>>; 2]
+    where
+        I: Iterator<Item = &'a MMap>,
+    {
+        new_tables.map(|mut table| {
+            table.fill(segments());
+
+            // Replace the inactive table, which no reader can be in
+            let inactive = self.active.load(Ordering::SeqCst) ^ 1;
+            let old = self.tables[inactive].swap(Box::into_raw(table), Ordering::SeqCst);
+
+            self.switch(inactive);
+
+            (!old.is_null()).then(|| unsafe { Box::from_raw(old) })
+        })
+    }
+
+    /// Switches readers to a table and waits until no reader can still be in the other one
+    fn switch(&self, active: usize) {
+        self.active.store(active, Ordering::SeqCst);
+
+        // Readers which read the version before the last switch may still be on the next
+        // counter, then readers arriving on the current counter from before this switch
+        let previous = self.version.load(Ordering::SeqCst);
+        let next = previous ^ 1;
+
+        self.drain(next);
+        self.version.store(next, Ordering::SeqCst);
+        self.drain(previous);
+    }
+
+    /// Waits until no reader is announced on a counter
+    fn drain(&self, version: usize) {
+        while self.readers[version].load(Ordering::SeqCst) != 0 {
+            yield_now();
+        }
+    }
+}
+
+impl Drop for Snapshots {
+    /// Frees the tables
+    fn drop(&mut self) {
+        for table in &self.tables {
+            let table = table.swap(null_mut(), Ordering::SeqCst);
+
+            if !table.is_null() {
+                drop(unsafe { Box::from_raw(table) });
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Synchronisation primitives used by the allocator. When compiled with `--cfg loom` the mutexes
+//! are replaced with loom's model checked versions so the locking can be tested with loom.
+//!
+//! Each mutex counts its acquisitions and times the ones which had to wait for another thread,
+//! so contention on the allocator's locks can be told apart from time spent in the kernel.
+//!
+//! The critical sections never make system calls, so with the `spin-locks` feature the mutexes
+//! spin instead of sleeping in the kernel, avoiding the wakeup latency of std's mutex for
+//! real-time threads.
+
+#[cfg(loom)]
+use loom::sync as imp;
+#[cfg(all(feature = "spin-locks", not(loom)))]
+use spin as imp;
+#[cfg(not(any(loom, feature = "spin-locks")))]
+use std::sync as imp;
+
+use std::fmt;
+use std::sync::{PoisonError, TryLockError};
+
+use crate::counters::{LockStats, Timer};
+
+pub use imp::MutexGuard;
+
+/// Declares a function which is const unless compiled for loom, as loom's primitives can't be
+/// created in a const context
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        $vis const fn $name($($arg: $ty),*) -> $ret $body
+
+        #[cfg(loom)]
+        $(#[$attr])*
+        $vis fn $name($($arg: $ty),*) -> $ret $body
+    };
+}
+
+pub(crate) use const_fn;
+
+/// A mutex which recovers from poisoning. The allocator's state is never left inconsistent by a
+/// panic so a panic in unrelated code while a lock is held must not abort later allocations.
+pub struct Mutex<T> {
+    inner: imp::Mutex<T>,
+    stats: LockStats,
+}
+
+impl<T> Mutex<T> {
+    const_fn! {
+        /// Creates a new mutex
+        pub fn new(value: T) -> Self {
+            Self {
+                inner: imp::Mutex::new(value),
+                stats: LockStats::new(),
+            }
+        }
+    }
+
+    /// Locks the mutex, recovering a poisoned lock
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if cfg!(feature = "no-stats") {
+            return self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        }
+
+        self.stats.acquired();
+
+        // Only waits are timed, so an uncontended lock costs one more atomic
+        match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                let timer = Timer::start();
+                let guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+                self.stats.waited(timer.elapsed_ns());
+
+                guard
+            }
+        }
+    }
+
+    /// Locks the mutex if no other thread holds it, recovering a poisoned lock. Returns None
+    /// without waiting otherwise.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+
+        if !cfg!(feature = "no-stats") {
+            self.stats.acquired();
+        }
+
+        Some(guard)
+    }
+
+    /// Returns the mutex's contention counters
+    pub fn stats(&self) -> &LockStats {
+        &self.stats
+    }
+
+    /// Returns true if the mutex is locked by any thread
+    #[cfg(test)]
+    pub fn is_locked(&self) -> bool {
+        matches!(self.inner.try_lock(), Err(TryLockError::WouldBlock))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// A spinlock with the parts of std's mutex interface the allocator uses. It is never poisoned.
+#[cfg(all(feature = "spin-locks", not(loom)))]
+mod spin {
+    use std::cell::UnsafeCell;
+    use std::fmt;
+    use std::hint::spin_loop;
+    use std::ops::{Deref, DerefMut};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{LockResult, TryLockError, TryLockResult};
+    use std::thread::yield_now;
+
+    /// Number of spins before yielding the processor to the lock holder
+    const SPINS: usize = 100;
+
+    pub struct Mutex<T> {
+        locked: AtomicBool,
+        value: UnsafeCell<T>,
+    }
+
+    // The lock serialises access to the value
+    unsafe impl<T: Send> Send for Mutex<T> {}
+    unsafe impl<T: Send> Sync for Mutex<T> {}
+
+    impl<T> Mutex<T> {
+        pub const fn new(value: T) -> Self {
+            Self {
+                locked: AtomicBool::new(false),
+                value: UnsafeCell::new(value),
+            }
+        }
+
+        /// Spins until the lock is taken, yielding the processor once it has spun for a while in
+        /// case the holder has been preempted
+        pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+            let mut spins = 0;
+
+            loop {
+                if let Ok(guard) = self.try_lock() {
+                    return Ok(guard);
+                }
+
+                // Wait for the lock to look free before trying to take it again
+                while self.locked.load(Ordering::Relaxed) {
+                    if spins < SPINS {
+                        spin_loop();
+                        spins += 1;
+                    } else {
+                        yield_now();
+                    }
+                }
+            }
+        }
+
+        pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+            match self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => Ok(MutexGuard { mutex: self }),
+                Err(_) => Err(TryLockError::WouldBlock),
+            }
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.try_lock() {
+                Ok(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+                Err(_) => f.debug_struct("Mutex").field("data", &format_args!("<locked>")).finish(),
+            }
+        }
+    }
+
+    /// Unlocks the spinlock when dropped
+    pub struct MutexGuard<'a, T> {
+        mutex: &'a Mutex<T>,
+    }
+
+    impl<T> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            unsafe { &*self.mutex.value.get() }
+        }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            unsafe { &mut *self.mutex.value.get() }
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        fn drop(&mut self) {
+            self.mutex.locked.store(false, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! The memory mapping system calls. All mapping operations go through the [`Syscalls`] trait so
+//! the mapper's bookkeeping can be unit tested without real mappings, and faults can be injected
+//! when the fault-injection feature is enabled.
+
+use std::ffi::{c_int, c_uint, c_ulong, c_void};
+use std::fmt::Debug;
+use std::ptr::null_mut;
+
+use nix::{
+    errno::Errno,
+    sys::mman::{self, MRemapFlags, MapFlags, ProtFlags},
+};
+
+use crate::numa::MPOL_PREFERRED;
+
+#[cfg(feature = "fault-injection")]
+use crate::fault::{self, Syscall};
+
+/// madvise advice asking the kernel to collapse a range in to transparent huge pages in place
+/// (Linux 6.1 or later). Not defined by libc for every target.
+pub(crate) const MADV_COLLAPSE: c_int = 25;
+
+/// Memory mapping system calls used by the mapper
+pub trait Syscalls: Debug + Sync {
+    /// Maps an anonymous read write segment with given flags, private unless MAP_SHARED is given
+    unsafe fn mmap(&self, size: usize, flags: MapFlags) -> nix::Result<*mut c_void>;
+
+    /// Maps an anonymous read write segment with given flags at a fixed address, failing with
+    /// EEXIST if anything is already mapped there. Private unless MAP_SHARED is given.
+    unsafe fn mmap_at(&self, addr: *mut c_void, size: usize, flags: MapFlags) -> nix::Result<*mut c_void>;
+
+    /// Resizes a mapped segment
+    unsafe fn mremap(&self, ptr: *mut c_void, old_size: usize, new_size: usize, flags: MRemapFlags) -> nix::Result<*mut c_void>;
+
+    /// Moves a mapped segment to a fixed address, replacing any mapping already there. If
+    /// keep_source is true the old address range stays mapped, empty, with MREMAP_DONTUNMAP.
+    unsafe fn mremap_fixed(&self, ptr: *mut c_void, size: usize, new_ptr: *mut c_void, keep_source: bool) -> nix::Result<*mut c_void>;
+
+    /// Unmaps a mapped segment
+    unsafe fn munmap(&self, ptr: *mut c_void, size: usize) -> nix::Result<()>;
+
+    /// Sets the protection of a mapped segment
+    unsafe fn mprotect(&self, ptr: *mut c_void, size: usize, prot: ProtFlags) -> nix::Result<()>;
+
+    /// Sets the protection and protection key of a mapped segment
+    unsafe fn pkey_mprotect(&self, ptr: *mut c_void, size: usize, prot: ProtFlags, pkey: c_int) -> nix::Result<()>;
+
+    /// Gives advice about the use of a mapped segment
+    unsafe fn madvise(&self, ptr: *mut c_void, size: usize, advice: c_int) -> nix::Result<()>;
+
+    /// Locks the pages of a mapped segment in to memory, faulting them in
+    unsafe fn mlock(&self, ptr: *mut c_void, size: usize) -> nix::Result<()>;
+
+    /// Prefers a NUMA node for the pages of a mapped segment not yet faulted in
+    unsafe fn mbind(&self, ptr: *mut c_void, size: usize, node: usize) -> nix::Result<()>;
+}
+
+/// The real system calls
+#[derive(Debug)]
+pub struct LinuxSyscalls;
+
+impl Syscalls for LinuxSyscalls {
+    unsafe fn mmap(&self, size: usize, flags: MapFlags) -> nix::Result<*mut c_void> {
+        #[cfg(feature = "fault-injection")]
+        fault::check(Syscall::Mmap, flags.contains(MapFlags::MAP_HUGETLB))?;
+
+        mman::mmap(
+            null_mut::<c_void>(),
+            size,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_ANON | private(flags) | flags,
+            0,
+            0,
+        )
+    }
+
+    unsafe fn mmap_at(&self, addr: *mut c_void, size: usize, flags: MapFlags) -> nix::Result<*mut c_void> {
+        #[cfg(feature = "fault-injection")]
+        fault::check(Syscall::Mmap, flags.contains(MapFlags::MAP_HUGETLB))?;
+
+        let ptr = mman::mmap(
+            addr,
+            size,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_ANON | private(flags) | MapFlags::MAP_FIXED_NOREPLACE | flags,
+            0,
+            0,
+        )?;
+
+        // Kernels before 4.17 treat the address as a hint
+        if ptr != addr {
+            let _ = mman::munmap(ptr, size);
+            return Err(Errno::EEXIST);
+        }
+
+        Ok(ptr)
+    }
+
+    unsafe fn mremap(&self, ptr: *mut c_void, old_size: usize, new_size: usize, flags: MRemapFlags) -> nix::Result<*mut c_void> {
+        #[cfg(feature = "fault-injection")]
+        fault::check(Syscall::Mremap, false)?;
+
+        mman::mremap(ptr, old_size, new_size, flags, None)
+    }
+
+    unsafe fn mremap_fixed(&self, ptr: *mut c_void, size: usize, new_ptr: *mut c_void, keep_source: bool) -> nix::Result<*mut c_void> {
+        #[cfg(feature = "fault-injection")]
+        fault::check(Syscall::Mremap, false)?;
+
+        let mut flags = MRemapFlags::MREMAP_MAYMOVE | MRemapFlags::MREMAP_FIXED;
+
+        if keep_source {
+            // MREMAP_DONTUNMAP isn't named by MRemapFlags
+            flags |= MRemapFlags::from_bits_unchecked(libc::MREMAP_DONTUNMAP);
+        }
+
+        mman::mremap(ptr, size, size, flags, Some(new_ptr))
+    }
+
+    unsafe fn munmap(&self, ptr: *mut c_void, size: usize) -> nix::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        fault::check(Syscall::Munmap, false)?;
+
+        mman::munmap(ptr, size)
+    }
+
+    unsafe fn mprotect(&self, ptr: *mut c_void, size: usize, prot: ProtFlags) -> nix::Result<()> {
+        mman::mprotect(ptr, size, prot)
+    }
+
+    unsafe fn pkey_mprotect(&self, ptr: *mut c_void, size: usize, prot: ProtFlags, pkey: c_int) -> nix::Result<()> {
+        Errno::result(libc::syscall(libc::SYS_pkey_mprotect, ptr, size, prot.bits(), pkey)).map(drop)
+    }
+
+    unsafe fn madvise(&self, ptr: *mut c_void, size: usize, advice: c_int) -> nix::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        fault::check(Syscall::Madvise, false)?;
+
+        Errno::result(libc::madvise(ptr, size, advice)).map(drop)
+    }
+
+    unsafe fn mlock(&self, ptr: *mut c_void, size: usize) -> nix::Result<()> {
+        mman::mlock(ptr, size)
+    }
+
+    unsafe fn mbind(&self, ptr: *mut c_void, size: usize, node: usize) -> nix::Result<()> {
+        let Some(mask) = (1 as c_ulong).checked_shl(node as u32) else { return Err(Errno::EINVAL) };
+
+        Errno::result(libc::syscall(
+            libc::SYS_mbind,
+            ptr,
+            size,
+            MPOL_PREFERRED,
+            &mask as *const c_ulong,
+            c_ulong::BITS as c_ulong + 1,
+            0 as c_uint,
+        ))
+        .map(drop)
+    }
+}
+
+/// Returns MAP_PRIVATE unless the flags ask for a shared mapping
+fn private(flags: MapFlags) -> MapFlags {
+    if flags.contains(MapFlags::MAP_SHARED) {
+        MapFlags::empty()
+    } else {
+        MapFlags::MAP_PRIVATE
+    }
+}
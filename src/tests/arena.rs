@@ -0,0 +1,53 @@
+use std::ptr::NonNull;
+
+use super::*;
+
+/// Maps and fills chunks from a region source the way an arena crate would
+fn map_chunks<S: RegionSource>(source: &S, sizes: &[usize]) -> Vec<NonNull<[u8]>> {
+    sizes
+        .iter()
+        .map(|&size| {
+            let chunk = source.map_region(Layout::from_size_align(size, 4096).unwrap()).unwrap();
+            assert!(chunk.len() >= size);
+
+            unsafe { (chunk.as_ptr() as *mut u8).write_bytes(0xaa, chunk.len()) };
+
+            chunk
+        })
+        .collect()
+}
+
+/// Returns chunks to the region source
+fn unmap_chunks<S: RegionSource>(source: &S, chunks: Vec<NonNull<[u8]>>) {
+    for chunk in chunks {
+        unsafe { source.unmap_region(chunk) };
+    }
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn region_source() {
+    let allocator = HugeGlobalAllocator::new(mb(4));
+
+    // Chunks are mapped whatever the threshold, and counted
+    let chunks = map_chunks(&&allocator, &[64 * 1024, mb(1), mb(3)]);
+
+    let stats = allocator.stats().unwrap();
+    assert_eq!(3, stats.segments);
+    assert_eq!(64 * 1024 + mb(4), stats.alloc);
+    assert_eq!(chunks.iter().map(|chunk| chunk.len()).sum::<usize>(), stats.mapped);
+
+    unmap_chunks(&&allocator, chunks);
+    assert_eq!(0, allocator.stats().unwrap().segments);
+
+    let allocator = HugeGlobalAllocatorConst::<{ mb(4) }>::new();
+
+    assert!(allocator.map_region(Layout::from_size_align(0, 8).unwrap()).is_none());
+
+    let chunks = map_chunks(&allocator, &[mb(1)]);
+    assert_eq!(1, allocator.stats().unwrap().segments);
+
+    unmap_chunks(&allocator, chunks);
+    assert_eq!(0, allocator.stats().unwrap().segments);
+}
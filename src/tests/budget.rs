@@ -0,0 +1,27 @@
+use super::*;
+
+#[test]
+fn charges() {
+    let budget = Budget::new(mb(4));
+
+    assert!(budget.charge(mb(2)));
+    assert!(budget.charge(mb(2)));
+    assert!(!budget.charge(1));
+    assert_eq!(mb(4), budget.used());
+    assert_eq!(0, budget.available());
+    assert_eq!(1, budget.denied());
+
+    budget.refund(mb(2));
+    assert_eq!(mb(2), budget.available());
+
+    // Lowering the limit below what is used refuses further charges
+    budget.set_limit(mb(1));
+    assert_eq!(mb(1), budget.limit());
+    assert_eq!(0, budget.available());
+    assert!(!budget.charge(1));
+
+    // Zero is unlimited
+    budget.set_limit(0);
+    assert_eq!(usize::MAX, budget.available());
+    assert!(budget.charge(mb(1024)));
+}
@@ -0,0 +1,33 @@
+use std::fs;
+
+use super::*;
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn read_to_huge() {
+    let allocator = HugeGlobalAllocator::new(mb(4));
+    let path = std::env::temp_dir().join(format!("huge_global_alloc_buffer_{}", std::process::id()));
+
+    let contents: Vec<u8> = (0..mb(3) + 5).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &contents).unwrap();
+
+    let mut buffer = allocator.read_to_huge(&path).unwrap();
+    assert_eq!(contents, *buffer);
+    assert!(format!("{buffer:?}").contains("len: 3145733"));
+
+    // Mapped even though the file is below the threshold
+    #[cfg(not(feature = "passthrough"))]
+    assert!(allocator.is_managed(buffer.as_ptr()));
+
+    buffer[0] = 0xff;
+    drop(buffer);
+
+    #[cfg(not(feature = "passthrough"))]
+    assert_eq!(0, allocator.stats().unwrap().segments);
+
+    fs::write(&path, []).unwrap();
+    assert!(allocator.read_to_huge(&path).unwrap().is_empty());
+
+    fs::remove_file(&path).unwrap();
+    assert!(allocator.read_to_huge(&path).is_err());
+}
@@ -0,0 +1,41 @@
+use super::*;
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn attribution() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let layout = |size| Layout::from_size_align(size, 8).unwrap();
+
+    let mut blocks = Vec::new();
+
+    for _ in 0..3 {
+        blocks.push(allocator.alloc_raw(layout(mb(1))).unwrap());
+    }
+
+    blocks.push(allocator.alloc_raw(layout(mb(4))).unwrap());
+
+    for block in blocks {
+        unsafe { allocator.dealloc_raw(block) };
+    }
+
+    // Allocations through GlobalAlloc aren't attributed
+    unsafe {
+        let ptr = allocator.alloc(layout(mb(2)));
+        allocator.dealloc(ptr, layout(mb(2)));
+    }
+
+    let callsites = allocator.callsite_stats();
+    assert_eq!(2, callsites.len(), "{:?}", callsites);
+
+    assert_eq!(file!(), callsites[0].location.file());
+    assert_eq!(1, callsites[0].allocs);
+    assert_eq!(mb(4), callsites[0].bytes);
+
+    assert_eq!(3, callsites[1].allocs);
+    assert_eq!(mb(3), callsites[1].bytes);
+    assert_ne!(callsites[0].location, callsites[1].location);
+
+    allocator.ctl("stats.reset", "").unwrap();
+    assert!(allocator.callsite_stats().is_empty());
+}
@@ -0,0 +1,53 @@
+use std::alloc::GlobalAlloc;
+
+use super::*;
+use crate::canary;
+use crate::mmap::MMap;
+use crate::sys::LinuxSyscalls;
+
+#[test]
+fn overwrite_detected() {
+    let layout = Layout::from_size_align(mb(1) + 100, 8).unwrap();
+    let mut mmap = MMap::map_default(&LinuxSyscalls, layout, None, false, false, false).unwrap();
+
+    canary::write(&mut mmap);
+    assert!(mmap.has_canary());
+    assert_eq!(canary::len(&mmap), canary::CANARY_BYTES);
+    assert_eq!(canary::check(&mmap), None);
+
+    // One byte overwritten 10 bytes past the end
+    let byte = unsafe { mmap.as_ptr().add(mb(1) + 110) };
+    unsafe { byte.write(!byte.read()) };
+    assert_eq!(canary::check(&mmap), Some(10));
+}
+
+#[test]
+fn page_aligned_payload() {
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+    let mut mmap = MMap::map_default(&LinuxSyscalls, layout, None, false, false, false).unwrap();
+
+    canary::write(&mut mmap);
+    assert_eq!(canary::len(&mmap), 0);
+    assert_eq!(canary::check(&mmap), None);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn checked_on_realloc_and_dealloc() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let layout = Layout::from_size_align(mb(1) + 1, 8).unwrap();
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0xa5, layout.size()) };
+
+    // Grow, then shrink so the payload ends inside the old canary
+    let ptr = unsafe { allocator.realloc(ptr, layout, mb(3) + 7) };
+    let layout = Layout::from_size_align(mb(3) + 7, 8).unwrap();
+    let ptr = unsafe { allocator.realloc(ptr, layout, mb(3) + 3) };
+    unsafe { allocator.dealloc(ptr, Layout::from_size_align(mb(3) + 3, 8).unwrap()) };
+
+    // Raw blocks can use their whole mapping
+    let block = allocator.alloc_raw(Layout::from_size_align(mb(1) + 1, 8).unwrap()).unwrap();
+    unsafe { (block.as_ptr() as *mut u8).write_bytes(0x5a, block.len()) };
+    unsafe { allocator.dealloc_raw(block) };
+}
@@ -0,0 +1,34 @@
+use std::collections::{HashMap, HashSet};
+
+use super::*;
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn huge_capacity() {
+    let mut vec: Vec<u8> = Vec::with_huge_capacity(1024);
+    assert!(GLOBAL_ALLOCATOR.is_managed(vec.as_ptr()));
+    assert!(vec.capacity() >= 1024);
+
+    // The thread's threshold is restored afterwards
+    assert_eq!(None, thread_threshold());
+    assert!(!GLOBAL_ALLOCATOR.is_managed(Vec::<u8>::with_capacity(1024).as_ptr()));
+
+    // Growing follows the threshold
+    vec.resize(4096, 1);
+    assert!(!GLOBAL_ALLOCATOR.is_managed(vec.as_ptr()));
+    assert_eq!(1, vec[4095]);
+
+    // Growing out of a multi-page buffer keeps the contents
+    let mut vec: Vec<u8> = Vec::with_huge_capacity(64 * 1024);
+    assert!(GLOBAL_ALLOCATOR.is_managed(vec.as_ptr()));
+    vec.resize(64 * 1024, 2);
+    vec.reserve_exact(mb(1) / 2);
+    assert!(!GLOBAL_ALLOCATOR.is_managed(vec.as_ptr()));
+    assert!(vec.iter().all(|&b| b == 2));
+
+    let map: HashMap<u64, u64> = HashMap::with_huge_capacity(1000);
+    assert!(map.capacity() >= 1000);
+
+    let set: HashSet<u64> = HashSet::with_huge_capacity(1000);
+    assert!(set.capacity() >= 1000);
+}
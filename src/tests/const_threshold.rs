@@ -0,0 +1,59 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use super::*;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn defaults() {
+    let allocator = HugeGlobalAllocator::<System>::default();
+    assert_eq!(allocator.threshold(), DEFAULT_THRESHOLD);
+
+    let allocator = HugeGlobalAllocatorConst::<DEFAULT_THRESHOLD>::default();
+    assert_eq!(allocator.threshold(), DEFAULT_THRESHOLD);
+
+    let allocator: HugeGlobalAllocatorConst = HugeGlobalAllocatorConst::new();
+    assert_eq!(allocator.threshold(), DEFAULT_THRESHOLD);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn const_threshold() {
+    let allocator = HugeGlobalAllocatorConst::<{ mb(2) }>::new();
+
+    unsafe {
+        let small = allocator.alloc(layout(mb(1)));
+        assert_eq!(allocator.stats().unwrap().segments, 0);
+
+        let big = allocator.alloc_zeroed(layout(mb(2)));
+        assert_eq!(*big.add(mb(2) - 1), 0);
+        assert_eq!(allocator.stats().unwrap().segments, 1);
+
+        // Grow across the threshold
+        let small = allocator.realloc(small, layout(mb(1)), mb(3));
+        assert_eq!(allocator.stats().unwrap().segments, 2);
+
+        // Shrink across the threshold
+        let big = allocator.realloc(big, layout(mb(2)), 1024);
+        assert_eq!(allocator.stats().unwrap().segments, 1);
+
+        allocator.dealloc(small, layout(mb(3)));
+        allocator.dealloc(big, layout(1024));
+    }
+
+    assert_eq!(allocator.stats().unwrap().segments, 0);
+}
+
+#[test]
+fn const_threshold_off() {
+    let allocator = HugeGlobalAllocatorConst::<0>::new();
+
+    unsafe {
+        let ptr = allocator.alloc(layout(mb(2)));
+        assert_eq!(allocator.stats().unwrap().segments, 0);
+
+        allocator.dealloc(ptr, layout(mb(2)));
+    }
+}
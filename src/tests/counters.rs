@@ -0,0 +1,68 @@
+use std::thread;
+
+use crate::counters::{Churn, ChurnWindow, ShardedStats, CHURN_WINDOW_SECS};
+use crate::HugeGlobalAllocatorStats;
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn churn_window() {
+    let window = ChurnWindow::new();
+    let start = 1000;
+
+    for _ in 0..30 {
+        window.record_at(Churn::Alloc, start);
+    }
+
+    window.record_at(Churn::Dealloc, start + 1);
+    window.record_at(Churn::Remap, start + 1);
+    window.record_at(Churn::Remap, start + 2);
+
+    let window_secs = CHURN_WINDOW_SECS as f64;
+    assert_eq!([30.0 / window_secs, 1.0 / window_secs, 2.0 / window_secs], window.rates_at(start + 2));
+
+    // The first second drops out of the window
+    assert_eq!([0.0, 1.0 / window_secs, 2.0 / window_secs], window.rates_at(start + CHURN_WINDOW_SECS));
+
+    // Its bucket is recycled when the clock comes round again
+    window.record_at(Churn::Alloc, start + CHURN_WINDOW_SECS);
+    assert_eq!([1.0 / window_secs, 1.0 / window_secs, 2.0 / window_secs], window.rates_at(start + CHURN_WINDOW_SECS));
+
+    assert_eq!([0.0; 3], window.rates_at(start + 10 * CHURN_WINDOW_SECS));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn sharded_stats() {
+    let stats = ShardedStats::new();
+
+    // Threads count in their own shards, added up on read
+    thread::scope(|scope| {
+        for i in 0..32 {
+            let stats = &stats;
+
+            scope.spawn(move || {
+                stats.remaps_failed.add(1);
+                stats.missed_allocs.add(1);
+                stats.prefault_max_time_ns.max(i);
+            });
+        }
+    });
+
+    // Taken back on a thread other than the one which counted
+    stats.missed_allocs.sub(2);
+
+    let mut out_stats = HugeGlobalAllocatorStats::default();
+    stats.read(&mut out_stats);
+    assert_eq!(32, out_stats.remaps_failed);
+    assert_eq!(30, out_stats.missed_allocs);
+    assert_eq!(31, out_stats.prefault_max_time.as_nanos());
+
+    // Taking back more than was counted stops at zero
+    stats.reset();
+    stats.missed_allocs.sub(1);
+
+    let mut out_stats = HugeGlobalAllocatorStats::default();
+    stats.read(&mut out_stats);
+    assert_eq!(0, out_stats.remaps_failed);
+    assert_eq!(0, out_stats.missed_allocs);
+}
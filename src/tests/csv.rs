@@ -0,0 +1,36 @@
+use std::fs;
+
+use super::*;
+use crate::ctl::STAT_NAMES;
+
+#[test]
+fn append_rows() {
+    let path = std::env::temp_dir().join(format!("huge_global_alloc_stats_{}.csv", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    for name in STAT_NAMES {
+        assert!(allocator.ctl_read(&format!("stats.{name}")).is_ok(), "{}", name);
+    }
+
+    allocator.append_stats_csv(&path).unwrap();
+    allocator.append_stats_csv(&path).unwrap();
+
+    let csv = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(3, lines.len());
+
+    let header: Vec<&str> = lines[0].split(',').collect();
+    assert_eq!("time", header[0]);
+    assert_eq!(STAT_NAMES[..], header[1..]);
+
+    for row in &lines[1..] {
+        let columns: Vec<&str> = row.split(',').collect();
+        assert_eq!(header.len(), columns.len());
+        assert!(columns[0].parse::<f64>().unwrap() > 0.0);
+        assert_eq!("0", columns[3], "segments");
+    }
+}
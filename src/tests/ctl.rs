@@ -0,0 +1,87 @@
+use super::*;
+use crate::ctl::{parse_bool, parse_size};
+
+#[test]
+fn sizes() {
+    assert_eq!(Some(4096), parse_size("4096"));
+    assert_eq!(Some(4096), parse_size("4k"));
+    assert_eq!(Some(mb(2)), parse_size("2M"));
+    assert_eq!(Some(1024 * mb(1)), parse_size("1g"));
+    assert_eq!(None, parse_size(""));
+    assert_eq!(None, parse_size("m"));
+    assert_eq!(None, parse_size("2x"));
+    assert_eq!(None, parse_size("99999999999999999999g"));
+}
+
+#[test]
+fn bools() {
+    assert_eq!(Some(true), parse_bool("1"));
+    assert_eq!(Some(false), parse_bool("off"));
+    assert_eq!(None, parse_bool("maybe"));
+}
+
+#[test]
+fn settings() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    for (key, value, expected) in [
+        ("threshold", "2m", "2097152"),
+        ("soft_limit", "1g", "1073741824"),
+        ("huge_retry.count", "3", "3"),
+        ("huge_retry.backoff_us", "250", "250"),
+        ("sigbus_protection", "on", "true"),
+        ("pretouch_validation", "true", "true"),
+        ("remap_failure", "fail", "fail"),
+        ("move_strategy", "dontunmap", "dontunmap"),
+        ("map_strategy", "fixed:512", "fixed:512"),
+        ("map_strategy", "ordered", "ordered"),
+        ("unmap_failure", "leak", "leak"),
+        ("shrink_threshold", "50", "50"),
+        ("max_slack", "25", "25"),
+        ("placement.window", "0x500000000000-0x600000000000", "0x500000000000-0x600000000000"),
+        ("placement.window", "off", "off"),
+        ("secure_profile", "on", "true"),
+        ("fork_shared", "yes", "true"),
+        ("numa_local", "on", "true"),
+        ("strict_overcommit", "on", "true"),
+        ("segment_cache.count", "2", "2"),
+        ("segment_cache.bytes", "64m", "67108864"),
+    ] {
+        allocator.ctl(key, value).unwrap();
+        assert_eq!(expected, allocator.ctl_read(key).unwrap(), "{}", key);
+    }
+
+    // Setting one retry parameter leaves the other alone
+    assert_eq!("3", allocator.ctl_read("huge_retry.count").unwrap());
+
+    assert!(allocator.ctl("threshold", "lots").is_err());
+    assert!(allocator.ctl("remap_failure", "panic").is_err());
+    assert!(allocator.ctl("move_strategy", "splice").is_err());
+    assert!(allocator.ctl("map_strategy", "fixed").is_err());
+    assert!(allocator.ctl("shrink_threshold", "101").is_err());
+    assert!(allocator.ctl("segment_cache.count", "5").is_err());
+    assert!(allocator.ctl("placement.window", "0x2000-0x1000").is_err());
+    assert!(allocator.ctl("no.such.key", "1").is_err());
+    assert!(allocator.ctl_read("no.such.key").is_err());
+    assert!(allocator.ctl_read("stats.no_such_stat").is_err());
+}
+
+#[test]
+#[cfg_attr(any(feature = "no-stats", feature = "passthrough"), ignore = "needs statistics for mapped segments")]
+fn stats() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    allocator.ctl("soft_limit", "1m").unwrap();
+
+    let layout = std::alloc::Layout::from_size_align(mb(2), 8).unwrap();
+    let ptr = unsafe { std::alloc::GlobalAlloc::alloc(&allocator, layout) };
+
+    assert_eq!("1", allocator.ctl_read("stats.segments").unwrap());
+    assert_eq!("1", allocator.ctl_read("stats.soft_limit_exceeded").unwrap());
+    assert!(allocator.ctl("stats.segments", "0").is_err(), "read only");
+
+    allocator.ctl("stats.reset", "").unwrap();
+    assert_eq!("0", allocator.ctl_read("stats.soft_limit_exceeded").unwrap());
+    assert_eq!("1", allocator.ctl_read("stats.segments").unwrap());
+
+    unsafe { std::alloc::GlobalAlloc::dealloc(&allocator, ptr, layout) };
+}
@@ -0,0 +1,41 @@
+use super::*;
+use crate::diagnose::{parse_container, parse_limit, parse_thp, size_label};
+
+#[test]
+fn parsers() {
+    assert_eq!(Some(ThpMode::Madvise), parse_thp("always [madvise] never\n"));
+    assert_eq!(Some(ThpMode::Never), parse_thp("always madvise [never]"));
+    assert_eq!(None, parse_thp(""));
+
+    assert_eq!(Some("kubernetes"), parse_container("0::/kubepods.slice/kubepods-burstable.slice/cri-containerd-1.scope\n"));
+    assert_eq!(Some("docker"), parse_container("0::/system.slice/docker-0123abcd.scope\n"));
+    assert_eq!(None, parse_container("0::/user.slice/user-1000.slice/session-2.scope\n"));
+
+    assert_eq!("2MB", size_label(mb(2)));
+    assert_eq!("1GB", size_label(mb(1024)));
+    assert_eq!("64KB", size_label(64 * 1024));
+
+    assert_eq!(None, parse_limit("max\n"));
+    assert_eq!(Some(0), parse_limit("0\n"));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn diagnosis() {
+    let diagnosis = diagnose();
+
+    assert_eq!(huge_page_size(), diagnosis.huge_page_size);
+    assert!(!diagnosis.probes.is_empty());
+
+    let mappable = diagnosis.probes.iter().any(|probe| probe.name == "mmap MAP_HUGETLB" && probe.available());
+
+    if cfg!(feature = "passthrough") {
+        assert_eq!(Backing::InnerAllocator, diagnosis.backing);
+    } else {
+        assert_eq!(mappable && diagnosis.hugetlb_limit != Some(0), diagnosis.backing == Backing::HugePages);
+    }
+
+    let report = diagnosis.to_string();
+    assert!(report.contains("backing:"), "{report}");
+    eprint!("{report}");
+}
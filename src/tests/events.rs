@@ -0,0 +1,58 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use super::*;
+use crate::events::{Backing, Event, Operation};
+
+fn layout(mb: usize) -> Layout {
+    Layout::from_size_align(super::mb(mb), 8).unwrap()
+}
+
+#[test]
+fn vec_growth() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    allocator.set_event_recording(true);
+
+    let no_huge = huge_page_info(mb(2)).map_or(true, |info| info.free == 0);
+
+    unsafe {
+        // Grow from below the threshold to 4mb and shrink back below it
+        let ptr = allocator.alloc(Layout::from_size_align(1024, 8).unwrap());
+        let ptr = allocator.realloc(ptr, Layout::from_size_align(1024, 8).unwrap(), mb(1));
+        let ptr = allocator.realloc(ptr, layout(1), mb(2));
+        let ptr = allocator.realloc(ptr, layout(2), mb(4));
+        let ptr = allocator.realloc(ptr, layout(4), 1024);
+        allocator.dealloc(ptr, Layout::from_size_align(1024, 8).unwrap());
+    }
+
+    let events = allocator.events();
+
+    let ops: Vec<(Operation, usize, usize)> = events.iter().map(|e| (e.operation, e.old_size, e.size)).collect();
+    assert_eq!(
+        ops,
+        vec![
+            (Operation::Alloc, 0, mb(1)),
+            (Operation::Realloc, mb(1), mb(2)),
+            (Operation::Realloc, mb(2), mb(4)),
+            (Operation::Dealloc, mb(4), 0),
+        ]
+    );
+
+    if no_huge {
+        assert!(events.iter().all(|e| e.backing == Backing::Default), "default backing");
+    }
+
+    allocator.clear_events();
+    assert_eq!(allocator.events(), Vec::<Event>::new());
+}
+
+#[test]
+fn recording_off() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    unsafe {
+        let ptr = allocator.alloc(layout(2));
+        allocator.dealloc(ptr, layout(2));
+    }
+
+    assert!(allocator.events().is_empty());
+}
@@ -0,0 +1,100 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use super::*;
+use crate::fault::{self, Syscall};
+
+fn layout(mb: usize) -> Layout {
+    Layout::from_size_align(super::mb(mb), 8).unwrap()
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn hugetlb_fallback() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    fault::reset();
+    fault::fail_hugetlb(true);
+
+    let ptr = unsafe { allocator.alloc(layout(4)) };
+    assert!(!ptr.is_null());
+
+    let stats = allocator.stats().unwrap();
+    assert_eq!(1, stats.default_segments, "default segments");
+    assert_eq!(0, stats.huge_segments, "huge segments");
+    assert_eq!(1, stats.missed_allocs, "missed allocs");
+
+    unsafe { allocator.dealloc(ptr, layout(4)) };
+    fault::reset();
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn remap_failure_copy() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    fault::reset();
+
+    let ptr = unsafe { allocator.alloc(layout(1)) };
+    unsafe { ptr.write_bytes(0xa5, mb(1)) };
+
+    fault::fail_nth(Syscall::Mremap, 1);
+
+    let new_ptr = unsafe { allocator.realloc(ptr, layout(1), mb(8)) };
+    assert!(!new_ptr.is_null());
+    assert_eq!(0xa5, unsafe { *new_ptr.add(mb(1) - 1) });
+
+    let stats = allocator.stats().unwrap();
+    assert_eq!(1, stats.segments, "segments");
+    assert_eq!(1, stats.remaps_failed, "remaps failed");
+    assert_eq!(1, stats.remaps_copied, "remaps copied");
+    assert_eq!(mb(1), stats.remap_copied_bytes, "remap copied bytes");
+
+    unsafe { allocator.dealloc(new_ptr, layout(8)) };
+    fault::reset();
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn remap_failure_fail() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    allocator.set_remap_failure(RemapFailure::Fail);
+
+    fault::reset();
+
+    let ptr = unsafe { allocator.alloc(layout(1)) };
+
+    fault::fail_nth(Syscall::Mremap, 1);
+
+    let new_ptr = unsafe { allocator.realloc(ptr, layout(1), mb(8)) };
+    assert!(new_ptr.is_null());
+
+    let stats = allocator.stats().unwrap();
+    assert_eq!(1, stats.segments, "segments");
+    assert_eq!(mb(1), stats.alloc, "alloc");
+    assert_eq!(1, stats.remaps_refused, "remaps refused");
+
+    unsafe { allocator.dealloc(ptr, layout(1)) };
+    fault::reset();
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn unmap_failure_leak() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    allocator.set_unmap_failure(UnmapFailure::Leak);
+
+    fault::reset();
+
+    let ptr = unsafe { allocator.alloc(layout(1)) };
+
+    fault::fail_nth(Syscall::Munmap, 1);
+
+    unsafe { allocator.dealloc(ptr, layout(1)) };
+
+    let stats = allocator.stats().unwrap();
+    assert_eq!(0, stats.segments, "segments");
+    assert_eq!(1, stats.unmaps_failed, "unmaps failed");
+    assert!(stats.leaked_bytes >= mb(1), "leaked bytes");
+
+    fault::reset();
+}
@@ -0,0 +1,74 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::ffi::{c_char, CStr};
+
+use super::*;
+use crate::ffi::ctl::{huge_global_alloc_ctl, huge_global_alloc_ctl_read};
+use crate::ffi::stats::{huge_global_alloc_stats, huge_global_alloc_stats_json, HugeGlobalAllocatorCStats};
+
+static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+
+#[test]
+fn c_stats() {
+    ALLOCATOR.export_stats();
+
+    let layout = Layout::from_size_align(mb(3), 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+
+    let mut stats = HugeGlobalAllocatorCStats::default();
+    assert_eq!(0, unsafe { huge_global_alloc_stats(&mut stats) });
+
+    assert_eq!(1, stats.segments);
+    assert_eq!(mb(3) as u64, stats.alloc);
+    assert_eq!(stats.default_mapped + stats.huge_mapped, stats.mapped);
+
+    assert_eq!(-1, unsafe { huge_global_alloc_stats(std::ptr::null_mut()) });
+
+    let mut buf = [0x7f as c_char; 2048];
+    let len = unsafe { huge_global_alloc_stats_json(buf.as_mut_ptr(), buf.len()) };
+    let json = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap();
+
+    assert_eq!(len as usize, json.len());
+    assert!(json.starts_with("{\"alloc\":"));
+    assert!(json.contains(",\"pressure\":"));
+    assert!(json.ends_with('}'));
+    assert!(json.contains(",\"segments\":1,"));
+    assert!(json.contains(&format!("\"alloc\":{},", mb(3))));
+    assert!(json.contains(",\"remaps_per_sec\":"));
+    assert_eq!(53, json.matches(':').count());
+
+    // Truncated
+    let mut short = [0x7f as c_char; 8];
+    assert_eq!(len, unsafe { huge_global_alloc_stats_json(short.as_mut_ptr(), short.len()) });
+    assert_eq!(&json[..7], unsafe { CStr::from_ptr(short.as_ptr()) }.to_str().unwrap());
+
+    assert_eq!(len, unsafe { huge_global_alloc_stats_json(std::ptr::null_mut(), 0) });
+
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+}
+
+#[test]
+fn c_ctl() {
+    ALLOCATOR.export_stats();
+
+    unsafe {
+        assert_eq!(0, huge_global_alloc_ctl(c"soft_limit".as_ptr(), c"64m".as_ptr()));
+
+        let mut buf = [0x7f as c_char; 16];
+        assert_eq!(8, huge_global_alloc_ctl_read(c"soft_limit".as_ptr(), buf.as_mut_ptr(), buf.len()));
+        assert_eq!(c"67108864", CStr::from_ptr(buf.as_ptr()));
+
+        // Truncated
+        let mut buf = [0x7f as c_char; 4];
+        assert_eq!(8, huge_global_alloc_ctl_read(c"soft_limit".as_ptr(), buf.as_mut_ptr(), buf.len()));
+        assert_eq!(c"671", CStr::from_ptr(buf.as_ptr()));
+
+        assert_eq!(8, huge_global_alloc_ctl_read(c"soft_limit".as_ptr(), std::ptr::null_mut(), 0));
+
+        assert_eq!(0, huge_global_alloc_ctl(c"soft_limit".as_ptr(), c"0".as_ptr()));
+
+        assert_eq!(-1, huge_global_alloc_ctl(c"no_such_key".as_ptr(), c"1".as_ptr()));
+        assert_eq!(-1, huge_global_alloc_ctl(c"soft_limit".as_ptr(), c"lots".as_ptr()));
+        assert_eq!(-1, huge_global_alloc_ctl(std::ptr::null(), c"1".as_ptr()));
+        assert_eq!(-1, huge_global_alloc_ctl_read(c"no_such_key".as_ptr(), buf.as_mut_ptr(), buf.len()));
+    }
+}
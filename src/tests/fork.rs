@@ -0,0 +1,51 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use super::mb;
+use crate::HugeGlobalAllocator;
+
+/// Forks a child which writes value to the first byte of ptr and exits, and waits for it
+unsafe fn write_in_child(ptr: *mut u8, value: u8) {
+    let pid = libc::fork();
+    assert!(pid >= 0, "fork failed");
+
+    if pid == 0 {
+        // Only async signal safe calls in the child
+        ptr.write_volatile(value);
+        libc::_exit(0);
+    }
+
+    let mut status = 0;
+    assert_eq!(pid, libc::waitpid(pid, &mut status, 0));
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn shared_with_children() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let layout = Layout::from_size_align(mb(4), 8).unwrap();
+
+    unsafe {
+        // Private segments are copied on write
+        let private = allocator.alloc(layout);
+        private.write(1);
+        write_in_child(private, 2);
+        assert_eq!(1, private.read_volatile());
+
+        allocator.set_fork_shared(true);
+        assert_eq!("true", allocator.ctl_read("fork_shared").unwrap());
+
+        // Shared segments see the child's write, before and after growing
+        let shared = allocator.alloc(layout);
+        shared.write(1);
+        write_in_child(shared, 2);
+        assert_eq!(2, shared.read_volatile());
+
+        let grown = allocator.realloc(shared, layout, mb(16));
+        write_in_child(grown, 3);
+        assert_eq!(3, grown.read_volatile());
+
+        allocator.dealloc(private, layout);
+        allocator.dealloc(grown, Layout::from_size_align(mb(16), 8).unwrap());
+    }
+}
@@ -0,0 +1,44 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use super::*;
+use crate::heaptrack::HeaptrackWriter;
+
+fn layout(mb: usize) -> Layout {
+    Layout::from_size_align(super::mb(mb), 8).unwrap()
+}
+
+#[test]
+fn trace() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let mut writer = HeaptrackWriter::new(Vec::new()).unwrap();
+
+    // Not traced as the ring is disabled
+    let early = unsafe { allocator.alloc(layout(1)) };
+
+    allocator.event_ring().set_enabled(true);
+
+    unsafe {
+        let ptr = allocator.alloc(layout(1));
+        let ptr = allocator.realloc(ptr, layout(1), mb(4));
+        allocator.dealloc(ptr, layout(4));
+
+        let ptr = allocator.alloc(layout(1));
+        allocator.dealloc(ptr, layout(1));
+
+        allocator.dealloc(early, layout(1));
+    }
+
+    writer.drain(allocator.event_ring()).unwrap();
+
+    let trace = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<&str> = trace.lines().filter(|line| !line.starts_with('c')).collect();
+
+    assert!(lines[0].starts_with("v "), "{}", trace);
+    assert!(lines[1].starts_with("X "), "{}", trace);
+    assert!(lines[2].starts_with("I "), "{}", trace);
+
+    // Allocation infos are shared by segments of the same size and backing, and the untraced
+    // segment's free is skipped. The trace depends on the backing so isn't compared.
+    let ops: Vec<String> = lines[10..].iter().map(|line| line.split(' ').take(2).collect::<Vec<_>>().join(" ")).collect();
+    assert_eq!(ops, vec!["a 100000", "+ 0", "- 0", "a 400000", "+ 1", "- 1", "+ 0", "- 0"], "{}", trace);
+}
@@ -0,0 +1,56 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use super::*;
+use crate::ctl::STAT_NAMES;
+
+/// Sends a request and returns the status line and body of the response
+fn request(addr: SocketAddr, request: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+
+    (status, body.to_string())
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn serve() {
+    static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(mb(1));
+
+    let addr = ALLOCATOR.serve_debug_http(0).unwrap();
+    assert!(addr.ip().is_loopback());
+
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    ALLOCATOR.tag(ptr, "http test");
+
+    let (status, body) = request(addr, "GET /stats HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!("HTTP/1.0 200 OK", status);
+    assert!(body.starts_with('{') && body.trim_end().ends_with('}'), "{}", body);
+
+    for name in STAT_NAMES {
+        assert!(body.contains(&format!("\"{name}\":")), "{}", name);
+    }
+
+    #[cfg(not(feature = "no-stats"))]
+    assert!(body.contains("\"segments\":1,"), "{}", body);
+
+    let (status, body) = request(addr, "GET /segments HTTP/1.1\r\n\r\n");
+    assert_eq!("HTTP/1.0 200 OK", status);
+    assert!(body.contains("http test"), "{}", body);
+
+    let (status, _) = request(addr, "GET /nothing HTTP/1.1\r\n\r\n");
+    assert_eq!("HTTP/1.0 404 Not Found", status);
+
+    let (status, _) = request(addr, "POST /stats HTTP/1.1\r\n\r\n");
+    assert_eq!("HTTP/1.0 405 Method Not Allowed", status);
+
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+}
@@ -0,0 +1,65 @@
+use bytes::{Buf, BufMut};
+
+use super::*;
+
+#[test]
+fn put_and_get() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let mut buf = allocator.bytes_mut(16).unwrap();
+
+    assert!(buf.is_empty());
+    assert!(buf.capacity() >= 16);
+    assert!(allocator.is_managed(buf.as_ptr()));
+
+    buf.put_u16(0x1234);
+    buf.put_slice(b"frame");
+    assert_eq!(7, buf.len());
+
+    assert_eq!(0x1234, buf.get_u16());
+    assert_eq!(b"frame", &buf[..]);
+
+    // Consuming everything starts writing from the front again
+    buf.advance(5);
+    assert!(buf.is_empty());
+    assert_eq!(buf.capacity(), buf.chunk_mut().len());
+}
+
+#[test]
+fn grows() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let mut buf = allocator.bytes_mut(0).unwrap();
+    let capacity = buf.capacity();
+
+    // Partially consumed bytes are moved to the front before growing
+    buf.put_bytes(1, capacity);
+    buf.advance(capacity - 10);
+    buf.put_bytes(2, capacity - 10);
+    assert_eq!(capacity, buf.capacity());
+    assert_eq!(capacity, buf.len());
+
+    // Writing past the end grows the segment, keeping the contents
+    buf.put_bytes(3, mb(2));
+    assert!(buf.capacity() >= capacity + mb(2));
+    assert!(allocator.is_managed(buf.as_ptr()));
+
+    assert_eq!(&[1; 10], &buf[..10]);
+    assert_eq!(2, buf[capacity - 1]);
+    assert_eq!(3, buf[capacity + mb(2) - 1]);
+    assert_eq!(capacity + mb(2), buf.remaining());
+}
+
+#[test]
+fn freeze() {
+    static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+
+    let mut buf = ALLOCATOR.bytes_mut(1024).unwrap();
+    buf.put_slice(b"header:payload");
+    buf.advance(7);
+
+    let bytes = buf.freeze();
+    let payload = bytes.slice(0..4);
+    drop(bytes);
+
+    assert_eq!(&b"payl"[..], payload);
+    assert!(ALLOCATOR.owner_of(payload.as_ptr()).is_some());
+}
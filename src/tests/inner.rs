@@ -0,0 +1,102 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::*;
+
+/// Wrapping allocator counting the live blocks it has handed out
+struct Counting<A> {
+    inner: A,
+    live: AtomicUsize,
+    allocs: AtomicUsize,
+}
+
+impl<A> Counting<A> {
+    const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            live: AtomicUsize::new(0),
+            allocs: AtomicUsize::new(0),
+        }
+    }
+
+    fn live(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    fn allocs(&self) -> usize {
+        self.allocs.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.live.fetch_add(1, Ordering::Relaxed);
+        self.allocs.fetch_add(1, Ordering::Relaxed);
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.live.fetch_sub(1, Ordering::Relaxed);
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn inner_ownership() {
+    let allocator = HugeGlobalAllocator::with_inner(mb(1), Counting::new(System));
+
+    unsafe {
+        // Small blocks are served by the inner allocator
+        let small = allocator.alloc(layout(1024));
+        assert_eq!(allocator.inner().live(), 1);
+
+        // Big blocks are mapped and never reach the inner allocator
+        let big = allocator.alloc(layout(mb(2)));
+        assert_eq!(allocator.inner().live(), 1);
+        assert_eq!(allocator.inner().allocs(), 1);
+
+        // Growing across the threshold moves the block out of the inner allocator
+        let small = allocator.realloc(small, layout(1024), mb(2));
+        assert_eq!(allocator.inner().live(), 0);
+        assert_eq!(allocator.stats().unwrap().segments, 2);
+
+        // Shrinking across the threshold moves it back
+        let big = allocator.realloc(big, layout(mb(2)), 1024);
+        assert_eq!(allocator.inner().live(), 1);
+        assert_eq!(allocator.stats().unwrap().segments, 1);
+
+        allocator.dealloc(small, layout(mb(2)));
+        allocator.dealloc(big, layout(1024));
+    }
+
+    assert_eq!(allocator.inner().live(), 0);
+    assert_eq!(allocator.stats().unwrap().segments, 0);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn chained() {
+    // Counting wrapper around a huge page allocator around a counting wrapper around the system
+    static ALLOCATOR: Counting<HugeGlobalAllocator<Counting<System>>> =
+        Counting::new(HugeGlobalAllocator::with_inner(1024 * 1024, Counting::new(System)));
+
+    unsafe {
+        let small = ALLOCATOR.alloc(layout(1024));
+        let big = ALLOCATOR.alloc(layout(mb(2)));
+
+        assert_eq!(ALLOCATOR.live(), 2);
+        assert_eq!(ALLOCATOR.inner.inner().live(), 1);
+        assert_eq!(ALLOCATOR.inner.stats().unwrap().segments, 1);
+
+        ALLOCATOR.dealloc(big, layout(mb(2)));
+        ALLOCATOR.dealloc(small, layout(1024));
+    }
+
+    assert_eq!(ALLOCATOR.live(), 0);
+    assert_eq!(ALLOCATOR.inner.inner().live(), 0);
+}
@@ -0,0 +1,48 @@
+use std::alloc::Layout;
+
+use super::mb;
+use crate::jit::JitSegment;
+
+#[test]
+fn dual_mapping() {
+    let layout = Layout::from_size_align(mb(3), 64).unwrap();
+    let segment = JitSegment::new(layout).unwrap();
+
+    assert_eq!(segment.layout(), layout);
+    assert!(segment.alloc_size() >= mb(3));
+    assert_eq!(segment.alloc_size() % segment.page_size(), 0);
+    assert_ne!(segment.as_mut_ptr() as *const u8, segment.as_exec_ptr());
+
+    // Writes through the read write view are visible through the read execute view
+    unsafe { segment.as_mut_ptr().add(mb(2)).write(0xa5) };
+
+    let exec_ptr = segment.exec_ptr_for(unsafe { segment.as_mut_ptr().add(mb(2)) }).unwrap();
+    assert_eq!(exec_ptr, unsafe { segment.as_exec_ptr().add(mb(2)) });
+    assert_eq!(0xa5, unsafe { exec_ptr.read() });
+
+    // Pointers outside the read write view have no translation
+    let end = unsafe { segment.as_mut_ptr().add(segment.alloc_size()) };
+    assert!(segment.exec_ptr_for(end).is_none());
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn run_code() {
+    let layout = Layout::from_size_align(mb(1), 16).unwrap();
+    let segment = JitSegment::new(layout).unwrap();
+
+    // mov eax, edi; add eax, 1; ret
+    let code = [0x89, 0xf8, 0x83, 0xc0, 0x01, 0xc3];
+
+    unsafe { segment.as_mut_ptr().copy_from_nonoverlapping(code.as_ptr(), code.len()) };
+    segment.sync_icache(0, code.len());
+
+    let func: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(segment.as_exec_ptr()) };
+    assert_eq!(42, func(41));
+}
+
+#[test]
+fn invalid_layout() {
+    let layout = Layout::from_size_align(0, 8).unwrap();
+    assert!(JitSegment::new(layout).is_err());
+}
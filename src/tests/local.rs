@@ -0,0 +1,127 @@
+use std::alloc::GlobalAlloc;
+use std::thread;
+
+use super::*;
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn thread_threshold_override() {
+    let allocator = HugeGlobalAllocator::new(mb(4));
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            assert_eq!(None, set_thread_threshold(Some(mb(1) / 4)));
+            assert_eq!(Some(mb(1) / 4), thread_threshold());
+
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(allocator.is_managed(ptr));
+            unsafe { allocator.dealloc(ptr, layout) };
+
+            // Switched off for this thread
+            set_thread_threshold(Some(0));
+            let ptr = unsafe { allocator.alloc(Layout::from_size_align(mb(8), 8).unwrap()) };
+            assert!(!allocator.is_managed(ptr));
+            unsafe { allocator.dealloc(ptr, Layout::from_size_align(mb(8), 8).unwrap()) };
+
+            assert_eq!(Some(0), set_thread_threshold(None));
+        });
+    });
+
+    // Other threads keep the allocator's threshold
+    assert_eq!(None, thread_threshold());
+    assert_eq!(mb(4), allocator.threshold());
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!allocator.is_managed(ptr));
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn grow_in_to_inner() {
+    let allocator = HugeGlobalAllocator::new(mb(4));
+    let guarded = GuardedWindow::new(mb(1) / 2);
+    let layout = Layout::from_size_align(mb(1) / 2, 8).unwrap();
+
+    allocator.set_placement_window(Some(guarded.window.clone())).unwrap();
+
+    thread::scope(|scope| {
+        scope.spawn(|| unsafe {
+            set_thread_threshold(Some(mb(1) / 4));
+
+            let ptr = allocator.alloc(layout);
+            assert!(allocator.is_managed(ptr));
+            ptr.write_bytes(0xa5, layout.size());
+
+            // Dropping the override sends the grown block to the inner allocator, copying only
+            // the old block's bytes
+            set_thread_threshold(None);
+
+            let ptr = allocator.realloc(ptr, layout, mb(1));
+            assert!(!allocator.is_managed(ptr));
+            assert!(std::slice::from_raw_parts(ptr, layout.size()).iter().all(|&b| b == 0xa5));
+
+            // Lowering the override again sends the shrunk block back in to the mapper, copying
+            // only the new block's bytes
+            set_thread_threshold(Some(mb(1) / 4));
+
+            let ptr = allocator.realloc(ptr, Layout::from_size_align(mb(1), 8).unwrap(), layout.size());
+            assert!(allocator.is_managed(ptr));
+            assert!(std::slice::from_raw_parts(ptr, layout.size()).iter().all(|&b| b == 0xa5));
+
+            allocator.dealloc(ptr, layout);
+            set_thread_threshold(None);
+        });
+    });
+
+    assert_eq!(0, allocator.stats().unwrap().segments);
+}
+
+#[test]
+fn scoped_config() {
+    thread::spawn(|| {
+        set_thread_threshold(Some(mb(2)));
+
+        {
+            let mut config = ThreadConfig::current();
+            assert_eq!(Some(mb(2)), config.threshold);
+
+            config.threshold = Some(mb(1));
+            let _outer = config.apply();
+            assert_eq!(Some(mb(1)), thread_threshold());
+
+            {
+                let _inner = ThreadConfig::default().apply();
+                assert_eq!(None, thread_threshold());
+            }
+
+            assert_eq!(Some(mb(1)), thread_threshold());
+        }
+
+        assert_eq!(Some(mb(2)), thread_threshold());
+
+        // Restored when the closure panics
+        let result = std::panic::catch_unwind(|| {
+            with_thread_config(|config| config.threshold = Some(0), || panic!("loading failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(Some(mb(2)), thread_threshold());
+
+        assert_eq!(42, with_thread_config(|config| config.threshold = None, || thread_threshold().map_or(42, |_| 0)));
+        assert_eq!(Some(mb(2)), thread_threshold());
+
+        // Every setting is restored
+        let slack = with_thread_config(|config| config.max_slack = Some(10), thread_max_slack);
+        assert_eq!(Some(10), slack);
+        assert_eq!(None, thread_max_slack());
+
+        assert!(with_thread_config(|config| config.high_priority = true, thread_high_priority));
+        assert!(!thread_high_priority());
+
+        assert_eq!(Some("gc"), with_thread_config(|config| config.tag = Some("gc"), thread_tag));
+        assert_eq!(None, thread_tag());
+    })
+    .join()
+    .unwrap();
+}
@@ -0,0 +1,73 @@
+//! Loom model tests for the mapper's locking. Run with:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib
+//! ```
+
+use std::alloc::Layout;
+
+use loom::sync::Arc;
+use loom::thread;
+
+use crate::mmapper::MMapper;
+
+const MB: usize = 1024 * 1024;
+
+fn layout(bytes: usize) -> Layout {
+    Layout::from_size_align(bytes, 8).unwrap()
+}
+
+#[test]
+fn concurrent_alloc_dealloc() {
+    loom::model(|| {
+        let mapper = Arc::new(MMapper::new());
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let mapper = mapper.clone();
+
+                thread::spawn(move || {
+                    let ptr = mapper.alloc(layout(MB));
+                    assert!(mapper.is_managed_ptr(ptr));
+                    assert!(mapper.dealloc(ptr));
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let stats = mapper.stats().unwrap();
+        assert_eq!(0, stats.segments);
+    });
+}
+
+#[test]
+fn concurrent_realloc() {
+    loom::model(|| {
+        let mapper = Arc::new(MMapper::new());
+
+        let ptr = mapper.alloc(layout(MB));
+
+        let other = {
+            let mapper = mapper.clone();
+
+            thread::spawn(move || {
+                let ptr = mapper.alloc(layout(2 * MB));
+                assert!(mapper.dealloc(ptr));
+            })
+        };
+
+        let ptr = mapper.realloc(ptr, layout(3 * MB));
+        assert!(mapper.is_managed_ptr(ptr));
+
+        other.join().unwrap();
+
+        let stats = mapper.stats().unwrap();
+        assert_eq!(1, stats.segments);
+        assert_eq!(3 * MB, stats.alloc);
+
+        assert!(mapper.dealloc(ptr));
+    });
+}
@@ -0,0 +1,48 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use super::*;
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn worker() {
+    static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(mb(1));
+
+    // Changing the interval needs a started worker
+    assert!(ALLOCATOR.ctl("maintenance.interval_ms", "10").is_err());
+    assert!(ALLOCATOR.start_maintenance(Duration::ZERO).is_err());
+
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+
+    ALLOCATOR.start_maintenance(Duration::from_millis(1)).unwrap();
+    assert_eq!("1", ALLOCATOR.ctl_read("maintenance.interval_ms").unwrap());
+
+    // Starting again only changes the interval
+    ALLOCATOR.start_maintenance(Duration::from_millis(2)).unwrap();
+    ALLOCATOR.ctl("maintenance.interval_ms", "1").unwrap();
+
+    let start = Instant::now();
+
+    while ALLOCATOR.stats().unwrap().maintenance_passes < 2 {
+        assert!(start.elapsed() < Duration::from_secs(10), "no maintenance passes");
+        sleep(Duration::from_millis(1));
+    }
+
+    // Huge page segments aren't offered for collapsing
+    let stats = ALLOCATOR.stats().unwrap();
+    assert_eq!(stats.default_segments, stats.collapses + stats.collapses_failed);
+
+    ALLOCATOR.ctl("maintenance.interval_ms", "0").unwrap();
+    assert_eq!("0", ALLOCATOR.ctl_read("maintenance.interval_ms").unwrap());
+
+    // At most one more pass after stopping
+    sleep(Duration::from_millis(20));
+    let passes = ALLOCATOR.stats().unwrap().maintenance_passes;
+    sleep(Duration::from_millis(20));
+    assert_eq!(passes, ALLOCATOR.stats().unwrap().maintenance_passes);
+
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+}
@@ -0,0 +1,87 @@
+use std::ffi::c_void;
+
+use super::*;
+use crate::ffi::malloc::{self as shim, MALLOC_ALLOCATOR};
+
+fn managed(ptr: *mut c_void) -> bool {
+    MALLOC_ALLOCATOR.mapper.is_managed_ptr(ptr as *mut u8)
+}
+
+#[test]
+fn malloc_free() {
+    unsafe {
+        let small = shim::malloc(1024);
+        assert!(!small.is_null());
+        assert!(!managed(small), "small block mapped");
+
+        let big = shim::malloc(mb(2));
+        assert!(!big.is_null());
+        assert!(managed(big), "big block not mapped");
+        assert_eq!(mb(2), shim::malloc_usable_size(big));
+        assert!(shim::malloc_usable_size(small) >= 1024);
+
+        shim::free(small);
+        shim::free(big);
+        assert!(!managed(big));
+
+        shim::free(std::ptr::null_mut());
+    }
+}
+
+#[test]
+fn calloc() {
+    unsafe {
+        let ptr = shim::calloc(mb(1), 2) as *mut u8;
+        assert!(managed(ptr as *mut c_void));
+        assert_eq!(0, *ptr.add(mb(2) - 1));
+        shim::free(ptr as *mut c_void);
+
+        assert!(shim::calloc(usize::MAX, 2).is_null(), "overflow");
+    }
+}
+
+#[test]
+fn realloc_across_threshold() {
+    unsafe {
+        let ptr = shim::malloc(1024) as *mut u8;
+        ptr.write_bytes(0xa5, 1024);
+
+        // glibc to mapped
+        let ptr = shim::realloc(ptr as *mut c_void, mb(2)) as *mut u8;
+        assert!(managed(ptr as *mut c_void));
+        assert_eq!(0xa5, *ptr.add(1023));
+        ptr.add(mb(2) - 1).write(0x5a);
+
+        // mapped to mapped
+        let ptr = shim::realloc(ptr as *mut c_void, mb(4)) as *mut u8;
+        assert!(managed(ptr as *mut c_void));
+        assert_eq!(0xa5, *ptr.add(1023));
+        assert_eq!(0x5a, *ptr.add(mb(2) - 1));
+
+        // mapped to glibc
+        let ptr = shim::realloc(ptr as *mut c_void, 512) as *mut u8;
+        assert!(!managed(ptr as *mut c_void));
+        assert_eq!(0xa5, *ptr.add(511));
+
+        assert!(shim::realloc(ptr as *mut c_void, 0).is_null());
+    }
+}
+
+#[test]
+fn posix_memalign() {
+    unsafe {
+        let mut ptr = std::ptr::null_mut();
+
+        assert_eq!(libc::EINVAL, shim::posix_memalign(&mut ptr, 3, 1024));
+
+        assert_eq!(0, shim::posix_memalign(&mut ptr, 4096, mb(2)));
+        assert!(managed(ptr));
+        assert!((ptr as usize).is_multiple_of(4096));
+        shim::free(ptr);
+
+        assert_eq!(0, shim::posix_memalign(&mut ptr, 256, 1024));
+        assert!(!managed(ptr));
+        assert!((ptr as usize).is_multiple_of(256));
+        shim::free(ptr);
+    }
+}
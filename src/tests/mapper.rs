@@ -0,0 +1,1875 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
+use std::ptr::copy_nonoverlapping;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::mman::{MRemapFlags, MapFlags, ProtFlags};
+
+use super::mb;
+use crate::layers::Layers;
+use crate::mmapper::MMapper;
+use crate::mte;
+use crate::numa;
+use crate::report;
+use crate::sys::{Syscalls, MADV_COLLAPSE};
+use crate::{
+    set_thread_high_priority, set_thread_max_slack, thread_access_pattern, thread_noreserve, with_thread_config,
+    AccessPattern, Budget, MapStrategy, MoveStrategy, Overcommit, OvercommitMode, PagePolicy, PageSizePref, RemapFailure,
+    SegmentHooks, SegmentInfo,
+};
+
+/// System calls which hand out memory from the system allocator instead of mapping it, so huge page
+/// availability and mremap failures can be simulated
+#[derive(Debug)]
+struct MockSyscalls {
+    huge_free: AtomicUsize,
+    fail_mremap: AtomicBool,
+    fail_collapse: AtomicBool,
+    fail_move: AtomicBool,
+    pkeys: Mutex<Vec<(usize, c_int)>>,
+    dirty_unmaps: AtomicUsize,
+    advice: Mutex<Vec<(usize, c_int)>>,
+    locked: Mutex<Vec<usize>>,
+    fail_mlock: AtomicBool,
+    blocked_advice: Mutex<Vec<c_int>>,
+    bound: Mutex<Vec<(usize, usize)>>,
+    maps: Mutex<Option<HashMap<usize, (usize, bool)>>>,
+    map_locked: OnceLock<fn() -> bool>,
+    locked_calls: AtomicUsize,
+    noreserve: Mutex<Vec<usize>>,
+}
+
+impl MockSyscalls {
+    const fn new(huge_free: usize) -> Self {
+        Self {
+            huge_free: AtomicUsize::new(huge_free),
+            fail_mremap: AtomicBool::new(false),
+            fail_collapse: AtomicBool::new(false),
+            fail_move: AtomicBool::new(false),
+            pkeys: Mutex::new(Vec::new()),
+            dirty_unmaps: AtomicUsize::new(0),
+            advice: Mutex::new(Vec::new()),
+            locked: Mutex::new(Vec::new()),
+            fail_mlock: AtomicBool::new(false),
+            blocked_advice: Mutex::new(Vec::new()),
+            bound: Mutex::new(Vec::new()),
+            maps: Mutex::new(None),
+            map_locked: OnceLock::new(),
+            locked_calls: AtomicUsize::new(0),
+            noreserve: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn mapped(&self) -> usize {
+        self.maps.lock().unwrap().iter().flatten().map(|(_, (size, _))| size).sum()
+    }
+
+    fn take_huge(&self, size: usize) -> nix::Result<()> {
+        self.huge_free
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |free| free.checked_sub(size))
+            .map(drop)
+            .map_err(|_| Errno::ENOMEM)
+    }
+
+    /// Counts a mapping or unmapping made with the watched pointer map locked
+    fn check_unlocked(&self) {
+        if self.map_locked.get().is_some_and(|map_locked| map_locked()) {
+            self.locked_calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn add(&self, ptr: *mut u8, size: usize, huge: bool) -> *mut c_void {
+        let ptr = ptr as usize;
+
+        self.maps.lock().unwrap().get_or_insert_with(HashMap::new).insert(ptr, (size, huge));
+
+        ptr as *mut c_void
+    }
+}
+
+impl Syscalls for MockSyscalls {
+    unsafe fn mmap(&self, size: usize, flags: MapFlags) -> nix::Result<*mut c_void> {
+        self.check_unlocked();
+
+        let huge = flags.contains(MapFlags::MAP_HUGETLB);
+
+        if huge {
+            self.take_huge(size)?;
+        }
+
+        let ptr = self.add(System.alloc_zeroed(page_layout(size)), size, huge);
+
+        if flags.contains(MapFlags::MAP_NORESERVE) {
+            self.noreserve.lock().unwrap().push(ptr as usize);
+        }
+
+        Ok(ptr)
+    }
+
+    unsafe fn mmap_at(&self, _addr: *mut c_void, _size: usize, _flags: MapFlags) -> nix::Result<*mut c_void> {
+        // Addresses are chosen by the system allocator
+        Err(Errno::EEXIST)
+    }
+
+    unsafe fn mremap(&self, ptr: *mut c_void, old_size: usize, new_size: usize, flags: MRemapFlags) -> nix::Result<*mut c_void> {
+        if self.fail_mremap.load(Ordering::Relaxed) {
+            return Err(Errno::ENOMEM);
+        }
+
+        if new_size > old_size && !flags.contains(MRemapFlags::MREMAP_MAYMOVE) {
+            // Growing in place would need the address range after the mapping
+            return Err(Errno::ENOMEM);
+        }
+
+        let (size, huge) = self.maps.lock().unwrap().as_mut().unwrap().remove(&(ptr as usize)).unwrap();
+        assert_eq!(old_size, size);
+
+        if huge {
+            if new_size > old_size {
+                self.take_huge(new_size - old_size)?;
+            } else {
+                self.huge_free.fetch_add(old_size - new_size, Ordering::Relaxed);
+            }
+        }
+
+        if !flags.contains(MRemapFlags::MREMAP_MAYMOVE) {
+            // Shrinking in place keeps the whole block. The system allocator frees huge page aligned
+            // blocks without using the size, so recording the smaller size is harmless.
+            return Ok(self.add(ptr as *mut u8, new_size, huge));
+        }
+
+        Ok(self.add(System.realloc(ptr as *mut u8, page_layout(old_size), new_size), new_size, huge))
+    }
+
+    unsafe fn mremap_fixed(&self, ptr: *mut c_void, size: usize, new_ptr: *mut c_void, keep_source: bool) -> nix::Result<*mut c_void> {
+        if self.fail_move.load(Ordering::Relaxed) {
+            return Err(Errno::EINVAL);
+        }
+
+        let mut lock = self.maps.lock().unwrap();
+        let maps = lock.as_mut().unwrap();
+
+        let (moved_size, huge) = maps[&(ptr as usize)];
+        assert_eq!(moved_size, size);
+
+        // The mock can't split allocations, so the moved pages replace the start of the mapping
+        // already there, taking it over if it is the same size
+        let (replaced_size, replaced_huge) = maps[&(new_ptr as usize)];
+        assert!(size <= replaced_size);
+
+        if size == replaced_size {
+            maps.insert(new_ptr as usize, (size, huge));
+        }
+
+        copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8, size);
+
+        if keep_source {
+            // The old range stays mapped, empty, and still counts its huge pages
+            (ptr as *mut u8).write_bytes(0, size);
+        } else {
+            if replaced_huge {
+                self.huge_free.fetch_add(size, Ordering::Relaxed);
+            }
+
+            maps.remove(&(ptr as usize));
+            System.dealloc(ptr as *mut u8, page_layout(size));
+        }
+
+        Ok(new_ptr)
+    }
+
+    unsafe fn munmap(&self, ptr: *mut c_void, size: usize) -> nix::Result<()> {
+        self.check_unlocked();
+
+        let mut lock = self.maps.lock().unwrap();
+        let maps = lock.as_mut().unwrap();
+
+        // Unmapping the end of a mapping keeps the whole block, as when shrinking in place
+        let tail_of = maps.iter().find(|(&start, &(len, _))| start < ptr as usize && start + len == ptr as usize + size);
+
+        if let Some((&start, &(len, huge))) = tail_of {
+            maps.insert(start, (len - size, huge));
+
+            if huge {
+                self.huge_free.fetch_add(size, Ordering::Relaxed);
+            }
+
+            return Ok(());
+        }
+
+        let (old_size, huge) = maps.remove(&(ptr as usize)).unwrap();
+        drop(lock);
+        assert_eq!(old_size, size);
+
+        self.noreserve.lock().unwrap().retain(|&addr| addr != ptr as usize);
+
+        if huge {
+            self.huge_free.fetch_add(size, Ordering::Relaxed);
+        }
+
+        if std::slice::from_raw_parts(ptr as *const u8, size).iter().any(|&b| b != 0) {
+            self.dirty_unmaps.fetch_add(1, Ordering::Relaxed);
+        }
+
+        System.dealloc(ptr as *mut u8, page_layout(size));
+
+        Ok(())
+    }
+
+    unsafe fn mprotect(&self, _ptr: *mut c_void, _size: usize, _prot: ProtFlags) -> nix::Result<()> {
+        Ok(())
+    }
+
+    unsafe fn pkey_mprotect(&self, ptr: *mut c_void, _size: usize, _prot: ProtFlags, pkey: c_int) -> nix::Result<()> {
+        self.pkeys.lock().unwrap().push((ptr as usize, pkey));
+
+        Ok(())
+    }
+
+    unsafe fn madvise(&self, ptr: *mut c_void, _size: usize, advice: c_int) -> nix::Result<()> {
+        if advice == MADV_COLLAPSE && self.fail_collapse.load(Ordering::Relaxed) {
+            return Err(Errno::EINVAL);
+        }
+
+        if self.blocked_advice.lock().unwrap().contains(&advice) {
+            return Err(Errno::EPERM);
+        }
+
+        self.advice.lock().unwrap().push((ptr as usize, advice));
+
+        Ok(())
+    }
+
+    unsafe fn mlock(&self, ptr: *mut c_void, _size: usize) -> nix::Result<()> {
+        if self.fail_mlock.load(Ordering::Relaxed) {
+            return Err(Errno::EAGAIN);
+        }
+
+        self.locked.lock().unwrap().push(ptr as usize);
+
+        Ok(())
+    }
+
+    unsafe fn mbind(&self, ptr: *mut c_void, _size: usize, node: usize) -> nix::Result<()> {
+        self.bound.lock().unwrap().push((ptr as usize, node));
+
+        Ok(())
+    }
+}
+
+/// Huge page aligned layout for mock mappings
+fn page_layout(bytes: usize) -> Layout {
+    Layout::from_size_align(bytes, mb(2)).unwrap()
+}
+
+fn layout(bytes: usize) -> Layout {
+    Layout::from_size_align(bytes, 8).unwrap()
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn huge_and_default_segments() {
+    static SYS: MockSyscalls = MockSyscalls::new(2 * 1024 * 1024);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // Doesn't fit in the huge page pool
+    let ptr1 = mapper.alloc(layout(mb(3)));
+
+    // Fits in the huge page pool
+    let ptr2 = mapper.alloc(layout(mb(1)));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(2, stats.segments);
+    assert_eq!(1, stats.default_segments);
+    assert_eq!(1, stats.huge_segments);
+    assert_eq!(mb(4), stats.alloc);
+    assert_eq!(mb(3), stats.default_mapped);
+    assert_eq!(mb(2), stats.huge_mapped);
+    assert_eq!(mb(5), SYS.mapped());
+    assert_eq!(80, stats.efficiency);
+    assert_eq!(1, stats.missed_allocs);
+    assert_eq!(3.0, stats.missed_mb);
+
+    assert!(!mapper.is_huge_ptr(ptr1));
+    assert!(mapper.is_huge_ptr(ptr2));
+    assert!(!mapper.is_huge_ptr(std::ptr::null_mut()));
+
+    assert!(mapper.is_managed_ptr(ptr1));
+    assert!(mapper.dealloc(ptr1));
+    assert!(!mapper.is_managed_ptr(ptr1));
+    assert!(!mapper.dealloc(ptr1));
+
+    assert!(mapper.dealloc(ptr2));
+    assert_eq!(0, SYS.mapped());
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(0, stats.segments);
+    assert_eq!(100, stats.efficiency);
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn realloc_in_place() {
+    static SYS: MockSyscalls = MockSyscalls::new(4 * 1024 * 1024);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let ptr = mapper.alloc(layout(mb(1)));
+    let ptr = mapper.realloc(ptr, layout(mb(3)));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.huge_segments);
+    assert_eq!(mb(3), stats.alloc);
+    assert_eq!(mb(4), stats.mapped);
+    assert_eq!(0, stats.remaps_failed);
+
+    // Fails to remap
+    mapper.set_remap_failure(RemapFailure::Fail);
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+
+    assert!(mapper.realloc(ptr, layout(mb(5))).is_null());
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.segments);
+    assert_eq!(mb(3), stats.alloc);
+    assert_eq!(1, stats.remaps_failed);
+    assert_eq!(1, stats.remaps_refused);
+
+    assert!(mapper.dealloc(ptr));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn huge_retries() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_huge_retry(2, Duration::ZERO);
+
+    let ptr = mapper.alloc(layout(mb(2)));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(2, stats.huge_retries);
+    assert_eq!(1, stats.default_segments);
+
+    assert!(mapper.dealloc(ptr));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn soft_limit() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn pressure(mapped: usize, limit: usize) {
+        assert!(mapped > limit);
+        CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mapper = MMapper::with_syscalls(&SYS);
+    mapper.set_soft_limit(mb(3), Some(pressure));
+
+    let ptr1 = mapper.alloc(layout(mb(2)));
+    assert_eq!(0, CALLS.load(Ordering::Relaxed));
+
+    let ptr2 = mapper.alloc(layout(mb(2)));
+    assert_eq!(1, CALLS.load(Ordering::Relaxed));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.soft_limit_exceeded);
+    assert_eq!(133, stats.pressure);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+}
+
+#[test]
+fn segment_report() {
+    static SYS: MockSyscalls = MockSyscalls::new(2 * 1024 * 1024);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let ptr1 = mapper.alloc(layout(mb(1)));
+    let ptr2 = mapper.alloc(layout(mb(3)));
+
+    assert!(mapper.set_tag(ptr2, "frames"));
+    assert!(!mapper.set_tag(std::ptr::null_mut(), "nothing"));
+
+    let segments = mapper.segments();
+    assert_eq!(2, segments.len());
+    assert!(segments[0].ptr < segments[1].ptr);
+
+    let segment = segments.iter().find(|segment| segment.ptr == ptr2 as usize).unwrap();
+    assert_eq!(mb(3), segment.size);
+    assert_eq!(Some("frames"), segment.tag);
+    assert!(!segment.huge);
+    let id = segment.id;
+
+    let report = report::format(&segments);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(4, lines.len());
+    assert!(lines[0].trim_start().starts_with("id"));
+    assert!(report.contains(&format!("{:#x}", ptr1 as usize)));
+    assert!(report.contains(" huge "));
+    assert!(report.contains(" frames"));
+    assert_eq!(format!("2 segments, {} bytes allocated, {} bytes mapped", mb(4), mb(5)), lines[3]);
+
+    // The tag and id follow the allocation when it is copied to a new segment
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let ptr2 = mapper.realloc(ptr2, layout(mb(5)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+
+    let segments = mapper.segments();
+    let segment = segments.iter().find(|segment| segment.ptr == ptr2 as usize).unwrap();
+    assert_eq!(Some("frames"), segment.tag);
+    assert_eq!(id, segment.id);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+
+    assert!(mapper.segments().is_empty());
+    assert_eq!("0 segments, 0 bytes allocated, 0 bytes mapped", report::format(&[]).lines().nth(1).unwrap());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn stats_snapshot() {
+    static SYS: MockSyscalls = MockSyscalls::new(2 * 1024 * 1024);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let ptr1 = mapper.alloc(layout(mb(1)));
+    let ptr2 = mapper.alloc(layout(mb(3)));
+    let ptr2 = mapper.realloc(ptr2, layout(mb(5)));
+
+    let stats = mapper.stats().unwrap();
+    let snapshot = mapper.stats_snapshot();
+    assert_eq!(format!("{stats:?}"), format!("{snapshot:?}"));
+    assert!(snapshot.allocs_per_sec > 0.0);
+    assert!(snapshot.remaps_per_sec > 0.0);
+    assert_eq!(0.0, snapshot.deallocs_per_sec);
+    assert_eq!(1, snapshot.huge_segments);
+    assert_eq!(1, snapshot.default_segments);
+    assert_eq!(mb(6), snapshot.alloc);
+    assert_eq!(mb(7), snapshot.mapped);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+
+    let snapshot = mapper.stats_snapshot();
+    assert_eq!(0, snapshot.segments);
+    assert_eq!(0, snapshot.mapped);
+    assert_eq!(100, snapshot.efficiency);
+}
+
+#[test]
+#[cfg(feature = "no-stats")]
+fn no_stats() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_huge_retry(2, Duration::ZERO);
+
+    let ptr = mapper.alloc(layout(mb(2)));
+
+    // The segment is still visible to stats(), but the counters and the snapshot totals are not kept
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.segments);
+    assert_eq!(0, stats.missed_allocs);
+    assert_eq!(0, stats.huge_retries);
+
+    let snapshot = mapper.stats_snapshot();
+    assert_eq!(0, snapshot.segments);
+    assert_eq!(0, snapshot.mapped);
+
+    assert!(mapper.dealloc(ptr));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn promote() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // Both fall back to default pages. The 1mb segment can't be promoted in place as it would
+    // need to grow to a whole huge page.
+    let ptr1 = mapper.alloc(layout(mb(2)));
+    let ptr2 = mapper.alloc(layout(mb(1)));
+
+    unsafe { ptr1.write_bytes(0xa5, mb(2)) };
+
+    // Nothing can be promoted until there are huge pages
+    assert_eq!(0, unsafe { mapper.promote() });
+    assert_eq!(2, mapper.stats().unwrap().missed_allocs);
+
+    SYS.huge_free.store(mb(8), Ordering::Relaxed);
+
+    assert_eq!(1, unsafe { mapper.promote() });
+    assert!(mapper.is_huge_ptr(ptr1));
+    assert!(!mapper.is_huge_ptr(ptr2));
+    assert!(unsafe { std::slice::from_raw_parts(ptr1, mb(2)) }.iter().all(|&b| b == 0xa5));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.promotions);
+    assert_eq!(1, stats.missed_allocs);
+    assert_eq!(1.0, stats.missed_mb);
+    assert_eq!(1, stats.huge_segments);
+    assert_eq!(mb(2), stats.huge_mapped);
+    assert_eq!(1, stats.default_segments);
+    assert_eq!(mb(6), SYS.huge_free.load(Ordering::Relaxed));
+
+    // Already promoted
+    assert_eq!(0, unsafe { mapper.promote() });
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+    assert_eq!(0, SYS.mapped());
+    assert_eq!(mb(8), SYS.huge_free.load(Ordering::Relaxed));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn promote_grown() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // A missed segment which can't be promoted
+    mapper.set_max_slack(50);
+    let missed = mapper.alloc(layout(mb(1)));
+
+    // Too small to want huge pages, so only the growth counts as missed
+    let ptr = mapper.alloc(layout(4096));
+    assert_eq!(1, mapper.stats().unwrap().missed_allocs);
+
+    let ptr = mapper.realloc(ptr, layout(mb(2)));
+    let stats = mapper.stats().unwrap();
+    assert_eq!(2, stats.missed_allocs);
+    assert_eq!((mb(3) - 4096) as f64 / mb(1) as f64, stats.missed_mb);
+
+    // Promotion takes back only what was counted for the segment
+    SYS.huge_free.store(mb(2), Ordering::Relaxed);
+    assert_eq!(1, unsafe { mapper.promote() });
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.missed_allocs);
+    assert_eq!(1.0, stats.missed_mb);
+
+    assert!(mapper.dealloc(ptr));
+    assert!(mapper.dealloc(missed));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn collapse() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(2));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // One huge segment, then two default page size segments
+    let huge = mapper.alloc(layout(mb(2)));
+    let ptr1 = mapper.alloc(layout(mb(1)));
+    let ptr2 = mapper.alloc(layout(mb(1)));
+    assert!(mapper.is_huge_ptr(huge));
+
+    // Only default page size segments are offered, once each
+    assert_eq!(2, mapper.collapse());
+    assert_eq!(0, mapper.collapse());
+
+    // Growing a segment offers it again
+    let ptr1 = mapper.realloc(ptr1, layout(mb(3)));
+    SYS.fail_collapse.store(true, Ordering::Relaxed);
+    assert_eq!(0, mapper.collapse());
+
+    mapper.maintain();
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(2, stats.collapses);
+    assert_eq!(1, stats.collapses_failed);
+    assert_eq!(1, stats.maintenance_passes);
+
+    assert!(mapper.dealloc(huge));
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn shrink_threshold() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_shrink_threshold(50);
+
+    let ptr = mapper.alloc(layout(mb(8)));
+
+    // Shrinks staying above half of the mapped size keep the mapping
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(6))));
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(5))));
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(7))));
+    assert_eq!(mb(8), SYS.mapped());
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(2, stats.shrinks_deferred);
+    assert_eq!(mb(8), stats.mapped);
+    assert_eq!(mb(7), stats.alloc);
+
+    // Segments resized recently aren't trimmed
+    assert_eq!(0, mapper.trim(Duration::from_secs(3600)));
+    assert_eq!(mb(1), mapper.trim(Duration::ZERO));
+    assert_eq!(mb(7), SYS.mapped());
+
+    // Dropping below half releases the pages straight away
+    let ptr = mapper.realloc(ptr, layout(mb(3)));
+    assert_eq!(mb(3), SYS.mapped());
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(2, stats.shrinks_deferred);
+    assert_eq!(mb(1), stats.trimmed_bytes);
+    assert_eq!(mb(3), stats.mapped);
+    assert_eq!(mb(3), mapper.stats_snapshot().mapped);
+
+    assert!(mapper.dealloc(ptr));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+fn reserve_hint() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    assert!(!mapper.reserve_hint(std::ptr::null_mut(), mb(16)));
+
+    // The mock can't grow in place, so the reservation is mapped by the next reallocation
+    let ptr = mapper.alloc(layout(mb(2)));
+    assert!(mapper.reserve_hint(ptr, mb(16)));
+    assert_eq!(mb(2), SYS.mapped());
+
+    let ptr = mapper.realloc(ptr, layout(mb(4)));
+    assert_eq!(mb(16), SYS.mapped());
+
+    // Growing and shrinking within the reservation keeps the mapping
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(12))));
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(1))));
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(16))));
+    assert_eq!(mb(16), SYS.mapped());
+    assert_eq!(0, mapper.trim(Duration::ZERO));
+
+    // Growing past it remaps as usual
+    let ptr = mapper.realloc(ptr, layout(mb(20)));
+    assert_eq!(mb(20), SYS.mapped());
+
+    // Trimming the segment cancels the reservation
+    assert!(mapper.reserve_hint(ptr, mb(32)));
+    let ptr = mapper.realloc(ptr, layout(mb(24)));
+    assert_eq!(mb(32), SYS.mapped());
+
+    assert_eq!(None, mapper.trim_segment(std::ptr::null_mut()));
+    assert_eq!(Some(mb(8)), mapper.trim_segment(ptr));
+    assert_eq!(Some(0), mapper.trim_segment(ptr));
+    assert_eq!(mb(24), SYS.mapped());
+
+    assert!(mapper.dealloc(ptr));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn max_slack() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(16));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_max_slack(25);
+
+    // 3mb in two huge pages leaves 25% unused, 2mb + 1 byte leaves nearly 50%
+    let ptr1 = mapper.alloc(layout(mb(3)));
+    let ptr2 = mapper.alloc(layout(mb(2) + 1));
+    assert!(mapper.is_huge_ptr(ptr1));
+    assert!(!mapper.is_huge_ptr(ptr2));
+
+    // The thread override takes precedence
+    set_thread_max_slack(Some(100));
+    let ptr3 = mapper.alloc(layout(mb(2) + 1));
+    set_thread_max_slack(None);
+    assert!(mapper.is_huge_ptr(ptr3));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.exact_size_allocs);
+    assert_eq!(0, stats.missed_allocs);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+    assert!(mapper.dealloc(ptr3));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn move_strategy() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(16));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+
+    let moved = |strategy| {
+        mapper.set_move_strategy(strategy);
+
+        let ptr = mapper.alloc(layout(mb(1)));
+        unsafe { ptr.write_bytes(0x5a, mb(1)) };
+
+        // Can't grow in place so moves to a new segment
+        let new_ptr = mapper.realloc(ptr, layout(mb(5)));
+        assert_ne!(ptr, new_ptr);
+        assert!(mapper.is_huge_ptr(new_ptr));
+        assert!(unsafe { std::slice::from_raw_parts(new_ptr, mb(1)) }.iter().all(|&b| b == 0x5a));
+
+        assert!(mapper.dealloc(new_ptr));
+        assert_eq!(0, SYS.mapped());
+        assert_eq!(mb(16), SYS.huge_free.load(Ordering::Relaxed));
+    };
+
+    moved(MoveStrategy::Memcpy);
+    moved(MoveStrategy::Mremap);
+    moved(MoveStrategy::DontUnmap);
+
+    // Falls back to copying if the kernel refuses the move
+    SYS.fail_move.store(true, Ordering::Relaxed);
+    moved(MoveStrategy::Mremap);
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(mb(2), stats.move_memcpy_bytes);
+    assert_eq!(mb(2), stats.move_mremap_bytes);
+    assert_eq!(mb(2), stats.move_dontunmap_bytes);
+    assert_eq!(4, stats.remaps_copied);
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn demotion() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(6));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_demotion(Some(Duration::ZERO), Some(|_, _, tag| tag == Some("pinned")));
+
+    // Fill the pool, oldest first
+    let pinned = mapper.alloc(layout(mb(2)));
+    assert!(mapper.set_tag(pinned, "pinned"));
+
+    let mut ptrs = Vec::new();
+
+    for fill in [1, 2] {
+        std::thread::sleep(Duration::from_millis(1));
+
+        let ptr = mapper.alloc(layout(mb(2)));
+        unsafe { ptr.write_bytes(fill, mb(2)) };
+        ptrs.push(ptr);
+    }
+
+    // Normal priority allocations fall back to default pages
+    let normal = mapper.alloc(layout(mb(2)));
+    assert!(!mapper.is_huge_ptr(normal));
+
+    // High priority allocations demote the least recently used segment which isn't vetoed
+    set_thread_high_priority(true);
+    let high = mapper.alloc(layout(mb(2)));
+    set_thread_high_priority(false);
+
+    assert!(mapper.is_huge_ptr(high));
+    assert!(mapper.is_huge_ptr(pinned));
+    assert!(!mapper.is_huge_ptr(ptrs[0]));
+    assert!(mapper.is_huge_ptr(ptrs[1]));
+    assert!(unsafe { std::slice::from_raw_parts(ptrs[0], mb(2)) }.iter().all(|&b| b == 1));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.demotions);
+    assert_eq!(1, stats.demotions_vetoed);
+    assert_eq!(3, stats.huge_segments);
+    assert_eq!(2, stats.default_segments);
+
+    // Disabled
+    mapper.set_demotion(None, None);
+    set_thread_high_priority(true);
+    let refused = mapper.alloc(layout(mb(2)));
+    set_thread_high_priority(false);
+    assert!(!mapper.is_huge_ptr(refused));
+
+    for ptr in [pinned, ptrs[0], ptrs[1], normal, high, refused] {
+        assert!(mapper.dealloc(ptr));
+    }
+
+    assert_eq!(0, SYS.mapped());
+    assert_eq!(mb(6), SYS.huge_free.load(Ordering::Relaxed));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn placement_fallback() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(4));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // The mock can't map at an address so the kernel's placement is used
+    mapper.set_placement_window(Some(0x5000_0000_0000..0x6000_0000_0000));
+    let ptr = mapper.alloc(layout(mb(2)));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(0, stats.randomized_placements);
+    assert_eq!(1, stats.placement_fallbacks);
+
+    assert!(mapper.dealloc(ptr));
+}
+
+#[test]
+fn mte_unsupported() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(4));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    if mte::supported() {
+        return;
+    }
+
+    // Enabling fails and segments are left untagged
+    assert!(mapper.set_mte(true).is_err());
+    assert!(!mapper.mte());
+
+    let ptr = mapper.alloc(layout(mb(2)));
+    assert!(mapper.is_managed_ptr(ptr));
+    assert_eq!(0, mapper.stats().unwrap().mte_segments);
+
+    assert!(mapper.set_mte(false).is_ok());
+    assert!(mapper.dealloc(ptr));
+}
+
+#[test]
+fn protection_key() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    assert!(mapper.set_protection_key(mb(1) as *mut u8, 1).is_err());
+
+    let ptr = mapper.alloc(layout(mb(2)));
+    mapper.set_protection_key(ptr, 1).unwrap();
+    assert_eq!(Some(&(ptr as usize, 1)), SYS.pkeys.lock().unwrap().last());
+
+    // A reallocation which copies to a new segment keeps the key
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let new_ptr = mapper.realloc(ptr, layout(mb(4)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+
+    assert_ne!(ptr, new_ptr);
+    assert_eq!(Some(&(new_ptr as usize, 1)), SYS.pkeys.lock().unwrap().last());
+
+    // Segments with a key stay on their pages
+    assert_eq!(0, unsafe { mapper.promote() });
+
+    assert!(mapper.dealloc(new_ptr));
+}
+
+#[test]
+fn sensitive_wipe() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    assert!(!mapper.set_sensitive(mb(1) as *mut u8, true));
+
+    // Not sensitive - unmapped as it is
+    let ptr = mapper.alloc(layout(mb(2)));
+    unsafe { ptr.write_bytes(0xaa, mb(2)) };
+    assert!(mapper.dealloc(ptr));
+    assert_eq!(1, SYS.dirty_unmaps.load(Ordering::Relaxed));
+
+    let ptr = mapper.alloc(layout(mb(2)));
+    unsafe { ptr.write_bytes(0xaa, mb(2)) };
+    assert!(mapper.set_sensitive(ptr, true));
+
+    // A deferred shrink wipes the bytes cut off
+    mapper.set_shrink_threshold(0);
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(1))));
+    assert!(unsafe { std::slice::from_raw_parts(ptr.add(mb(1)), mb(1)) }.iter().all(|&b| b == 0));
+
+    // A reallocation which copies to a new segment wipes the old one and keeps the flag
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let new_ptr = mapper.realloc(ptr, layout(mb(3)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+
+    assert_ne!(ptr, new_ptr);
+    assert_eq!(0xaa, unsafe { new_ptr.read() });
+
+    assert!(mapper.dealloc(new_ptr));
+    assert_eq!(1, SYS.dirty_unmaps.load(Ordering::Relaxed));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn secure_profile() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let secured = |ptr: *mut u8| {
+        let advice = SYS.advice.lock().unwrap();
+
+        advice.contains(&(ptr as usize, libc::MADV_WIPEONFORK)) && advice.contains(&(ptr as usize, libc::MADV_DONTDUMP))
+    };
+
+    // Off by default
+    let plain = mapper.alloc(layout(mb(2)));
+    assert!(!secured(plain));
+
+    mapper.set_secure_profile(true);
+    assert!(mapper.secure_profile());
+
+    let ptr = mapper.alloc(layout(mb(2)));
+    assert!(secured(ptr));
+    assert!(SYS.locked.lock().unwrap().contains(&(ptr as usize)));
+    assert_eq!(1, mapper.stats().unwrap().secure_segments);
+
+    // A reallocation which copies to a new segment keeps the profile, even once it is switched off
+    mapper.set_secure_profile(false);
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let new_ptr = mapper.realloc(ptr, layout(mb(3)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+
+    assert_ne!(ptr, new_ptr);
+    assert!(secured(new_ptr));
+    assert_eq!(2, mapper.stats().unwrap().secure_segments);
+
+    // Segments which can't be locked keep the advice and are counted
+    mapper.set_secure_profile(true);
+    SYS.locked.lock().unwrap().clear();
+    SYS.fail_mlock.store(true, Ordering::Relaxed);
+    let unlocked = mapper.alloc(layout(mb(1)));
+    SYS.fail_mlock.store(false, Ordering::Relaxed);
+
+    assert!(secured(unlocked));
+    assert!(SYS.locked.lock().unwrap().is_empty());
+    assert_eq!(1, mapper.stats().unwrap().secure_failures);
+
+    assert!(mapper.dealloc(plain));
+    assert!(mapper.dealloc(new_ptr));
+    assert!(mapper.dealloc(unlocked));
+}
+
+#[test]
+fn probe() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // Everything but huge pages works, so nothing is switched off
+    mapper.set_move_strategy(MoveStrategy::DontUnmap);
+    mapper.set_pretouch_validation(true);
+
+    let report = mapper.probe();
+
+    assert!(report.available("mmap"));
+    assert!(report.available("mremap MREMAP_DONTUNMAP"));
+    assert!(!report.available("mmap MAP_HUGETLB"));
+    assert!(!report.get("mmap MAP_HUGETLB").unwrap().blocked());
+    assert!(report.disabled.is_empty());
+    assert_eq!(0, SYS.mapped());
+
+    // Blocked calls switch off the settings depending on them
+    SYS.fail_move.store(true, Ordering::Relaxed);
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    SYS.blocked_advice.lock().unwrap().push(libc::MADV_POPULATE_WRITE);
+    mapper.set_remap_failure(RemapFailure::Fail);
+    mapper.set_sigbus_protection(true);
+
+    let report = mapper.probe();
+
+    SYS.fail_move.store(false, Ordering::Relaxed);
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    SYS.blocked_advice.lock().unwrap().clear();
+
+    assert!(!report.available("mremap MREMAP_FIXED"));
+    assert!(report.get("madvise MADV_POPULATE_WRITE").unwrap().blocked());
+    assert!(!report.all_available());
+    assert_eq!(
+        vec!["remap_failure=fail", "move_strategy=dontunmap", "pretouch_validation=true", "sigbus_protection=true"],
+        report.disabled
+    );
+
+    assert_eq!(RemapFailure::Copy, mapper.remap_failure());
+    assert_eq!(MoveStrategy::Memcpy, mapper.move_strategy());
+    assert!(!mapper.pretouch_validation());
+    assert!(!mapper.sigbus_protection());
+    assert_eq!(0, SYS.mapped());
+
+    let table = report.to_string();
+    assert!(table.contains("blocked: EPERM"));
+    assert!(table.contains("disabled: remap_failure=fail"));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn stable_address() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    assert!(!mapper.set_stable_address(mb(1) as *mut u8, true));
+
+    let ptr = mapper.alloc(layout(mb(2)));
+    unsafe { ptr.write_bytes(0xaa, mb(2)) };
+    assert!(mapper.set_stable_address(ptr, true));
+
+    // The mock can't grow in place, and the segment may not move, even with the copy action
+    assert_eq!(RemapFailure::Copy, mapper.remap_failure());
+    assert!(mapper.realloc(ptr, layout(mb(3))).is_null());
+    assert_eq!(1, mapper.stats().unwrap().remaps_refused);
+    assert_eq!(0, mapper.stats().unwrap().remaps_copied);
+
+    // A larger alignment it doesn't meet can't be reached without moving either
+    let misaligned = (ptr as usize & (ptr as usize).wrapping_neg()) * 2;
+    let aligned = Layout::from_size_align(mb(2), misaligned).unwrap();
+    assert!(mapper.realloc(ptr, aligned).is_null());
+    assert_eq!(2, mapper.stats().unwrap().remaps_refused);
+
+    // Shrinking is done in place
+    assert_eq!(ptr, mapper.realloc(ptr, layout(mb(1))));
+    assert_eq!(0xaa, unsafe { ptr.add(mb(1) - 1).read() });
+
+    assert!(mapper.dealloc(ptr));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn page_size_pref() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_max_slack(25);
+
+    // 2mb + 1 byte leaves too much slack for the policy but not for an explicit request
+    let ptr1 = mapper.alloc_with(layout(mb(2) + 1), PageSizePref::Policy);
+    let ptr2 = mapper.alloc_with(layout(mb(2) + 1), PageSizePref::Huge);
+    assert!(!mapper.is_huge_ptr(ptr1));
+    assert!(mapper.is_huge_ptr(ptr2));
+
+    // Base pages are used even when huge pages are free
+    let ptr3 = mapper.alloc_with(layout(mb(2)), PageSizePref::Base);
+    assert!(!mapper.is_huge_ptr(ptr3));
+
+    // The pool can't supply a 1gb page, so the configured huge page size is used
+    let ptr4 = mapper.alloc_with(layout(mb(2)), PageSizePref::Huge1G);
+    assert!(mapper.is_huge_ptr(ptr4));
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.exact_size_allocs);
+    assert_eq!(0, stats.missed_allocs);
+
+    // The pool is exhausted, so an explicit request falls back to base pages and is counted
+    let ptr5 = mapper.alloc_with(layout(mb(4)), PageSizePref::Huge);
+    assert!(!mapper.is_huge_ptr(ptr5));
+    assert_eq!(1, mapper.stats().unwrap().missed_allocs);
+
+    // A moved segment keeps its preference
+    assert!(mapper.dealloc(ptr4));
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let ptr3 = mapper.realloc(ptr3, layout(mb(3)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert_eq!(1, mapper.stats().unwrap().remaps_copied);
+    assert!(!mapper.is_huge_ptr(ptr3));
+
+    for ptr in [ptr1, ptr2, ptr3, ptr5] {
+        assert!(mapper.dealloc(ptr));
+    }
+
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+fn segment_hooks() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    static EVENTS: Mutex<Vec<(&str, usize, usize, usize)>> = Mutex::new(Vec::new());
+
+    fn on_map(segment: &SegmentInfo) {
+        // Called without the map locked
+        assert!(MAPPER.get().unwrap().is_managed_ptr(segment.ptr as *mut u8));
+        EVENTS.lock().unwrap().push(("map", segment.ptr, segment.size, segment.alloc_size));
+    }
+
+    fn on_unmap(segment: &SegmentInfo) {
+        assert!(!MAPPER.get().unwrap().is_managed_ptr(segment.ptr as *mut u8));
+        EVENTS.lock().unwrap().push(("unmap", segment.ptr, segment.size, segment.alloc_size));
+    }
+
+    fn on_remap(old: &SegmentInfo, new: &SegmentInfo) {
+        assert!(MAPPER.get().unwrap().is_managed_ptr(new.ptr as *mut u8));
+        assert_eq!(old.id, new.id);
+        EVENTS.lock().unwrap().push(("remap", old.ptr, old.size, new.size));
+    }
+
+    static MAPPER: OnceLock<MMapper> = OnceLock::new();
+    let mapper = MAPPER.get_or_init(|| MMapper::with_syscalls(&SYS));
+
+    let hooks = SegmentHooks {
+        on_map: Some(on_map),
+        on_unmap: Some(on_unmap),
+        on_remap: Some(on_remap),
+    };
+
+    mapper.set_hooks(hooks).unwrap();
+    assert!(mapper.set_hooks(SegmentHooks::default()).is_err());
+
+    let ptr1 = mapper.alloc(layout(mb(3)));
+    let ptr2 = mapper.realloc(ptr1, layout(mb(2)));
+    let ptr3 = mapper.realloc(ptr2, layout(mb(5)));
+
+    assert!(mapper.dealloc(ptr3));
+
+    let events = EVENTS.lock().unwrap();
+    assert_eq!(
+        vec![
+            ("map", ptr1 as usize, mb(3), mb(4)),
+            ("remap", ptr1 as usize, mb(3), mb(2)),
+            ("remap", ptr2 as usize, mb(2), mb(5)),
+            ("unmap", ptr3 as usize, mb(5), mb(6)),
+        ],
+        *events
+    );
+}
+
+#[test]
+fn skip_checkpoint() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let advised = |ptr: *mut u8, advice| SYS.advice.lock().unwrap().contains(&(ptr as usize, advice));
+
+    assert!(mapper.set_skip_checkpoint(mb(1) as *mut u8, true).is_err());
+
+    let keep = mapper.alloc(layout(mb(1)));
+    let cache = mapper.alloc(layout(mb(3)));
+    mapper.set_skip_checkpoint(cache, true).unwrap();
+    assert!(advised(cache, libc::MADV_DONTDUMP));
+
+    let segments = mapper.checkpoint_segments();
+    assert_eq!(2, segments.len());
+
+    let segment = segments.iter().find(|segment| segment.start == cache as usize).unwrap();
+    assert_eq!(cache as usize + mb(4), segment.end);
+    assert_eq!(mb(3), segment.size);
+    assert!(segment.huge && segment.skip && !segment.shared);
+    assert!(segment.to_string().ends_with(" private skip -"));
+
+    assert!(!segments.iter().find(|segment| segment.start == keep as usize).unwrap().skip);
+
+    // A reallocation which copies to a new segment keeps the mark
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let cache = mapper.realloc(cache, layout(mb(5)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert!(advised(cache, libc::MADV_DONTDUMP));
+    assert!(mapper.checkpoint_segments().iter().any(|segment| segment.start == cache as usize && segment.skip));
+
+    mapper.set_skip_checkpoint(cache, false).unwrap();
+    assert!(advised(cache, libc::MADV_DODUMP));
+    assert!(mapper.checkpoint_segments().iter().all(|segment| !segment.skip));
+
+    assert!(mapper.dealloc(keep));
+    assert!(mapper.dealloc(cache));
+    assert!(mapper.checkpoint_segments().is_empty());
+}
+
+#[test]
+fn access_pattern() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let advice = |ptr: *mut u8| {
+        SYS.advice
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&&(addr, _)| addr == ptr as usize)
+            .map(|&(_, advice)| advice)
+            .collect::<Vec<_>>()
+    };
+
+    let normal = mapper.alloc(layout(mb(2)));
+    assert!(advice(normal).is_empty());
+
+    let streaming = with_thread_config(|config| config.access_pattern = AccessPattern::Streaming, || mapper.alloc(layout(mb(2))));
+    assert_eq!(vec![libc::MADV_SEQUENTIAL], advice(streaming));
+
+    // Random access segments are pre-faulted in one call
+    let random = with_thread_config(|config| config.access_pattern = AccessPattern::Random, || mapper.alloc(layout(mb(2))));
+    assert_eq!(vec![libc::MADV_RANDOM, libc::MADV_POPULATE_WRITE], advice(random));
+    assert_eq!(AccessPattern::Normal, thread_access_pattern());
+
+    // A reallocation which copies to a new segment keeps the pattern
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let random = mapper.realloc(random, layout(mb(1)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert_eq!(vec![libc::MADV_RANDOM, libc::MADV_POPULATE_WRITE], advice(random));
+
+    for ptr in [normal, streaming, random] {
+        assert!(mapper.dealloc(ptr));
+    }
+}
+
+#[test]
+fn noreserve() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(2));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let noreserve = |ptr: *mut u8| SYS.noreserve.lock().unwrap().contains(&(ptr as usize));
+    let sparse = |f: &dyn Fn() -> *mut u8| with_thread_config(|config| config.noreserve = true, f);
+
+    // Huge page segments are unaffected
+    let huge = sparse(&|| mapper.alloc(layout(mb(2))));
+    assert!(mapper.is_huge_ptr(huge));
+    assert!(!noreserve(huge));
+
+    let table = sparse(&|| mapper.alloc(layout(mb(4))));
+    assert!(!mapper.is_huge_ptr(table));
+    assert!(noreserve(table));
+    assert!(!thread_noreserve());
+
+    // Sparse random access segments aren't pre-faulted
+    let random = with_thread_config(|config| config.access_pattern = AccessPattern::Random, || sparse(&|| mapper.alloc(layout(mb(4)))));
+    assert!(noreserve(random));
+    assert!(!SYS.advice.lock().unwrap().contains(&(random as usize, libc::MADV_POPULATE_WRITE)));
+
+    // A reallocation which copies to a new segment keeps the setting
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let table = mapper.realloc(table, layout(mb(6)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert!(noreserve(table));
+
+    let normal = mapper.alloc(layout(mb(4)));
+    assert!(!noreserve(normal));
+
+    for ptr in [huge, table, random, normal] {
+        assert!(mapper.dealloc(ptr));
+    }
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn noreserve_uncached() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_segment_cache(2, mb(8));
+
+    let cached = mapper.alloc(layout(mb(4)));
+    assert!(mapper.dealloc(cached));
+    assert_eq!(mb(4), SYS.mapped());
+
+    // The cached segment is left in the cache, and not counted as a hit
+    let sparse = with_thread_config(|config| config.noreserve = true, || mapper.alloc(layout(mb(4))));
+    assert_ne!(cached, sparse);
+    assert_eq!(mb(8), SYS.mapped());
+    assert_eq!(0, mapper.stats().unwrap().segment_cache_hits);
+
+    // Noreserve segments aren't cached, so the next allocation reuses the one which was
+    assert!(mapper.dealloc(sparse));
+    assert_eq!(mb(4), SYS.mapped());
+    assert_eq!(cached, mapper.alloc(layout(mb(4))));
+    assert_eq!(1, mapper.stats().unwrap().segment_cache_hits);
+    assert!(mapper.dealloc(cached));
+}
+
+#[test]
+fn strict_overcommit() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(2));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let populated = |ptr: *mut u8| SYS.advice.lock().unwrap().contains(&(ptr as usize, libc::MADV_POPULATE_WRITE));
+    let noreserve = |ptr: *mut u8| SYS.noreserve.lock().unwrap().contains(&(ptr as usize));
+
+    let policy = |mode| Overcommit {
+        mode,
+        ratio: 50,
+        kbytes: 0,
+        commit_limit: Some(mb(1024)),
+        committed: Some(mb(256)),
+    };
+
+    let report = mapper.adapt_to_overcommit(Some(policy(OvercommitMode::Heuristic)));
+    assert!(report.adjusted.is_empty() && report.warnings.is_empty());
+    assert!(!mapper.strict_overcommit());
+
+    let report = mapper.adapt_to_overcommit(Some(policy(OvercommitMode::Always)));
+    assert!(report.adjusted.is_empty());
+    assert_eq!(1, report.warnings.len());
+
+    assert!(!mapper.adapt_to_overcommit(None).warnings.is_empty());
+
+    let report = mapper.adapt_to_overcommit(Some(policy(OvercommitMode::Never)));
+    assert_eq!(vec!["strict_overcommit=true"], report.adjusted);
+    assert!(report.warnings[0].contains(&mb(768).to_string()), "{report}");
+    assert!(mapper.strict_overcommit());
+
+    // Adapting again changes nothing
+    assert!(mapper.adapt_to_overcommit(Some(policy(OvercommitMode::Never))).adjusted.is_empty());
+
+    // Huge page segments aren't pre-faulted
+    let huge = mapper.alloc(layout(mb(2)));
+    assert!(mapper.is_huge_ptr(huge));
+    assert!(!populated(huge));
+
+    // Default page size segments are, and MAP_NORESERVE is ignored
+    let table = with_thread_config(|config| config.noreserve = true, || mapper.alloc(layout(mb(4))));
+    assert!(!mapper.is_huge_ptr(table));
+    assert!(populated(table));
+    assert!(!noreserve(table));
+
+    for ptr in [huge, table] {
+        assert!(mapper.dealloc(ptr));
+    }
+}
+
+#[test]
+fn thp_fallback() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let advised = |ptr: *mut u8| SYS.advice.lock().unwrap().contains(&(ptr as usize, libc::MADV_HUGEPAGE));
+
+    // Fallbacks spanning a transparent huge page are advised
+    let fallback = mapper.alloc(layout(mb(4)));
+    assert!(!mapper.is_huge_ptr(fallback));
+    assert_eq!(0, fallback as usize % mb(2));
+    assert!(advised(fallback));
+
+    // Smaller fallbacks, segments which never wanted huge pages and sparse segments aren't
+    let small = mapper.alloc(layout(mb(1)));
+    assert!(!advised(small));
+
+    let base = mapper.alloc_with(layout(mb(4)), PageSizePref::Base);
+    assert!(!advised(base));
+
+    let sparse = with_thread_config(|config| config.noreserve = true, || mapper.alloc(layout(mb(4))));
+    assert!(!advised(sparse));
+
+    for ptr in [fallback, small, base, sparse] {
+        assert!(mapper.dealloc(ptr));
+    }
+}
+
+#[test]
+fn mergeable() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let advised = |ptr: *mut u8, advice| SYS.advice.lock().unwrap().contains(&(ptr as usize, advice));
+
+    assert!(mapper.set_mergeable(mb(1) as *mut u8, true).is_err());
+
+    let table = mapper.alloc(layout(mb(2)));
+    let buffer = mapper.alloc(layout(mb(2)));
+
+    mapper.set_mergeable(table, true).unwrap();
+    mapper.set_mergeable(buffer, false).unwrap();
+    assert!(advised(table, libc::MADV_MERGEABLE));
+    assert!(advised(buffer, libc::MADV_UNMERGEABLE));
+
+    // A reallocation which copies to a new segment keeps the advice
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let buffer = mapper.realloc(buffer, layout(mb(3)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert!(advised(buffer, libc::MADV_UNMERGEABLE));
+
+    assert!(mapper.dealloc(table));
+    assert!(mapper.dealloc(buffer));
+}
+
+#[test]
+fn syscalls_unlocked() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(4));
+    static MAPPER: MMapper = MMapper::with_syscalls(&SYS);
+
+    SYS.map_locked.set(|| MAPPER.is_map_locked()).unwrap();
+    MAPPER.set_shrink_threshold(50);
+    MAPPER.set_demotion(Some(Duration::ZERO), None);
+
+    // Map and grow by copying
+    let ptr1 = MAPPER.alloc(layout(mb(2)));
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let ptr1 = MAPPER.realloc(ptr1, layout(mb(4)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+
+    // Trim the pages kept by a deferred shrink
+    let ptr1 = MAPPER.realloc(ptr1, layout(mb(2)));
+    assert_eq!(mb(2), MAPPER.trim(Duration::ZERO));
+
+    // Demote to make room for a high priority allocation
+    set_thread_high_priority(true);
+    let ptr2 = MAPPER.alloc(layout(mb(4)));
+    set_thread_high_priority(false);
+    assert!(!MAPPER.is_huge_ptr(ptr1));
+    assert!(MAPPER.is_huge_ptr(ptr2));
+
+    assert!(MAPPER.dealloc(ptr1));
+    assert!(MAPPER.dealloc(ptr2));
+
+    assert_eq!(0, SYS.locked_calls.load(Ordering::Relaxed));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn segment_cache() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(6));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_segment_cache(2, mb(8));
+
+    // A freed segment stays mapped, holding its huge pages
+    let ptr1 = mapper.alloc(layout(mb(4)));
+    unsafe { ptr1.write(0xaa) };
+    assert!(mapper.dealloc(ptr1));
+    assert_eq!(mb(4), SYS.mapped());
+
+    // An allocation of the same mapped size reuses it zeroed, although the pool can't supply 4mb
+    let ptr2 = mapper.alloc(layout(mb(3) + 1));
+    assert_eq!(ptr1, ptr2);
+    assert!(mapper.is_huge_ptr(ptr2));
+    assert_eq!(0, unsafe { ptr2.read() });
+    assert_eq!(mb(3) + 1, mapper.segments()[0].size);
+
+    // Other sizes are mapped
+    let ptr3 = mapper.alloc(layout(mb(2)));
+    assert_ne!(ptr2, ptr3);
+
+    let stats = mapper.stats().unwrap();
+    assert_eq!(1, stats.segment_cache_hits);
+    assert_eq!(2, stats.segment_cache_misses);
+
+    // A reallocation moving to a new segment reuses a cached one too
+    assert!(mapper.dealloc(ptr2));
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let ptr3 = mapper.realloc(ptr3, layout(mb(4)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert_eq!(ptr1, ptr3);
+
+    // Sensitive segments are unmapped, and the byte limit evicts the oldest segment
+    let ptr4 = mapper.alloc(layout(mb(2)));
+    assert!(mapper.set_sensitive(ptr4, true));
+    assert!(mapper.dealloc(ptr4));
+    assert_eq!(mb(4), SYS.mapped());
+
+    let ptr5 = mapper.alloc(layout(mb(6)));
+    let ptr6 = mapper.alloc(layout(mb(2)));
+    assert!(mapper.dealloc(ptr5));
+    assert!(mapper.dealloc(ptr6));
+    assert!(mapper.dealloc(ptr3));
+    assert_eq!(mb(6), SYS.mapped());
+
+    // Trimming releases the cached segments
+    assert_eq!(mb(6), mapper.trim(Duration::ZERO));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn realloc_cached() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(8));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_segment_cache(2, mb(8));
+
+    let ptr1 = mapper.alloc(layout(mb(2)));
+    unsafe { ptr1.write_bytes(0xa5, mb(2)) };
+
+    let ptr2 = mapper.alloc(layout(mb(4)));
+    assert!(mapper.dealloc(ptr2));
+    assert_eq!(mb(6), SYS.mapped());
+
+    // Growing in to a new segment copies in to the cached one without mapping anything, and the
+    // old segment is released
+    SYS.fail_mremap.store(true, Ordering::Relaxed);
+    let ptr3 = mapper.realloc(ptr1, layout(mb(4)));
+    SYS.fail_mremap.store(false, Ordering::Relaxed);
+    assert_eq!(ptr2, ptr3);
+    assert_eq!(mb(4), SYS.mapped());
+    assert!(unsafe { std::slice::from_raw_parts(ptr3, mb(2)) }.iter().all(|byte| *byte == 0xa5));
+    assert_eq!(mb(4), mapper.segments()[0].size);
+    assert_eq!(1, mapper.stats().unwrap().segment_cache_hits);
+
+    assert!(mapper.dealloc(ptr3));
+    assert_eq!(mb(4), mapper.trim(Duration::ZERO));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg(any(debug_assertions, feature = "verify"))]
+fn verify() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(4));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    assert!(mapper.verify().is_ok());
+
+    // Huge, default and moved segments keep the map and totals in step
+    let ptr1 = mapper.alloc(layout(mb(2)));
+    let ptr2 = mapper.alloc(layout(mb(3)));
+    let ptr2 = mapper.realloc(ptr2, layout(mb(5)));
+
+    let report = mapper.verify();
+    assert!(report.is_ok(), "{report}");
+    assert_eq!(2, report.segments);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr2));
+
+    assert_eq!(0, mapper.verify().segments);
+}
+
+#[test]
+fn snapshot_lookups() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(2));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // Enough segments to grow the map and the snapshots several times
+    let huge = mapper.alloc(layout(mb(2)));
+    let ptrs: Vec<_> = (0..100).map(|_| mapper.alloc(layout(4096))).collect();
+    assert!(mapper.set_stable_address(ptrs[0], true));
+
+    // Lookups don't take the map lock
+    mapper.with_map_locked(|| {
+        assert!(mapper.is_huge_ptr(huge));
+        assert!(!mapper.is_stable_ptr(huge));
+        assert!(ptrs.iter().all(|&ptr| mapper.is_managed_ptr(ptr) && !mapper.is_huge_ptr(ptr)));
+        assert!(mapper.is_stable_ptr(ptrs[0]));
+        assert!(!mapper.is_stable_ptr(ptrs[1]));
+        assert!(!mapper.is_managed_ptr(unsafe { huge.add(1) }));
+    });
+
+    // Segments stay visible to readers while other segments come and go
+    let done = AtomicBool::new(false);
+    let (first, last) = (huge as usize, ptrs[99] as usize);
+
+    std::thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                assert!(mapper.is_huge_ptr(first as *mut u8));
+                assert!(mapper.is_managed_ptr(last as *mut u8));
+            }
+        });
+
+        for _ in 0..100 {
+            let ptr = mapper.alloc(layout(4096));
+            assert!(mapper.is_managed_ptr(ptr));
+            assert!(mapper.dealloc(ptr));
+            assert!(!mapper.is_managed_ptr(ptr));
+        }
+
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+    });
+
+    for ptr in ptrs {
+        assert!(mapper.dealloc(ptr));
+        assert!(!mapper.is_managed_ptr(ptr));
+    }
+
+    assert!(mapper.dealloc(huge));
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+fn alloc_batch() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(4));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // More segments than the initial map capacity, growing it once for the whole batch
+    let mut layouts = vec![layout(mb(2)); 2];
+    layouts.extend([layout(4096); 40]);
+
+    let ptrs = mapper.alloc_batch(&layouts);
+    assert_eq!(layouts.len(), ptrs.len());
+    assert!(ptrs.iter().all(|&ptr| mapper.is_managed_ptr(ptr)));
+    assert!(mapper.is_huge_ptr(ptrs[0]) && mapper.is_huge_ptr(ptrs[1]));
+    assert!(!mapper.is_huge_ptr(ptrs[2]));
+    assert_eq!(mb(4) + 40 * 4096, SYS.mapped());
+    assert_eq!(layouts.len(), mapper.segments().len());
+
+    for ptr in ptrs {
+        assert!(mapper.dealloc(ptr));
+    }
+
+    assert!(mapper.alloc_batch(&[]).is_empty());
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn lock_contention() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let ptr = mapper.alloc(layout(4096));
+    let stats = mapper.stats_snapshot();
+    assert!(stats.lock_acquisitions > 0);
+    assert_eq!(0, stats.lock_contended);
+
+    // Hold the map lock until another thread has tried to take it
+    let ptr2 = std::thread::scope(|scope| {
+        let thread = mapper.with_map_locked(|| {
+            let acquisitions = mapper.stats_snapshot().lock_acquisitions;
+            let thread = scope.spawn(|| mapper.alloc(layout(4096)) as usize);
+
+            while mapper.stats_snapshot().lock_acquisitions == acquisitions {
+                std::thread::yield_now();
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+
+            thread
+        });
+
+        thread.join().unwrap() as *mut u8
+    });
+
+    let stats = mapper.stats_snapshot();
+    assert_eq!(1, stats.lock_contended);
+    assert!(stats.lock_max_wait_time >= Duration::from_millis(10));
+    assert_eq!(stats.lock_max_wait_time, stats.lock_wait_time);
+
+    mapper.reset_stats();
+    assert_eq!(0, mapper.stats_snapshot().lock_acquisitions);
+
+    assert!(mapper.dealloc(ptr));
+    assert!(mapper.dealloc(ptr2));
+}
+
+#[test]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn recent_segment() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // Growing the segment just allocated skips the lookup, as does growing it again
+    let ptr1 = mapper.alloc(layout(4096));
+    assert!(mapper.is_managed_realloc(ptr1));
+    let ptr1 = mapper.realloc(ptr1, layout(8192));
+    assert!(mapper.is_managed_realloc(ptr1));
+
+    // Another thread's allocations don't displace this thread's segment
+    let ptr2 = std::thread::scope(|scope| scope.spawn(|| mapper.alloc(layout(4096)) as usize).join().unwrap());
+    let ptr2 = ptr2 as *mut u8;
+    assert!(mapper.is_managed_realloc(ptr1));
+
+    let stats = mapper.stats_snapshot();
+    assert_eq!(3, stats.recent_segment_hits);
+    assert_eq!(0, stats.recent_segment_misses);
+
+    // Other segments are looked up, and any segment freed invalidates the record
+    assert!(mapper.is_managed_realloc(ptr2));
+    assert!(mapper.dealloc(ptr2));
+    assert!(mapper.is_managed_realloc(ptr1));
+    assert!(!mapper.is_managed_realloc(ptr2));
+
+    let stats = mapper.stats_snapshot();
+    assert_eq!(3, stats.recent_segment_hits);
+    assert_eq!(2, stats.recent_segment_misses);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(!mapper.is_managed_realloc(ptr1));
+}
+
+#[test]
+fn numa_local() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(4));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    // Segments aren't bound by default
+    let ptr1 = mapper.alloc(layout(mb(2)));
+    assert!(SYS.bound.lock().unwrap().is_empty());
+
+    // Bound segments prefer the node of the thread mapping them, huge or not
+    mapper.set_numa_local(true);
+    assert!(mapper.numa_local());
+
+    let ptr2 = mapper.alloc(layout(mb(2)));
+    let ptr3 = mapper.alloc(layout(mb(4)));
+    assert!(!mapper.is_huge_ptr(ptr3));
+
+    let node = numa::running_node();
+    assert_eq!(vec![(ptr2 as usize, node), (ptr3 as usize, node)], *SYS.bound.lock().unwrap());
+
+    for ptr in [ptr1, ptr2, ptr3] {
+        assert!(mapper.dealloc(ptr));
+    }
+}
+
+#[test]
+fn try_stats() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let ptr = mapper.alloc(layout(mb(1)));
+
+    // Gives the exact statistics when the pointer map is free
+    let stats = mapper.try_stats().unwrap();
+    assert_eq!(1, stats.segments);
+    assert_eq!(mb(1), stats.alloc);
+
+    // Fails rather than waiting while it is locked
+    assert!(mapper.with_map_locked(|| mapper.try_stats()).is_err());
+    assert_eq!(1, mapper.try_stats().unwrap().segments);
+
+    assert!(mapper.dealloc(ptr));
+}
+
+#[test]
+fn map_strategy() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    assert_eq!(MapStrategy::Hash, mapper.map_strategy());
+
+    let mut ptrs: Vec<*mut u8> = (0..4).map(|_| mapper.alloc(layout(mb(1)))).collect();
+
+    // Live segments are moved in to each new map
+    for strategy in [MapStrategy::Ordered, MapStrategy::Fixed(8), MapStrategy::Fixed(64), MapStrategy::Hash, MapStrategy::Fixed(2)] {
+        mapper.set_map_strategy(strategy);
+        assert_eq!(strategy, mapper.map_strategy());
+        assert!(ptrs.iter().all(|ptr| mapper.is_managed_ptr(*ptr)));
+    }
+
+    // A full fixed table is replaced by a larger one
+    ptrs.extend((0..32).map(|_| mapper.alloc(layout(mb(1)))));
+    assert!(ptrs.iter().all(|ptr| mapper.is_managed_ptr(*ptr)));
+
+    for ptr in ptrs {
+        assert!(mapper.dealloc(ptr));
+        assert!(!mapper.is_managed_ptr(ptr));
+    }
+}
+
+#[test]
+#[cfg_attr(feature = "passthrough", ignore = "passthrough layers never free through the mapper")]
+fn page_policy() {
+    static SYS: MockSyscalls = MockSyscalls::new(mb(16));
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    mapper.set_max_slack(25);
+
+    /// Huge pages for page aligned layouts whatever the slack, base size pages otherwise
+    struct Aligned;
+
+    impl PagePolicy for Aligned {
+        fn page_size(&self, layout: Layout) -> PageSizePref {
+            if layout.align() >= 4096 {
+                PageSizePref::Huge
+            } else {
+                PageSizePref::Base
+            }
+        }
+    }
+
+    let layers = Layers {
+        mapper: &mapper,
+        inner: &System,
+        policy: &Aligned,
+        use_mapper: |layout: Layout| layout.size() >= mb(1),
+    };
+
+    let aligned = |size| Layout::from_size_align(size, 4096).unwrap();
+
+    unsafe {
+        let ptr1 = layers.alloc(layout(mb(4)));
+        let ptr2 = layers.alloc(aligned(mb(2) + 1));
+        assert!(!mapper.is_huge_ptr(ptr1));
+        assert!(mapper.is_huge_ptr(ptr2));
+
+        // Reallocating in to the mapper asks the policy
+        let ptr3 = layers.alloc(aligned(4096));
+        assert!(!mapper.is_managed_ptr(ptr3));
+
+        let ptr3 = layers.realloc(ptr3, aligned(4096), mb(2));
+        assert!(mapper.is_huge_ptr(ptr3));
+
+        // Moving a segment keeps its page size
+        let ptr3 = layers.realloc(ptr3, aligned(mb(2)), mb(6));
+        assert!(mapper.is_huge_ptr(ptr3));
+
+        layers.dealloc(ptr1, layout(mb(4)));
+        layers.dealloc(ptr2, aligned(mb(2) + 1));
+        layers.dealloc(ptr3, aligned(mb(6)));
+    }
+
+    assert_eq!(0, SYS.mapped());
+}
+
+#[test]
+fn segment_ids() {
+    static SYS: MockSyscalls = MockSyscalls::new(0);
+    let mapper = MMapper::with_syscalls(&SYS);
+
+    let id = |ptr: *mut u8| mapper.owner_of(ptr).map(|segment| segment.id);
+
+    let ptr1 = mapper.alloc(layout(mb(1)));
+    let ptr2 = mapper.alloc(layout(mb(1)));
+    assert!(id(ptr1).unwrap() < id(ptr2).unwrap());
+
+    // Interior pointers find their segment
+    assert_eq!(id(ptr1), id(ptr1.wrapping_add(mb(1) - 1)));
+    assert_eq!(Some(ptr2 as usize), mapper.owner_of(ptr2.wrapping_add(4096)).map(|segment| segment.ptr));
+    assert_eq!(None, id(std::ptr::null_mut()));
+
+    // Kept when resized in place
+    let id1 = id(ptr1);
+    let ptr1 = mapper.realloc(ptr1, layout(mb(3)));
+    assert_eq!(id1, id(ptr1));
+
+    // A cached segment reused by a new allocation gets a new id
+    mapper.set_segment_cache(1, mb(8));
+
+    assert!(mapper.dealloc(ptr2));
+    let ptr3 = mapper.alloc(layout(mb(1)));
+    assert_eq!(ptr2, ptr3);
+    assert!(id(ptr3).unwrap() > id(ptr1).unwrap());
+
+    mapper.set_segment_cache(0, 0);
+
+    assert!(mapper.dealloc(ptr1));
+    assert!(mapper.dealloc(ptr3));
+}
+
+#[test]
+fn shared_budget() {
+    static SYS: MockSyscalls = MockSyscalls::new(16 * 1024 * 1024);
+    static BUDGET: Budget = Budget::new(4 * 1024 * 1024);
+
+    let ingest = MMapper::with_syscalls(&SYS);
+    let index = MMapper::with_syscalls(&SYS);
+
+    ingest.set_budget(&BUDGET).unwrap();
+    index.set_budget(&BUDGET).unwrap();
+    assert!(index.set_budget(&BUDGET).is_err());
+
+    // Both allocators draw from the budget until it runs out, then map default size pages
+    let ptr1 = ingest.alloc(layout(mb(2)));
+    let ptr2 = index.alloc(layout(mb(2)));
+    assert!(ingest.is_huge_ptr(ptr1));
+    assert!(index.is_huge_ptr(ptr2));
+    assert_eq!(mb(4), BUDGET.used());
+    assert_eq!(0, BUDGET.available());
+
+    let ptr3 = index.alloc(layout(mb(1)));
+    assert!(!index.is_huge_ptr(ptr3));
+    assert_eq!(1, BUDGET.denied());
+
+    // Growing a huge page segment needs more of the budget, so it moves to default size pages
+    let ptr1 = ingest.realloc(ptr1, layout(mb(3)));
+    assert!(!ingest.is_huge_ptr(ptr1));
+    assert_eq!(mb(2), BUDGET.used());
+
+    // Growing in place while the budget has room charges the growth, and shrinking refunds it
+    let ptr2 = index.realloc(ptr2, layout(mb(4)));
+    assert!(index.is_huge_ptr(ptr2));
+    assert_eq!(mb(4), BUDGET.used());
+
+    index.set_shrink_threshold(100);
+    let ptr2 = index.realloc(ptr2, layout(mb(1)));
+    assert!(index.is_huge_ptr(ptr2));
+    assert_eq!(mb(2), BUDGET.used());
+
+    assert!(ingest.dealloc(ptr1));
+    assert!(index.dealloc(ptr2));
+    assert!(index.dealloc(ptr3));
+    assert_eq!(0, BUDGET.used());
+    assert_eq!(0, SYS.mapped());
+}
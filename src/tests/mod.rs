@@ -3,7 +3,7 @@ use super::*;
 #[global_allocator]
 static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
 
-fn mb(mb: usize) -> usize {
+const fn mb(mb: usize) -> usize {
     mb * 1024 * 1024
 }
 
@@ -14,12 +14,8 @@ fn check_stats(desc: &str, expected_segs: usize, expected_mapped: usize) -> Huge
 
     assert_eq!(expected_segs, stats.segments, "{} segments", desc);
 
-    let avail_bytes = if let Ok(env) = std::env::var("TEST_NR_PAGES") {
-        let avail_pages = env.parse::<usize>().expect("TEST_NR_PAGES not numeric");
-        avail_pages * mb(2)
-    } else {
-        0
-    };
+    // Huge pages available to the test are those still free plus those already mapped by it
+    let avail_bytes = huge_page_info(mb(2)).map_or(0, |info| info.free_bytes()) + stats.huge_mapped;
 
     if avail_bytes >= mb(6) {
         // Enough huge pages to satisfy
@@ -59,7 +55,42 @@ fn check_stats_lt(desc: &str, expected_alloc: usize, expected_segs: usize, expec
     assert!(stats.alloc < expected_alloc, "{} alloc", desc);
 }
 
+/// A free address range followed by an inaccessible guard page, so reading past the end of a
+/// segment placed in the range faults
+struct GuardedWindow {
+    window: std::ops::Range<usize>,
+}
+
+impl GuardedWindow {
+    /// Reserves size bytes and a guard page, then frees the size bytes for use as a placement
+    /// window
+    fn new(size: usize) -> Self {
+        unsafe {
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                size + base_page_size(),
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(ptr, libc::MAP_FAILED);
+            assert_eq!(0, libc::munmap(ptr, size));
+
+            Self { window: ptr as usize..ptr as usize + size }
+        }
+    }
+}
+
+impl Drop for GuardedWindow {
+    /// Frees the guard page
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.window.end as *mut libc::c_void, base_page_size()) };
+    }
+}
+
 #[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
 fn huge_alloc() {
     let mut vec = Vec::new();
 
@@ -127,3 +158,199 @@ fn huge_alloc() {
         }
     }
 }
+
+#[test]
+#[cfg(any(miri, feature = "passthrough"))]
+fn passthrough() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let layout = Layout::from_size_align(mb(2), 8).unwrap();
+
+    unsafe {
+        let ptr = allocator.alloc_zeroed(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(*ptr.add(mb(2) - 1), 0);
+
+        let ptr = allocator.realloc(ptr, layout, mb(4));
+        assert!(!ptr.is_null());
+
+        allocator.dealloc(ptr, Layout::from_size_align(mb(4), 8).unwrap());
+    }
+
+    assert_eq!(allocator.stats().unwrap().segments, 0);
+    assert_eq!(allocator.stats_snapshot().segments, 0);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn raw_alloc() {
+    let allocator = HugeGlobalAllocator::new(0);
+    let layout = Layout::from_size_align(mb(1) + 1, 64).unwrap();
+
+    // Mapped regardless of the threshold
+    let block = allocator.alloc_raw(layout).unwrap();
+    assert!(block.len() > layout.size());
+    assert_eq!(block.as_ptr() as *mut u8 as usize % 64, 0);
+
+    // The slack up to the end of the mapping is usable
+    unsafe { (block.as_ptr() as *mut u8).write_bytes(0xa5, block.len()) };
+
+    let stats = allocator.stats().unwrap();
+    assert_eq!(stats.segments, 1);
+    assert_eq!(stats.mapped, block.len());
+
+    unsafe { allocator.dealloc_raw(block) };
+    assert_eq!(allocator.stats().unwrap().segments, 0);
+
+    assert!(allocator.alloc_raw(Layout::from_size_align(0, 8).unwrap()).is_none());
+
+    // Alignments above the page size are honoured
+    let block = allocator.alloc_raw(Layout::from_size_align(mb(1), mb(4)).unwrap()).unwrap();
+    assert_eq!(block.as_ptr() as *mut u8 as usize % mb(4), 0);
+    assert_eq!(allocator.stats().unwrap().mapped, block.len());
+    unsafe { allocator.dealloc_raw(block) };
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn raw_realloc() {
+    let allocator = HugeGlobalAllocator::new(0);
+
+    let mut block = allocator.alloc_raw(Layout::from_size_align(mb(1), 8).unwrap()).unwrap();
+    unsafe { (block.as_ptr() as *mut u8).write_bytes(0xa5, mb(1)) };
+
+    // Growing to a larger alignment keeps the contents, however the pages are moved
+    for (align, strategy) in [(mb(2), MoveStrategy::Mremap), (mb(8), MoveStrategy::DontUnmap), (64, MoveStrategy::Memcpy)] {
+        allocator.set_move_strategy(strategy);
+
+        let layout = Layout::from_size_align(mb(3), align).unwrap();
+        block = unsafe { allocator.realloc_raw(block, layout) }.unwrap();
+
+        assert_eq!(block.as_ptr() as *mut u8 as usize % align, 0);
+        assert!(block.len() >= layout.size());
+
+        let contents = unsafe { std::slice::from_raw_parts(block.as_ptr() as *const u8, mb(1)) };
+        assert!(contents.iter().all(|&b| b == 0xa5));
+    }
+
+    assert_eq!(allocator.stats().unwrap().segments, 1);
+
+    assert!(unsafe { allocator.realloc_raw(block, Layout::from_size_align(0, 8).unwrap()) }.is_none());
+
+    unsafe { allocator.dealloc_raw(block) };
+    assert_eq!(allocator.stats().unwrap().segments, 0);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn alloc_with_realloc() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let guarded = GuardedWindow::new(64 * 1024);
+    let layout = Layout::from_size_align(64 * 1024, 8).unwrap();
+
+    allocator.set_placement_window(Some(guarded.window.clone())).unwrap();
+
+    // Mapped below the threshold
+    let ptr = allocator.alloc_with(PageSizePref::Base, layout);
+    assert!(allocator.is_managed(ptr));
+
+    unsafe {
+        ptr.write_bytes(0xa5, layout.size());
+
+        // Growing below the threshold moves the block to the inner allocator
+        let ptr = allocator.realloc(ptr, layout, mb(1) / 2);
+        assert!(!allocator.is_managed(ptr));
+        assert!(std::slice::from_raw_parts(ptr, layout.size()).iter().all(|&b| b == 0xa5));
+
+        allocator.dealloc(ptr, Layout::from_size_align(mb(1) / 2, 8).unwrap());
+    }
+
+    assert_eq!(0, allocator.stats().unwrap().segments);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn placement_window() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let window = 0x5000_0000_0000..0x5000_4000_0000;
+
+    assert!(allocator.set_placement_window(Some(0x1000..0x1000)).is_err());
+    allocator.set_placement_window(Some(window.clone())).unwrap();
+
+    let layouts = [mb(2), mb(5), mb(16)].map(|size| Layout::from_size_align(size, 8).unwrap());
+    let ptrs = layouts.map(|layout| unsafe { allocator.alloc(layout) });
+
+    for ptr in ptrs {
+        assert!(window.contains(&(ptr as usize)));
+    }
+
+    #[cfg(not(feature = "no-stats"))]
+    assert_eq!(allocator.stats().unwrap().randomized_placements, 3);
+
+    for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // Back to the kernel's placement
+    allocator.set_placement_window(None).unwrap();
+    let ptr = unsafe { allocator.alloc(layouts[0]) };
+    assert!(!window.contains(&(ptr as usize)));
+    unsafe { allocator.dealloc(ptr, layouts[0]) };
+}
+
+mod arena;
+mod budget;
+mod buffer;
+mod callsite;
+mod capacity;
+#[cfg(feature = "canary")]
+mod canary;
+mod const_threshold;
+mod counters;
+mod ctl;
+mod csv;
+mod diagnose;
+#[cfg(all(feature = "event-log", not(feature = "passthrough")))]
+mod events;
+#[cfg(all(feature = "fault-injection", not(feature = "passthrough")))]
+mod fault;
+#[cfg(all(feature = "c-stats", not(feature = "passthrough")))]
+mod ffi;
+#[cfg(not(feature = "passthrough"))]
+mod fork;
+#[cfg(all(feature = "heaptrack", not(feature = "passthrough")))]
+mod heaptrack;
+#[cfg(feature = "debug-http")]
+mod http;
+#[cfg(all(feature = "bytes", not(feature = "passthrough")))]
+mod huge_bytes;
+mod inner;
+#[cfg(feature = "jit")]
+mod jit;
+mod local;
+mod maintenance;
+#[cfg(all(feature = "malloc-shim", target_env = "gnu", not(feature = "passthrough")))]
+mod malloc;
+mod mapper;
+mod numa;
+#[cfg(feature = "otel")]
+mod otel;
+mod overcommit;
+mod pagesize;
+mod pkey;
+mod policy;
+#[cfg(all(feature = "preload", target_env = "gnu", not(feature = "passthrough")))]
+mod preload;
+mod raw;
+mod registry;
+#[cfg(all(feature = "event-ring", not(feature = "passthrough")))]
+mod ring;
+mod segmap;
+#[cfg(feature = "shared-segments")]
+mod shared;
+mod snapshot;
+mod sync;
+mod thp;
+#[cfg(all(feature = "tracy", not(feature = "passthrough")))]
+mod tracy;
+#[cfg(any(debug_assertions, feature = "verify"))]
+mod verify;
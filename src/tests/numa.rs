@@ -0,0 +1,26 @@
+use crate::numa::{current_node, node_count, running_node, shard_for};
+
+#[test]
+fn node_shards() {
+    // A single node spreads threads over every shard
+    assert_eq!((0..16).collect::<Vec<_>>(), (0..16).map(|thread| shard_for(0, 1, thread, 16)).collect::<Vec<_>>());
+
+    // Two nodes get half the shards each
+    assert_eq!(vec![0, 1, 7, 0], [0, 1, 7, 8].map(|thread| shard_for(0, 2, thread, 16)).to_vec());
+    assert_eq!(vec![8, 9, 15, 8], [0, 1, 7, 8].map(|thread| shard_for(1, 2, thread, 16)).to_vec());
+
+    // Nodes share groups when there are more nodes than shards
+    assert_eq!(shard_for(1, 32, 5, 16), shard_for(17, 32, 5, 16));
+    assert!((0..64).all(|node| shard_for(node, 64, node, 16) < 16));
+
+    // Three nodes leave a shard unused rather than overlapping
+    assert_eq!(14, shard_for(2, 3, 4, 16));
+}
+
+#[test]
+fn current() {
+    assert!(node_count() >= 1);
+
+    let node = running_node();
+    assert_eq!(node, current_node());
+}
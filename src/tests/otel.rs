@@ -0,0 +1,118 @@
+use std::sync::{Arc, Mutex};
+
+use opentelemetry::{
+    metrics::{
+        AsyncInstrument, AsyncInstrumentBuilder, Callback, InstrumentProvider, Meter, ObservableCounter, ObservableGauge,
+    },
+    KeyValue,
+};
+
+use super::*;
+use crate::otel::names;
+
+/// Records the last value observed by an instrument's callbacks
+struct Observation(Mutex<Option<f64>>);
+
+impl AsyncInstrument<f64> for Observation {
+    fn observe(&self, measurement: f64, _attributes: &[KeyValue]) {
+        *self.0.lock().unwrap() = Some(measurement);
+    }
+}
+
+/// Collects the value of an instrument by running its callbacks
+type Collector = Box<dyn Fn() -> Option<f64> + Send + Sync>;
+
+/// Instrument provider which keeps the callbacks of each observable instrument so the test can
+/// collect them without an SDK
+#[derive(Default)]
+struct Provider {
+    instruments: Mutex<Vec<(String, String, Collector)>>,
+}
+
+impl Provider {
+    fn add(&self, name: &str, unit: Option<&str>, callbacks: Vec<Callback<f64>>) {
+        let collect = move || {
+            let observation = Observation(Mutex::new(None));
+            callbacks.iter().for_each(|callback| callback(&observation));
+            observation.0.into_inner().unwrap()
+        };
+
+        let unit = unit.unwrap_or_default().to_string();
+        self.instruments.lock().unwrap().push((name.to_string(), unit, Box::new(collect)));
+    }
+
+    fn collect(&self, name: &str) -> f64 {
+        let instruments = self.instruments.lock().unwrap();
+        let (_, _, collect) = instruments.iter().find(|(n, _, _)| n == name).unwrap();
+        collect().unwrap()
+    }
+}
+
+impl InstrumentProvider for Provider {
+    fn u64_observable_counter(&self, b: AsyncInstrumentBuilder<'_, ObservableCounter<u64>, u64>) -> ObservableCounter<u64> {
+        self.add(&b.name, b.unit.as_deref(), b.callbacks.into_iter().map(to_f64).collect());
+        ObservableCounter::new()
+    }
+
+    fn f64_observable_counter(&self, b: AsyncInstrumentBuilder<'_, ObservableCounter<f64>, f64>) -> ObservableCounter<f64> {
+        self.add(&b.name, b.unit.as_deref(), b.callbacks);
+        ObservableCounter::new()
+    }
+
+    fn u64_observable_gauge(&self, b: AsyncInstrumentBuilder<'_, ObservableGauge<u64>, u64>) -> ObservableGauge<u64> {
+        self.add(&b.name, b.unit.as_deref(), b.callbacks.into_iter().map(to_f64).collect());
+        ObservableGauge::new()
+    }
+
+    fn f64_observable_gauge(&self, b: AsyncInstrumentBuilder<'_, ObservableGauge<f64>, f64>) -> ObservableGauge<f64> {
+        self.add(&b.name, b.unit.as_deref(), b.callbacks);
+        ObservableGauge::new()
+    }
+}
+
+/// Adapts a u64 callback to observe f64 values
+fn to_f64(callback: Callback<u64>) -> Callback<f64> {
+    struct Adapter<'a>(&'a dyn AsyncInstrument<f64>);
+
+    impl AsyncInstrument<u64> for Adapter<'_> {
+        fn observe(&self, measurement: u64, attributes: &[KeyValue]) {
+            self.0.observe(measurement as f64, attributes);
+        }
+    }
+
+    Box::new(move |observer| callback(&Adapter(observer)))
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn register_metrics() {
+    static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(mb(1));
+
+    let provider = Arc::new(Provider::default());
+    ALLOCATOR.register_metrics(&Meter::new(provider.clone()));
+
+    let registered: Vec<String> = provider.instruments.lock().unwrap().iter().map(|(name, _, _)| name.clone()).collect();
+    assert_eq!(names().collect::<Vec<_>>(), registered);
+
+    for (name, unit, _) in provider.instruments.lock().unwrap().iter() {
+        assert!(name.starts_with("huge_global_alloc."), "{}", name);
+        assert!(!unit.is_empty(), "{} unit", name);
+    }
+
+    assert_eq!(0.0, provider.collect("huge_global_alloc.segments"));
+
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+
+    // Values are read when collected
+    assert_eq!(1.0, provider.collect("huge_global_alloc.segments"));
+    assert_eq!(mb(1) as f64, provider.collect("huge_global_alloc.alloc"));
+    assert!(provider.collect("huge_global_alloc.allocs.rate") > 0.0);
+
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+
+    assert_eq!(0.0, provider.collect("huge_global_alloc.segments"));
+    assert_eq!(0.0, provider.collect("huge_global_alloc.unmaps.failed"));
+}
@@ -0,0 +1,29 @@
+use super::*;
+use crate::overcommit::{parse_meminfo, parse_mode};
+
+#[test]
+fn parsers() {
+    assert_eq!(Some(OvercommitMode::Heuristic), parse_mode("0\n"));
+    assert_eq!(Some(OvercommitMode::Always), parse_mode("1\n"));
+    assert_eq!(Some(OvercommitMode::Never), parse_mode("2\n"));
+    assert_eq!(None, parse_mode("3\n"));
+
+    let meminfo = "MemTotal:       16318412 kB\nCommitLimit:     8159204 kB\nCommitted_AS:   12345678 kB\n";
+    assert_eq!(Some(8159204 * 1024), parse_meminfo(meminfo, "CommitLimit"));
+    assert_eq!(Some(12345678 * 1024), parse_meminfo(meminfo, "Committed_AS"));
+    assert_eq!(None, parse_meminfo(meminfo, "Commit"));
+    assert_eq!(None, parse_meminfo("", "CommitLimit"));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn adapt() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    let report = allocator.adapt_to_overcommit();
+    assert!(report.to_string().contains("overcommit:"), "{report}");
+    eprint!("{report}");
+
+    let strict = report.overcommit.is_some_and(|overcommit| overcommit.mode == OvercommitMode::Never);
+    assert_eq!(strict, allocator.ctl_read("strict_overcommit").unwrap() == "true");
+}
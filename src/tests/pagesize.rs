@@ -0,0 +1,33 @@
+use super::*;
+use crate::pagesize::huge_size_flags;
+
+#[test]
+fn size_flags() {
+    assert_eq!(libc::MAP_HUGE_2MB, huge_size_flags(mb(2)));
+    assert_eq!(libc::MAP_HUGE_1GB, huge_size_flags(1024 * mb(1)));
+    assert_eq!(libc::MFD_HUGE_2MB as i32, huge_size_flags(mb(2)));
+}
+
+// Successful overrides are process wide and only possible before the sizes are read, so only the
+// defaults and rejected values are tested here
+#[test]
+fn overrides() {
+    assert_eq!(DEFAULT_HUGE_PAGE_SIZE, huge_page_size());
+    assert_eq!(unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize, base_page_size());
+
+    assert!(set_base_page_size(3000).is_err());
+    assert!(set_base_page_size(base_page_size() / 2).is_err());
+    assert!(set_base_page_size(huge_page_size()).is_err());
+    assert!(set_huge_page_size(base_page_size()).is_err());
+    assert!(set_huge_page_size(mb(3)).is_err());
+
+    // Valid sizes are refused once the sizes have been read
+    assert!(set_huge_page_size(1024 * mb(1)).is_err());
+    assert!(set_base_page_size(base_page_size() * 2).is_err());
+
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    assert!(allocator.ctl("page_size.huge", "1g").is_err());
+    assert!(allocator.ctl_read("page_size.base").is_err());
+
+    assert_eq!(DEFAULT_HUGE_PAGE_SIZE, huge_page_size());
+}
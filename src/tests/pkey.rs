@@ -0,0 +1,74 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::fs;
+
+use super::mb;
+use crate::{HugeGlobalAllocator, KeyAccess, ProtectionKey};
+
+/// Returns the protection key of the mapping containing addr from /proc/self/smaps
+fn smaps_pkey(addr: usize) -> Option<i32> {
+    let smaps = fs::read_to_string("/proc/self/smaps").ok()?;
+    let mut in_mapping = false;
+
+    for line in smaps.lines() {
+        if let Some((range, _)) = line.split_once(' ') {
+            if let Some((start, end)) = range.split_once('-') {
+                if let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) {
+                    in_mapping = (start..end).contains(&addr);
+                    continue;
+                }
+            }
+        }
+
+        if in_mapping {
+            if let Some(pkey) = line.strip_prefix("ProtectionKey:") {
+                return pkey.trim().parse().ok();
+            }
+        }
+    }
+
+    None
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn protection_key() {
+    let Ok(key) = ProtectionKey::alloc(KeyAccess::ReadWrite) else {
+        // No CPU or kernel support
+        return;
+    };
+
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let layout = Layout::from_size_align(mb(2), 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+
+    assert!(allocator.set_protection_key(ptr, Some(key)).is_ok());
+    assert_eq!(Some(key.as_raw()), smaps_pkey(ptr as usize));
+
+    unsafe { ptr.write(42) };
+
+    key.set_access(KeyAccess::ReadOnly);
+    assert_eq!(KeyAccess::ReadOnly, key.access());
+    assert_eq!(42, unsafe { ptr.read_volatile() });
+
+    key.set_access(KeyAccess::NoAccess);
+    assert_eq!(KeyAccess::NoAccess, key.access());
+
+    // Growing keeps the key
+    key.set_access(KeyAccess::ReadWrite);
+    assert_eq!(KeyAccess::ReadWrite, key.access());
+
+    let ptr = unsafe { allocator.realloc(ptr, layout, mb(6)) };
+    let layout = Layout::from_size_align(mb(6), 8).unwrap();
+
+    assert_eq!(42, unsafe { ptr.read() });
+    assert_eq!(Some(key.as_raw()), smaps_pkey(ptr as usize + mb(5)));
+
+    // Back to the default key
+    assert!(allocator.set_protection_key(ptr, None).is_ok());
+    assert_eq!(Some(0), smaps_pkey(ptr as usize));
+
+    unsafe { allocator.dealloc(ptr, layout) };
+    key.free().unwrap();
+
+    assert!(allocator.set_protection_key(ptr, None).is_err());
+}
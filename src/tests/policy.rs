@@ -0,0 +1,184 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+
+use super::*;
+
+/// Page size policy recording the layouts it is asked about
+#[derive(Default)]
+struct Recording {
+    layouts: Mutex<Vec<Layout>>,
+}
+
+impl PagePolicy for Recording {
+    fn page_size(&self, layout: Layout) -> PageSizePref {
+        self.layouts.lock().unwrap().push(layout);
+
+        PageSizePref::Base
+    }
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 64).unwrap()
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn consulted() {
+    let allocator = HugeGlobalAllocator::with_policy(mb(1), System, Recording::default());
+
+    unsafe {
+        // Allocations below the threshold don't reach the policy
+        let small = allocator.alloc(layout(1024));
+        assert!(allocator.policy().layouts.lock().unwrap().is_empty());
+
+        let big = allocator.alloc_zeroed(layout(mb(2)));
+        assert!(allocator.is_managed(big));
+        assert!(!allocator.is_huge(big));
+
+        // Growing in to the mapper asks the policy, growing a segment doesn't
+        let small = allocator.realloc(small, layout(1024), mb(1));
+        assert!(allocator.is_managed(small));
+
+        let big = allocator.realloc(big, layout(mb(2)), mb(8));
+        assert!(!allocator.is_huge(big));
+
+        assert_eq!(vec![layout(mb(2)), layout(mb(1))], *allocator.policy().layouts.lock().unwrap());
+
+        allocator.dealloc(small, layout(mb(1)));
+        allocator.dealloc(big, layout(mb(8)));
+    }
+}
+
+#[test]
+fn default_policy() {
+    let allocator: HugeGlobalAllocator = HugeGlobalAllocator::default();
+    assert_eq!(&DefaultPagePolicy, allocator.policy());
+
+    assert_eq!(PageSizePref::Policy, DefaultPagePolicy.page_size(Layout::new::<u8>()));
+    assert_eq!(PageSizePref::Huge, DefaultPagePolicy.page_size(Layout::from_size_align(4096, HUGE_ALIGNMENT).unwrap()));
+}
+
+/// Maps big or huge page aligned allocations, except on threads tagged "gc"
+struct NotOnGc;
+
+impl PagePolicy for NotOnGc {
+    fn maps(&self, layout: Layout, threshold: usize) -> bool {
+        thread_tag() != Some("gc") && (layout.size() >= threshold || layout.align() >= mb(2))
+    }
+}
+
+#[test]
+fn maps() {
+    let aligned = Layout::from_size_align(4096, mb(2)).unwrap();
+
+    // The default maps on size or huge page alignment, and nothing with a threshold of zero
+    assert!(DefaultPagePolicy.maps(layout(mb(1)), mb(1)));
+    assert!(!DefaultPagePolicy.maps(layout(mb(1) - 1), mb(1)));
+    assert!(DefaultPagePolicy.maps(aligned, mb(1)));
+    assert!(!DefaultPagePolicy.maps(Layout::from_size_align(4096, mb(1)).unwrap(), mb(1)));
+    assert!(!DefaultPagePolicy.maps(layout(mb(1)), 0));
+    assert!(!DefaultPagePolicy.maps(aligned, 0));
+
+    assert!(NotOnGc.maps(aligned, mb(1)));
+
+    std::thread::spawn(move || {
+        assert_eq!(None, set_thread_tag(Some("gc")));
+        assert!(!NotOnGc.maps(aligned, mb(1)));
+        assert!(!NotOnGc.maps(layout(mb(4)), mb(1)));
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn maps_by_layout() {
+    let allocator = HugeGlobalAllocator::with_policy(mb(1), System, NotOnGc);
+    let aligned = Layout::from_size_align(4096, mb(2)).unwrap();
+
+    unsafe {
+        let ptr = allocator.alloc(aligned);
+        assert!(allocator.is_managed(ptr));
+        assert_eq!(0, ptr.align_offset(mb(2)));
+
+        // The thread override is the threshold passed to the policy
+        let small = with_thread_config(|config| config.threshold = Some(1024), || allocator.alloc(layout(1024)));
+        assert!(allocator.is_managed(small));
+
+        allocator.dealloc(ptr, aligned);
+        allocator.dealloc(small, layout(1024));
+    }
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn grow_out_of_mapper() {
+    let allocator = HugeGlobalAllocator::with_policy(64 * 1024, System, NotOnGc);
+    let guarded = GuardedWindow::new(64 * 1024);
+
+    allocator.set_placement_window(Some(guarded.window.clone())).unwrap();
+
+    unsafe {
+        let ptr = allocator.alloc(layout(64 * 1024));
+        assert!(allocator.is_managed(ptr));
+        ptr.write_bytes(0xa5, 64 * 1024);
+
+        // The policy sends the grown block to the inner allocator, copying only the old block
+        let ptr = with_thread_config(|config| config.tag = Some("gc"), || allocator.realloc(ptr, layout(64 * 1024), mb(1)));
+        assert!(!allocator.is_managed(ptr));
+        assert!(std::slice::from_raw_parts(ptr, 64 * 1024).iter().all(|&b| b == 0xa5));
+
+        allocator.dealloc(ptr, layout(mb(1)));
+    }
+
+    assert_eq!(0, allocator.stats().unwrap().segments);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn shrink_in_to_mapper() {
+    let allocator = HugeGlobalAllocator::with_policy(64 * 1024, System, NotOnGc);
+    let guarded = GuardedWindow::new(64 * 1024);
+
+    allocator.set_placement_window(Some(guarded.window.clone())).unwrap();
+
+    unsafe {
+        let ptr = with_thread_config(|config| config.tag = Some("gc"), || allocator.alloc(layout(mb(1))));
+        assert!(!allocator.is_managed(ptr));
+        ptr.write_bytes(0xa5, mb(1));
+
+        // Once the tag changes the policy sends the shrunk block to the mapper, copying only the
+        // new block
+        let ptr = allocator.realloc(ptr, layout(mb(1)), 64 * 1024);
+        assert!(allocator.is_managed(ptr));
+        assert!(std::slice::from_raw_parts(ptr, 64 * 1024).iter().all(|&b| b == 0xa5));
+
+        allocator.dealloc(ptr, layout(64 * 1024));
+    }
+
+    assert_eq!(0, allocator.stats().unwrap().segments);
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+fn huge_alignment() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let aligned = Layout::from_size_align(4096, HUGE_ALIGNMENT).unwrap();
+
+    unsafe {
+        // Mapped below the threshold
+        let ptr = allocator.alloc(aligned);
+        assert!(allocator.is_managed(ptr));
+        assert_eq!(0, ptr.align_offset(HUGE_ALIGNMENT));
+
+        allocator.dealloc(ptr, aligned);
+    }
+
+    let allocator: HugeGlobalAllocatorConst = HugeGlobalAllocatorConst::new();
+
+    unsafe {
+        let ptr = allocator.alloc(aligned);
+        assert!(allocator.is_managed(ptr));
+        allocator.dealloc(ptr, aligned);
+    }
+}
@@ -0,0 +1,20 @@
+use std::ffi::CStr;
+
+use super::*;
+use crate::preload::configure;
+
+#[test]
+fn configure_from_env() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+
+    configure(&allocator, |name: &CStr| match name.to_bytes() {
+        b"HUGE_ALLOC_THRESHOLD" => Some("4m"),
+        b"HUGE_ALLOC_SOFT_LIMIT" => Some("bogus"),
+        b"HUGE_ALLOC_UNMAP_FAILURE" => Some("log"),
+        _ => None,
+    });
+
+    assert_eq!(mb(4), allocator.threshold());
+    assert_eq!("0", allocator.ctl_read("soft_limit").unwrap());
+    assert_eq!("log", allocator.ctl_read("unmap_failure").unwrap());
+}
@@ -0,0 +1,49 @@
+use nix::errno::Errno;
+
+use super::mb;
+use crate::raw;
+
+#[test]
+fn map_default() {
+    let page_size = raw::page_size();
+
+    let region = raw::map_default(mb(1) + 1).unwrap();
+    assert_eq!(raw::round_to_pages(mb(1) + 1, page_size), region.len());
+    assert_eq!(0, region.as_ptr() as *mut u8 as usize % page_size);
+
+    unsafe {
+        let ptr = region.as_ptr() as *mut u8;
+        ptr.write_bytes(0xa5, region.len());
+
+        // Grows keeping the contents
+        let region = raw::remap(region, mb(4), page_size).unwrap();
+        assert_eq!(mb(4), region.len());
+
+        let ptr = region.as_ptr() as *mut u8;
+        assert_eq!(0xa5, *ptr);
+        assert_eq!(0xa5, *ptr.add(mb(1)));
+
+        // Discarded pages read back as zero
+        raw::advise(region, libc::MADV_DONTNEED).unwrap();
+        assert_eq!(0, *ptr);
+
+        assert_eq!(Err(Errno::EINVAL), raw::remap(region, 0, page_size));
+
+        raw::unmap(region).unwrap();
+    }
+
+    assert_eq!(Err(Errno::EINVAL), raw::map_default(0));
+}
+
+#[test]
+fn map_huge() {
+    match raw::map_huge(mb(3)) {
+        Ok(region) => {
+            assert_eq!(mb(4), region.len());
+            assert_eq!(0, region.as_ptr() as *mut u8 as usize % raw::HUGE_PAGE_SIZE);
+
+            unsafe { raw::unmap(region) }.unwrap();
+        }
+        Err(err) => assert_eq!(Errno::ENOMEM, err),
+    }
+}
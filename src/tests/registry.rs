@@ -0,0 +1,82 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::time::Duration;
+
+use super::*;
+
+static INGEST: HugeGlobalAllocator = HugeGlobalAllocator::new(1024 * 1024);
+static INDEX: HugeGlobalAllocatorConst<{ 1024 * 1024 }> = HugeGlobalAllocatorConst::new();
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+#[cfg_attr(any(miri, feature = "passthrough"), ignore)]
+#[cfg_attr(feature = "no-stats", ignore = "statistics are compiled out")]
+fn named() {
+    assert_eq!(None, INGEST.name());
+
+    INGEST.register("ingest").unwrap();
+    INDEX.register("index").unwrap();
+
+    // Neither the allocator nor the name can be registered twice
+    assert!(INGEST.register("ingest2").is_err());
+    assert!(INDEX.register("ingest").is_err());
+
+    assert_eq!(Some("ingest"), INGEST.name());
+    assert_eq!(Some("index"), INDEX.name());
+
+    unsafe {
+        let ingest = INGEST.alloc(layout(mb(2)));
+        let index = INDEX.alloc(layout(mb(1)));
+
+        let stats = registered_stats();
+        let names: Vec<&str> = stats.iter().map(|(name, _)| *name).collect();
+        assert_eq!(vec!["ingest", "index"], names);
+
+        // Each allocator's statistics are kept apart, and the merged totals cover both
+        assert_eq!(mb(2), stats[0].1.alloc);
+        assert_eq!(mb(1), stats[1].1.alloc);
+        assert_eq!(2, merged_stats().segments);
+        assert_eq!(mb(3), merged_stats().alloc);
+
+        INGEST.dealloc(ingest, layout(mb(2)));
+        INDEX.dealloc(index, layout(mb(1)));
+    }
+}
+
+#[test]
+fn merge() {
+    let mut merged = HugeGlobalAllocatorStats::default();
+
+    let ingest = HugeGlobalAllocatorStats {
+        alloc: mb(3),
+        mapped: mb(4),
+        segments: 2,
+        pressure: 80,
+        prefault_max_time: Duration::from_millis(5),
+        mte_tag_faults: 1,
+        ..Default::default()
+    };
+
+    let index = HugeGlobalAllocatorStats {
+        alloc: mb(1),
+        mapped: mb(4),
+        segments: 1,
+        pressure: 20,
+        prefault_max_time: Duration::from_millis(2),
+        mte_tag_faults: 1,
+        ..Default::default()
+    };
+
+    merged.merge(&ingest);
+    merged.merge(&index);
+
+    assert_eq!(mb(4), merged.alloc);
+    assert_eq!(mb(8), merged.mapped);
+    assert_eq!(3, merged.segments);
+    assert_eq!(50, merged.efficiency);
+    assert_eq!(80, merged.pressure);
+    assert_eq!(Duration::from_millis(5), merged.prefault_max_time);
+    assert_eq!(1, merged.mte_tag_faults);
+}
@@ -0,0 +1,144 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use super::*;
+use crate::events::Operation;
+use crate::ring::{RingEvent, CAPACITY};
+
+fn layout(mb: usize) -> Layout {
+    Layout::from_size_align(super::mb(mb), 8).unwrap()
+}
+
+fn drain(allocator: &HugeGlobalAllocator) -> Vec<RingEvent> {
+    let mut events = Vec::new();
+    allocator.event_ring().drain(|event| events.push(event));
+    events
+}
+
+#[test]
+fn realloc_addresses() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    allocator.event_ring().set_enabled(true);
+
+    let (ptr, new_ptr) = unsafe {
+        let ptr = allocator.alloc(layout(1));
+        let new_ptr = allocator.realloc(ptr, layout(1), mb(4));
+        allocator.dealloc(new_ptr, layout(4));
+        (ptr as usize, new_ptr as usize)
+    };
+
+    let ring_events = drain(&allocator);
+
+    // The events of one allocation share its id
+    assert!(ring_events.iter().all(|e| e.id == ring_events[0].id));
+
+    let events: Vec<(Operation, usize, usize, usize, usize)> =
+        ring_events.iter().map(|e| (e.operation, e.old_address, e.old_size, e.address, e.size)).collect();
+
+    assert_eq!(
+        events,
+        vec![
+            (Operation::Alloc, 0, 0, ptr, mb(1)),
+            (Operation::Realloc, ptr, mb(1), new_ptr, mb(4)),
+            (Operation::Dealloc, new_ptr, mb(4), 0, 0),
+        ]
+    );
+
+    assert!(allocator.event_ring().pop().is_none());
+}
+
+#[test]
+fn disabled() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    assert!(!allocator.event_ring().is_enabled());
+
+    unsafe {
+        let ptr = allocator.alloc(layout(2));
+        allocator.dealloc(ptr, layout(2));
+    }
+
+    assert!(drain(&allocator).is_empty());
+}
+
+#[test]
+fn full() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    allocator.event_ring().set_enabled(true);
+
+    let ops = CAPACITY / 2 + 10;
+
+    for _ in 0..ops {
+        unsafe {
+            let ptr = allocator.alloc(layout(1));
+            allocator.dealloc(ptr, layout(1));
+        }
+    }
+
+    // Events beyond the capacity are dropped, keeping the oldest
+    assert_eq!(20, allocator.event_ring().dropped());
+
+    let events = drain(&allocator);
+    assert_eq!(CAPACITY, events.len());
+    assert!(events.windows(2).all(|pair| pair[0].timestamp_ns <= pair[1].timestamp_ns));
+
+    // Space is reclaimed once drained
+    unsafe {
+        let ptr = allocator.alloc(layout(1));
+        allocator.dealloc(ptr, layout(1));
+    }
+
+    assert_eq!(2, drain(&allocator).len());
+}
+
+#[test]
+fn concurrent() {
+    static ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(mb(1));
+    ALLOCATOR.event_ring().set_enabled(true);
+
+    const THREADS: usize = 4;
+    const OPS: usize = 500;
+
+    let done = AtomicBool::new(false);
+
+    let (allocs, deallocs) = thread::scope(|scope| {
+        let consumer = scope.spawn(|| {
+            let (mut allocs, mut deallocs) = (0, 0);
+
+            loop {
+                let finished = done.load(Ordering::Acquire);
+
+                ALLOCATOR.event_ring().drain(|event| match event.operation {
+                    Operation::Alloc => allocs += 1,
+                    Operation::Dealloc => deallocs += 1,
+                    Operation::Realloc => panic!("unexpected realloc"),
+                });
+
+                if finished {
+                    break (allocs, deallocs);
+                }
+            }
+        });
+
+        let producers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                scope.spawn(|| {
+                    for _ in 0..OPS {
+                        unsafe {
+                            let ptr = ALLOCATOR.alloc(layout(1));
+                            ALLOCATOR.dealloc(ptr, layout(1));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        producers.into_iter().for_each(|producer| producer.join().unwrap());
+        done.store(true, Ordering::Release);
+
+        consumer.join().unwrap()
+    });
+
+    // Every event is either consumed or counted as dropped
+    assert_eq!(2 * THREADS * OPS, allocs + deallocs + ALLOCATOR.event_ring().dropped() as usize);
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use super::*;
+use crate::mmap::MMap;
+use crate::segmap::{FixedTable, OrderedMap, PtrMap, SegmentMap};
+use crate::sys::LinuxSyscalls;
+use crate::MapStrategy;
+
+/// Maps a one page segment
+fn segment() -> MMap {
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+
+    MMap::map_default(&LinuxSyscalls, layout, None, false, false, false).unwrap()
+}
+
+/// Inserts, finds, replaces, iterates and removes segments keyed by their addresses
+fn exercise(mut map: impl SegmentMap) {
+    let segments: Vec<MMap> = (0..3).map(|_| segment()).collect();
+    let mut keys: Vec<usize> = segments.iter().map(MMap::ptr).collect();
+
+    for mmap in segments {
+        assert!(map.insert(mmap.ptr(), mmap).is_none());
+    }
+
+    assert_eq!(3, map.len());
+    assert!(map.capacity() >= 3);
+
+    for key in &keys {
+        assert_eq!(Some(*key), map.get_mut(key).map(|mmap| mmap.ptr()));
+    }
+
+    // Inserting at a recorded address returns the segment replaced
+    let replacement = segment();
+    let replacement_ptr = replacement.ptr();
+    assert_eq!(Some(keys[0]), map.insert(keys[0], replacement).map(|mmap| mmap.ptr()));
+    assert_eq!(Some(replacement_ptr), map.get_mut(&keys[0]).map(|mmap| mmap.ptr()));
+    assert_eq!(3, map.len());
+
+    let mut iterated: Vec<usize> = map.iter().map(|(key, _)| *key).collect();
+    iterated.sort_unstable();
+    keys.sort_unstable();
+    assert_eq!(keys, iterated);
+    assert_eq!(3, map.values().count());
+
+    assert_eq!(Some(keys[1]), map.remove(&keys[1]).map(|mmap| mmap.ptr()));
+    assert!(map.remove(&keys[1]).is_none());
+    assert!(map.get_mut(&keys[1]).is_none());
+    assert_eq!(2, map.len());
+
+    let mut drained = Vec::new();
+    map.drain_into(|key, _| drained.push(key));
+    drained.sort_unstable();
+    assert_eq!(vec![keys[0], keys[2]], drained);
+
+    assert_eq!(0, map.len());
+    assert_eq!(0, map.iter().count());
+}
+
+#[test]
+fn strategies() {
+    exercise(HashMap::with_capacity(4));
+    exercise(OrderedMap::with_capacity(4));
+    exercise(FixedTable::with_capacity(4));
+
+    for strategy in [MapStrategy::Hash, MapStrategy::Ordered, MapStrategy::Fixed(8)] {
+        exercise(PtrMap::with_capacity(strategy, 4));
+    }
+}
+
+#[test]
+fn ordered_iteration() {
+    let mut map = OrderedMap::with_capacity(8);
+
+    for key in [5, 1, 4, 2, 3].map(|page| page << 12) {
+        map.insert(key, segment());
+    }
+
+    assert_eq!(vec![1, 2, 3, 4, 5], map.iter().map(|(key, _)| key >> 12).collect::<Vec<_>>());
+}
+
+#[test]
+fn fixed_removal() {
+    // A full table of neighbouring pages collides, so removals shift entries back in to the hole.
+    // Every other entry must still be found whichever is removed first.
+    for first in 0..8 {
+        let mut map = FixedTable::with_capacity(8);
+        let mut model = HashMap::new();
+
+        for page in 0..8 {
+            let mmap = segment();
+            model.insert(page << 12, mmap.ptr());
+            map.insert(page << 12, mmap);
+        }
+
+        assert_eq!(8, map.capacity());
+
+        for page in (first..first + 8).map(|page| page % 8) {
+            assert_eq!(model.remove(&(page << 12)), map.remove(&(page << 12)).map(|mmap| mmap.ptr()));
+
+            for (key, ptr) in &model {
+                assert_eq!(Some(*ptr), map.get_mut(key).map(|mmap| mmap.ptr()));
+            }
+        }
+
+        assert_eq!(0, map.len());
+    }
+}
+
+#[test]
+fn fixed_capacity() {
+    // Sized for the larger of the requested capacity and the configured segments
+    let map = PtrMap::with_capacity(MapStrategy::Fixed(100), 10);
+    assert!(map.capacity() >= 100);
+    assert!(map.is(MapStrategy::Fixed(1)));
+    assert!(!map.is(MapStrategy::Hash));
+
+    assert!(PtrMap::with_capacity(MapStrategy::Fixed(0), 10).capacity() >= 10);
+}
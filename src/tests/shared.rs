@@ -0,0 +1,129 @@
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::thread;
+
+use super::*;
+use crate::fdpass::{recv_segment, send_segment};
+use crate::shared::{Access, SharedSegment};
+
+fn segment_name(test: &str) -> String {
+    format!("test_{}_{test}", std::process::id())
+}
+
+#[test]
+fn share_segment() {
+    let (producer, consumer) = UnixStream::pair().unwrap();
+    let layout = Layout::from_size_align(mb(3), 64).unwrap();
+
+    let segment = SharedSegment::new(layout).unwrap();
+    assert_eq!(segment.layout(), layout);
+    assert!(segment.alloc_size() >= mb(3));
+    assert_eq!(segment.alloc_size() % segment.page_size(), 0);
+
+    unsafe { segment.as_ptr().write_bytes(0xa5, mb(3)) };
+
+    let page_size = segment.page_size();
+
+    let handle = thread::spawn(move || {
+        let received = recv_segment(&consumer).unwrap();
+        assert_eq!(received.layout(), layout);
+        assert_eq!(received.page_size(), page_size);
+
+        unsafe {
+            assert_eq!(*received.as_ptr(), 0xa5);
+            assert_eq!(*received.as_ptr().add(mb(3) - 1), 0xa5);
+
+            // Writes are visible to the sender
+            *received.as_ptr() = 0x5a;
+        }
+    });
+
+    send_segment(&producer, &segment).unwrap();
+    handle.join().unwrap();
+
+    assert_eq!(unsafe { *segment.as_ptr() }, 0x5a);
+}
+
+#[test]
+fn share_segment_datagram() {
+    let (producer, consumer) = UnixDatagram::pair().unwrap();
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+
+    let segment = SharedSegment::new(layout).unwrap();
+    send_segment(&producer, &segment).unwrap();
+
+    let received = recv_segment(&consumer).unwrap();
+    assert_eq!(received.layout(), layout);
+    assert_eq!(received.page_size(), segment.page_size());
+}
+
+#[test]
+fn recv_without_fd() {
+    let (mut producer, consumer) = UnixStream::pair().unwrap();
+
+    producer.write_all(&[0u8; 24]).unwrap();
+
+    assert!(recv_segment(&consumer).is_err());
+}
+
+#[test]
+fn invalid_layout() {
+    assert!(SharedSegment::new(Layout::from_size_align(0, 8).unwrap()).is_err());
+    assert!(SharedSegment::new(Layout::from_size_align(mb(1), mb(4)).unwrap()).is_err());
+}
+
+#[test]
+fn from_fd_too_small() {
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+    let segment = SharedSegment::new(layout).unwrap();
+    let fd = segment.as_fd().try_clone_to_owned().unwrap();
+
+    let big = Layout::from_size_align(segment.alloc_size() + 1, 8).unwrap();
+    assert!(SharedSegment::from_fd(fd, big, segment.page_size()).is_err());
+}
+
+#[test]
+fn named_segment() {
+    let name = segment_name("named");
+    let layout = Layout::from_size_align(mb(3), 8).unwrap();
+
+    let producer = SharedSegment::create_named(&name, layout).unwrap();
+    assert!(producer.is_writable());
+    assert!(SharedSegment::create_named(&name, layout).is_err());
+
+    unsafe { producer.as_ptr().write_bytes(0xa5, mb(3)) };
+
+    let reader = SharedSegment::open_named(&name, Access::ReadOnly).unwrap();
+    assert!(!reader.is_writable());
+    assert_eq!(reader.alloc_size(), producer.alloc_size());
+    assert_eq!(reader.page_size(), producer.page_size());
+    assert_eq!(reader.size(), producer.alloc_size());
+
+    let writer = SharedSegment::open_named(&name, Access::ReadWrite).unwrap();
+    assert!(writer.is_writable());
+
+    unsafe {
+        assert_eq!(*reader.as_ptr().add(mb(3) - 1), 0xa5);
+
+        *writer.as_ptr() = 0x5a;
+        assert_eq!(*reader.as_ptr(), 0x5a);
+        assert_eq!(*producer.as_ptr(), 0x5a);
+    }
+
+    SharedSegment::unlink_named(&name).unwrap();
+
+    // Existing mappings survive the unlink
+    assert_eq!(unsafe { *reader.as_ptr() }, 0x5a);
+    assert!(SharedSegment::open_named(&name, Access::ReadOnly).is_err());
+    assert!(SharedSegment::unlink_named(&name).is_err());
+}
+
+#[test]
+fn named_segment_invalid_name() {
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+
+    assert!(SharedSegment::create_named("", layout).is_err());
+    assert!(SharedSegment::create_named("../escape", layout).is_err());
+    assert!(SharedSegment::open_named("a/b", Access::ReadOnly).is_err());
+}
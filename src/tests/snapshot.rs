@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+
+use crate::snapshot::{SnapshotEntry, Snapshots};
+
+#[test]
+fn table_model() {
+    let snapshots = Snapshots::new();
+    let _ = snapshots.publish_grown([Snapshots::alloc_table(64), Snapshots::alloc_table(64)], std::iter::empty);
+    assert_eq!(64, snapshots.capacity());
+
+    // Random inserts, updates and removes of a few hundred addresses, checked against a map so
+    // probe sequences broken by a removal are caught
+    let mut model = HashMap::new();
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+
+    for _ in 0..4000 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+
+        let ptr = (seed as usize % 256) << 12;
+
+        if seed & (1 << 40) != 0 && (model.len() < snapshots.capacity() || model.contains_key(&ptr)) {
+            let entry = SnapshotEntry { ptr, huge: seed & (1 << 41) != 0, stable: false };
+
+            snapshots.insert(entry);
+            model.insert(ptr, entry);
+        } else {
+            snapshots.remove(ptr);
+            model.remove(&ptr);
+        }
+
+        for page in 0..256 {
+            assert_eq!(model.get(&(page << 12)).copied(), snapshots.get(page << 12));
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::thread;
+
+use crate::sync::Mutex;
+
+#[test]
+fn mutex_contention() {
+    static COUNT: Mutex<usize> = Mutex::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    *COUNT.lock() += 1;
+                }
+            });
+        }
+    });
+
+    assert_eq!(8000, *COUNT.lock());
+
+    let mut stats = crate::HugeGlobalAllocatorStats::default();
+    COUNT.stats().read(&mut stats);
+
+    if cfg!(feature = "no-stats") {
+        assert_eq!(0, stats.lock_acquisitions);
+    } else {
+        assert_eq!(8001, stats.lock_acquisitions);
+        assert!(stats.lock_contended <= 8000);
+        assert!(stats.lock_max_wait_time <= stats.lock_wait_time);
+    }
+}
@@ -0,0 +1,38 @@
+use super::*;
+use std::slice;
+
+use crate::thp::parse_smaps;
+
+#[test]
+fn parser() {
+    let smaps = "\
+7f0000000000-7f0000800000 rw-p 00000000 00:00 0
+Size:               8192 kB
+AnonHugePages:      6144 kB
+VmFlags: rd wr mr mw me ac hg
+7f0000800000-7f0000a00000 rw-p 00000000 00:00 0
+Size:               2048 kB
+AnonHugePages:      2048 kB
+VmFlags: rd wr mr mw me ac
+";
+
+    assert_eq!(mb(6), parse_smaps(smaps, slice::from_ref(&(0x7f0000100000..0x7f0000200000))));
+    assert_eq!(mb(8), parse_smaps(smaps, slice::from_ref(&(0x7f0000000000..0x7f0000a00000))));
+    assert_eq!(0, parse_smaps(smaps, slice::from_ref(&(0x7f0000a00000..0x7f0000c00000))));
+    assert_eq!(0, parse_smaps("", slice::from_ref(&(0x7f0000000000..0x7f0000a00000))));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn promoted() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    assert_eq!(0, allocator.thp_promoted().unwrap());
+
+    let layout = Layout::from_size_align(mb(8), 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(1, mb(8)) };
+
+    assert!(allocator.thp_promoted().unwrap() <= mb(8));
+
+    unsafe { allocator.dealloc(ptr, layout) };
+}
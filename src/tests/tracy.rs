@@ -0,0 +1,31 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use tracy_client::Client;
+
+use super::*;
+
+#[test]
+fn segment_events() {
+    let allocator = HugeGlobalAllocator::new(mb(1));
+    let layout = Layout::from_size_align(mb(1), 8).unwrap();
+
+    // Events are skipped until the client is running
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        allocator.dealloc(ptr, layout);
+    }
+
+    let _client = Client::start();
+
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+
+        let ptr = allocator.realloc(ptr, layout, mb(4));
+        assert!(!ptr.is_null());
+
+        allocator.dealloc(ptr, Layout::from_size_align(mb(4), 8).unwrap());
+    }
+
+    assert_eq!(0, allocator.stats().unwrap().segments);
+}
@@ -0,0 +1,58 @@
+use super::mb;
+use crate::verify::{check, Entry};
+use crate::Problem;
+
+/// Returns a consistent entry for a huge page segment
+fn entry(ptr: usize, size: usize) -> Entry {
+    Entry {
+        key: ptr,
+        ptr,
+        size,
+        align: 8,
+        alloc_size: size.next_multiple_of(mb(2)),
+        page_size: mb(2),
+        huge: true,
+        canary: None,
+    }
+}
+
+#[test]
+fn consistent() {
+    let entries = vec![entry(mb(2), mb(3)), entry(mb(8), mb(2))];
+
+    let report = check(entries, (0, 0, 0), (mb(5), mb(6), 2), mb(6));
+
+    assert!(report.is_ok(), "{report}");
+    assert_eq!(2, report.segments);
+}
+
+#[test]
+fn inconsistent() {
+    let mut bad_key = entry(mb(20), mb(2));
+    bad_key.key = mb(22);
+
+    let mut partial = entry(mb(30), mb(2));
+    partial.alloc_size = mb(3);
+
+    let mut overrun = entry(mb(40), mb(4));
+    overrun.canary = Some(3);
+    overrun.size = mb(5);
+
+    let entries = vec![entry(mb(2), mb(3)), entry(mb(4), mb(2)), bad_key, partial, overrun];
+
+    let report = check(entries, (0, 0, 0), (0, 0, 0), 0);
+
+    for problem in [
+        Problem::Overlap { first: mb(2), first_end: mb(6), second: mb(4) },
+        Problem::KeyMismatch { key: mb(22), ptr: mb(20) },
+        Problem::PartialPage { ptr: mb(30), alloc_size: mb(3), page_size: mb(2) },
+        Problem::SizeExceedsMapping { ptr: mb(40), size: mb(5), alloc_size: mb(4) },
+        Problem::CanaryOverwritten { ptr: mb(40), offset: 3 },
+        Problem::TotalMismatch { total: "mapped", expected: mb(15), actual: 0 },
+    ] {
+        assert!(report.problems.contains(&problem), "{problem} missing from {report}");
+    }
+
+    assert!(!report.is_ok());
+    assert!(report.to_string().contains("overlaps segment"));
+}
@@ -0,0 +1,48 @@
+//! Transparent huge page backing of default page size fallbacks
+//!
+//! An allocation which wanted huge pages but couldn't get them from the hugetlb pool falls back to
+//! default size pages. If it spans at least one transparent huge page, the fallback is aligned to
+//! the transparent huge page size and advised MADV_HUGEPAGE, so khugepaged can later back it with
+//! transparent huge pages without the allocator moving it. How much of the fallbacks has been
+//! backed is read from /proc/self/smaps on demand.
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+/// Size of the transparent huge pages fallbacks are aligned to (2mb, the PMD size on x86_64 and
+/// on aarch64 with 4kb pages)
+pub(crate) const THP_SIZE: usize = 2 * 1024 * 1024;
+
+/// Returns the bytes of transparent huge pages backing the mappings overlapping the address ranges
+pub(crate) fn anon_huge_bytes(ranges: &[Range<usize>]) -> io::Result<usize> {
+    Ok(parse_smaps(&fs::read_to_string("/proc/self/smaps")?, ranges))
+}
+
+/// Sums the AnonHugePages of the mappings in the contents of /proc/self/smaps which overlap any of
+/// the address ranges. The kernel merges adjacent mappings with the same flags, so a mapping
+/// counted may extend beyond the ranges.
+pub(crate) fn parse_smaps(smaps: &str, ranges: &[Range<usize>]) -> usize {
+    let mut overlaps = false;
+    let mut bytes = 0;
+
+    for line in smaps.lines() {
+        if let Some(vma) = parse_vma(line) {
+            overlaps = ranges.iter().any(|range| range.start < vma.end && vma.start < range.end);
+        } else if let Some(kb) = line.strip_prefix("AnonHugePages:") {
+            if overlaps {
+                bytes += kb.trim().strip_suffix("kB").and_then(|kb| kb.trim().parse::<usize>().ok()).unwrap_or(0) * 1024;
+            }
+        }
+    }
+
+    bytes
+}
+
+/// Parses the address range of a mapping's header line, eg. "7f0000000000-7f0000200000 rw-p ..."
+fn parse_vma(line: &str) -> Option<Range<usize>> {
+    let (range, _) = line.split_once(' ')?;
+    let (start, end) = range.split_once('-')?;
+
+    Some(usize::from_str_radix(start, 16).ok()?..usize::from_str_radix(end, 16).ok()?)
+}
@@ -0,0 +1,27 @@
+//! Tracy profiler memory events
+//!
+//! Memory mapped segments are reported to Tracy as allocations in a named memory pool, so they
+//! appear in the profiler's memory views alongside the application's frames and zones. Events are
+//! only emitted while the Tracy client is running, which the application starts with
+//! tracy_client::Client::start().
+
+use std::ffi::{c_char, CStr};
+
+use tracy_client::{sys, Client};
+
+/// Name of the Tracy memory pool holding the memory mapped segments
+const POOL: &CStr = c"huge_global_alloc";
+
+/// Reports a segment allocation
+pub(crate) fn alloc(ptr: *const u8, size: usize) {
+    if Client::running().is_some() {
+        unsafe { sys::___tracy_emit_memory_alloc_named(ptr.cast(), size, 1, POOL.as_ptr() as *const c_char) };
+    }
+}
+
+/// Reports a segment deallocation
+pub(crate) fn free(ptr: *const u8) {
+    if Client::running().is_some() {
+        unsafe { sys::___tracy_emit_memory_free_named(ptr.cast(), 1, POOL.as_ptr() as *const c_char) };
+    }
+}
@@ -0,0 +1,220 @@
+//! Consistency check of the mapper's bookkeeping
+//!
+//! Sweeps a snapshot of the pointer map for entries which don't describe a sane mapping, segments
+//! which overlap, and running totals which have drifted from the segments they count. Available
+//! in debug builds, or in release builds with the verify feature.
+
+use std::fmt::{self, Display};
+
+/// A snapshot of one pointer map entry
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    /// The key the entry is stored under
+    pub key: usize,
+    /// Address of the segment
+    pub ptr: usize,
+    /// Requested size in bytes
+    pub size: usize,
+    /// Requested alignment
+    pub align: usize,
+    /// Mapped size in bytes
+    pub alloc_size: usize,
+    /// Page size backing the segment
+    pub page_size: usize,
+    /// True if backed by huge pages
+    pub huge: bool,
+    /// Offset past the end of the payload of the first overwritten canary byte
+    pub canary: Option<usize>,
+}
+
+/// Running totals of one kind of segment: allocated bytes, mapped bytes and segment count
+pub(crate) type Totals = (usize, usize, usize);
+
+/// An inconsistency found by [HugeGlobalAllocator::verify](crate::HugeGlobalAllocator::verify)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A segment is stored under a key other than its address
+    KeyMismatch {
+        /// The key the segment is stored under
+        key: usize,
+        /// The segment's address
+        ptr: usize,
+    },
+    /// A segment's address isn't a multiple of its page size or its alignment
+    MisalignedAddress {
+        /// The segment's address
+        ptr: usize,
+        /// The alignment it should have
+        align: usize,
+    },
+    /// A segment's mapped size isn't a whole number of pages
+    PartialPage {
+        /// The segment's address
+        ptr: usize,
+        /// The segment's mapped size
+        alloc_size: usize,
+        /// The segment's page size
+        page_size: usize,
+    },
+    /// A segment's requested size is larger than its mapping
+    SizeExceedsMapping {
+        /// The segment's address
+        ptr: usize,
+        /// The segment's requested size
+        size: usize,
+        /// The segment's mapped size
+        alloc_size: usize,
+    },
+    /// Two segments' mappings overlap
+    Overlap {
+        /// Address of the lower segment
+        first: usize,
+        /// End of the lower segment's mapping
+        first_end: usize,
+        /// Address of the higher segment
+        second: usize,
+    },
+    /// The bytes after a segment's payload have been overwritten
+    CanaryOverwritten {
+        /// The segment's address
+        ptr: usize,
+        /// Offset past the end of the payload of the first overwritten byte
+        offset: usize,
+    },
+    /// A running total differs from the sum over the segments
+    TotalMismatch {
+        /// The statistic holding the total
+        total: &'static str,
+        /// The sum over the segments
+        expected: usize,
+        /// The running total
+        actual: usize,
+    },
+}
+
+impl Display for Problem {
+    /// Describes the problem on one line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Problem::KeyMismatch { key, ptr } => write!(f, "segment {ptr:#x} stored under key {key:#x}"),
+            Problem::MisalignedAddress { ptr, align } => write!(f, "segment {ptr:#x} not aligned to {align}"),
+            Problem::PartialPage { ptr, alloc_size, page_size } => {
+                write!(f, "segment {ptr:#x} mapped size {alloc_size} not a multiple of page size {page_size}")
+            }
+            Problem::SizeExceedsMapping { ptr, size, alloc_size } => {
+                write!(f, "segment {ptr:#x} size {size} exceeds mapped size {alloc_size}")
+            }
+            Problem::Overlap { first, first_end, second } => {
+                write!(f, "segment {first:#x}-{first_end:#x} overlaps segment {second:#x}")
+            }
+            Problem::CanaryOverwritten { ptr, offset } => {
+                write!(f, "segment {ptr:#x} canary overwritten {offset} bytes past the end")
+            }
+            Problem::TotalMismatch { total, expected, actual } => {
+                write!(f, "{total} is {actual} but the segments sum to {expected}")
+            }
+        }
+    }
+}
+
+/// Result of a consistency check
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of segments checked
+    pub segments: usize,
+    /// The inconsistencies found
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Returns true if no inconsistencies were found
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for VerifyReport {
+    /// Formats the report with one problem per line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} segments checked, {} problems", self.segments, self.problems.len())?;
+
+        for problem in &self.problems {
+            writeln!(f, "{problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks a snapshot of the pointer map against itself and against the running totals of mapped
+/// bytes and of default and huge page segments. The segment totals are skipped if statistics are
+/// compiled out.
+pub(crate) fn check(mut entries: Vec<Entry>, default: Totals, huge: Totals, mapped: usize) -> VerifyReport {
+    let mut problems = Vec::new();
+
+    for entry in &entries {
+        let ptr = entry.ptr;
+
+        if entry.key != ptr {
+            problems.push(Problem::KeyMismatch { key: entry.key, ptr });
+        }
+
+        let align = entry.align.max(entry.page_size);
+
+        if !ptr.is_multiple_of(align) {
+            problems.push(Problem::MisalignedAddress { ptr, align });
+        }
+
+        if entry.page_size == 0 || !entry.alloc_size.is_multiple_of(entry.page_size) {
+            problems.push(Problem::PartialPage { ptr, alloc_size: entry.alloc_size, page_size: entry.page_size });
+        }
+
+        if entry.size > entry.alloc_size {
+            problems.push(Problem::SizeExceedsMapping { ptr, size: entry.size, alloc_size: entry.alloc_size });
+        }
+
+        if let Some(offset) = entry.canary {
+            problems.push(Problem::CanaryOverwritten { ptr, offset });
+        }
+    }
+
+    entries.sort_unstable_by_key(|entry| entry.ptr);
+
+    for pair in entries.windows(2) {
+        let first_end = pair[0].ptr + pair[0].alloc_size;
+
+        if first_end > pair[1].ptr {
+            problems.push(Problem::Overlap { first: pair[0].ptr, first_end, second: pair[1].ptr });
+        }
+    }
+
+    let sum = |huge: bool| {
+        entries.iter().filter(|entry| entry.huge == huge).fold((0, 0, 0), |(alloc, mapped, segments), entry| {
+            (alloc + entry.size, mapped + entry.alloc_size, segments + 1)
+        })
+    };
+
+    let (default_sum, huge_sum) = (sum(false), sum(true));
+
+    // The mapped total is kept for the soft limit even without statistics
+    let mut totals = vec![("mapped", default_sum.1 + huge_sum.1, mapped)];
+
+    if cfg!(not(feature = "no-stats")) {
+        totals.extend([
+            ("default_alloc", default_sum.0, default.0),
+            ("default_mapped", default_sum.1, default.1),
+            ("default_segments", default_sum.2, default.2),
+            ("huge_alloc", huge_sum.0, huge.0),
+            ("huge_mapped", huge_sum.1, huge.1),
+            ("huge_segments", huge_sum.2, huge.2),
+        ]);
+    }
+
+    for (total, expected, actual) in totals {
+        if expected != actual {
+            problems.push(Problem::TotalMismatch { total, expected, actual });
+        }
+    }
+
+    VerifyReport { segments: entries.len(), problems }
+}
@@ -42,7 +42,7 @@ function run_tests {
 
     set_huge_pages $nr_pages
 
-    TEST_NR_PAGES=$nr_pages cargo test --quiet tests::huge_alloc
+    cargo test --quiet tests::huge_alloc
 
     if [ $? -ne 0 ]
     then
@@ -0,0 +1,70 @@
+//! Runs the examples' workloads briefly, so they double as integration tests of the public API
+
+#[path = "../examples/hash_cache/harness.rs"]
+mod hash_cache;
+#[path = "../examples/huge_vec/harness.rs"]
+mod huge_vec;
+#[path = "../examples/monitor/harness.rs"]
+mod monitor;
+
+use std::time::Duration;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+const THRESHOLD: usize = 256 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(THRESHOLD);
+
+#[test]
+fn hash_cache() {
+    let config = hash_cache::Config {
+        threshold: THRESHOLD,
+        entries: 20_000,
+        large_every: 1_000,
+    };
+
+    let summary = hash_cache::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+
+    assert_eq!(config.entries, summary.inserts);
+    assert_eq!(config.entries / 2, summary.hits);
+    assert_eq!(config.entries / 2, summary.misses);
+    assert!(summary.table_bytes >= THRESHOLD, "table smaller than the threshold");
+
+    if !cfg!(feature = "passthrough") {
+        assert_eq!(config.entries / config.large_every, summary.managed_values);
+    }
+}
+
+#[test]
+fn huge_vec() {
+    let config = huge_vec::Config {
+        elements: 1024 * 1024,
+    };
+
+    let summary = huge_vec::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+
+    assert!(summary.grows > 0, "vector never grew");
+    assert!(summary.moves <= summary.grows, "moves > grows");
+}
+
+#[test]
+fn monitor() {
+    let config = monitor::Config {
+        threshold: THRESHOLD,
+        threads: 4,
+        duration: Duration::from_millis(500),
+        interval: Duration::from_millis(1),
+    };
+
+    let summary = monitor::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+
+    assert!(summary.allocs > 0, "no allocations made");
+    assert!(summary.exact + summary.snapshots > 0, "no polls made");
+}
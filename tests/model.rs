@@ -0,0 +1,132 @@
+//! Replays arbitrary sequences of alloc / realloc / dealloc operations against a reference model,
+//! checking the allocator's bookkeeping and that data is preserved across reallocations.
+
+use std::alloc::{GlobalAlloc, Layout};
+
+use huge_global_alloc::HugeGlobalAllocator;
+use proptest::prelude::*;
+
+const THRESHOLD: usize = 1024 * 1024;
+const MAX_SIZE: usize = 8 * 1024 * 1024;
+const STRIDE: usize = 4096;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Alloc { size: usize, align_shift: u32, zeroed: bool },
+    Realloc { idx: usize, size: usize },
+    Dealloc { idx: usize },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (1..MAX_SIZE, 0..13u32, any::<bool>()).prop_map(|(size, align_shift, zeroed)| Op::Alloc { size, align_shift, zeroed }),
+        (any::<usize>(), 1..MAX_SIZE).prop_map(|(idx, size)| Op::Realloc { idx, size }),
+        any::<usize>().prop_map(|idx| Op::Dealloc { idx }),
+    ]
+}
+
+/// A live allocation in the reference model
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+    fill: u8,
+}
+
+impl Block {
+    /// Writes the fill byte at every stride through the block and at the last byte
+    fn fill(&self) {
+        for offset in (0..self.layout.size()).step_by(STRIDE).chain([self.layout.size() - 1]) {
+            unsafe { *self.ptr.add(offset) = self.fill };
+        }
+    }
+
+    /// Checks the filled bytes up to the given length
+    fn check(&self, len: usize) {
+        let last = self.layout.size() - 1;
+
+        for offset in (0..len).step_by(STRIDE).chain([last].into_iter().filter(|&last| last < len)) {
+            assert_eq!(self.fill, unsafe { *self.ptr.add(offset) }, "data lost at offset {}", offset);
+        }
+    }
+}
+
+fn check_stats(allocator: &HugeGlobalAllocator, blocks: &[Block]) {
+    let stats = allocator.stats().unwrap();
+
+    // Nothing is mapped when the allocator passes everything through
+    let managed = blocks.iter().filter(|b| !cfg!(feature = "passthrough") && b.layout.size() >= THRESHOLD);
+
+    assert_eq!(managed.clone().count(), stats.segments, "segments");
+    assert_eq!(managed.map(|b| b.layout.size()).sum::<usize>(), stats.alloc, "alloc");
+    assert_eq!(stats.default_segments + stats.huge_segments, stats.segments, "segment sum");
+    assert_eq!(stats.default_mapped + stats.huge_mapped, stats.mapped, "mapped sum");
+    assert_eq!(stats.default_alloc + stats.huge_alloc, stats.alloc, "alloc sum");
+    assert!(stats.mapped >= stats.alloc, "mapped >= alloc");
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn alloc_sequence(ops in prop::collection::vec(op(), 1..32)) {
+        let allocator = HugeGlobalAllocator::new(THRESHOLD);
+        let mut blocks: Vec<Block> = Vec::new();
+
+        for (i, op) in ops.into_iter().enumerate() {
+            match op {
+                Op::Alloc { size, align_shift, zeroed } => {
+                    let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+
+                    let ptr = unsafe {
+                        if zeroed {
+                            allocator.alloc_zeroed(layout)
+                        } else {
+                            allocator.alloc(layout)
+                        }
+                    };
+
+                    assert!(!ptr.is_null());
+                    assert_eq!(0, ptr as usize % layout.align(), "alignment");
+
+                    if zeroed {
+                        Block { ptr, layout, fill: 0 }.check(size);
+                    }
+
+                    let block = Block { ptr, layout, fill: i as u8 };
+                    block.fill();
+                    blocks.push(block);
+                }
+                Op::Realloc { idx, size } if !blocks.is_empty() => {
+                    let len = blocks.len();
+                    let block = &mut blocks[idx % len];
+
+                    let ptr = unsafe { allocator.realloc(block.ptr, block.layout, size) };
+                    assert!(!ptr.is_null());
+                    assert_eq!(0, ptr as usize % block.layout.align(), "alignment");
+
+                    block.ptr = ptr;
+                    block.check(size.min(block.layout.size()));
+
+                    block.layout = Layout::from_size_align(size, block.layout.align()).unwrap();
+                    block.fill();
+                }
+                Op::Dealloc { idx } if !blocks.is_empty() => {
+                    let block = blocks.swap_remove(idx % blocks.len());
+
+                    block.check(block.layout.size());
+
+                    unsafe { allocator.dealloc(block.ptr, block.layout) };
+                }
+                _ => (),
+            }
+
+            check_stats(&allocator, &blocks);
+        }
+
+        for block in blocks {
+            unsafe { allocator.dealloc(block.ptr, block.layout) };
+        }
+
+        check_stats(&allocator, &[]);
+    }
+}
@@ -0,0 +1,35 @@
+//! Hammers the allocator from many threads with sizes around the threshold, checking data
+//! integrity, statistics consistency and that no thread deadlocks. A longer run is available with
+//! the stress example.
+
+#[path = "../examples/stress/harness.rs"]
+mod harness;
+
+use std::time::Duration;
+
+use huge_global_alloc::HugeGlobalAllocator;
+
+const THRESHOLD: usize = 64 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: HugeGlobalAllocator = HugeGlobalAllocator::new(THRESHOLD);
+
+#[test]
+fn stress() {
+    let config = harness::Config {
+        threshold: THRESHOLD,
+        threads: 8,
+        duration: Duration::from_secs(2),
+        live: 8,
+        deadlock_timeout: Duration::from_secs(30),
+    };
+
+    let summary = harness::run(&GLOBAL_ALLOCATOR, &config);
+
+    println!("{:?}", summary);
+
+    assert!(summary.allocs > 0, "no allocations made");
+    assert!(summary.reallocs > 0, "no reallocations made");
+    assert_eq!(summary.allocs, summary.deallocs, "allocs != deallocs");
+    assert!(summary.checks > 0, "no statistics checks made");
+}